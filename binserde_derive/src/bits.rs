@@ -0,0 +1,55 @@
+use darling::ast::{Data, Style};
+use proc_macro2::TokenStream;
+use quote::quote;
+
+use crate::common::*;
+
+/// Implements `BitField` for enums marked `#[binserde(repr_width = n)]`,
+/// storing the tag of the variant.
+pub fn impl_bit_field(opts: &BinSerdeOpts, width: u32) -> TokenStream {
+    let name = &opts.ident;
+
+    let variants = match &opts.data {
+        Data::Enum(variants) => variants,
+        Data::Struct(_) => {
+            return quote! {
+                compile_error!("#[binserde(repr_width)] is only supported on enums");
+            }
+        }
+    };
+
+    if variants.iter().any(|el| el.fields.style != Style::Unit) {
+        return quote! {
+            compile_error!("#[binserde(repr_width)] is only supported on enums without fields");
+        };
+    }
+
+    let indices = variant_tag_values(opts, variants);
+
+    if width < 64 && indices.iter().any(|&tag| tag >> width != 0) {
+        let msg = format!("variant tags don't fit in {} bits", width);
+        return quote!(compile_error!(#msg););
+    }
+
+    let idents: Vec<_> = variants.iter().map(|el| &el.ident).collect();
+
+    let generic_defs = generic_defs(opts).map(|el| quote!(<#el>));
+    let generic_params = generic_params_on_target(opts).map(|el| quote!(<#el>));
+
+    quote! {
+        impl #generic_defs ::binserde::bits::BitField for #name #generic_params {
+            fn to_bits(&self) -> u64 {
+                match self {
+                    #( Self::#idents => #indices, )*
+                }
+            }
+
+            fn from_bits(bits: u64) -> ::binserde::Result<Self> {
+                match bits {
+                    #( #indices => Ok(Self::#idents), )*
+                    x => Err(::binserde::Error::UnknownVariant(x)),
+                }
+            }
+        }
+    }
+}
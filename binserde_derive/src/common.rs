@@ -1,16 +1,45 @@
 use std::borrow::Cow;
 
-use darling::ast::{Fields, Style};
+use darling::ast::{Data, Fields, Style};
 use darling::{FromDeriveInput, FromField, FromVariant};
-use proc_macro2::{Span, TokenStream};
+use proc_macro2::{Literal, Span, TokenStream, TokenTree};
 use quote::{quote, ToTokens};
-use syn::{ConstParam, GenericParam, Generics, Ident, LifetimeDef, Type, TypeParam};
+use syn::{
+    ConstParam, GenericArgument, GenericParam, Generics, Ident, Lifetime, LifetimeDef, Meta,
+    NestedMeta, PathArguments, Type, TypeArray, TypeGroup, TypeParam, TypeParen, TypePath,
+    TypeReference, TypeSlice, TypeTuple,
+};
 
 #[derive(FromDeriveInput, Debug)]
-#[darling(attributes(binserde), supports(struct_any, enum_any))]
+#[darling(
+    attributes(binserde),
+    forward_attrs(repr),
+    supports(struct_any, enum_any)
+)]
 pub struct BinSerdeOpts {
     pub ident: Ident,
     pub generics: Generics,
+    pub attrs: Vec<syn::Attribute>,
+    #[darling(default)]
+    pub soa: bool,
+    #[darling(default)]
+    pub tag_variants_by_hash: bool,
+    #[darling(default)]
+    pub tagged: bool,
+    #[darling(default)]
+    pub count_fields: bool,
+    #[darling(default)]
+    pub repr_width: Option<u32>,
+    #[darling(default)]
+    pub repr: Option<String>,
+    #[darling(default)]
+    pub version: Option<u32>,
+    #[darling(default)]
+    pub include: Option<String>,
+    #[darling(default)]
+    pub from_discriminant: Option<String>,
+    #[darling(default)]
+    pub pack_bools: bool,
     pub data: darling::ast::Data<BinSerdeVariant, BinSerdeField>,
 }
 
@@ -19,6 +48,8 @@ pub struct BinSerdeOpts {
 pub struct BinSerdeVariant {
     pub ident: Ident,
     pub fields: Fields<BinSerdeField>,
+    #[darling(default)]
+    pub tag: Option<usize>,
 }
 
 #[derive(FromField, Debug)]
@@ -29,9 +60,149 @@ pub struct BinSerdeField {
     #[darling(default)]
     pub no_dedup: bool,
     #[darling(default)]
+    pub skip_dedup_scan: bool,
+    #[darling(default)]
     pub skip: bool,
     #[darling(default)]
     pub index: Option<usize>,
+    #[darling(default)]
+    pub bits: Option<u32>,
+    #[darling(default)]
+    pub fixed_len: Option<usize>,
+    #[darling(default)]
+    pub rest: bool,
+    #[darling(default)]
+    pub since: Option<u32>,
+    #[darling(default)]
+    pub skip_if: Option<syn::Path>,
+    #[darling(default)]
+    pub default: Option<syn::Path>,
+    #[darling(default)]
+    pub with: Option<syn::Path>,
+    #[darling(default)]
+    pub serialize_with: Option<syn::Path>,
+    #[darling(default)]
+    pub deserialize_with: Option<syn::Path>,
+    #[darling(default)]
+    pub niche: bool,
+    #[darling(default)]
+    pub len: Option<String>,
+}
+
+impl BinSerdeField {
+    /// The function to serialize this field with instead of its
+    /// `BinSerialize` impl, if any.
+    pub fn serialize_fn(&self) -> Option<TokenStream> {
+        match (&self.serialize_with, &self.with) {
+            (Some(path), _) => Some(quote!(#path)),
+            (None, Some(path)) => Some(quote!(#path::serialize)),
+            (None, None) if self.niche => Some(quote!(::binserde::niche::serialize)),
+            (None, None) => self.len_width().map(|width| {
+                quote!((|__value, __serializer| {
+                    ::binserde::ser::write_with_len(#width, __value, __serializer)
+                }))
+            }),
+        }
+    }
+
+    /// The function to deserialize this field with instead of its
+    /// `BinDeserialize` impl, if any.
+    pub fn deserialize_fn(&self) -> Option<TokenStream> {
+        match (&self.deserialize_with, &self.with) {
+            (Some(path), _) => Some(quote!(#path)),
+            (None, Some(path)) => Some(quote!(#path::deserialize)),
+            (None, None) if self.niche => Some(quote!(::binserde::niche::deserialize)),
+            (None, None) => self.len_width().map(|width| {
+                quote!((|__deserializer| ::binserde::de::read_with_len(#width, __deserializer)))
+            }),
+        }
+    }
+
+    /// The value to use for this field when it isn't read from the input.
+    pub fn default_value(&self) -> TokenStream {
+        match &self.default {
+            Some(path) => quote!(#path()),
+            None => quote!(Default::default()),
+        }
+    }
+
+    /// The width of the length prefix given with `#[binserde(len = "type")]`,
+    /// as a `UsizeLen`. Unsupported types are reported by `field_groups`.
+    fn len_width(&self) -> Option<TokenStream> {
+        let width = match self.len.as_deref()? {
+            "u8" => quote!(U8),
+            "u16" => quote!(U16),
+            "u32" => quote!(U32),
+            "u64" => quote!(U64),
+            _ => return None,
+        };
+
+        Some(quote!(::binserde::serde::UsizeLen::#width))
+    }
+
+    pub fn has_custom_fn(&self) -> bool {
+        self.with.is_some()
+            || self.serialize_with.is_some()
+            || self.deserialize_with.is_some()
+            || self.niche
+            || self.len.is_some()
+    }
+
+    /// Whether any attribute changing how this field is serialized is set.
+    fn has_attrs(&self) -> bool {
+        self.no_dedup
+            || self.skip_dedup_scan
+            || self.index.is_some()
+            || self.bits.is_some()
+            || self.fixed_len.is_some()
+            || self.rest
+            || self.since.is_some()
+            || self.skip_if.is_some()
+            || self.default.is_some()
+            || self.has_custom_fn()
+    }
+}
+
+/// Returns the index of the field to serialize in place of the struct if it
+/// is `#[repr(transparent)]`. This is the only field which isn't skipped or
+/// `PhantomData`, and only if it has no attributes of its own. The others
+/// are set to their default value when deserializing.
+pub fn transparent_field(opts: &BinSerdeOpts) -> Option<usize> {
+    let is_transparent = opts.attrs.iter().any(|attr| match attr.parse_meta() {
+        Ok(Meta::List(list)) if list.path.is_ident("repr") => list.nested.iter().any(
+            |el| matches!(el, NestedMeta::Meta(Meta::Path(path)) if path.is_ident("transparent")),
+        ),
+        _ => false,
+    });
+
+    let fields = match &opts.data {
+        Data::Struct(fields)
+            if is_transparent && !opts.tagged && !opts.count_fields && opts.version.is_none() =>
+        {
+            fields
+        }
+        _ => return None,
+    };
+
+    let mut candidates = fields
+        .iter()
+        .enumerate()
+        .filter(|(_, el)| !el.skip && !is_phantom_data(&el.ty));
+
+    match (candidates.next(), candidates.next()) {
+        (Some((idx, el)), None) if !el.has_attrs() => Some(idx),
+        _ => None,
+    }
+}
+
+fn is_phantom_data(ty: &Type) -> bool {
+    match ty {
+        Type::Path(TypePath { path, .. }) => path
+            .segments
+            .last()
+            .is_some_and(|el| el.ident == "PhantomData"),
+        _ => false,
+    }
 }
 
 pub enum StructField<'a> {
@@ -48,7 +219,7 @@ impl ToTokens for StructField<'_> {
     }
 }
 
-pub fn to_struct_fields(fields: &Fields<BinSerdeField>, skip: bool) -> Vec<StructField> {
+pub fn to_struct_fields(fields: &Fields<BinSerdeField>, skip: bool) -> Vec<StructField<'_>> {
     let iter = fields
         .iter()
         .enumerate()
@@ -65,7 +236,7 @@ pub fn to_struct_fields(fields: &Fields<BinSerdeField>, skip: bool) -> Vec<Struc
     }
 }
 
-pub fn to_idents(fields: &Fields<BinSerdeField>, skip: bool) -> Vec<Cow<Ident>> {
+pub fn to_idents(fields: &Fields<BinSerdeField>, skip: bool) -> Vec<Cow<'_, Ident>> {
     let iter = fields
         .iter()
         .enumerate()
@@ -82,6 +253,555 @@ pub fn to_idents(fields: &Fields<BinSerdeField>, skip: bool) -> Vec<Cow<Ident>>
     }
 }
 
+/// Returns the name of each field as written in the source, or its position
+/// for tuple fields.
+pub fn field_names(fields: &Fields<BinSerdeField>) -> Vec<String> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(idx, el)| match &el.ident {
+            None => idx.to_string(),
+            Some(ident) => ident.to_string(),
+        })
+        .collect()
+}
+
+/// Returns the value written to identify each variant, which is either its
+/// index, the value of its `#[binserde(tag = n)]` attribute, or with
+/// `#[binserde(tag_variants_by_hash)]`, a hash of its name.
+pub fn variant_tag_values(opts: &BinSerdeOpts, variants: &[BinSerdeVariant]) -> Vec<u64> {
+    variants
+        .iter()
+        .enumerate()
+        .map(|(idx, el)| {
+            if opts.tag_variants_by_hash {
+                variant_hash(&el.ident.to_string())
+            } else {
+                el.tag.unwrap_or(idx) as u64
+            }
+        })
+        .collect()
+}
+
+/// Like [`variant_tag_values`], but as literals of the type returned by
+/// [`variant_tag_type`].
+pub fn variant_tags(opts: &BinSerdeOpts, variants: &[BinSerdeVariant]) -> Vec<TokenStream> {
+    variant_tag_values(opts, variants)
+        .into_iter()
+        .map(|tag| tag_literal(opts, tag))
+        .collect()
+}
+
+fn tag_literal(opts: &BinSerdeOpts, tag: u64) -> TokenStream {
+    let lit = match opts.repr.as_deref() {
+        _ if opts.tag_variants_by_hash => Literal::u64_suffixed(tag),
+        None => Literal::usize_suffixed(tag as usize),
+        Some("u8") => Literal::u8_suffixed(tag as u8),
+        Some("u16") => Literal::u16_suffixed(tag as u16),
+        Some("u32") => Literal::u32_suffixed(tag as u32),
+        Some("u64") => Literal::u64_suffixed(tag),
+        Some(_) => Literal::usize_suffixed(tag as usize),
+    };
+    quote!(#lit)
+}
+
+/// Returns the additional tags each variant is read from, which are given
+/// with `#[binserde(from_discriminant = "3 => Variant, ...")]`, or a compile
+/// error if the list is malformed or a tag is already taken.
+pub fn legacy_variant_tags(
+    opts: &BinSerdeOpts,
+    variants: &[BinSerdeVariant],
+) -> Result<Vec<Vec<TokenStream>>, TokenStream> {
+    let mut legacy = vec![Vec::new(); variants.len()];
+
+    let list = match &opts.from_discriminant {
+        None => return Ok(legacy),
+        Some(v) => v,
+    };
+
+    let error = |msg: String| quote!(compile_error!(#msg););
+    let mut taken = variant_tag_values(opts, variants);
+    let max = opts.repr.as_deref().and_then(max_variant_tag);
+
+    for entry in list.split(',').map(str::trim).filter(|el| !el.is_empty()) {
+        let (tag, name) = match entry.split_once("=>") {
+            Some((tag, name)) => (tag.trim(), name.trim()),
+            None => {
+                return Err(error(format!(
+                    "invalid from_discriminant entry `{}`, expected `n => Variant`",
+                    entry
+                )))
+            }
+        };
+
+        let tag: u64 = match tag.parse() {
+            Ok(v) => v,
+            Err(_) => return Err(error(format!("invalid discriminant `{}`", tag))),
+        };
+
+        let idx = match variants.iter().position(|el| el.ident == name) {
+            Some(v) => v,
+            None => {
+                return Err(error(format!(
+                    "from_discriminant lists unknown variant `{}`",
+                    name
+                )))
+            }
+        };
+
+        if max.is_some_and(|max| tag > max) {
+            return Err(error(format!(
+                "discriminant {} doesn't fit in {}",
+                tag,
+                opts.repr.as_deref().unwrap_or_default()
+            )));
+        }
+
+        if taken.contains(&tag) {
+            return Err(error(format!("discriminant {} is already used", tag)));
+        }
+
+        taken.push(tag);
+        legacy[idx].push(tag_literal(opts, tag));
+    }
+
+    Ok(legacy)
+}
+
+/// The type variant tags are written as, which is chosen with
+/// `#[binserde(repr = "type")]`.
+pub fn variant_tag_type(opts: &BinSerdeOpts) -> TokenStream {
+    match opts.repr.as_deref() {
+        _ if opts.tag_variants_by_hash => quote!(u64),
+        Some(ty) if max_variant_tag(ty).is_some() => {
+            let ty = Ident::new(ty, Span::call_site());
+            quote!(#ty)
+        }
+        // unsupported types are reported by check_variant_tags
+        _ => quote!(usize),
+    }
+}
+
+/// Returns the largest tag a variant can have with `#[binserde(repr = "type")]`,
+/// or `None` if the type isn't supported.
+fn max_variant_tag(repr: &str) -> Option<u64> {
+    match repr {
+        "u8" => Some(u8::MAX as u64),
+        "u16" => Some(u16::MAX as u64),
+        "u32" => Some(u32::MAX as u64),
+        "u64" => Some(u64::MAX),
+        _ => None,
+    }
+}
+
+/// Returns a compile error if two variants have the same tag, which for
+/// `#[binserde(tag_variants_by_hash)]` means that their names hash to the
+/// same value.
+pub fn check_variant_tags(opts: &BinSerdeOpts) -> Option<TokenStream> {
+    let variants = match &opts.data {
+        Data::Enum(variants) => variants,
+        Data::Struct(_) if opts.repr.is_some() => {
+            return Some(quote!(compile_error!("repr is only supported on enums");));
+        }
+        Data::Struct(_) => return None,
+    };
+
+    if opts.tag_variants_by_hash && variants.iter().any(|el| el.tag.is_some()) {
+        return Some(quote!(compile_error!("tag can't be combined with tag_variants_by_hash");));
+    }
+
+    let tags = variant_tag_values(opts, variants);
+
+    if let Some(repr) = &opts.repr {
+        if opts.tag_variants_by_hash {
+            return Some(
+                quote!(compile_error!("repr can't be combined with tag_variants_by_hash");),
+            );
+        }
+
+        let max = match max_variant_tag(repr) {
+            None => {
+                return Some(quote!(compile_error!("repr must be one of u8, u16, u32 or u64");))
+            }
+            Some(v) => v,
+        };
+
+        if let Some(pos) = tags.iter().position(|&tag| tag > max) {
+            let msg = format!(
+                "tag {} of variant `{}` doesn't fit in {}",
+                tags[pos], variants[pos].ident, repr
+            );
+            return Some(quote!(compile_error!(#msg);));
+        }
+    }
+
+    for (idx, a) in variants.iter().enumerate() {
+        for (pos, b) in variants[..idx].iter().enumerate() {
+            if tags[idx] != tags[pos] {
+                continue;
+            }
+
+            let msg = if opts.tag_variants_by_hash {
+                format!(
+                    "variants `{}` and `{}` have the same hash, rename one of them",
+                    b.ident, a.ident
+                )
+            } else {
+                format!(
+                    "variants `{}` and `{}` have the same tag {}",
+                    b.ident, a.ident, tags[idx]
+                )
+            };
+
+            return Some(quote!(compile_error!(#msg);));
+        }
+    }
+
+    None
+}
+
+/// Returns a compile error if `#[binserde(since = k)]` is used without a
+/// version on the type or in a way that doesn't work with it.
+pub fn check_versions(opts: &BinSerdeOpts) -> Option<TokenStream> {
+    let fields: Vec<_> = match &opts.data {
+        Data::Enum(variants) => variants.iter().flat_map(|v| v.fields.iter()).collect(),
+        Data::Struct(fields) => fields.iter().collect(),
+    };
+
+    let error = |msg: &str| Some(quote!(compile_error!(#msg);));
+
+    if opts.version.is_some() && opts.tagged {
+        return error("version can't be combined with tagged");
+    }
+
+    for el in fields {
+        let since = match el.since {
+            None => continue,
+            Some(v) => v,
+        };
+
+        match opts.version {
+            None => return error("since requires a version on the type"),
+            Some(version) if since > version => {
+                return error("since can't be greater than the version of the type")
+            }
+            _ => {}
+        }
+
+        if el.skip || el.bits.is_some() || el.rest {
+            return error("since can't be combined with skip, bits or rest");
+        }
+    }
+
+    None
+}
+
+/// Returns a compile error if `#[binserde(count_fields)]` is used together
+/// with attributes it doesn't work with.
+pub fn check_count_fields(opts: &BinSerdeOpts) -> Option<TokenStream> {
+    if !opts.count_fields {
+        return None;
+    }
+
+    let error = |msg: &str| Some(quote!(compile_error!(#msg);));
+
+    if opts.tagged || opts.version.is_some() {
+        return error("count_fields can't be combined with tagged or version");
+    }
+
+    let fields: Vec<_> = match &opts.data {
+        Data::Enum(variants) => variants.iter().flat_map(|v| v.fields.iter()).collect(),
+        Data::Struct(fields) => fields.iter().collect(),
+    };
+
+    if fields.iter().any(|el| el.bits.is_some()) {
+        return error("fields of a type marked count_fields can't be marked bits");
+    }
+
+    None
+}
+
+/// Applies `#[binserde(include = "...")]` by marking every field which isn't
+/// in the list as skipped. Returns a compile error if the list names a field
+/// which doesn't exist or the attribute is used on an enum.
+pub fn apply_include(opts: &mut BinSerdeOpts) -> Option<TokenStream> {
+    let include = opts.include.as_ref()?;
+
+    let error = |msg: &str| Some(quote!(compile_error!(#msg);));
+
+    let fields = match &mut opts.data {
+        Data::Enum(_) => return error("include is only supported on structs"),
+        Data::Struct(fields) => fields,
+    };
+
+    let names = field_names(fields);
+    let listed: Vec<_> = include
+        .split(',')
+        .map(str::trim)
+        .filter(|el| !el.is_empty())
+        .collect();
+
+    if let Some(name) = listed.iter().find(|el| !names.iter().any(|n| n == *el)) {
+        let msg = format!("include lists unknown field `{}`", name);
+        return Some(quote!(compile_error!(#msg);));
+    }
+
+    for (el, name) in fields.fields.iter_mut().zip(names) {
+        if listed.contains(&&*name) {
+            continue;
+        }
+
+        // attributes which only matter when the field is written
+        el.skip = true;
+        el.skip_if = None;
+        el.since = None;
+        el.bits = None;
+        el.rest = false;
+    }
+
+    None
+}
+
+/// Applies `#[binserde(pack_bools)]` by marking every `bool` field without
+/// attributes that conflict with bit-packing as `#[binserde(bits = 1)]`, so
+/// that runs of them are packed together. Returns a compile error if the type
+/// doesn't support bit-packed fields.
+pub fn apply_pack_bools(opts: &mut BinSerdeOpts) -> Option<TokenStream> {
+    if !opts.pack_bools {
+        return None;
+    }
+
+    if opts.tagged || opts.count_fields {
+        return Some(
+            quote!(compile_error!("pack_bools can't be combined with tagged or count_fields");),
+        );
+    }
+
+    let fields: Vec<_> = match &mut opts.data {
+        Data::Enum(variants) => variants
+            .iter_mut()
+            .flat_map(|v| v.fields.fields.iter_mut())
+            .collect(),
+        Data::Struct(fields) => fields.fields.iter_mut().collect(),
+    };
+
+    for el in fields {
+        let packable = !el.skip
+            && el.bits.is_none()
+            && el.fixed_len.is_none()
+            && !el.rest
+            && el.since.is_none()
+            && el.skip_if.is_none()
+            && !el.has_custom_fn();
+
+        if packable && is_bool(&el.ty) {
+            el.bits = Some(1);
+        }
+    }
+
+    None
+}
+
+fn is_bool(ty: &Type) -> bool {
+    match ty {
+        Type::Path(TypePath { qself: None, path }) => path.is_ident("bool"),
+        _ => false,
+    }
+}
+
+/// 64-bit FNV-1a hash of the variant name. This must never change since it
+/// is part of the serialized format.
+fn variant_hash(name: &str) -> u64 {
+    name.bytes().fold(0xcbf29ce484222325, |acc, b| {
+        (acc ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// A field serialized on its own, or a run of consecutive fields marked
+/// `#[binserde(bits = n)]` which get packed together. Fields are referred to
+/// by their position.
+pub enum FieldGroup {
+    Single(usize),
+    Bits(Vec<(usize, u32)>),
+}
+
+impl FieldGroup {
+    pub fn total_bits(items: &[(usize, u32)]) -> u32 {
+        items.iter().map(|el| el.1).sum()
+    }
+}
+
+/// Returns the positions of the fields that aren't skipped, in the order they
+/// are serialized in after applying `#[binserde(index = n)]`. Each such
+/// attribute moves its field together with all fields declared after it to
+/// position `n`, evaluated from top to bottom.
+pub fn field_order(fields: &Fields<BinSerdeField>) -> Result<Vec<usize>, TokenStream> {
+    if fields.iter().any(|el| el.skip && el.index.is_some()) {
+        return Err(quote!(compile_error!("skipped fields can't have an index");));
+    }
+
+    let serialized: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .filter(|(_, el)| !el.skip)
+        .map(|(idx, _)| idx)
+        .collect();
+    let mut order = serialized.clone();
+
+    for (decl_pos, &idx) in serialized.iter().enumerate() {
+        let target = match fields.fields[idx].index {
+            None => continue,
+            Some(v) => v,
+        };
+
+        // the field and everything declared after it always stay together
+        let count = serialized.len() - decl_pos;
+
+        if target > order.len() - count {
+            return Err(quote!(compile_error!("field index out of range");));
+        }
+
+        let pos = order.iter().position(|el| *el == idx).unwrap();
+
+        if target <= pos {
+            swap_at(&mut order[target..pos + count], pos - target);
+        } else {
+            swap_at(&mut order[pos..target + count], count);
+        }
+    }
+
+    Ok(order)
+}
+
+/// Groups the fields in serialization order, followed by the skipped fields.
+pub fn field_groups(fields: &Fields<BinSerdeField>) -> Result<Vec<FieldGroup>, TokenStream> {
+    let mut groups = Vec::new();
+
+    let order = field_order(fields)?;
+
+    for (pos, &idx) in order.iter().enumerate() {
+        let el = &fields.fields[idx];
+
+        if el.rest && (el.bits.is_some() || el.fixed_len.is_some() || el.has_custom_fn()) {
+            return Err(quote! {
+                compile_error!("rest can't be combined with bits, fixed_len or custom (de)serialization functions");
+            });
+        }
+
+        if el.rest && pos != order.len() - 1 {
+            return Err(quote!(compile_error!("rest must be the last serialized field");));
+        }
+    }
+
+    let skipped = fields
+        .iter()
+        .enumerate()
+        .filter(|(_, el)| el.skip)
+        .map(|(idx, _)| idx);
+
+    for idx in order.into_iter().chain(skipped) {
+        let el = &fields.fields[idx];
+
+        if el.rest && el.skip {
+            return Err(quote!(compile_error!("skipped fields can't be marked rest");));
+        }
+
+        if el.fixed_len.is_some() && (el.skip || el.bits.is_some()) {
+            return Err(quote! {
+                compile_error!("fixed_len can't be combined with skip or bits");
+            });
+        }
+
+        if el.with.is_some() && (el.serialize_with.is_some() || el.deserialize_with.is_some()) {
+            return Err(quote! {
+                compile_error!("with can't be combined with serialize_with or deserialize_with");
+            });
+        }
+
+        if el.niche
+            && (el.with.is_some() || el.serialize_with.is_some() || el.deserialize_with.is_some())
+        {
+            return Err(quote! {
+                compile_error!("niche can't be combined with with, serialize_with or deserialize_with");
+            });
+        }
+
+        if el.len.is_some() && el.len_width().is_none() {
+            return Err(quote!(compile_error!("len must be one of u8, u16, u32 or u64");));
+        }
+
+        if el.len.is_some()
+            && (el.niche
+                || el.with.is_some()
+                || el.serialize_with.is_some()
+                || el.deserialize_with.is_some())
+        {
+            return Err(quote! {
+                compile_error!("len can't be combined with niche, with, serialize_with or deserialize_with");
+            });
+        }
+
+        if el.skip_if.is_some() && (el.skip || el.bits.is_some() || el.rest) {
+            return Err(quote! {
+                compile_error!("skip_if can't be combined with skip, bits or rest");
+            });
+        }
+
+        if el.has_custom_fn() && (el.skip || el.bits.is_some() || el.fixed_len.is_some()) {
+            return Err(quote! {
+                compile_error!("custom (de)serialization functions can't be combined with skip, bits or fixed_len");
+            });
+        }
+
+        match (el.bits, groups.last_mut()) {
+            (Some(0), _) => {
+                return Err(quote!(compile_error!("bit width must be at least 1");));
+            }
+            (Some(_), _) if el.skip => {
+                return Err(quote!(compile_error!("skipped fields can't be bit-packed");));
+            }
+            (Some(bits), Some(FieldGroup::Bits(items))) => items.push((idx, bits)),
+            (Some(bits), _) => groups.push(FieldGroup::Bits(vec![(idx, bits)])),
+            (None, _) => groups.push(FieldGroup::Single(idx)),
+        }
+    }
+
+    for el in groups.iter() {
+        if let FieldGroup::Bits(items) = el {
+            if FieldGroup::total_bits(items) > 64 {
+                return Err(quote! {
+                    compile_error!("bit-packed fields can't be wider than 64 bits in total");
+                });
+            }
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Returns the positions of the fields that aren't skipped in a type marked
+/// `#[binserde(tagged)]`. Since these are identified by name, their order
+/// doesn't matter.
+pub fn tagged_fields(fields: &Fields<BinSerdeField>) -> Result<Vec<usize>, TokenStream> {
+    field_groups(fields)?;
+
+    if fields
+        .iter()
+        .any(|el| el.bits.is_some() || el.rest || el.skip_if.is_some())
+    {
+        return Err(quote! {
+            compile_error!("bits, rest and skip_if can't be used in tagged types");
+        });
+    }
+
+    Ok(fields
+        .iter()
+        .enumerate()
+        .filter(|(_, el)| !el.skip)
+        .map(|(idx, _)| idx)
+        .collect())
+}
+
 pub fn generic_defs(opts: &BinSerdeOpts) -> Option<TokenStream> {
     if !opts.generics.params.is_empty() {
         let params = &opts.generics.params;
@@ -105,37 +825,177 @@ pub fn generic_params_on_target(opts: &BinSerdeOpts) -> Option<TokenStream> {
     }
 }
 
-pub fn add_trait_bounds(opts: &BinSerdeOpts, bound: &TokenStream) -> TokenStream {
+/// Adds `bound` to the type parameters used by fields which aren't skipped
+/// and for which `is_custom` returns false.
+pub fn add_trait_bounds<F>(opts: &BinSerdeOpts, bound: &TokenStream, is_custom: F) -> TokenStream
+where
+    F: Fn(&BinSerdeField) -> bool,
+{
     let prefix = match &opts.generics.where_clause {
         None => quote!(where),
         Some(p) => quote!(#p ,),
     };
 
+    let field_types: Vec<&Type> = match &opts.data {
+        Data::Enum(variants) => variants
+            .iter()
+            .flat_map(|el| el.fields.iter())
+            .filter(|el| !el.skip && !is_custom(el))
+            .map(|el| &el.ty)
+            .collect(),
+        Data::Struct(fields) => fields
+            .iter()
+            .filter(|el| !el.skip && !is_custom(el))
+            .map(|el| &el.ty)
+            .collect(),
+    };
+
+    // type parameters only used in PhantomData (or not at all) don't need to
+    // implement the trait
     let v = opts.generics.params.iter().filter_map(|el| match el {
-        GenericParam::Type(TypeParam { ident, .. }) => Some(quote!(#ident : #bound)),
+        GenericParam::Type(TypeParam { ident, .. })
+            if field_types.iter().any(|ty| uses_param(ty, ident)) =>
+        {
+            Some(quote!(#ident : #bound))
+        }
         _ => None,
     });
 
     quote!(#prefix #( #v ),*)
 }
 
-fn move_sort<T, F>(slice: &mut [T], op: F)
-where
-    F: Fn(&T) -> Option<usize>,
-{
-    let more = slice.len();
-    let mut idx = 0;
+/// Returns the lifetimes of references in the types of fields which aren't
+/// skipped, other than in `PhantomData`. These can borrow from the input when
+/// deserializing, so the input lifetime must outlive them.
+pub fn borrowed_lifetimes(opts: &BinSerdeOpts) -> Vec<Lifetime> {
+    let mut lifetimes = Vec::new();
+
+    let fields: Vec<&BinSerdeField> = match &opts.data {
+        Data::Enum(variants) => variants.iter().flat_map(|el| el.fields.iter()).collect(),
+        Data::Struct(fields) => fields.iter().collect(),
+    };
+
+    for field in fields.into_iter().filter(|el| !el.skip) {
+        collect_borrowed(&field.ty, &mut lifetimes);
+    }
 
-    while more > 0 {
-        let d = &slice[idx];
-        let new_idx = op(d);
+    lifetimes
+}
+
+fn collect_borrowed(ty: &Type, out: &mut Vec<Lifetime>) {
+    match ty {
+        Type::Path(TypePath { path, .. }) => {
+            // PhantomData never borrows anything
+            if path
+                .segments
+                .last()
+                .is_some_and(|el| el.ident == "PhantomData")
+            {
+                return;
+            }
+
+            for seg in path.segments.iter() {
+                if let PathArguments::AngleBracketed(args) = &seg.arguments {
+                    for arg in args.args.iter() {
+                        if let GenericArgument::Type(ty) = arg {
+                            collect_borrowed(ty, out);
+                        }
+                    }
+                }
+            }
+        }
+        Type::Reference(TypeReference { lifetime, elem, .. }) => {
+            if let Some(lifetime) = lifetime {
+                if !out.contains(lifetime) {
+                    out.push(lifetime.clone());
+                }
+            }
 
-        if let Some(new_idx) = new_idx {
-            if new_idx < idx {}
-            idx = new_idx;
+            collect_borrowed(elem, out);
         }
+        Type::Slice(TypeSlice { elem, .. })
+        | Type::Array(TypeArray { elem, .. })
+        | Type::Paren(TypeParen { elem, .. })
+        | Type::Group(TypeGroup { elem, .. }) => collect_borrowed(elem, out),
+        Type::Tuple(TypeTuple { elems, .. }) => {
+            elems.iter().for_each(|el| collect_borrowed(el, out))
+        }
+        _ => {}
+    }
+}
+
+/// Checks whether the type parameter `param` is used in `ty` outside of
+/// `PhantomData`.
+fn uses_param(ty: &Type, param: &Ident) -> bool {
+    match ty {
+        Type::Path(TypePath { qself: None, path }) => {
+            if path
+                .segments
+                .last()
+                .is_some_and(|el| el.ident == "PhantomData")
+            {
+                return false;
+            }
+
+            if path.segments.first().is_some_and(|el| el.ident == *param) {
+                return true;
+            }
+
+            path.segments.iter().any(|seg| match &seg.arguments {
+                PathArguments::AngleBracketed(args) => args.args.iter().any(|el| match el {
+                    GenericArgument::Type(ty) => uses_param(ty, param),
+                    _ => false,
+                }),
+                _ => false,
+            })
+        }
+        Type::Reference(TypeReference { elem, .. })
+        | Type::Slice(TypeSlice { elem, .. })
+        | Type::Array(TypeArray { elem, .. })
+        | Type::Paren(TypeParen { elem, .. })
+        | Type::Group(TypeGroup { elem, .. }) => uses_param(elem, param),
+        Type::Tuple(TypeTuple { elems, .. }) => elems.iter().any(|el| uses_param(el, param)),
+        // anything else, check whether the parameter appears anywhere
+        _ => tokens_contain(ty.to_token_stream(), param),
+    }
+}
+
+fn tokens_contain(tokens: TokenStream, param: &Ident) -> bool {
+    tokens.into_iter().any(|el| match el {
+        TokenTree::Ident(ident) => ident == *param,
+        TokenTree::Group(group) => tokens_contain(group.stream(), param),
+        _ => false,
+    })
+}
 
-        idx += 1;
+/// Checks whether the type mentions any of the string types that get
+/// deduplicated anywhere in its declaration. Types that contain them without
+/// naming them, like type aliases and other structs, aren't recognized.
+pub fn is_dedupable(ty: &Type) -> bool {
+    match ty {
+        Type::Path(TypePath { path, .. }) => path.segments.iter().any(|seg| {
+            if ["String", "str", "Path", "PathBuf"]
+                .iter()
+                .any(|el| seg.ident == el)
+            {
+                return true;
+            }
+
+            match &seg.arguments {
+                PathArguments::AngleBracketed(args) => args.args.iter().any(|el| match el {
+                    GenericArgument::Type(ty) => is_dedupable(ty),
+                    _ => false,
+                }),
+                _ => false,
+            }
+        }),
+        Type::Reference(TypeReference { elem, .. })
+        | Type::Slice(TypeSlice { elem, .. })
+        | Type::Array(TypeArray { elem, .. })
+        | Type::Paren(TypeParen { elem, .. })
+        | Type::Group(TypeGroup { elem, .. }) => is_dedupable(elem),
+        Type::Tuple(TypeTuple { elems, .. }) => elems.iter().any(is_dedupable),
+        _ => false,
     }
 }
 
@@ -155,7 +1015,7 @@ fn swap_at<T>(slice: &mut [T], idx: usize) {
     } else if right.len() < left.len() {
         let count = slice.len() - idx;
         swap_outer(slice, count);
-        swap_at(&mut slice[idx..], count);
+        swap_at(&mut slice[count..], idx - count);
     }
 }
 
@@ -168,7 +1028,10 @@ fn swap_outer<T>(slice: &mut [T], count: usize) {
 
 #[cfg(test)]
 mod test {
-    use crate::common::swap_at;
+    use darling::ast::{Fields, Style};
+    use syn::parse_quote;
+
+    use crate::common::{field_order, swap_at, variant_hash, BinSerdeField};
 
     #[test]
     fn test_swap_at() {
@@ -176,4 +1039,95 @@ mod test {
         swap_at(&mut arr, 3);
         assert_eq!([4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 1, 2, 3], arr);
     }
+
+    #[test]
+    fn test_variant_hash() {
+        assert_eq!(0xcbf29ce484222325, variant_hash(""));
+        assert_eq!(0xaf63dc4c8601ec8c, variant_hash("a"));
+        assert_eq!(0x85944171f73967e8, variant_hash("foobar"));
+
+        // used by the compile_fail doctest for colliding variant names
+        assert_eq!(
+            variant_hash("Va85cfb84eadef993"),
+            variant_hash("Vf7a240fb8fc0a8ed")
+        );
+    }
+
+    fn fields(indices: &[(Option<usize>, bool)]) -> Fields<BinSerdeField> {
+        let fields = indices
+            .iter()
+            .map(|&(index, skip)| BinSerdeField {
+                ident: None,
+                ty: parse_quote!(u8),
+                no_dedup: false,
+                skip_dedup_scan: false,
+                skip,
+                index,
+                bits: None,
+                fixed_len: None,
+                rest: false,
+                since: None,
+                skip_if: None,
+                default: None,
+                with: None,
+                serialize_with: None,
+                deserialize_with: None,
+                niche: false,
+                len: None,
+            })
+            .collect();
+
+        Fields {
+            style: Style::Tuple,
+            fields,
+        }
+    }
+
+    #[test]
+    fn test_field_order() {
+        let order = |indices: &[(Option<usize>, bool)]| field_order(&fields(indices)).ok();
+
+        assert_eq!(
+            Some(vec![2, 3, 0, 1]),
+            order(&[
+                (None, false),
+                (None, false),
+                (Some(0), false),
+                (None, false)
+            ])
+        );
+        assert_eq!(
+            Some(vec![3, 1, 2, 0]),
+            order(&[
+                (None, false),
+                (Some(0), false),
+                (None, false),
+                (Some(0), false)
+            ])
+        );
+        assert_eq!(
+            Some(vec![0, 3, 1, 2]),
+            order(&[
+                (None, false),
+                (None, false),
+                (None, false),
+                (Some(1), false)
+            ])
+        );
+        assert_eq!(
+            Some(vec![2, 0, 1, 3]),
+            order(&[
+                (None, false),
+                (None, false),
+                (Some(0), false),
+                (Some(3), false)
+            ])
+        );
+        assert_eq!(
+            Some(vec![3, 0, 2]),
+            order(&[(None, false), (None, true), (None, false), (Some(0), false)])
+        );
+        assert_eq!(None, order(&[(None, false), (Some(2), false)]));
+        assert_eq!(None, order(&[(None, false), (Some(0), true)]));
+    }
 }
@@ -1,31 +1,69 @@
+use std::borrow::Cow;
+
 use darling::ast::{Data, Fields, Style};
 use proc_macro2::TokenStream;
 use quote::quote;
-use syn::Index;
+use syn::Ident;
 
 use crate::common::*;
 
 pub fn impl_bin_deserialize(opts: &BinSerdeOpts) -> TokenStream {
     let name = &opts.ident;
-    let deserialize_body = gen_deserialize_method_body(opts);
+    let transparent = transparent_field(opts);
+    let deserialize_body = match (&opts.data, transparent) {
+        (Data::Struct(fields), Some(idx)) => gen_transparent_body(fields, idx),
+        _ => {
+            let read_version = gen_read_version(opts);
+            let body = gen_deserialize_method_body(opts);
+            quote!(#read_version #body)
+        }
+    };
 
-    let deserialize_in_place_m = match &opts.data {
-        Data::Enum(_) => quote!(),
-        Data::Struct(fields) => {
+    let deserialize_in_place_m = match (&opts.data, transparent) {
+        (Data::Enum(_), _) => quote!(),
+        // fields missing from the input are reset like with deserialize
+        (Data::Struct(_), None) if opts.tagged || opts.count_fields => quote!(),
+        (Data::Struct(fields), Some(idx)) => {
+            let field = &to_struct_fields(fields, false)[idx];
+            quote! {
+                fn deserialize_in_place<D: ::binserde::BinDeserializer<'de>>(&mut self, mut deserializer: D) -> ::binserde::Result<()> {
+                    ::binserde::BinDeserialize::deserialize_in_place(&mut self.#field, &mut deserializer)
+                }
+            }
+        }
+        (Data::Struct(fields), None) => {
             let body = gen_deserialize_in_place_method_body(fields);
+            let read_version = gen_read_version(opts);
             quote! {
                 fn deserialize_in_place<D: ::binserde::BinDeserializer<'de>>(&mut self, mut deserializer: D) -> ::binserde::Result<()> {
+                    #read_version
                     #body
                 }
             }
         }
     };
 
-    let generic_defs = generic_defs(opts).map_or_else(|| quote!(<'de>), |el| quote!(<'de, #el>));
+    let borrowed = borrowed_lifetimes(opts);
+    let de_lifetime = if borrowed.is_empty() {
+        quote!('de)
+    } else {
+        quote!('de: #( #borrowed )+*)
+    };
+    let generic_defs =
+        generic_defs(opts).map_or_else(|| quote!(<#de_lifetime>), |el| quote!(<#de_lifetime, #el>));
     let generic_params = generic_params_on_target(opts).map(|el| quote!(<#el>));
-    let where_clause = add_trait_bounds(opts, &quote!(::binserde::BinDeserialize<'de>));
+    let where_clause = add_trait_bounds(opts, &quote!(::binserde::BinDeserialize<'de>), |el| {
+        el.deserialize_fn().is_some()
+    });
+    let tag_check = check_variant_tags(opts);
+    let version_check = check_versions(opts);
+    let count_check = check_count_fields(opts);
 
     let gen = quote! {
+        #tag_check
+        #version_check
+        #count_check
+
         impl #generic_defs ::binserde::BinDeserialize<'de> for #name #generic_params #where_clause {
             fn deserialize<D: ::binserde::BinDeserializer<'de>>(mut deserializer: D) -> ::binserde::Result<Self> {
                 #deserialize_body
@@ -38,8 +76,150 @@ pub fn impl_bin_deserialize(opts: &BinSerdeOpts) -> TokenStream {
     gen
 }
 
+/// Reads the version written in front of types marked
+/// `#[binserde(version = n)]` into `__version`.
+fn gen_read_version(opts: &BinSerdeOpts) -> TokenStream {
+    match opts.version {
+        None => quote!(),
+        Some(v) => quote! {
+            let __version = ::binserde::de::read_version(#v, &mut deserializer)?;
+        },
+    }
+}
+
+/// Deserializes the field at `idx` in place of the struct, see
+/// [`transparent_field`].
+fn gen_transparent_body(fields: &Fields<BinSerdeField>, idx: usize) -> TokenStream {
+    let values = to_struct_fields(fields, false)
+        .into_iter()
+        .zip(fields.iter())
+        .enumerate()
+        .map(|(pos, (field, el))| {
+            if pos == idx {
+                quote!(#field: ::binserde::BinDeserialize::deserialize(&mut deserializer)?)
+            } else {
+                let default = el.default_value();
+                quote!(#field: #default)
+            }
+        });
+
+    quote!(Ok(Self { #( #values ),* }))
+}
+
+/// Generates the expression reading a single field from `de`, which must
+/// evaluate to a mutable reference to a deserializer.
+fn gen_read_field(field: &BinSerdeField, de: TokenStream) -> TokenStream {
+    let mut expr = de.clone();
+
+    if field.no_dedup || field.skip_dedup_scan {
+        expr = quote!(::binserde::BinDeserializer::disable_dedup(#expr));
+    }
+
+    let read = match (field.fixed_len, field.deserialize_fn()) {
+        (Some(len), _) => quote!(::binserde::fixed_str::read(#len, #expr)),
+        (None, _) if field.rest => quote!(::binserde::de::read_rest(#expr)),
+        (None, Some(func)) => quote!(#func( #expr )),
+        (None, None) => quote!(::binserde::BinDeserialize::deserialize( #expr )),
+    };
+
+    match &field.skip_if {
+        None => read,
+        Some(_) => {
+            let default = field.default_value();
+
+            quote! {
+                match <bool as ::binserde::BinDeserialize>::deserialize(#de) {
+                    Ok(true) => #read,
+                    Ok(false) => Ok(#default),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+}
+
+/// Generates the statements reading `fields` of a type marked
+/// `#[binserde(tagged)]` into variables named like `idents` and then
+/// evaluating to `struct_value`. Fields that aren't in the input are set to
+/// their default value.
+fn gen_tagged_struct_like(
+    struct_value: TokenStream,
+    prefix: &str,
+    fields: &Fields<BinSerdeField>,
+    idents: &[Cow<'_, Ident>],
+) -> TokenStream {
+    let order = match tagged_fields(fields) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let names = field_names(fields);
+
+    let decls = fields.iter().zip(idents).map(|(el, ident)| {
+        let ty = &el.ty;
+
+        if el.skip {
+            let default = el.default_value();
+            quote!(let #ident: #ty = #default;)
+        } else {
+            quote!(let mut #ident: Option<#ty> = None;)
+        }
+    });
+
+    let arms = order.iter().map(|&idx| {
+        let ident = &idents[idx];
+        let tag = &names[idx];
+        let name = format!("{}{}", prefix, tag);
+        let read = gen_read_field(&fields.fields[idx], quote!(&mut *deserializer));
+
+        quote! {
+            #tag => {
+                #ident = Some(::binserde::de::field_context(
+                    ::binserde::BinDeserializer::mode(&*deserializer),
+                    #name,
+                    #read,
+                )?);
+                Ok(true)
+            }
+        }
+    });
+
+    let read_fields = if order.is_empty() {
+        quote!(::binserde::de::read_tagged_fields(&mut deserializer, |_, _| Ok(false))?;)
+    } else {
+        quote! {
+            ::binserde::de::read_tagged_fields(&mut deserializer, |__name, deserializer| {
+                match __name {
+                    #( #arms )*
+                    _ => Ok(false),
+                }
+            })?;
+        }
+    };
+
+    let unwraps = order.iter().map(|&idx| {
+        let ident = &idents[idx];
+        let default = fields.fields[idx].default_value();
+        quote!(let #ident = #ident.unwrap_or_else(|| #default);)
+    });
+
+    quote! {
+        ::binserde::de::enter_struct(&deserializer);
+        #( #decls )*
+        #read_fields
+        ::binserde::de::exit_struct(&deserializer);
+        #( #unwraps )*
+        Ok( #struct_value )
+    }
+}
+
 fn gen_deserialize_method_body(opts: &BinSerdeOpts) -> TokenStream {
-    fn gen_struct_like(struct_like: TokenStream, fields: &Fields<BinSerdeField>) -> TokenStream {
+    fn gen_struct_like(
+        opts: &BinSerdeOpts,
+        struct_like: TokenStream,
+        prefix: &str,
+        fields: &Fields<BinSerdeField>,
+    ) -> TokenStream {
         let idents = to_idents(fields, false);
 
         let fields_list = quote! { #( #idents ),* };
@@ -49,32 +229,113 @@ fn gen_deserialize_method_body(opts: &BinSerdeOpts) -> TokenStream {
             Style::Unit => quote! { #struct_like },
         };
 
-        let exprs = fields.iter().map(|el| {
-            if el.skip {
-                quote!(Default::default())
-            } else {
-                let mut expr = quote!(&mut deserializer);
+        if opts.tagged {
+            return gen_tagged_struct_like(struct_value, prefix, fields, &idents);
+        }
 
-                if el.no_dedup {
-                    expr = quote!(::binserde::BinDeserializer::disable_dedup(#expr));
+        let groups = match field_groups(fields) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+
+        let names = field_names(fields);
+        let fields: Vec<_> = fields.iter().collect();
+        let count = fields.iter().filter(|el| !el.skip).count() as u32;
+
+        // skipped fields come last, so this is the position of the field in
+        // the input for types marked count_fields
+        let stmts = groups.iter().enumerate().map(|(pos, group)| match group {
+            FieldGroup::Single(idx) => {
+                let ident = &idents[*idx];
+
+                let default = fields[*idx].default_value();
+
+                if fields[*idx].skip {
+                    quote!(let #ident = #default;)
+                } else {
+                    let read = gen_read_field(fields[*idx], quote!(&mut deserializer));
+                    let tag = &names[*idx];
+                    let name = format!("{}{}", prefix, tag);
+
+                    let read = quote! {
+                        if __tagged {
+                            ::binserde::de::read_field_tag(#tag, &mut deserializer)?;
+                        }
+
+                        ::binserde::de::field_context(
+                            ::binserde::BinDeserializer::mode(&deserializer),
+                            #name,
+                            #read,
+                        )?
+                    };
+
+                    let pos = pos as u32;
+
+                    match fields[*idx].since {
+                        None if opts.count_fields => quote! {
+                            let #ident = if __count > #pos {
+                                #read
+                            } else {
+                                #default
+                            };
+                        },
+                        None => quote!(let #ident = { #read };),
+                        Some(since) => quote! {
+                            let #ident = if __version >= #since {
+                                #read
+                            } else {
+                                #default
+                            };
+                        },
+                    }
                 }
+            }
+            FieldGroup::Bits(items) => {
+                let total = FieldGroup::total_bits(items);
+                let unpacks = items.iter().map(|(idx, bits)| {
+                    let ident = &idents[*idx];
+                    quote! {
+                        let #ident = ::binserde::bits::unpack(__group, #bits, &mut __offset)?;
+                    }
+                });
 
-                quote!( ::binserde::BinDeserialize::deserialize( #expr )? )
+                quote! {
+                    let __group = ::binserde::bits::read_group(#total, &mut deserializer)?;
+                    let mut __offset = 0u32;
+                    #( #unpacks )*
+                }
             }
         });
 
-        quote! {
-            #( let #idents = #exprs; )*
+        let body = quote! {
+            let __tagged = ::binserde::de::enter_struct(&deserializer);
+            #( #stmts )*
+            ::binserde::de::exit_struct(&deserializer);
             Ok( #struct_value )
+        };
+
+        if opts.count_fields {
+            quote! {
+                ::binserde::de::read_counted_fields(#count, &mut deserializer, |__count, mut deserializer| {
+                    #body
+                })
+            }
+        } else {
+            body
         }
     }
 
-    fn gen_variant_impl(index: usize, variant: &BinSerdeVariant) -> TokenStream {
+    fn gen_variant_impl(
+        opts: &BinSerdeOpts,
+        tag: TokenStream,
+        legacy: &[TokenStream],
+        variant: &BinSerdeVariant,
+    ) -> TokenStream {
         let name = &variant.ident;
-        let index = Index::from(index);
-        let g = gen_struct_like(quote!(Self::#name), &variant.fields);
+        let prefix = format!("{}.", name);
+        let g = gen_struct_like(opts, quote!(Self::#name), &prefix, &variant.fields);
         quote! {
-            #index => { #g }
+            #tag #( | #legacy )* => { #g }
         }
     }
 
@@ -86,40 +347,144 @@ fn gen_deserialize_method_body(opts: &BinSerdeOpts) -> TokenStream {
             }
         }
         Data::Enum(variants) => {
+            let tag_type = variant_tag_type(opts);
+            let legacy = match legacy_variant_tags(opts, variants) {
+                Ok(v) => v,
+                Err(e) => return e,
+            };
             let variants = variants
                 .iter()
-                .enumerate()
-                .map(|(idx, el)| gen_variant_impl(idx, el));
+                .zip(variant_tags(opts, variants))
+                .zip(&legacy)
+                .map(|((el, tag), legacy)| gen_variant_impl(opts, tag, legacy, el));
             quote! {
-                match <usize as ::binserde::BinDeserialize>::deserialize(&mut deserializer)? {
+                match <#tag_type as ::binserde::BinDeserialize>::deserialize(&mut deserializer)? {
                     #( #variants )*
-                    x @ _ => Err(::binserde::Error::custom(&format!("invalid variant {}", x))),
+                    x @ _ => Err(::binserde::Error::UnknownVariant(x as u64)),
                 }
             }
         }
-        Data::Struct(fields) => gen_struct_like(quote!(Self), fields),
+        Data::Struct(_) if opts.from_discriminant.is_some() => {
+            quote!(compile_error!("from_discriminant is only supported on enums");)
+        }
+        Data::Struct(fields) => gen_struct_like(opts, quote!(Self), "", fields),
     }
 }
 
 fn gen_deserialize_in_place_method_body(fields: &Fields<BinSerdeField>) -> TokenStream {
+    let groups = match field_groups(fields) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
     let idents = to_struct_fields(fields, false);
+    let names = field_names(fields);
+    let fields: Vec<_> = fields.iter().collect();
 
-    let exprs = fields.iter().zip(idents.iter()).map(|(el, field)| {
-        if el.skip {
-            quote!(self.#field = Default::default();)
-        } else {
-            let mut expr = quote!(&mut deserializer);
+    let stmts = groups.iter().map(|group| match group {
+        FieldGroup::Single(idx) => {
+            let field = &idents[*idx];
+
+            let default = fields[*idx].default_value();
+
+            if fields[*idx].skip {
+                quote!(self.#field = #default;)
+            } else {
+                let mut expr = quote!(&mut deserializer);
 
-            if el.no_dedup {
-                expr = quote!(::binserde::BinDeserializer::disable_dedup(#expr));
+                if fields[*idx].no_dedup || fields[*idx].skip_dedup_scan {
+                    expr = quote!(::binserde::BinDeserializer::disable_dedup(#expr));
+                }
+
+                let name = &names[*idx];
+
+                let read = match (fields[*idx].fixed_len, fields[*idx].deserialize_fn()) {
+                    (Some(len), _) => quote! {
+                        self.#field = ::binserde::de::field_context(
+                            ::binserde::BinDeserializer::mode(&deserializer),
+                            #name,
+                            ::binserde::fixed_str::read(#len, #expr),
+                        )?;
+                    },
+                    (None, _) if fields[*idx].rest => quote! {
+                        self.#field = ::binserde::de::field_context(
+                            ::binserde::BinDeserializer::mode(&deserializer),
+                            #name,
+                            ::binserde::de::read_rest(#expr),
+                        )?;
+                    },
+                    (None, Some(func)) => quote! {
+                        self.#field = ::binserde::de::field_context(
+                            ::binserde::BinDeserializer::mode(&deserializer),
+                            #name,
+                            #func( #expr ),
+                        )?;
+                    },
+                    (None, None) => quote! {
+                        ::binserde::de::field_context(
+                            ::binserde::BinDeserializer::mode(&deserializer),
+                            #name,
+                            ::binserde::BinDeserialize::deserialize_in_place( &mut self.#field, #expr ),
+                        )?;
+                    },
+                };
+
+                let read = match &fields[*idx].skip_if {
+                    None => read,
+                    Some(_) => quote! {
+                        if ::binserde::de::field_context(
+                            ::binserde::BinDeserializer::mode(&deserializer),
+                            #name,
+                            <bool as ::binserde::BinDeserialize>::deserialize(&mut deserializer),
+                        )? {
+                            #read
+                        } else {
+                            self.#field = #default;
+                        }
+                    },
+                };
+
+                let read = quote! {
+                    if __tagged {
+                        ::binserde::de::read_field_tag(#name, &mut deserializer)?;
+                    }
+
+                    #read
+                };
+
+                match fields[*idx].since {
+                    None => read,
+                    Some(since) => quote! {
+                        if __version >= #since {
+                            #read
+                        } else {
+                            self.#field = #default;
+                        }
+                    },
+                }
             }
+        }
+        FieldGroup::Bits(items) => {
+            let total = FieldGroup::total_bits(items);
+            let unpacks = items.iter().map(|(idx, bits)| {
+                let field = &idents[*idx];
+                quote! {
+                    self.#field = ::binserde::bits::unpack(__group, #bits, &mut __offset)?;
+                }
+            });
 
-            quote!( ::binserde::BinDeserialize::deserialize_in_place( &mut self.#field, #expr )?; )
+            quote! {
+                let __group = ::binserde::bits::read_group(#total, &mut deserializer)?;
+                let mut __offset = 0u32;
+                #( #unpacks )*
+            }
         }
     });
 
     quote! {
-        #( #exprs )*
+        let __tagged = ::binserde::de::enter_struct(&deserializer);
+        #( #stmts )*
+        ::binserde::de::exit_struct(&deserializer);
         Ok(())
     }
 }
@@ -4,20 +4,52 @@ use proc_macro::TokenStream;
 
 use darling::FromDeriveInput;
 
+mod bits;
 mod common;
 mod de;
 mod ser;
+mod soa;
 
 #[proc_macro_derive(BinSerialize, attributes(binserde))]
 pub fn bin_serialize_derive(input: TokenStream) -> TokenStream {
     let ast = syn::parse(input).expect("failed to parse token stream");
-    let opts: common::BinSerdeOpts = FromDeriveInput::from_derive_input(&ast).unwrap();
-    ser::impl_bin_serialize(&opts).into()
+    let mut opts: common::BinSerdeOpts = match FromDeriveInput::from_derive_input(&ast) {
+        Ok(v) => v,
+        Err(e) => return e.write_errors().into(),
+    };
+    let include_error = common::apply_include(&mut opts);
+    let pack_error = common::apply_pack_bools(&mut opts);
+    let mut gen = ser::impl_bin_serialize(&opts);
+    gen.extend(include_error);
+    gen.extend(pack_error);
+
+    if opts.soa {
+        gen.extend(soa::impl_soa_serialize(&opts));
+    }
+
+    if let Some(width) = opts.repr_width {
+        gen.extend(bits::impl_bit_field(&opts, width));
+    }
+
+    gen.into()
 }
 
 #[proc_macro_derive(BinDeserialize, attributes(binserde))]
 pub fn bin_deserialize_derive(input: TokenStream) -> TokenStream {
     let ast = syn::parse(input).expect("failed to parse token stream");
-    let opts: common::BinSerdeOpts = FromDeriveInput::from_derive_input(&ast).unwrap();
-    de::impl_bin_deserialize(&opts).into()
+    let mut opts: common::BinSerdeOpts = match FromDeriveInput::from_derive_input(&ast) {
+        Ok(v) => v,
+        Err(e) => return e.write_errors().into(),
+    };
+    let include_error = common::apply_include(&mut opts);
+    let pack_error = common::apply_pack_bools(&mut opts);
+    let mut gen = de::impl_bin_deserialize(&opts);
+    gen.extend(include_error);
+    gen.extend(pack_error);
+
+    if opts.soa {
+        gen.extend(soa::impl_soa_deserialize(&opts));
+    }
+
+    gen.into()
 }
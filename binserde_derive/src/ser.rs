@@ -1,5 +1,3 @@
-use std::borrow::Cow;
-
 use darling::ast::{Data, Fields, Style};
 use proc_macro2::TokenStream;
 use quote::quote;
@@ -8,51 +6,371 @@ use crate::common::*;
 
 pub fn impl_bin_serialize(opts: &BinSerdeOpts) -> TokenStream {
     let name = &opts.ident;
-    let body = match &opts.data {
-        Data::Enum(variants) => gen_variants(&variants),
-        Data::Struct(s) => gen_serialize_fields(s),
+    let transparent = transparent_field(opts);
+    let body = match (&opts.data, transparent) {
+        (Data::Enum(variants), _) => gen_variants(opts, variants),
+        (Data::Struct(s), Some(idx)) => {
+            let field = &to_struct_fields(s, false)[idx];
+            quote!(::binserde::BinSerialize::serialize(&self.#field, &mut serializer))
+        }
+        (Data::Struct(s), None) => gen_serialize_fields(opts, s),
     };
 
     let generic_defs = generic_defs(opts).map(|el| quote!(<#el>));
     let generic_params = generic_params_on_target(opts).map(|el| quote!(<#el>));
-    let where_clause = add_trait_bounds(opts, &quote!(::binserde::BinSerialize));
+    let where_clause = add_trait_bounds(opts, &quote!(::binserde::BinSerialize), |el| {
+        el.serialize_fn().is_some()
+    });
+
+    let (dedup_fields, schema) = match (&opts.data, transparent) {
+        (Data::Struct(s), Some(idx)) => {
+            let ty = &s.fields[idx].ty;
+            (
+                quote!(<#ty as ::binserde::BinSerialize>::dedup_fields()),
+                quote!(<#ty as ::binserde::BinSerialize>::schema()),
+            )
+        }
+        _ => {
+            let dedup_fields = gen_dedup_fields(opts);
+            (quote!(&[ #( #dedup_fields ),* ]), gen_schema(opts))
+        }
+    };
+    let size_hint = gen_size_hint(opts, transparent);
+    let tag_check = check_variant_tags(opts);
+    let version_check = check_versions(opts);
+    let count_check = check_count_fields(opts);
+    let write_version = opts
+        .version
+        .map(|v| quote!(::binserde::ser::write_version(#v, &mut serializer)?;));
 
     let gen = quote! {
+        #tag_check
+        #version_check
+        #count_check
+
         impl #generic_defs ::binserde::BinSerialize for #name #generic_params #where_clause {
             fn serialize<S: ::binserde::BinSerializer>(&self, mut serializer: S) -> ::binserde::Result<()> {
+                #write_version
                 #body
             }
+
+            fn dedup_fields() -> &'static [&'static str] {
+                #dedup_fields
+            }
+
+            fn schema() -> ::binserde::schema::Schema {
+                #schema
+            }
+
+            fn size_hint(&self, mode: ::binserde::Mode) -> usize {
+                #size_hint
+            }
         }
     };
 
     gen
 }
 
-fn gen_serialize_fields(fields: &Fields<BinSerdeField>) -> TokenStream {
-    let idents = to_struct_fields(fields, true);
+/// Sums up the size hints of the fields of a struct which are serialized as
+/// they are. Other fields, and enums, don't contribute to the hint.
+fn gen_size_hint(opts: &BinSerdeOpts, transparent: Option<usize>) -> TokenStream {
+    let fields = match &opts.data {
+        Data::Struct(fields) => fields,
+        Data::Enum(_) => return quote!(0),
+    };
+
+    let idents = to_struct_fields(fields, false);
+    let hints = idents
+        .iter()
+        .zip(fields.iter())
+        .enumerate()
+        .filter(|(idx, (_, el))| {
+            transparent == Some(*idx)
+                || (!el.skip
+                    && el.bits.is_none()
+                    && el.fixed_len.is_none()
+                    && !el.rest
+                    && el.skip_if.is_none()
+                    && el.serialize_fn().is_none())
+        })
+        .map(|(_, (ident, _))| quote!(::binserde::BinSerialize::size_hint(&self.#ident, mode)));
+
+    quote!(0 #( + #hints )*)
+}
+
+fn gen_dedup_fields(opts: &BinSerdeOpts) -> Vec<String> {
+    let names: Vec<_> = match &opts.data {
+        Data::Enum(variants) => variants
+            .iter()
+            .flat_map(|v| {
+                field_names(&v.fields)
+                    .into_iter()
+                    .zip(v.fields.iter())
+                    .map(move |(name, el)| (format!("{}.{}", v.ident, name), el))
+            })
+            .collect(),
+        Data::Struct(fields) => field_names(fields).into_iter().zip(fields.iter()).collect(),
+    };
+
+    names
+        .into_iter()
+        .filter(|(_, el)| {
+            !el.skip
+                && !el.no_dedup
+                && !el.skip_dedup_scan
+                && el.fixed_len.is_none()
+                && !el.rest
+                && is_dedupable(&el.ty)
+        })
+        .map(|(name, _)| name)
+        .collect()
+}
+
+fn gen_schema(opts: &BinSerdeOpts) -> TokenStream {
+    fn gen_fields(fields: &Fields<BinSerdeField>) -> TokenStream {
+        let order = match field_order(fields) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+
+        let names = field_names(fields);
+        let entries = order.into_iter().map(|idx| {
+            let el = &fields.fields[idx];
+            let ty = &el.ty;
+            let name = &names[idx];
+
+            // bit-packed fields are written as part of a group with the
+            // fields next to them, and fixed length strings without their
+            // length, which a schema can't describe
+            if el.serialize_fn().is_some()
+                || el.rest
+                || el.skip_if.is_some()
+                || el.bits.is_some()
+                || el.fixed_len.is_some()
+            {
+                quote! {
+                    (::binserde::__private::String::from(#name), ::binserde::schema::Schema::Unknown)
+                }
+            } else if el.no_dedup || el.skip_dedup_scan {
+                quote! {
+                    (
+                        ::binserde::__private::String::from(#name),
+                        ::binserde::schema::Schema::NoDedup(::binserde::__private::Box::new(
+                            <#ty as ::binserde::BinSerialize>::schema(),
+                        )),
+                    )
+                }
+            } else {
+                quote! {
+                    (::binserde::__private::String::from(#name), <#ty as ::binserde::BinSerialize>::schema())
+                }
+            }
+        });
+
+        quote! {
+            ::binserde::schema::Schema::Struct(::binserde::__private::vec![ #( #entries ),* ])
+        }
+    }
+
+    if opts.tagged || opts.count_fields || opts.version.is_some() {
+        return quote!(::binserde::schema::Schema::Unknown);
+    }
+
+    match &opts.data {
+        Data::Enum(variants) => {
+            let tag_type = variant_tag_type(opts);
+            let variants = variants
+                .iter()
+                .zip(variant_tags(opts, variants))
+                .map(|(el, tag)| {
+                    let name = el.ident.to_string();
+                    let fields = gen_fields(&el.fields);
+                    quote! {
+                        (#tag as u64, ::binserde::__private::String::from(#name), #fields)
+                    }
+                });
+
+            quote! {
+                ::binserde::schema::Schema::Enum {
+                    tag: ::binserde::__private::Box::new(<#tag_type as ::binserde::BinSerialize>::schema()),
+                    variants: ::binserde::__private::vec![ #( #variants ),* ],
+                }
+            }
+        }
+        Data::Struct(fields) => gen_fields(fields),
+    }
+}
+
+fn gen_serialize_fields(opts: &BinSerdeOpts, fields: &Fields<BinSerdeField>) -> TokenStream {
+    let accessors: Vec<_> = to_struct_fields(fields, false)
+        .into_iter()
+        .map(|el| quote!(&self.#el))
+        .collect();
+
+    if opts.tagged {
+        gen_serialize_tagged_fields_with(fields, &accessors)
+    } else {
+        gen_serialize_fields_with(fields, &accessors, opts.count_fields)
+    }
+}
+
+/// Generates the statement writing a single field to `serializer`, where
+/// `accessor` evaluates to a reference to it.
+fn gen_write_field(field: &BinSerdeField, accessor: &TokenStream) -> TokenStream {
+    let write = match field.fixed_len {
+        None if field.rest => {
+            quote!(::binserde::ser::write_rest(#accessor, &mut serializer)?;)
+        }
+        None => {
+            let mut expr = quote!(&mut serializer);
+
+            if field.no_dedup || field.skip_dedup_scan {
+                expr = quote!(::binserde::BinSerializer::disable_dedup(#expr));
+            }
 
-    let serializers = fields.iter().filter(|el| !el.skip).map(|el| {
-        let mut expr = quote!(&mut serializer);
+            let func = field
+                .serialize_fn()
+                .unwrap_or_else(|| quote!(::binserde::BinSerialize::serialize));
 
-        if el.no_dedup {
-            expr = quote!(::binserde::BinSerializer::disable_dedup(#expr));
+            if field.skip_dedup_scan {
+                quote! {
+                    if !::binserde::BinSerializer::is_prescan(&serializer) {
+                        #func(#accessor, #expr)?;
+                    }
+                }
+            } else {
+                quote!(#func(#accessor, #expr)?;)
+            }
+        }
+        Some(len) => {
+            quote!(::binserde::fixed_str::write(#accessor, #len, &mut serializer)?;)
         }
+    };
+
+    match &field.skip_if {
+        None => write,
+        Some(pred) => quote! {
+            let __present = !#pred(#accessor);
+            ::binserde::BinSerialize::serialize(&__present, &mut serializer)?;
+
+            if __present {
+                #write
+            }
+        },
+    }
+}
+
+/// Generates the statements serializing `fields` of a type marked
+/// `#[binserde(tagged)]`, as their number followed by each field's name and
+/// value.
+fn gen_serialize_tagged_fields_with(
+    fields: &Fields<BinSerdeField>,
+    accessors: &[TokenStream],
+) -> TokenStream {
+    let order = match tagged_fields(fields) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let count = order.len();
+    let names = field_names(fields);
+    let stmts = order.iter().map(|&idx| {
+        let name = &names[idx];
+        let write = gen_write_field(&fields.fields[idx], &accessors[idx]);
 
-        expr
+        quote! {
+            ::binserde::ser::write_tagged_field(#name, &mut serializer, |mut serializer| {
+                #write
+                Ok(())
+            })?;
+        }
     });
 
     quote! {
-        #( ::binserde::BinSerialize::serialize(&self.#idents, #serializers )?; )*
+        ::binserde::ser::enter_struct(&mut serializer);
+        ::binserde::BinSerialize::serialize(&#count, &mut serializer)?;
+        #( #stmts )*
+        ::binserde::ser::exit_struct(&mut serializer);
         Ok(())
     }
 }
 
-fn gen_variants(variants: &[BinSerdeVariant]) -> TokenStream {
+/// Generates the statements serializing `fields`, where `accessors` are
+/// expressions evaluating to a reference to each field. If `counted` is set,
+/// they are prefixed with their number and length for types marked
+/// `#[binserde(count_fields)]`.
+fn gen_serialize_fields_with(
+    fields: &Fields<BinSerdeField>,
+    accessors: &[TokenStream],
+    counted: bool,
+) -> TokenStream {
+    let groups = match field_groups(fields) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let names = field_names(fields);
+    let fields: Vec<_> = fields.iter().collect();
+    let count = fields.iter().filter(|el| !el.skip).count() as u32;
+
+    let stmts = groups.iter().map(|group| match group {
+        FieldGroup::Single(idx) if fields[*idx].skip => quote!(),
+        FieldGroup::Single(idx) => {
+            let name = &names[*idx];
+            let write = gen_write_field(fields[*idx], &accessors[*idx]);
+
+            quote! {
+                if __tagged {
+                    ::binserde::ser::write_field_tag(#name, &mut serializer)?;
+                }
+
+                #write
+            }
+        }
+        FieldGroup::Bits(items) => {
+            let total = FieldGroup::total_bits(items);
+            let packs = items.iter().map(|(idx, bits)| {
+                let accessor = &accessors[*idx];
+                quote! {
+                    ::binserde::bits::pack(#accessor, #bits, &mut __group, &mut __offset)?;
+                }
+            });
+
+            quote! {
+                {
+                    let mut __group = 0u64;
+                    let mut __offset = 0u32;
+                    #( #packs )*
+                    ::binserde::bits::write_group(__group, #total, &mut serializer)?;
+                }
+            }
+        }
+    });
+
+    let body = quote! {
+        let __tagged = ::binserde::ser::enter_struct(&mut serializer);
+        #( #stmts )*
+        ::binserde::ser::exit_struct(&mut serializer);
+        Ok(())
+    };
+
+    if counted {
+        quote! {
+            ::binserde::ser::write_counted_fields(#count, &mut serializer, |mut serializer| {
+                #body
+            })
+        }
+    } else {
+        body
+    }
+}
+
+fn gen_variants(opts: &BinSerdeOpts, variants: &[BinSerdeVariant]) -> TokenStream {
     if !variants.is_empty() {
         let variants = variants
             .iter()
-            .enumerate()
-            .map(|(idx, el)| gen_variant_impl(idx, el));
+            .zip(variant_tags(opts, variants))
+            .map(|(el, tag)| gen_variant_impl(opts, tag, el));
         quote! {
             match self {
                 #( #variants )*
@@ -65,29 +383,40 @@ fn gen_variants(variants: &[BinSerdeVariant]) -> TokenStream {
     }
 }
 
-fn gen_variant_impl(idx: usize, variant: &BinSerdeVariant) -> TokenStream {
+fn gen_variant_impl(
+    opts: &BinSerdeOpts,
+    tag: TokenStream,
+    variant: &BinSerdeVariant,
+) -> TokenStream {
     let name = &variant.ident;
     let fs = &variant.fields;
-    let (args, fields) = match variant.fields.style {
+    let args = match variant.fields.style {
         Style::Tuple => {
             let idents = to_idents(fs, true);
-            (quote! { ( #( #idents ),* ) }, idents)
+            quote! { ( #( #idents ),* ) }
         }
         Style::Struct => {
-            let idents: Vec<_> = fs
+            let idents = fs
                 .iter()
                 .filter(|el| !el.skip)
-                .map(|el| Cow::Borrowed(el.ident.as_ref().unwrap()))
-                .collect();
-            (quote! { { #( #idents ),* } }, idents)
+                .map(|el| el.ident.as_ref().unwrap());
+            quote! { { #( #idents ),* } }
         }
-        Style::Unit => (quote!(), vec![]),
+        Style::Unit => quote!(),
+    };
+    let accessors: Vec<_> = to_idents(fs, false)
+        .into_iter()
+        .map(|el| quote!(#el))
+        .collect();
+    let body = if opts.tagged {
+        gen_serialize_tagged_fields_with(fs, &accessors)
+    } else {
+        gen_serialize_fields_with(fs, &accessors, opts.count_fields)
     };
     quote! {
         Self::#name #args => {
-            ::binserde::BinSerialize::serialize(&#idx, &mut serializer)?;
-            #( ::binserde::BinSerialize::serialize(&#fields, &mut serializer)?; )*
-            Ok(())
+            ::binserde::BinSerialize::serialize(&#tag, &mut serializer)?;
+            #body
         }
     }
 }
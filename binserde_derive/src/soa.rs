@@ -0,0 +1,146 @@
+use darling::ast::{Data, Fields, Style};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+
+use crate::common::*;
+
+pub fn impl_soa_serialize(opts: &BinSerdeOpts) -> TokenStream {
+    let name = &opts.ident;
+    let fields = match soa_fields(opts) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let order = match field_order(fields) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let all_idents = to_struct_fields(fields, false);
+    let idents = order.iter().map(|idx| &all_idents[*idx]);
+
+    let serializers = order.iter().map(|idx| {
+        let el = &fields.fields[*idx];
+        let mut expr = quote!(&mut serializer);
+
+        if el.no_dedup || el.skip_dedup_scan {
+            expr = quote!(::binserde::BinSerializer::disable_dedup(#expr));
+        }
+
+        expr
+    });
+
+    let generic_defs = generic_defs(opts).map(|el| quote!(<#el>));
+    let generic_params = generic_params_on_target(opts).map(|el| quote!(<#el>));
+    let where_clause = add_trait_bounds(opts, &quote!(::binserde::BinSerialize), |_| false);
+
+    quote! {
+        impl #generic_defs ::binserde::soa::SoaSerialize for #name #generic_params #where_clause {
+            fn serialize_columns<S: ::binserde::BinSerializer>(items: &[Self], mut serializer: S) -> ::binserde::Result<()> {
+                #(
+                    for item in items {
+                        ::binserde::BinSerialize::serialize(&item.#idents, #serializers)?;
+                    }
+                )*
+                Ok(())
+            }
+        }
+    }
+}
+
+pub fn impl_soa_deserialize(opts: &BinSerdeOpts) -> TokenStream {
+    let name = &opts.ident;
+    let fields = match soa_fields(opts) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let columns: Vec<_> = to_idents(fields, false)
+        .iter()
+        .map(|el| format_ident!("column_{}", el))
+        .collect();
+
+    let order = match field_order(fields) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let column_defs = order.iter().map(|idx| {
+        let el = &fields.fields[*idx];
+        let column = &columns[*idx];
+        let ty = &el.ty;
+        let mut expr = quote!(&mut deserializer);
+
+        if el.no_dedup || el.skip_dedup_scan {
+            expr = quote!(::binserde::BinDeserializer::disable_dedup(#expr));
+        }
+
+        // len comes from the input, so it can't be trusted to allocate
+        quote! {
+            let mut #column = ::binserde::__private::Vec::<#ty>::new();
+            for _ in 0..len {
+                #column.push(::binserde::BinDeserialize::deserialize(#expr)?);
+            }
+            let mut #column = #column.into_iter();
+        }
+    });
+
+    let values: Vec<_> = fields
+        .iter()
+        .zip(columns.iter())
+        .map(|(el, column)| {
+            if el.skip {
+                el.default_value()
+            } else {
+                quote!(#column.next().unwrap())
+            }
+        })
+        .collect();
+
+    let struct_value = match fields.style {
+        Style::Tuple => quote! { Self ( #( #values ),* ) },
+        Style::Struct => {
+            let names = to_struct_fields(fields, false);
+            quote! { Self { #( #names: #values ),* } }
+        }
+        Style::Unit => quote! { Self },
+    };
+
+    let generic_defs = generic_defs(opts).map_or_else(|| quote!(<'de>), |el| quote!(<'de, #el>));
+    let generic_params = generic_params_on_target(opts).map(|el| quote!(<#el>));
+    let where_clause = add_trait_bounds(opts, &quote!(::binserde::BinDeserialize<'de>), |_| false);
+
+    quote! {
+        impl #generic_defs ::binserde::soa::SoaDeserialize<'de> for #name #generic_params #where_clause {
+            fn deserialize_columns<D: ::binserde::BinDeserializer<'de>>(len: usize, mut deserializer: D) -> ::binserde::Result<::binserde::__private::Vec<Self>> {
+                #( #column_defs )*
+                Ok((0..len).map(|_| #struct_value).collect())
+            }
+        }
+    }
+}
+
+/// Returns the fields of a struct marked `#[binserde(soa)]`, or a compile
+/// error if any of them change how they're serialized in a way the columns,
+/// which are written with the fields' own implementations, don't follow.
+fn soa_fields(opts: &BinSerdeOpts) -> Result<&Fields<BinSerdeField>, TokenStream> {
+    let unsupported = |el: &BinSerdeField| {
+        el.bits.is_some() || el.fixed_len.is_some() || el.rest || el.has_custom_fn()
+    };
+
+    match &opts.data {
+        Data::Struct(_) if opts.pack_bools => Err(quote! {
+            compile_error!("pack_bools can't be combined with soa");
+        }),
+        Data::Struct(fields) if fields.iter().any(|el| el.skip_if.is_some()) => Err(quote! {
+            compile_error!("skip_if can't be used in #[binserde(soa)] structs");
+        }),
+        Data::Struct(fields) if fields.iter().any(unsupported) => Err(quote! {
+            compile_error!("bits, fixed_len, rest, niche, len and custom (de)serialization functions can't be used in #[binserde(soa)] structs");
+        }),
+        Data::Struct(fields) => Ok(fields),
+        Data::Enum(_) => Err(quote! {
+            compile_error!("#[binserde(soa)] is only supported on structs");
+        }),
+    }
+}
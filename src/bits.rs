@@ -0,0 +1,122 @@
+//! Packing several small fields into the bits of a few bytes.
+//!
+//! Consecutive struct fields marked `#[binserde(bits = n)]` form a group that
+//! is written as a single little-endian integer of as many bytes as needed to
+//! hold all of its fields, with the first field in the lowest bits. Fields in
+//! a group must implement [`BitField`], which the derive macro implements for
+//! enums without fields marked `#[binserde(repr_width = n)]`.
+//!
+//! ```
+//! use binserde_derive::{BinDeserialize, BinSerialize};
+//!
+//! #[derive(Debug, Clone, Copy, Eq, PartialEq, BinSerialize, BinDeserialize)]
+//! #[binserde(repr_width = 2)]
+//! enum Kind {
+//!     File,
+//!     Directory,
+//!     Link,
+//! }
+//!
+//! #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+//! struct Entry {
+//!     #[binserde(bits = 1)]
+//!     hidden: bool,
+//!     #[binserde(bits = 2)]
+//!     kind: Kind,
+//!     #[binserde(bits = 1)]
+//!     readonly: bool,
+//! }
+//!
+//! let entry = Entry { hidden: true, kind: Kind::Link, readonly: true };
+//! let vec = binserde::serialize(&entry).unwrap();
+//!
+//! assert_eq!(&[0b1101], &*vec);
+//! assert_eq!(entry, binserde::deserialize(&vec).unwrap());
+//! ```
+
+use alloc::format;
+
+use crate::io::{Read, Write};
+use crate::{BinDeserializer, BinSerializer, Error, Result};
+
+/// A type that can be stored in a group of bit-packed fields.
+pub trait BitField: Sized {
+    fn to_bits(&self) -> u64;
+
+    fn from_bits(bits: u64) -> Result<Self>;
+}
+
+impl BitField for bool {
+    fn to_bits(&self) -> u64 {
+        *self as u64
+    }
+
+    fn from_bits(bits: u64) -> Result<Self> {
+        Ok(bits != 0)
+    }
+}
+
+macro_rules! impl_uint {
+    ($type:ty) => {
+        impl BitField for $type {
+            fn to_bits(&self) -> u64 {
+                *self as u64
+            }
+
+            fn from_bits(bits: u64) -> Result<Self> {
+                Ok(bits as $type)
+            }
+        }
+    };
+}
+
+impl_uint! { u8 }
+impl_uint! { u16 }
+impl_uint! { u32 }
+impl_uint! { u64 }
+
+/// Adds `value` to `group` at `offset`, advancing `offset` by `width`. Used
+/// by the derive macro.
+pub fn pack<T: BitField>(value: &T, width: u32, group: &mut u64, offset: &mut u32) -> Result<()> {
+    let bits = value.to_bits();
+
+    if width < 64 && bits >> width != 0 {
+        return Err(Error::custom(format!(
+            "value {} doesn't fit in {} bits",
+            bits, width
+        )));
+    }
+
+    *group |= bits << *offset;
+    *offset += width;
+    Ok(())
+}
+
+/// Reads a value of `width` bits from `group` at `offset`, advancing
+/// `offset` by `width`. Used by the derive macro.
+pub fn unpack<T: BitField>(group: u64, width: u32, offset: &mut u32) -> Result<T> {
+    let mask = if width < 64 {
+        (1 << width) - 1
+    } else {
+        u64::MAX
+    };
+    let bits = (group >> *offset) & mask;
+    *offset += width;
+    T::from_bits(bits)
+}
+
+/// Writes the lowest `width` bits of `group`. Used by the derive macro.
+pub fn write_group<S: BinSerializer>(group: u64, width: u32, mut serializer: S) -> Result<()> {
+    let len = width.div_ceil(8) as usize;
+    serializer.pipe().write_all(&group.to_le_bytes()[..len])?;
+    Ok(())
+}
+
+/// Reads a group of `width` bits written by [`write_group`]. Used by the
+/// derive macro.
+pub fn read_group<'de, D: BinDeserializer<'de>>(width: u32, mut deserializer: D) -> Result<u64> {
+    let len = width.div_ceil(8) as usize;
+    let mut buf = [0; 8];
+    deserializer.pipe().read_exact(&mut buf[..len])?;
+    Ok(u64::from_le_bytes(buf))
+}
@@ -0,0 +1,146 @@
+//! Streaming CRC-32 checksums, as used by [`Mode::with_trailing_crc`].
+//!
+//! [`Mode::with_trailing_crc`]: crate::Mode::with_trailing_crc
+
+use crate::io::{self, Read, Write};
+
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut idx = 0;
+
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xEDB88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[idx] = crc;
+        idx += 1;
+    }
+
+    table
+}
+
+/// Incrementally computes the CRC-32 (IEEE) checksum of a byte stream.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32(!0)
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        for b in buf {
+            self.0 = TABLE[((self.0 ^ *b as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+/// A writer computing the checksum of everything written through it.
+pub struct CrcWriter<W> {
+    pipe: W,
+    crc: Crc32,
+}
+
+impl<W> CrcWriter<W> {
+    pub fn new(pipe: W) -> Self {
+        CrcWriter {
+            pipe,
+            crc: Crc32::new(),
+        }
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.crc.finish()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.pipe
+    }
+}
+
+impl<W> Write for CrcWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.pipe.write(buf)?;
+        self.crc.update(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pipe.flush()
+    }
+}
+
+/// A reader computing the checksum of everything read through it.
+pub struct CrcReader<R> {
+    pipe: R,
+    crc: Crc32,
+}
+
+impl<R> CrcReader<R> {
+    pub fn new(pipe: R) -> Self {
+        CrcReader {
+            pipe,
+            crc: Crc32::new(),
+        }
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.crc.finish()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.pipe
+    }
+}
+
+impl<R> Read for CrcReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.pipe.read(buf)?;
+        self.crc.update(&buf[..len]);
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crc::Crc32;
+
+    #[test]
+    fn test_crc32() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(0xCBF43926, crc.finish());
+
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(0xCBF43926, crc.finish());
+    }
+}
@@ -1,8 +1,22 @@
-use std::io::Read;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::{Display, Formatter};
+use core::iter::FromIterator;
+use core::marker::PhantomData;
 
-use crate::dedup::DedupContext;
-use crate::serde::Mode;
-use crate::Result;
+use crate::dedup::{use_collection_table, DedupContext};
+use crate::erased::DynDeserializer;
+use crate::io::{self, Read};
+use crate::schema::Schema;
+use crate::ser::{use_compact_options, BinSerializerBase};
+use crate::serde::{Mode, UsizeLen};
+use crate::serdeimpl::read_array;
+use crate::try_iter::try_iter;
+use crate::util::{read_elements_len, read_len, VecLikeIter};
+use crate::write_ext::ReadExt;
+use crate::{read_preamble, BinSerialize, BinSerializer, Error, Result};
 
 pub trait BinDeserialize<'de>: Sized {
     fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self>;
@@ -11,20 +25,353 @@ pub trait BinDeserialize<'de>: Sized {
         *self = Self::deserialize(deserializer)?;
         Ok(())
     }
+
+    /// Reads a sequence of this type as written by
+    /// [`BinSerialize::serialize_slice`] and appends its elements to `target`.
+    ///
+    /// [`BinSerialize::serialize_slice`]: crate::BinSerialize::serialize_slice
+    fn deserialize_extend<D: BinDeserializer<'de>>(
+        target: &mut Vec<Self>,
+        deserializer: D,
+    ) -> Result<()> {
+        let iter = VecLikeIter::new(deserializer)?;
+        try_iter(iter, |iter| target.extend(iter))
+    }
+
+    /// Reads a fixed-size array of this type as written by
+    /// [`BinSerialize::serialize_array`].
+    ///
+    /// [`BinSerialize::serialize_array`]: crate::BinSerialize::serialize_array
+    fn deserialize_array<D: BinDeserializer<'de>, const LEN: usize>(
+        deserializer: D,
+    ) -> Result<[Self; LEN]> {
+        read_array(deserializer)
+    }
+
+    /// Reads an optional value of this type as written by
+    /// [`BinSerialize::serialize_option`].
+    ///
+    /// [`BinSerialize::serialize_option`]: crate::BinSerialize::serialize_option
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        read_tagged_option(deserializer)
+    }
+}
+
+/// Attaches the name of the field being deserialized to errors if
+/// [`Mode::error_context`] or [`Mode::error_offsets`] is enabled. Used by the
+/// derive macro.
+pub fn field_context<T>(mode: Mode, field: &str, result: Result<T>) -> Result<T> {
+    match result {
+        Err(e) if mode.error_context || mode.error_offsets => Err(e.in_field(field)),
+        x => x,
+    }
+}
+
+/// Used by the derive macro, see [`Mode::with_tag_depth`].
+pub fn enter_struct<'de, D: BinDeserializer<'de>>(deserializer: &D) -> bool {
+    deserializer
+        .dedup()
+        .enter_struct(deserializer.mode().tag_depth)
+}
+
+/// Used by the derive macro.
+pub fn exit_struct<'de, D: BinDeserializer<'de>>(deserializer: &D) {
+    deserializer.dedup().exit_struct();
+}
+
+/// Reads the name of a field when its struct is tagged and checks that it
+/// is `name`. Used by the derive macro.
+pub fn read_field_tag<'de, D: BinDeserializer<'de>>(name: &str, deserializer: D) -> Result<()> {
+    let found = String::deserialize(deserializer.disable_dedup())?;
+
+    if found != name {
+        return Err(Error::custom(format!(
+            "expected field `{}`, found `{}`",
+            name, found
+        )));
+    }
+
+    Ok(())
+}
+
+/// Reads the bytes of a field marked `#[binserde(rest)]`, which extend up to
+/// the end of the input. Used by the derive macro.
+pub fn read_rest<'de, D, T>(mut deserializer: D) -> Result<T>
+where
+    D: BinDeserializer<'de>,
+    T: From<Vec<u8>>,
+{
+    let mut buf = Vec::new();
+    deserializer.pipe().read_to_end(&mut buf)?;
+    Ok(buf.into())
+}
+
+/// Reads a field marked `#[binserde(len = "type")]`, written by
+/// [`write_with_len`]. Used by the derive macro.
+///
+/// [`write_with_len`]: crate::ser::write_with_len
+pub fn read_with_len<'de, D, T>(width: UsizeLen, mut deserializer: D) -> Result<T>
+where
+    D: BinDeserializer<'de>,
+    T: IntoIterator + FromIterator<<T as IntoIterator>::Item>,
+    T::Item: BinDeserialize<'de>,
+{
+    let len = read_elements_len((&mut deserializer).change_mode(|mode| mode.usize_len = width))?;
+    try_iter(
+        (0..len).map(|_| T::Item::deserialize(&mut deserializer)),
+        |iter| iter.collect(),
+    )
+}
+
+/// Reads the version written by [`write_version`] and checks that it isn't
+/// newer than `current`, since fields added after it couldn't be skipped.
+/// Used by the derive macro.
+///
+/// [`write_version`]: crate::ser::write_version
+pub fn read_version<'de, D: BinDeserializer<'de>>(
+    current: u32,
+    mut deserializer: D,
+) -> Result<u32> {
+    let version = deserializer.pipe().read_varuint()?;
+
+    if version > current.into() {
+        return Err(Error::custom(format!(
+            "data has version {}, but only up to {} is supported",
+            version, current
+        )));
+    }
+
+    Ok(version as u32)
+}
+
+/// Reads the fields of a struct or enum variant marked `#[binserde(tagged)]`,
+/// calling `read` with the name of each and a deserializer for its value.
+/// `read` returns whether it knows the field, the values of unknown fields
+/// are skipped. Used by the derive macro.
+pub fn read_tagged_fields<'de, D, F>(mut deserializer: D, mut read: F) -> Result<()>
+where
+    D: BinDeserializer<'de>,
+    F: FnMut(&str, &mut WithMode<BinDeserializerBase<'_, &[u8]>>) -> Result<bool>,
+{
+    let count = read_len(&mut deserializer)?;
+    let mode = deserializer.mode();
+    let mut bytes = deserializer.dedup().take_scratch();
+
+    for _ in 0..count {
+        let name = String::deserialize(&mut deserializer)?;
+        let len = read_len(&mut deserializer)?;
+        bytes.clear();
+        let got = deserializer
+            .pipe()
+            .take(len as u64)
+            .read_to_end(&mut bytes)?;
+
+        if got != len {
+            return Err(Error::UnexpectedEof { expected: len, got });
+        }
+
+        let mut field = BinDeserializerBase::new(&*bytes, deserializer.dedup()).with_mode(mode);
+        let known = read(&name, &mut field)?;
+        let left = field.deserializer.pipe.inner.len();
+
+        if known && left > 0 {
+            return Err(Error::custom(format!(
+                "field `{}` has {} bytes left over",
+                name, left
+            )));
+        }
+    }
+
+    deserializer.dedup().put_scratch(bytes);
+    Ok(())
+}
+
+/// Reads the fields of a struct or enum variant marked
+/// `#[binserde(count_fields)]`, calling `read` with the number of fields in
+/// the input and a deserializer for them. `read` reads at most `known` of
+/// them, the data of any further fields is skipped. Used by the derive macro.
+pub fn read_counted_fields<'de, D, F, T>(known: u32, mut deserializer: D, read: F) -> Result<T>
+where
+    D: BinDeserializer<'de>,
+    F: FnOnce(u32, &mut WithMode<BinDeserializerBase<'_, &[u8]>>) -> Result<T>,
+{
+    let count = u32::deserialize(&mut deserializer)?;
+    let len = read_len(&mut deserializer)?;
+    let mode = deserializer.mode();
+    let mut bytes = deserializer.dedup().take_scratch();
+    let got = deserializer
+        .pipe()
+        .take(len as u64)
+        .read_to_end(&mut bytes)?;
+
+    if got != len {
+        return Err(Error::UnexpectedEof { expected: len, got });
+    }
+
+    let mut fields = BinDeserializerBase::new(&*bytes, deserializer.dedup()).with_mode(mode);
+    let value = read(count, &mut fields)?;
+    let left = fields.deserializer.pipe.inner.len();
+
+    if count <= known && left > 0 {
+        return Err(Error::custom(format!(
+            "{} fields have {} bytes left over",
+            count, left
+        )));
+    }
+
+    deserializer.dedup().put_scratch(bytes);
+    Ok(value)
+}
+
+fn read_tagged_option<'de, T, D>(mut deserializer: D) -> Result<Option<T>>
+where
+    T: BinDeserialize<'de>,
+    D: BinDeserializer<'de>,
+{
+    let (none, some) = deserializer.mode().option_tags;
+
+    match u8::deserialize(&mut deserializer)? {
+        x if x == none => Ok(None),
+        x if x == some => Ok(Some(T::deserialize(deserializer)?)),
+        x => Err(Error::custom(format!("invalid enum variant index {}", x))),
+    }
+}
+
+/// Reads an optional collection written by [`write_collection_option`].
+///
+/// [`write_collection_option`]: crate::ser::write_collection_option
+pub(crate) fn read_collection_option<'de, T, D>(mut deserializer: D) -> Result<Option<T>>
+where
+    T: BinDeserialize<'de>,
+    D: BinDeserializer<'de>,
+{
+    if !use_compact_options(deserializer.mode()) {
+        return read_tagged_option(deserializer);
+    }
+
+    let len = match usize::deserialize(&mut deserializer)? {
+        0 => return Ok(None),
+        len => len - 1,
+    };
+
+    // put the actual length back in front of the rest of the collection
+    let mut prefix = Vec::new();
+    len.serialize(BinSerializerBase::new(&mut prefix).with_mode(deserializer.mode()))?;
+
+    let value = T::deserialize(Unread {
+        prefix,
+        pos: 0,
+        inner: deserializer,
+        marker: PhantomData,
+    })?;
+
+    Ok(Some(value))
+}
+
+/// A deserializer which reads `prefix` before continuing with the input of
+/// `inner`.
+struct Unread<'de, D> {
+    prefix: Vec<u8>,
+    pos: usize,
+    inner: D,
+    marker: PhantomData<&'de ()>,
+}
+
+impl<'de, D> Read for Unread<'de, D>
+where
+    D: BinDeserializer<'de>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos < self.prefix.len() {
+            let len = (&self.prefix[self.pos..]).read(buf)?;
+            self.pos += len;
+            Ok(len)
+        } else {
+            self.inner.pipe().read(buf)
+        }
+    }
+}
+
+impl<'de, D> BinDeserializer<'de> for Unread<'de, D>
+where
+    D: BinDeserializer<'de>,
+{
+    type Pipe = Self;
+
+    fn pipe(&mut self) -> &mut Self::Pipe {
+        self
+    }
+
+    fn dedup(&self) -> &DedupContext {
+        self.inner.dedup()
+    }
+
+    fn mode(&self) -> Mode {
+        self.inner.mode()
+    }
+
+    fn borrow_bytes(&mut self, len: usize) -> Result<&'de [u8]> {
+        if self.pos < self.prefix.len() {
+            return Err(Error::custom("can't borrow the length of a collection"));
+        }
+
+        self.inner.borrow_bytes(len)
+    }
+}
+
+/// Calls `read` with a [`DynDeserializer`] for the value inside a `Box` or
+/// a shared pointer, after checking the number of nested boxes against
+/// [`Mode::max_depth`].
+pub(crate) fn read_boxed<'de, D, F, T>(mut deserializer: D, read: F) -> Result<T>
+where
+    D: BinDeserializer<'de>,
+    F: FnOnce(DynDeserializer<'_, 'de>) -> Result<T>,
+{
+    let depth = deserializer.dedup().enter_box();
+
+    let result = match deserializer.mode().max_depth {
+        Some(max) if depth > max => Err(Error::RecursionLimit(max)),
+        _ => read(DynDeserializer::new(&mut deserializer)),
+    };
+
+    deserializer.dedup().exit_box();
+    result
 }
 
 pub trait BinDeserializeOwned: for<'de> BinDeserialize<'de> {}
 impl<T> BinDeserializeOwned for T where T: for<'de> BinDeserialize<'de> {}
 
+/// A source of values to deserialize. `'de` is the lifetime of the input,
+/// which values can only borrow from if the deserializer reads from a byte
+/// slice, see [`BinDeserializer::borrow_bytes`].
 pub trait BinDeserializer<'de>: Sized {
     type Pipe: Read;
 
     fn pipe(&mut self) -> &mut Self::Pipe;
 
-    fn dedup(&self) -> &'de DedupContext;
+    fn dedup(&self) -> &DedupContext;
 
     fn mode(&self) -> Mode;
 
+    /// Reads the next `len` bytes without copying them. This is only
+    /// supported when deserializing from a byte slice with
+    /// [`deserialize_borrowed`], and fails otherwise.
+    ///
+    /// [`deserialize_borrowed`]: crate::deserialize_borrowed
+    fn borrow_bytes(&mut self, len: usize) -> Result<&'de [u8]> {
+        let _ = len;
+        Err(Error::custom(
+            "borrowing from the input requires deserializing with deserialize_borrowed",
+        ))
+    }
+
+    /// Reads a value of type `T` and discards it, to advance past it in the
+    /// input.
+    fn skip<T: BinDeserialize<'de>>(&mut self) -> Result<()> {
+        T::deserialize(self)?;
+        Ok(())
+    }
+
     fn with_mode(self, mode: Mode) -> WithMode<Self> {
         WithMode {
             deserializer: self,
@@ -44,6 +391,37 @@ pub trait BinDeserializer<'de>: Sized {
     fn disable_dedup(self) -> WithMode<Self> {
         self.change_mode(|mode| mode.use_dedup = false)
     }
+
+    /// Starts reading a sequence written with
+    /// [`BinSerializer::serialize_seq`] by hand. The elements are then read
+    /// using [`SeqDeserializer::next_element`].
+    ///
+    /// [`BinSerializer::serialize_seq`]: crate::BinSerializer::serialize_seq
+    fn deserialize_seq(mut self) -> Result<SeqDeserializer<Self>> {
+        let len = read_elements_len(&mut self)?;
+
+        Ok(SeqDeserializer {
+            deserializer: self,
+            remaining: len,
+        })
+    }
+
+    /// Starts reading a struct with `n_fields` fields written with
+    /// [`BinSerializer::serialize_struct`] or a derived implementation by
+    /// hand. The fields are then read in order using
+    /// [`StructDeserializer::deserialize_field`].
+    ///
+    /// [`BinSerializer::serialize_struct`]: crate::BinSerializer::serialize_struct
+    fn deserialize_struct(self, n_fields: usize) -> Result<StructDeserializer<Self>> {
+        let tagged = enter_struct(&self);
+
+        Ok(StructDeserializer {
+            deserializer: self,
+            tagged,
+            n_fields,
+            read: 0,
+        })
+    }
 }
 
 impl<'de, T> BinDeserializer<'de> for &mut T
@@ -56,37 +434,131 @@ where
         (**self).pipe()
     }
 
-    fn dedup(&self) -> &'de DedupContext {
+    fn dedup(&self) -> &DedupContext {
         (**self).dedup()
     }
 
     fn mode(&self) -> Mode {
         (**self).mode()
     }
+
+    fn borrow_bytes(&mut self, len: usize) -> Result<&'de [u8]> {
+        (**self).borrow_bytes(len)
+    }
 }
 
-pub struct BinDeserializerBase<'de, R> {
-    pipe: R,
-    dedup: &'de DedupContext,
+/// Wraps the reader of a deserializer so that reads hitting the end of the
+/// input fail with [`Error::UnexpectedEof`] instead of a plain I/O error.
+pub struct EofReader<R> {
+    inner: R,
 }
 
-impl<'de, R> BinDeserializerBase<'de, R> {
-    pub fn new(pipe: R, dedup: &'de DedupContext) -> Self {
-        BinDeserializerBase { pipe, dedup }
+impl<R> EofReader<R> {
+    pub fn new(inner: R) -> Self {
+        EofReader { inner }
     }
 }
 
-impl<'de, R> BinDeserializer<'de> for BinDeserializerBase<'de, R>
+impl<R> Read for EofReader<R>
 where
     R: Read,
 {
-    type Pipe = R;
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let expected = buf.len();
+        let mut got = 0;
+
+        while got < expected {
+            match self.inner.read(&mut buf[got..]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        Truncated { expected, got },
+                    ))
+                }
+                Ok(n) => got += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts the bytes read from the reader it wraps, so that errors can be
+/// reported with their offset, see [`Mode::with_error_offsets`].
+pub(crate) struct CountRead<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R> CountRead<R> {
+    pub fn new(inner: R) -> Self {
+        CountRead { inner, count: 0 }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<R> Read for CountRead<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+/// The payload of the I/O errors returned by [`EofReader`], which is turned
+/// into [`Error::UnexpectedEof`] when converting them.
+#[derive(Debug)]
+pub(crate) struct Truncated {
+    pub expected: usize,
+    pub got: usize,
+}
+
+impl Display for Truncated {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "expected {} bytes, got {}", self.expected, self.got)
+    }
+}
+
+impl core::error::Error for Truncated {}
+
+pub struct BinDeserializerBase<'a, R> {
+    pipe: EofReader<R>,
+    dedup: &'a DedupContext,
+}
+
+impl<'a, R> BinDeserializerBase<'a, R> {
+    pub fn new(pipe: R, dedup: &'a DedupContext) -> Self {
+        BinDeserializerBase {
+            pipe: EofReader::new(pipe),
+            dedup,
+        }
+    }
+}
+
+// Nothing is borrowed from the reader, so this works for any input lifetime.
+impl<'de, R> BinDeserializer<'de> for BinDeserializerBase<'_, R>
+where
+    R: Read,
+{
+    type Pipe = EofReader<R>;
 
     fn pipe(&mut self) -> &mut Self::Pipe {
         &mut self.pipe
     }
 
-    fn dedup(&self) -> &'de DedupContext {
+    fn dedup(&self) -> &DedupContext {
         self.dedup
     }
 
@@ -95,6 +567,138 @@ where
     }
 }
 
+/// Deserializes from a byte slice, allowing values to borrow from it.
+pub struct SliceDeserializer<'de, 'a> {
+    input: EofReader<&'de [u8]>,
+    dedup: &'a DedupContext,
+}
+
+impl<'de, 'a> SliceDeserializer<'de, 'a> {
+    pub fn new(input: &'de [u8], dedup: &'a DedupContext) -> Self {
+        SliceDeserializer {
+            input: EofReader::new(input),
+            dedup,
+        }
+    }
+
+    /// Returns the part of the input that hasn't been read yet.
+    pub fn remaining(&self) -> &'de [u8] {
+        self.input.inner
+    }
+}
+
+impl<'de> BinDeserializer<'de> for SliceDeserializer<'de, '_> {
+    type Pipe = EofReader<&'de [u8]>;
+
+    fn pipe(&mut self) -> &mut Self::Pipe {
+        &mut self.input
+    }
+
+    fn dedup(&self) -> &DedupContext {
+        self.dedup
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::default()
+    }
+
+    fn borrow_bytes(&mut self, len: usize) -> Result<&'de [u8]> {
+        let input = self.input.inner;
+
+        if len > input.len() {
+            return Err(Error::UnexpectedEof {
+                expected: len,
+                got: input.len(),
+            });
+        }
+
+        let (bytes, rest) = input.split_at(len);
+        self.input.inner = rest;
+        Ok(bytes)
+    }
+}
+
+/// Reads the elements of a sequence, see
+/// [`BinDeserializer::deserialize_seq`].
+pub struct SeqDeserializer<D> {
+    deserializer: D,
+    remaining: usize,
+}
+
+impl<'de, D> SeqDeserializer<D>
+where
+    D: BinDeserializer<'de>,
+{
+    /// Returns the number of elements left to read.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Reads the next element, or returns `None` if all of them were read.
+    pub fn next_element<T: BinDeserialize<'de>>(&mut self) -> Result<Option<T>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+
+        self.remaining -= 1;
+        T::deserialize(&mut self.deserializer).map(Some)
+    }
+
+    /// Finishes the sequence, failing if not all of its elements were read.
+    pub fn end(self) -> Result<()> {
+        if self.remaining > 0 {
+            return Err(Error::custom(format!(
+                "{} unread elements left in sequence",
+                self.remaining
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the fields of a struct, see
+/// [`BinDeserializer::deserialize_struct`].
+pub struct StructDeserializer<D> {
+    deserializer: D,
+    tagged: bool,
+    n_fields: usize,
+    read: usize,
+}
+
+impl<'de, D> StructDeserializer<D>
+where
+    D: BinDeserializer<'de>,
+{
+    /// Reads the next field, checking its name if the struct is tagged
+    /// according to [`Mode::with_tag_depth`]. Errors are attached to `name`
+    /// like in derived implementations, see [`Mode::with_error_context`].
+    pub fn deserialize_field<T: BinDeserialize<'de>>(&mut self, name: &str) -> Result<T> {
+        if self.tagged {
+            read_field_tag(name, &mut self.deserializer)?;
+        }
+
+        self.read += 1;
+        let mode = self.deserializer.mode();
+        field_context(mode, name, T::deserialize(&mut self.deserializer))
+    }
+
+    /// Finishes the struct, failing if the number of fields read doesn't
+    /// match the number it was started with.
+    pub fn end(self) -> Result<()> {
+        exit_struct(&self.deserializer);
+
+        if self.read != self.n_fields {
+            return Err(Error::custom(format!(
+                "struct with {} fields has {} fields read",
+                self.n_fields, self.read
+            )));
+        }
+
+        Ok(())
+    }
+}
+
 pub struct WithMode<D> {
     deserializer: D,
     mode: Mode,
@@ -110,11 +714,117 @@ where
         self.deserializer.pipe()
     }
 
-    fn dedup(&self) -> &'de DedupContext {
+    fn dedup(&self) -> &DedupContext {
         self.deserializer.dedup()
     }
 
     fn mode(&self) -> Mode {
         self.mode
     }
+
+    fn borrow_bytes(&mut self, len: usize) -> Result<&'de [u8]> {
+        self.deserializer.borrow_bytes(len)
+    }
+}
+
+/// Reads the elements of a sequence one at a time, see
+/// [`deserialize_seq_with_from`].
+///
+/// [`deserialize_seq_with_from`]: crate::deserialize_seq_with_from
+pub struct SeqIter<R, T> {
+    pipe: R,
+    mode: Mode,
+    dedup: DedupContext,
+    /// The number of elements left, or `None` before the header was read.
+    remaining: Option<usize>,
+    failed: bool,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<R, T> SeqIter<R, T>
+where
+    R: Read,
+    T: BinDeserializeOwned,
+{
+    pub(crate) fn new(pipe: R, mode: Mode) -> Self {
+        SeqIter {
+            pipe,
+            mode,
+            dedup: DedupContext::new(),
+            remaining: None,
+            failed: false,
+            marker: PhantomData,
+        }
+    }
+
+    fn read_header(&mut self) -> Result<usize> {
+        if self.mode.prefix_total_len || self.mode.trailing_crc {
+            return Err(Error::custom(
+                "can't read sequences one element at a time with a total length prefix or trailing checksum",
+            ));
+        }
+
+        if use_collection_table(self.mode) {
+            return Err(Error::custom(
+                "can't read sequences one element at a time when collections are deduplicated",
+            ));
+        }
+
+        read_preamble(&mut self.pipe, self.mode)?;
+
+        if self.mode.write_schema_header {
+            Schema::read_from(&mut self.pipe)?;
+        }
+
+        self.dedup = DedupContext::read_header(&mut self.pipe, self.mode)?;
+        read_len(BinDeserializerBase::new(&mut self.pipe, &self.dedup).with_mode(self.mode))
+    }
+
+    fn next_item(&mut self) -> Result<Option<T>> {
+        let remaining = match self.remaining {
+            Some(v) => v,
+            None => self.read_header()?,
+        };
+        self.remaining = Some(remaining);
+
+        if remaining == 0 {
+            return Ok(None);
+        }
+
+        let deserializer =
+            BinDeserializerBase::new(&mut self.pipe, &self.dedup).with_mode(self.mode);
+        let value = T::deserialize(deserializer)?;
+        self.remaining = Some(remaining - 1);
+        Ok(Some(value))
+    }
+}
+
+impl<R, T> Iterator for SeqIter<R, T>
+where
+    R: Read,
+    T: BinDeserializeOwned,
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        match self.next_item() {
+            Ok(v) => v.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match (self.failed, self.remaining) {
+            (true, _) => (0, Some(0)),
+            (false, Some(remaining)) => (0, Some(remaining)),
+            (false, None) => (0, None),
+        }
+    }
 }
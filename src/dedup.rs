@@ -1,71 +1,1372 @@
-use std::io::{Read, Write};
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::borrow::Borrow;
+use core::cell::{Cell, RefCell};
+use core::hash::Hasher;
+use core::mem;
+#[cfg(feature = "std")]
+use std::collections::hash_map::DefaultHasher;
+#[cfg(feature = "std")]
+use std::time::UNIX_EPOCH;
 
-use crate::de::{BinDeserializer, BinDeserializerBase};
+use crate::de::{read_boxed, BinDeserializer, BinDeserializerBase, WithMode};
+use crate::erased::DynSerializer;
+use crate::io::{Read, Write};
+use crate::schema::Schema;
+use crate::ser::BufSerializer;
 use crate::serde::UsizeLen;
-use crate::Result;
-use crate::{BinDeserialize, BinSerializer, BinSerializerBase, Mode};
 use crate::util::serialize_iter;
+use crate::{
+    BinDeserialize, BinDeserializeOwned, BinSerialize, BinSerializer, BinSerializerBase, Mode,
+};
+use crate::{Error, Result};
 
 const DEDUP_MODE: Mode = Mode {
     usize_len: UsizeLen::Variable,
     dedup_idx: UsizeLen::Variable,
+    string_len: None,
     fixed_size_use_varint: false,
+    prefix_total_len: false,
+    checked_arithmetic: true,
+    inline_dedup: false,
+    pack_bools: false,
+    fail_fast: true,
+    error_context: false,
+    trailing_crc: false,
+    fixed_signed: false,
+    dedup_bytes: false,
+    deny_dedup_in_keys: false,
+    option_tags: (0, 1),
+    write_schema_header: false,
+    deterministic: false,
+    dedup_eq_by_content_hash: false,
+    tag_depth: 0,
+    max_map_entries: None,
+    big_endian: false,
+    max_container_len: None,
+    varint_group_encoding: false,
+    map_entry_count_hint: None,
+    hex_bytes: false,
+    #[cfg(feature = "std")]
+    time_epoch: UNIX_EPOCH,
+    header: None,
+    dedup_collections: false,
+    error_on_nan: false,
+    compact_options: false,
+    max_total_elements: None,
+    stable_float_repr: false,
+    max_depth: None,
+    mode_flags: false,
+    dictionary: None,
+    error_offsets: false,
     use_dedup: false,
 };
 
 pub struct DedupContext {
-    strings: Vec<(String, usize)>,
-    by_index: Vec<usize>,
+    strings: Table<String>,
+    bytes: Table<Vec<u8>>,
+    inline: RefCell<Vec<String>>,
+    /// The values deserialized from the byte blob table by [`Shared`], by
+    /// index, each one an `Arc<T>`.
+    shared: RefCell<BTreeMap<usize, Box<dyn Any>>>,
+    /// The byte blob table index of each shared pointer written so far, by
+    /// the address it points to. Only valid while the value being serialized
+    /// is borrowed, see [`write_pointee`].
+    pointers: BTreeMap<usize, usize>,
+    /// The byte blob table entries currently being deserialized, see
+    /// [`DedupContext::enter_entry`].
+    decoding: RefCell<Vec<usize>>,
+    hasher: fn(&[u8]) -> u64,
+    depth: Cell<usize>,
+    elements: Cell<usize>,
+    boxes: Cell<usize>,
+    /// A buffer for temporary reads, see [`crate::deserialize_with_scratch`].
+    scratch: RefCell<Vec<u8>>,
+}
+
+impl Clone for DedupContext {
+    fn clone(&self) -> Self {
+        DedupContext {
+            strings: self.strings.clone(),
+            bytes: self.bytes.clone(),
+            inline: self.inline.clone(),
+            shared: RefCell::new(BTreeMap::new()),
+            pointers: self.pointers.clone(),
+            decoding: RefCell::new(Vec::new()),
+            hasher: self.hasher,
+            depth: self.depth.clone(),
+            elements: self.elements.clone(),
+            boxes: self.boxes.clone(),
+            scratch: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl Default for DedupContext {
+    fn default() -> Self {
+        DedupContext::new()
+    }
 }
 
 impl DedupContext {
     pub fn new() -> Self {
+        DedupContext::with_hasher(default_hash)
+    }
+
+    /// Creates a context that uses `hasher` to look up entries when
+    /// [`Mode::dedup_eq_by_content_hash`] is enabled.
+    pub fn with_hasher(hasher: fn(&[u8]) -> u64) -> Self {
         DedupContext {
-            strings: Vec::new(),
-            by_index: Vec::new(),
+            strings: Table::new(),
+            bytes: Table::new(),
+            inline: RefCell::new(Vec::new()),
+            shared: RefCell::new(BTreeMap::new()),
+            pointers: BTreeMap::new(),
+            decoding: RefCell::new(Vec::new()),
+            hasher,
+            depth: Cell::new(0),
+            elements: Cell::new(0),
+            boxes: Cell::new(0),
+            scratch: RefCell::new(Vec::new()),
         }
     }
 
+    /// Reserves space for at least `additional` more strings.
+    pub fn reserve_strings(&mut self, additional: usize) {
+        self.strings.reserve(additional);
+    }
+
     pub fn put_str(&mut self, s: &str) -> usize {
-        match self.strings.binary_search_by(|el| (*el.0).cmp(s)) {
-            Ok(idx) => self.strings[idx].1,
-            Err(idx) => {
-                let l = self.strings.len();
-                self.strings.insert(idx, (s.to_string(), l));
-                l
-            }
+        self.insert_str(s).0
+    }
+
+    /// Like [`DedupContext::put_str`], but additionally returns whether the
+    /// string was newly added.
+    pub fn insert_str(&mut self, s: &str) -> (usize, bool) {
+        self.strings.insert(s)
+    }
+
+    /// Like [`DedupContext::insert_str`], but finds existing entries by
+    /// their hash instead of by comparing them, see
+    /// [`Mode::with_dedup_eq_by_content_hash`].
+    pub fn insert_str_by_hash(&mut self, s: &str) -> (usize, bool) {
+        self.strings.insert_by_hash(s, self.hasher)
+    }
+
+    /// Registers a string read inline from the stream when using
+    /// [`Mode::with_inline_dedup`], so that later references to it can be
+    /// resolved.
+    pub fn push_inline(&self, s: String) {
+        self.inline.borrow_mut().push(s);
+    }
+
+    pub fn get_inline(&self, idx: usize) -> Option<String> {
+        self.inline.borrow().get(idx).cloned()
+    }
+
+    /// Records entering a derived struct, returning whether its fields should
+    /// be tagged with their names according to `tag_depth`. This is tracked
+    /// here instead of in the mode so that derived impls don't have to wrap
+    /// the serializer for every nested struct.
+    pub(crate) fn enter_struct(&self, tag_depth: usize) -> bool {
+        let depth = self.depth.get();
+        self.depth.set(depth + 1);
+        depth < tag_depth
+    }
+
+    pub(crate) fn exit_struct(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    /// Adds `len` to the number of collection elements read so far, returning
+    /// the new total. See [`Mode::with_max_total_elements`].
+    pub(crate) fn count_elements(&self, len: usize) -> usize {
+        let total = self.elements.get().saturating_add(len);
+        self.elements.set(total);
+        total
+    }
+
+    /// Records entering a `Box` while deserializing, returning how many are
+    /// now nested. See [`Mode::with_max_depth`].
+    pub(crate) fn enter_box(&self) -> usize {
+        let depth = self.boxes.get() + 1;
+        self.boxes.set(depth);
+        depth
+    }
+
+    pub(crate) fn exit_box(&self) {
+        self.boxes.set(self.boxes.get() - 1);
+    }
+
+    /// Records starting to deserialize the byte blob table entry at `idx`,
+    /// failing if it is already being deserialized. Entries only refer to
+    /// entries written before them, so this only happens in crafted input
+    /// whose entries refer to each other in a cycle, which would otherwise
+    /// recurse until the stack overflows.
+    pub(crate) fn enter_entry(&self, idx: usize) -> Result<()> {
+        let mut decoding = self.decoding.borrow_mut();
+
+        if decoding.contains(&idx) {
+            return Err(Error::custom(format!(
+                "deduplicated value {} refers to itself",
+                idx
+            )));
+        }
+
+        decoding.push(idx);
+        Ok(())
+    }
+
+    pub(crate) fn exit_entry(&self) {
+        self.decoding.borrow_mut().pop();
+    }
+
+    /// Forgets the addresses of the shared pointers written so far, which
+    /// may be reused for other values once the value that contained them is
+    /// no longer borrowed.
+    pub(crate) fn clear_pointers(&mut self) {
+        self.pointers.clear();
+    }
+
+    /// Exchanges the scratch buffer with `buf`.
+    pub(crate) fn swap_scratch(&self, buf: &mut Vec<u8>) {
+        mem::swap(&mut *self.scratch.borrow_mut(), buf);
+    }
+
+    /// Takes the scratch buffer to read temporary data into, which should be
+    /// passed back to [`DedupContext::put_scratch`] when done. Nested reads
+    /// get an empty buffer while it is taken.
+    pub(crate) fn take_scratch(&self) -> Vec<u8> {
+        let mut buf = mem::take(&mut *self.scratch.borrow_mut());
+        buf.clear();
+        buf
+    }
+
+    pub(crate) fn put_scratch(&self, buf: Vec<u8>) {
+        let mut scratch = self.scratch.borrow_mut();
+
+        if buf.capacity() > scratch.capacity() {
+            *scratch = buf;
         }
     }
 
+    /// Adds all strings from `other` to this context, returning where each
+    /// of `other`'s indices ended up in this context. Strings that are in
+    /// both contexts are only stored once.
+    ///
+    /// Fails if `other` has entries in the byte blob table used with
+    /// [`Mode::with_dedup_bytes`]. These can refer to strings and other
+    /// entries by their index in `other`, which can't be rewritten without
+    /// knowing the type of the entry, so values containing them need to be
+    /// scanned into this context again instead.
+    pub fn merge(&mut self, other: &DedupContext) -> Result<DedupRemap> {
+        if !other.bytes.values.is_empty() {
+            return Err(Error::custom(format!(
+                "can't merge a context with {} byte blob table entries",
+                other.bytes.values.len()
+            )));
+        }
+
+        let remap = other
+            .strings
+            .values
+            .iter()
+            .map(|s| self.put_str(s))
+            .collect();
+
+        Ok(DedupRemap(remap))
+    }
+
     pub fn get_str(&self, idx: usize) -> Option<&str> {
-        self.by_index.get(idx).map(|el| &*self.strings[*el].0)
+        self.strings.get(idx).map(|s| &**s)
+    }
+
+    /// Adds a byte blob to the table used with [`Mode::with_dedup_bytes`],
+    /// returning its index.
+    pub fn put_bytes(&mut self, b: &[u8]) -> usize {
+        self.bytes.insert(b).0
+    }
+
+    /// Like [`DedupContext::put_bytes`], but finds existing entries by their
+    /// hash, see [`Mode::with_dedup_eq_by_content_hash`].
+    pub fn put_bytes_by_hash(&mut self, b: &[u8]) -> usize {
+        self.bytes.insert_by_hash(b, self.hasher).0
+    }
+
+    pub fn get_bytes(&self, idx: usize) -> Option<&[u8]> {
+        self.bytes.get(idx).map(|b| &**b)
     }
 
     pub fn write_to<W: Write>(&self, pipe: W) -> Result<()> {
-        let ser = BinSerializerBase::new(pipe).with_mode(DEDUP_MODE);
+        self.strings.write_to(pipe)
+    }
+
+    pub fn read_from<R: Read>(pipe: R) -> Result<Self> {
+        Ok(DedupContext {
+            strings: Table::read_from(pipe)?,
+            ..DedupContext::new()
+        })
+    }
+
+    /// Returns the strings of the dictionary set with
+    /// [`Mode::with_dictionary_from_bytes`], or an empty context if there is
+    /// none.
+    pub(crate) fn from_mode(mode: Mode) -> Result<Self> {
+        match mode.dictionary {
+            None => Ok(DedupContext::new()),
+            Some(dictionary) => DedupContext::read_from(dictionary),
+        }
+    }
+
+    /// Writes the tables that precede the value when serializing with
+    /// `mode`. Strings from the dictionary set with
+    /// [`Mode::with_dictionary_from_bytes`] are left out, since the reader
+    /// already has them. Together with [`serialize_shared_into`], this can be used to
+    /// write one table for several values.
+    ///
+    /// [`serialize_shared_into`]: crate::serialize_shared_into
+    pub fn write_header<W: Write>(&self, mut pipe: W, mode: Mode) -> Result<()> {
+        if mode.use_dedup && !mode.inline_dedup {
+            let start = DedupContext::from_mode(mode)?.strings.values.len();
+            self.strings.write_from(start, &mut pipe)?;
 
-        let mut by_index: Vec<_> = self.strings.iter().collect();
-        by_index.sort_unstable_by_key(|el| el.1);
-        serialize_iter(by_index.into_iter().map(|el| &el.0), ser)?;
+            if mode.dedup_bytes {
+                self.bytes.write_to(&mut pipe)?;
+            }
+        }
 
         Ok(())
     }
 
-    pub fn read_from<R: Read>(pipe: R) -> Result<Self> {
+    /// Reads the tables written by [`DedupContext::write_header`].
+    pub fn read_header<R: Read>(mut pipe: R, mode: Mode) -> Result<Self> {
+        if mode.use_dedup && !mode.inline_dedup {
+            let mut context = DedupContext::from_mode(mode)?;
+            let table = Table::<String>::read_from(&mut pipe)?;
+            context.strings.values.extend(table.values);
+
+            if mode.dedup_bytes {
+                context.bytes = Table::read_from(&mut pipe)?;
+            }
+
+            Ok(context)
+        } else {
+            Ok(DedupContext::new())
+        }
+    }
+}
+
+/// Deduplicates values of any type, in the same way as strings.
+///
+/// With [`Mode::with_dedup_bytes`], the wrapped value is serialized on its own
+/// and the result is stored in the byte blob table, so that equal values
+/// are only written once and referred to by their index everywhere else.
+/// Values are compared by their serialized representation. Deduplicated
+/// strings and other `Dedup` values nested in the value are stored in their
+/// tables as usual. In all other modes, the value is written in place.
+///
+/// ```
+/// use binserde::dedup::Dedup;
+/// use binserde::Mode;
+///
+/// let mode = Mode::dedup().with_dedup_bytes(true);
+/// let values = vec![Dedup((1u64, 2u64)); 10];
+///
+/// let vec = binserde::serialize_with(&values, mode).unwrap();
+/// assert_eq!(2 + 17 + 11, vec.len());
+/// assert_eq!(values, binserde::deserialize_with::<Vec<_>>(&vec, mode).unwrap());
+/// ```
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Dedup<T>(pub T);
+
+pub(crate) fn use_value_table(mode: Mode) -> bool {
+    mode.use_dedup && mode.dedup_bytes && !mode.inline_dedup
+}
+
+/// Returns whether `Vec`s and slices are stored in the byte blob table, see
+/// [`Mode::with_deduplicate_identical_collections`].
+pub(crate) fn use_collection_table(mode: Mode) -> bool {
+    use_value_table(mode) && mode.dedup_collections
+}
+
+/// Serializes the elements of a slice like [`BinSerialize::serialize_slice`],
+/// for storing them in the byte blob table.
+struct SliceItems<'a, T>(&'a [T]);
+
+impl<T> BinSerialize for SliceItems<'_, T>
+where
+    T: BinSerialize,
+{
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        T::serialize_slice(self.0, serializer)
+    }
+}
+
+/// Writes the elements of `slice` to the byte blob table and their index in
+/// place.
+pub(crate) fn write_collection<T, S>(slice: &[T], serializer: S) -> Result<()>
+where
+    T: BinSerialize,
+    S: BinSerializer,
+{
+    write_value(&SliceItems(slice), serializer)
+}
+
+/// Reads the elements written by [`write_collection`] and appends them to
+/// `target`.
+pub(crate) fn read_collection<'de, T, D>(target: &mut Vec<T>, mut deserializer: D) -> Result<()>
+where
+    T: BinDeserialize<'de>,
+    D: BinDeserializer<'de>,
+{
+    let idx = read_value_idx(&mut deserializer)?;
+    let mode = deserializer.mode();
+    let dedup = deserializer.dedup();
+    let mut bytes = dedup.get_bytes(idx).ok_or(Error::BytesOutOfRange(idx))?;
+
+    dedup.enter_entry(idx)?;
+    let result = T::deserialize_extend(
+        target,
+        BinDeserializerBase::new(&mut bytes, dedup).with_mode(mode),
+    );
+    dedup.exit_entry();
+    result?;
+
+    if !bytes.is_empty() {
+        return Err(Error::custom(format!(
+            "deduplicated collection {} has {} bytes left over",
+            idx,
+            bytes.len()
+        )));
+    }
+
+    Ok(())
+}
+
+impl<T> BinSerialize for Dedup<T>
+where
+    T: BinSerialize,
+{
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        if use_value_table(serializer.mode()) {
+            write_value(&self.0, serializer)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+
+    fn schema() -> Schema {
+        Schema::Dedup(Box::new(T::schema()))
+    }
+}
+
+impl<'de, T> BinDeserialize<'de> for Dedup<T>
+where
+    T: BinDeserialize<'de>,
+{
+    fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
+        if use_value_table(deserializer.mode()) {
+            let idx = read_value_idx(&mut deserializer)?;
+            Ok(Dedup(read_value(idx, &deserializer)?))
+        } else {
+            Ok(Dedup(T::deserialize(deserializer)?))
+        }
+    }
+}
+
+/// A value behind an [`Arc`] that keeps being shared after deserializing.
+///
+/// This is stored in the byte blob table like [`Dedup`], but additionally,
+/// every occurrence of the same table entry deserializes to a clone of the
+/// same `Arc`. This preserves the structure of graphs where several nodes
+/// point to the same child. Unlike `Dedup`, values are told apart by the
+/// address of the `Arc`, so separate but equal values stay separate. In modes
+/// that don't use the byte blob table, every occurrence is deserialized into
+/// its own `Arc`.
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use binserde::dedup::Shared;
+/// use binserde::Mode;
+///
+/// let mode = Mode::dedup().with_dedup_bytes(true);
+/// let child = Shared::new("child".to_string());
+///
+/// let vec = binserde::serialize_with(&(child.clone(), child), mode).unwrap();
+/// let (a, b): (Shared<String>, Shared<String>) = binserde::deserialize_with(&vec, mode).unwrap();
+///
+/// assert!(Arc::ptr_eq(&a.0, &b.0));
+/// ```
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Shared<T>(pub Arc<T>);
+
+impl<T> Shared<T> {
+    pub fn new(value: T) -> Self {
+        Shared(Arc::new(value))
+    }
+}
+
+// implemented manually so that this doesn't require T: Clone
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared(self.0.clone())
+    }
+}
+
+impl<T> BinSerialize for Shared<T>
+where
+    T: BinSerialize,
+{
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        write_pointee(&*self.0, Arc::as_ptr(&self.0), serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Dedup(Box::new(T::schema()))
+    }
+}
+
+impl<'de, T> BinDeserialize<'de> for Shared<T>
+where
+    T: BinDeserialize<'de> + 'static,
+{
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        Ok(Shared(read_pointee(deserializer, Arc::new)?))
+    }
+}
+
+/// Like [`Shared`], but for values behind an [`Rc`].
+///
+/// Together with [`RefCell`], this can be used for mutable graphs made of
+/// `Rc<RefCell<T>>` nodes. Since nodes are told apart by their address,
+/// separate nodes with equal contents are still separate after
+/// deserializing.
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct SharedRc<T>(pub Rc<T>);
+
+impl<T> SharedRc<T> {
+    pub fn new(value: T) -> Self {
+        SharedRc(Rc::new(value))
+    }
+}
+
+// implemented manually so that this doesn't require T: Clone
+impl<T> Clone for SharedRc<T> {
+    fn clone(&self) -> Self {
+        SharedRc(self.0.clone())
+    }
+}
+
+impl<T> BinSerialize for SharedRc<T>
+where
+    T: BinSerialize,
+{
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        write_pointee(&*self.0, Rc::as_ptr(&self.0), serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Dedup(Box::new(T::schema()))
+    }
+}
+
+impl<'de, T> BinDeserialize<'de> for SharedRc<T>
+where
+    T: BinDeserialize<'de> + 'static,
+{
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        Ok(SharedRc(read_pointee(deserializer, Rc::new)?))
+    }
+}
+
+/// Serialized like the inner value, or like [`Shared`] in modes that use the
+/// byte blob table, so that `Arc`s pointing to the same allocation are
+/// written once and are still shared after deserializing.
+impl<T> BinSerialize for Arc<T>
+where
+    T: BinSerialize + ?Sized,
+{
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        write_pointee(&**self, Arc::as_ptr(self), serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Dedup(Box::new(T::schema()))
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        (**self).size_hint(mode)
+    }
+}
+
+impl<'de, T> BinDeserialize<'de> for Arc<T>
+where
+    T: BinDeserialize<'de> + 'static,
+{
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        read_pointee(deserializer, Arc::new)
+    }
+}
+
+/// Like the implementation for [`Arc`], see there.
+impl<T> BinSerialize for Rc<T>
+where
+    T: BinSerialize + ?Sized,
+{
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        write_pointee(&**self, Rc::as_ptr(self), serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Dedup(Box::new(T::schema()))
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        (**self).size_hint(mode)
+    }
+}
+
+impl<'de, T> BinDeserialize<'de> for Rc<T>
+where
+    T: BinDeserialize<'de> + 'static,
+{
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        read_pointee(deserializer, Rc::new)
+    }
+}
+
+/// Serializes the value behind a [`Shared`], [`SharedRc`], `Arc` or `Rc`
+/// pointing to `ptr`. Outside of the byte blob table, it is passed a
+/// [`DynSerializer`] like the value inside a `Box`, so that recursive types
+/// can be serialized.
+///
+/// In the table, every pointer gets its own entry the first time it is
+/// written, which is found by its address afterwards. This happens while
+/// prescanning, so the serializer writing the value finds all of them in the
+/// table it got from the prescan.
+fn write_pointee<T, S>(value: &T, ptr: *const T, mut serializer: S) -> Result<()>
+where
+    T: BinSerialize + ?Sized,
+    S: BinSerializer,
+{
+    if !use_value_table(serializer.mode()) {
+        return value.serialize(DynSerializer::new(&mut serializer));
+    }
+
+    let addr = ptr.cast::<()>() as usize;
+
+    let pos = match serializer.dedup().pointers.get(&addr) {
+        Some(pos) => *pos,
+        None => {
+            let bytes = serialize_entry(value, &mut serializer)?;
+            let dedup = serializer.dedup();
+            let pos = dedup.bytes.push(bytes);
+            dedup.pointers.insert(addr, pos);
+            pos
+        }
+    };
+
+    pos.serialize(serializer.change_mode(|mode| mode.usize_len = mode.dedup_idx))
+}
+
+/// Deserializes the value behind a pointer written by [`write_pointee`] and
+/// wraps it using `new`.
+fn read_pointee<'de, T, P, D>(deserializer: D, new: fn(T) -> P) -> Result<P>
+where
+    T: BinDeserialize<'de>,
+    P: Clone + 'static,
+    D: BinDeserializer<'de>,
+{
+    if use_value_table(deserializer.mode()) {
+        read_shared(deserializer, new)
+    } else {
+        read_boxed(deserializer, |d| Ok(new(T::deserialize(d)?)))
+    }
+}
+
+/// Serializes `value` on its own and writes the index of the result in the
+/// byte blob table.
+fn write_value<T, S>(value: &T, mut serializer: S) -> Result<()>
+where
+    T: BinSerialize + ?Sized,
+    S: BinSerializer,
+{
+    let mode = serializer.mode();
+    let bytes = serialize_entry(value, &mut serializer)?;
+
+    let dedup = serializer.dedup();
+    let pos = if mode.dedup_eq_by_content_hash {
+        dedup.put_bytes_by_hash(&bytes)
+    } else {
+        dedup.put_bytes(&bytes)
+    };
+
+    pos.serialize(serializer.change_mode(|mode| mode.usize_len = mode.dedup_idx))
+}
+
+/// Serializes `value` on its own for storing it in the byte blob table.
+fn serialize_entry<T, S>(value: &T, mut serializer: S) -> Result<Vec<u8>>
+where
+    T: BinSerialize + ?Sized,
+    S: BinSerializer,
+{
+    let mode = serializer.mode();
+    let mut buf = BufSerializer::new(serializer.dedup(), mode);
+    value.serialize(&mut buf)?;
+    Ok(buf.into_inner())
+}
+
+pub(crate) fn read_value_idx<'de, D: BinDeserializer<'de>>(deserializer: D) -> Result<usize> {
+    usize::deserialize(deserializer.change_mode(|mode| mode.usize_len = mode.dedup_idx))
+}
+
+/// Reads a table index and returns the pointer cached for it, or
+/// deserializes the value and wraps it using `new` if this is the first
+/// occurrence.
+fn read_shared<'de, T, P, D>(mut deserializer: D, new: impl FnOnce(T) -> P) -> Result<P>
+where
+    T: BinDeserialize<'de>,
+    P: Clone + 'static,
+    D: BinDeserializer<'de>,
+{
+    let idx = read_value_idx(&mut deserializer)?;
+    let dedup = deserializer.dedup();
+
+    let existing = dedup
+        .shared
+        .borrow()
+        .get(&idx)
+        .and_then(|v| v.downcast_ref::<P>().cloned());
+
+    if let Some(ptr) = existing {
+        return Ok(ptr);
+    }
+
+    // the borrow must not be held here, since the value can contain
+    // further shared values
+    let ptr = new(read_value(idx, &deserializer)?);
+    dedup.shared.borrow_mut().insert(idx, Box::new(ptr.clone()));
+    Ok(ptr)
+}
+
+/// Deserializes the value written by [`write_value`] at `idx` in the byte
+/// blob table.
+fn read_value<'de, T, D>(idx: usize, deserializer: &D) -> Result<T>
+where
+    T: BinDeserialize<'de>,
+    D: BinDeserializer<'de>,
+{
+    read_entry(idx, deserializer.mode(), deserializer.dedup(), |d| {
+        T::deserialize(d)
+    })
+}
+
+/// Reads the entry at `idx` in the byte blob table of `dedup` using `read`,
+/// which must use up all of it.
+pub(crate) fn read_entry<T, F>(idx: usize, mode: Mode, dedup: &DedupContext, read: F) -> Result<T>
+where
+    F: FnOnce(WithMode<BinDeserializerBase<'_, &mut &[u8]>>) -> Result<T>,
+{
+    let mut bytes = dedup.get_bytes(idx).ok_or(Error::BytesOutOfRange(idx))?;
+
+    dedup.enter_entry(idx)?;
+    let value = read(BinDeserializerBase::new(&mut bytes, dedup).with_mode(mode));
+    dedup.exit_entry();
+    let value = value?;
+
+    if !bytes.is_empty() {
+        return Err(Error::custom(format!(
+            "deduplicated value {} has {} bytes left over",
+            idx,
+            bytes.len()
+        )));
+    }
+
+    Ok(value)
+}
+
+#[cfg(feature = "std")]
+fn default_hash(b: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(b);
+    hasher.finish()
+}
+
+// DefaultHasher isn't available without std, but is currently the same
+// algorithm with different parameters
+#[cfg(not(feature = "std"))]
+#[allow(deprecated)]
+fn default_hash(b: &[u8]) -> u64 {
+    let mut hasher = core::hash::SipHasher::new();
+    hasher.write(b);
+    hasher.finish()
+}
+
+/// The entries in the order of their index, along with two ways to look them
+/// up by value: a list of indices sorted by value, and a map from the hash
+/// of the value to the indices with that hash. Each of these only gets
+/// updated when it is used, so that only one of them is maintained when
+/// always using the same kind of lookup.
+#[derive(Clone)]
+struct Table<T> {
+    values: Vec<T>,
+    sorted: Vec<usize>,
+    hashed: BTreeMap<u64, Vec<usize>>,
+    hashed_len: usize,
+}
+
+impl<T: Ord + AsRef<[u8]>> Table<T> {
+    fn new() -> Self {
+        Table {
+            values: Vec::new(),
+            sorted: Vec::new(),
+            hashed: BTreeMap::new(),
+            hashed_len: 0,
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.values.reserve(additional);
+        self.sorted.reserve(additional);
+    }
+
+    fn insert<Q>(&mut self, value: &Q) -> (usize, bool)
+    where
+        Q: Ord + ToOwned<Owned = T> + ?Sized,
+        T: Borrow<Q>,
+    {
+        self.update_sorted();
+
+        let values = &self.values;
+        match self
+            .sorted
+            .binary_search_by(|el| values[*el].borrow().cmp(value))
+        {
+            Ok(pos) => (self.sorted[pos], false),
+            Err(pos) => {
+                let idx = self.values.len();
+                self.values.push(value.to_owned());
+                self.sorted.insert(pos, idx);
+                (idx, true)
+            }
+        }
+    }
+
+    fn insert_by_hash<Q>(&mut self, value: &Q, hasher: fn(&[u8]) -> u64) -> (usize, bool)
+    where
+        Q: Eq + AsRef<[u8]> + ToOwned<Owned = T> + ?Sized,
+        T: Borrow<Q>,
+    {
+        self.update_hashed(hasher);
+
+        let idx = self.values.len();
+        let values = &mut self.values;
+        let bucket = self.hashed.entry(hasher(value.as_ref())).or_default();
+
+        // only compare the full values if the hashes match
+        match bucket.iter().find(|el| values[**el].borrow() == value) {
+            Some(existing) => (*existing, false),
+            None => {
+                bucket.push(idx);
+                values.push(value.to_owned());
+                self.hashed_len += 1;
+                (idx, true)
+            }
+        }
+    }
+
+    fn update_sorted(&mut self) {
+        for idx in self.sorted.len()..self.values.len() {
+            let values = &self.values;
+            let pos = self
+                .sorted
+                .binary_search_by(|el| values[*el].cmp(&values[idx]))
+                .unwrap_or_else(|pos| pos);
+            self.sorted.insert(pos, idx);
+        }
+    }
+
+    fn update_hashed(&mut self, hasher: fn(&[u8]) -> u64) {
+        for idx in self.hashed_len..self.values.len() {
+            let hash = hasher(self.values[idx].as_ref());
+            self.hashed.entry(hash).or_default().push(idx);
+        }
+
+        self.hashed_len = self.values.len();
+    }
+
+    /// Adds `value` as a new entry, even if an equal one exists already.
+    fn push(&mut self, value: T) -> usize {
+        self.values.push(value);
+        self.values.len() - 1
+    }
+
+    fn get(&self, idx: usize) -> Option<&T> {
+        self.values.get(idx)
+    }
+}
+
+impl<T> Table<T>
+where
+    T: Ord + AsRef<[u8]> + BinSerialize + BinDeserializeOwned,
+{
+    fn write_to<W: Write>(&self, pipe: W) -> Result<()> {
+        self.write_from(0, pipe)
+    }
+
+    /// Writes the entries from index `start` onwards.
+    fn write_from<W: Write>(&self, start: usize, pipe: W) -> Result<()> {
+        let ser = BinSerializerBase::new(pipe).with_mode(DEDUP_MODE);
+        serialize_iter(self.values[start.min(self.values.len())..].iter(), ser)
+    }
+
+    fn read_from<R: Read>(pipe: R) -> Result<Self> {
         let empty = DedupContext::new();
         let de = BinDeserializerBase::new(pipe, &empty).with_mode(DEDUP_MODE);
 
-        let by_index: Vec<String> = Vec::deserialize(de)?;
-        let mut strings: Vec<_> = by_index
-            .into_iter()
-            .enumerate()
-            .map(|(idx, s)| (s, idx))
-            .collect();
-        strings.sort_unstable_by(|a, b| a.0.cmp(&b.0));
-        let mut by_index = vec![0; strings.len()];
-        for (idx, el) in strings.iter().enumerate() {
-            by_index[el.1] = idx;
+        Ok(Table {
+            values: Vec::deserialize(de)?,
+            ..Table::new()
+        })
+    }
+}
+
+/// Maps string indices of a merged [`DedupContext`] to their index in the
+/// context they were merged into, see [`DedupContext::merge`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DedupRemap(Vec<usize>);
+
+impl DedupRemap {
+    pub fn map(&self, idx: usize) -> Option<usize> {
+        self.0.get(idx).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    use binserde_derive::{BinDeserialize, BinSerialize};
+
+    use crate::dedup::{Dedup, DedupContext, Shared, SharedRc};
+    use crate::{deserialize_with, serialize_with, Mode};
+
+    #[test]
+    fn read_self_referential_table() {
+        // two entries, each one a single byte that looks like an index into
+        // the table itself (1 and 0 respectively)
+        let buf = [0x02, 0x01, 0x01, 0x01, 0x00];
+
+        let ctx = DedupContext::read_from(&buf[..]).unwrap();
+
+        assert_eq!(Some("\u{1}"), ctx.get_str(0));
+        assert_eq!(Some("\u{0}"), ctx.get_str(1));
+        assert_eq!(None, ctx.get_str(2));
+    }
+
+    #[test]
+    fn merge() {
+        let mut first = DedupContext::new();
+        first.put_str("a");
+        first.put_str("shared");
+
+        let mut second = DedupContext::new();
+        second.put_str("shared");
+        second.put_str("b");
+
+        let mut buf = Vec::new();
+        first.write_to(&mut buf).unwrap();
+        let mut merged = DedupContext::read_from(&*buf).unwrap();
+
+        let remap = merged.merge(&second).unwrap();
+        assert_eq!(Some(1), remap.map(0));
+        assert_eq!(Some(2), remap.map(1));
+        assert_eq!(None, remap.map(2));
+
+        assert_eq!(Some("a"), merged.get_str(0));
+        assert_eq!(Some("shared"), merged.get_str(1));
+        assert_eq!(Some("b"), merged.get_str(2));
+        assert_eq!(None, merged.get_str(3));
+
+        // byte blob table entries can't be remapped, but the context merged
+        // into may have some
+        let mode = Mode::dedup().with_dedup_bytes(true);
+        let with_bytes = crate::prescan(&(Dedup("c".to_string()), b"blob".to_vec()), mode).unwrap();
+        assert_eq!(Some(&[0][..]), with_bytes.get_bytes(0));
+        assert!(merged.merge(&with_bytes).is_err());
+        assert_eq!(None, merged.get_str(3));
+
+        let mut bytes_merged = with_bytes.clone();
+        let remap = bytes_merged.merge(&merged).unwrap();
+        assert_eq!(Some(1), remap.map(0));
+        assert_eq!(Some(3), remap.map(2));
+        assert_eq!(Some("c"), bytes_merged.get_str(0));
+        assert_eq!(Some("b"), bytes_merged.get_str(3));
+        assert_eq!(Some(&[0][..]), bytes_merged.get_bytes(0));
+        assert_eq!(Some(&b"blob"[..]), bytes_merged.get_bytes(1));
+    }
+
+    #[test]
+    fn hash_collisions() {
+        // every string has the same hash
+        let mut ctx = DedupContext::with_hasher(|_| 0);
+
+        assert_eq!((0, true), ctx.insert_str_by_hash("a"));
+        assert_eq!((1, true), ctx.insert_str_by_hash("b"));
+        assert_eq!((0, false), ctx.insert_str_by_hash("a"));
+        assert_eq!((1, false), ctx.insert_str("b"));
+        assert_eq!((2, true), ctx.insert_str("c"));
+        assert_eq!((2, false), ctx.insert_str_by_hash("c"));
+
+        assert_eq!(Some("a"), ctx.get_str(0));
+        assert_eq!(Some("b"), ctx.get_str(1));
+        assert_eq!(Some("c"), ctx.get_str(2));
+    }
+
+    #[test]
+    fn nested_values() {
+        type Inner = Dedup<(u32, String)>;
+        type Outer = Dedup<(String, Inner)>;
+
+        let inner: Inner = Dedup((7, "inner".to_string()));
+        let outer: Outer = Dedup(("outer".to_string(), inner.clone()));
+        let value = (vec![outer.clone(); 3], vec![inner; 2], "inner".to_string());
+
+        let mode = Mode::dedup().with_dedup_bytes(true);
+        let vec = serialize_with(&value, mode).unwrap();
+
+        #[rustfmt::skip]
+        let expected = [
+            // strings
+            2, 5, b'o', b'u', b't', b'e', b'r', 5, b'i', b'n', b'n', b'e', b'r',
+            // values: the inner tuple, then the outer one referring to it
+            2, 5, 7, 0, 0, 0, 1, 2, 0, 0,
+            // outer values, inner values, string
+            3, 1, 1, 1, 2, 0, 0, 1,
+        ];
+        assert_eq!(&expected[..], &*vec);
+        assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+
+        for mode in [
+            Mode::default(),
+            Mode::dedup(),
+            Mode::dedup().with_inline_dedup(true),
+            Mode::dedup()
+                .with_dedup_bytes(true)
+                .with_dedup_eq_by_content_hash(true),
+        ] {
+            let vec = serialize_with(&value, mode).unwrap();
+            assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+        }
+    }
+
+    #[test]
+    fn value_with_bytes_left_over() {
+        // no strings, a single value of two bytes
+        let buf = [0, 1, 2, 1, 2, 0];
+        let mode = Mode::dedup().with_dedup_bytes(true);
+
+        assert_eq!(Dedup(1u16 | 2 << 8), deserialize_with(&buf, mode).unwrap());
+        assert!(deserialize_with::<Dedup<u8>>(&buf, mode).is_err());
+    }
+
+    #[test]
+    fn shared_diamond() {
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Leaf {
+            name: String,
         }
-        Ok(DedupContext { strings, by_index })
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Inner {
+            weight: u32,
+            child: Shared<Leaf>,
+        }
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Root {
+            left: Shared<Inner>,
+            right: Shared<Inner>,
+        }
+
+        // root -> left -> leaf
+        //      -> right -> leaf
+        let leaf = Shared::new(Leaf {
+            name: "leaf".to_string(),
+        });
+        let root = Root {
+            left: Shared::new(Inner {
+                weight: 1,
+                child: leaf.clone(),
+            }),
+            right: Shared::new(Inner {
+                weight: 2,
+                child: leaf,
+            }),
+        };
+
+        let mode = Mode::dedup().with_dedup_bytes(true);
+        let vec = serialize_with(&root, mode).unwrap();
+        let out: Root = deserialize_with(&vec, mode).unwrap();
+
+        assert_eq!(root, out);
+        assert!(!Arc::ptr_eq(&out.left.0, &out.right.0));
+        assert!(Arc::ptr_eq(&out.left.0.child.0, &out.right.0.child.0));
+
+        // without the table, the leaf is written twice and not shared
+        let vec = serialize_with(&root, Mode::default()).unwrap();
+        let out: Root = deserialize_with(&vec, Mode::default()).unwrap();
+
+        assert_eq!(root, out);
+        assert!(!Arc::ptr_eq(&out.left.0.child.0, &out.right.0.child.0));
+    }
+
+    #[test]
+    fn shared_rc_refcell() {
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Leaf {
+            visits: u32,
+        }
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Node {
+            name: String,
+            children: Vec<SharedRc<RefCell<Leaf>>>,
+        }
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Graph {
+            nodes: Vec<SharedRc<RefCell<Node>>>,
+        }
+
+        let shared = SharedRc::new(RefCell::new(Leaf { visits: 3 }));
+        let own = SharedRc::new(RefCell::new(Leaf { visits: 7 }));
+        let a = SharedRc::new(RefCell::new(Node {
+            name: "a".to_string(),
+            children: vec![shared.clone(), own],
+        }));
+        let b = SharedRc::new(RefCell::new(Node {
+            name: "b".to_string(),
+            children: vec![shared],
+        }));
+        let graph = Graph {
+            nodes: vec![a.clone(), b, a],
+        };
+
+        let mode = Mode::dedup().with_dedup_bytes(true);
+        let vec = serialize_with(&graph, mode).unwrap();
+        let out: Graph = deserialize_with(&vec, mode).unwrap();
+
+        assert_eq!(graph, out);
+        assert!(Rc::ptr_eq(&out.nodes[0].0, &out.nodes[2].0));
+        assert!(!Rc::ptr_eq(&out.nodes[0].0, &out.nodes[1].0));
+
+        let a = out.nodes[0].0.borrow();
+        let b = out.nodes[1].0.borrow();
+        assert!(Rc::ptr_eq(&a.children[0].0, &b.children[0].0));
+        assert!(!Rc::ptr_eq(&a.children[0].0, &a.children[1].0));
+
+        // mutations through one node are visible through the other
+        b.children[0].0.borrow_mut().visits += 1;
+        assert_eq!(4, a.children[0].0.borrow().visits);
+    }
+
+    #[test]
+    fn plain_arc_and_rc() {
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Scene {
+            meshes: Vec<Arc<Vec<u32>>>,
+            names: (Rc<String>, Rc<String>),
+        }
+
+        let mesh = Arc::new((0..500).collect::<Vec<u32>>());
+        let name = Rc::new("shared name".to_string());
+        let scene = Scene {
+            meshes: vec![mesh.clone(), Arc::new(vec![1, 2]), mesh],
+            names: (name.clone(), name),
+        };
+
+        let mode = Mode::dedup().with_dedup_bytes(true);
+        let vec = serialize_with(&scene, mode).unwrap();
+        let out: Scene = deserialize_with(&vec, mode).unwrap();
+
+        assert_eq!(scene, out);
+        assert!(Arc::ptr_eq(&out.meshes[0], &out.meshes[2]));
+        assert!(!Arc::ptr_eq(&out.meshes[0], &out.meshes[1]));
+        assert!(Rc::ptr_eq(&out.names.0, &out.names.1));
+
+        // the large vector is only written once
+        let plain = serialize_with(&scene, Mode::default()).unwrap();
+        assert!(vec.len() * 3 < plain.len() * 2);
+
+        // other modes serialize the inner value each time
+        let out: Scene = deserialize_with(&plain, Mode::default()).unwrap();
+        assert_eq!(scene, out);
+        assert!(!Arc::ptr_eq(&out.meshes[0], &out.meshes[2]));
+        assert!(plain.len() > 2 * 500 * 4);
+    }
+
+    #[test]
+    fn recursive_shared() {
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Node {
+            name: String,
+            children: Vec<Shared<Node>>,
+        }
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct List {
+            value: u32,
+            next: Option<Arc<List>>,
+        }
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Cell {
+            next: Option<Rc<Cell>>,
+            cells: Vec<SharedRc<RefCell<Cell>>>,
+        }
+
+        let leaf = Shared::new(Node {
+            name: "leaf".to_string(),
+            children: Vec::new(),
+        });
+        let root = Node {
+            name: "root".to_string(),
+            children: vec![leaf.clone(), leaf],
+        };
+
+        let list = List {
+            value: 1,
+            next: Some(Arc::new(List {
+                value: 2,
+                next: None,
+            })),
+        };
+
+        let cell = Cell {
+            next: Some(Rc::new(Cell {
+                next: None,
+                cells: Vec::new(),
+            })),
+            cells: vec![SharedRc::new(RefCell::new(Cell {
+                next: None,
+                cells: Vec::new(),
+            }))],
+        };
+
+        for mode in [Mode::default(), Mode::dedup().with_dedup_bytes(true)] {
+            let vec = serialize_with(&root, mode).unwrap();
+            assert_eq!(root, deserialize_with(&vec, mode).unwrap());
+            let vec = serialize_with(&list, mode).unwrap();
+            assert_eq!(list, deserialize_with(&vec, mode).unwrap());
+            let vec = serialize_with(&cell, mode).unwrap();
+            assert_eq!(cell, deserialize_with(&vec, mode).unwrap());
+        }
+
+        let mode = Mode::dedup().with_dedup_bytes(true);
+        let vec = serialize_with(&root, mode).unwrap();
+        let out: Node = deserialize_with(&vec, mode).unwrap();
+        assert!(Arc::ptr_eq(&out.children[0].0, &out.children[1].0));
+    }
+
+    #[test]
+    fn equal_pointers_stay_separate() {
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Leaf {
+            v: u32,
+        }
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Graph {
+            leaves: Vec<Rc<RefCell<Leaf>>>,
+            a: SharedRc<Leaf>,
+            b: SharedRc<Leaf>,
+            numbers: (Arc<u32>, Arc<u32>),
+        }
+
+        let first = Rc::new(RefCell::new(Leaf { v: 1 }));
+        let second = Rc::new(RefCell::new(Leaf { v: 1 }));
+        let graph = Graph {
+            leaves: vec![first.clone(), second, first],
+            a: SharedRc::new(Leaf { v: 1 }),
+            b: SharedRc::new(Leaf { v: 1 }),
+            numbers: (Arc::new(5), Arc::new(5)),
+        };
+
+        let mode = Mode::dedup().with_dedup_bytes(true);
+        let vec = serialize_with(&graph, mode).unwrap();
+        let out: Graph = deserialize_with(&vec, mode).unwrap();
+
+        assert_eq!(graph, out);
+        assert!(Rc::ptr_eq(&out.leaves[0], &out.leaves[2]));
+        assert!(!Rc::ptr_eq(&out.leaves[0], &out.leaves[1]));
+        assert!(!Rc::ptr_eq(&out.a.0, &out.b.0));
+        assert!(!Arc::ptr_eq(&out.numbers.0, &out.numbers.1));
+
+        // mutating one of them doesn't affect the other
+        out.leaves[1].borrow_mut().v = 2;
+        assert_eq!(1, out.leaves[0].borrow().v);
+        assert_eq!(1, out.leaves[2].borrow().v);
+
+        // the same holds for a table shared between several values, where
+        // pointers are only recognized within the same value
+        let mut ctx = DedupContext::new();
+        let mut buf = Vec::new();
+        let shared = Rc::new(7u32);
+        crate::serialize_shared_into(&mut buf, &(shared.clone(), shared.clone()), mode, &mut ctx)
+            .unwrap();
+        crate::serialize_shared_into(&mut buf, &Rc::new(7u32), mode, &mut ctx).unwrap();
+
+        let mut pipe = &buf[..];
+        let (a, b): (Rc<u32>, Rc<u32>) =
+            crate::deserialize_shared_from(&mut pipe, mode, &ctx).unwrap();
+        let c: Rc<u32> = crate::deserialize_shared_from(&mut pipe, mode, &ctx).unwrap();
+        assert!(Rc::ptr_eq(&a, &b));
+        assert!(!Rc::ptr_eq(&a, &c));
+        assert_eq!(7, *c);
+    }
+
+    #[test]
+    fn cyclic_value_table() {
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Node {
+            children: Vec<Shared<Node>>,
+        }
+
+        let mode = Mode::dedup().with_dedup_bytes(true);
+
+        #[rustfmt::skip]
+        let cycles: [&[u8]; 2] = [
+            // no strings, one value which is a node whose child is itself
+            &[0, 1, 2, 1, 0, 0],
+            // two nodes which are each other's child
+            &[0, 2, 2, 1, 1, 2, 1, 0, 0],
+        ];
+
+        for buf in cycles.iter().copied() {
+            let err = deserialize_with::<Shared<Node>>(buf, mode).unwrap_err();
+            assert!(err.to_string().contains("refers to itself"), "{}", err);
+        }
+
+        // the same entry appearing several times in a value is fine
+        let leaf = Shared::new(Node {
+            children: Vec::new(),
+        });
+        let node = Shared::new(Node {
+            children: vec![leaf.clone(), leaf],
+        });
+        let vec = serialize_with(&vec![node.clone(), node], mode).unwrap();
+        assert_eq!(&[0, 2, 1, 0, 3, 2, 0, 0, 2, 1, 1], &*vec);
+        deserialize_with::<Vec<Shared<Node>>>(&vec, mode).unwrap();
     }
 }
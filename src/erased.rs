@@ -0,0 +1,184 @@
+//! Serializers and deserializers which forward to another one through a
+//! trait object.
+//!
+//! `Box<T>` and the shared pointers in [`crate::dedup`] pass these to `T`
+//! instead of their own (de)serializer. Otherwise, every level of a recursive
+//! type would be (de)serialized with a different type wrapping the previous
+//! one, which can't be compiled.
+
+use crate::de::BinDeserializer;
+use crate::dedup::DedupContext;
+use crate::io::{self, Read, Write};
+use crate::ser::BinSerializer;
+use crate::serde::Mode;
+use crate::Result;
+
+/// The methods of [`BinSerializer`] and its pipe, in a form that can be
+/// used as a trait object.
+trait ErasedSerializer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>;
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()>;
+
+    fn dedup(&mut self) -> &mut DedupContext;
+
+    fn mode(&self) -> Mode;
+
+    fn is_prescan(&self) -> bool;
+}
+
+impl<S> ErasedSerializer for S
+where
+    S: BinSerializer,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pipe().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.pipe().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pipe().flush()
+    }
+
+    fn dedup(&mut self) -> &mut DedupContext {
+        BinSerializer::dedup(self)
+    }
+
+    fn mode(&self) -> Mode {
+        BinSerializer::mode(self)
+    }
+
+    fn is_prescan(&self) -> bool {
+        BinSerializer::is_prescan(self)
+    }
+}
+
+/// Forwards to another serializer through a trait object.
+pub(crate) struct DynSerializer<'a> {
+    inner: &'a mut dyn ErasedSerializer,
+}
+
+impl<'a> DynSerializer<'a> {
+    pub(crate) fn new<S: BinSerializer>(inner: &'a mut S) -> Self {
+        DynSerializer { inner }
+    }
+}
+
+impl Write for DynSerializer<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.inner.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl BinSerializer for DynSerializer<'_> {
+    type Pipe = Self;
+
+    fn pipe(&mut self) -> &mut Self::Pipe {
+        self
+    }
+
+    fn dedup(&mut self) -> &mut DedupContext {
+        self.inner.dedup()
+    }
+
+    fn mode(&self) -> Mode {
+        self.inner.mode()
+    }
+
+    fn is_prescan(&self) -> bool {
+        self.inner.is_prescan()
+    }
+}
+
+/// The methods of [`BinDeserializer`] and its pipe, in a form that can be
+/// used as a trait object.
+trait ErasedDeserializer<'de> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()>;
+
+    fn dedup(&self) -> &DedupContext;
+
+    fn mode(&self) -> Mode;
+
+    fn borrow_bytes(&mut self, len: usize) -> Result<&'de [u8]>;
+}
+
+impl<'de, D> ErasedDeserializer<'de> for D
+where
+    D: BinDeserializer<'de>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipe().read(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.pipe().read_exact(buf)
+    }
+
+    fn dedup(&self) -> &DedupContext {
+        BinDeserializer::dedup(self)
+    }
+
+    fn mode(&self) -> Mode {
+        BinDeserializer::mode(self)
+    }
+
+    fn borrow_bytes(&mut self, len: usize) -> Result<&'de [u8]> {
+        BinDeserializer::borrow_bytes(self, len)
+    }
+}
+
+/// Forwards to another deserializer through a trait object.
+pub(crate) struct DynDeserializer<'a, 'de> {
+    inner: &'a mut dyn ErasedDeserializer<'de>,
+}
+
+impl<'a, 'de> DynDeserializer<'a, 'de> {
+    pub(crate) fn new<D: BinDeserializer<'de>>(inner: &'a mut D) -> Self {
+        DynDeserializer { inner }
+    }
+}
+
+impl Read for DynDeserializer<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buf)
+    }
+}
+
+impl<'de> BinDeserializer<'de> for DynDeserializer<'_, 'de> {
+    type Pipe = Self;
+
+    fn pipe(&mut self) -> &mut Self::Pipe {
+        self
+    }
+
+    fn dedup(&self) -> &DedupContext {
+        self.inner.dedup()
+    }
+
+    fn mode(&self) -> Mode {
+        self.inner.mode()
+    }
+
+    fn borrow_bytes(&mut self, len: usize) -> Result<&'de [u8]> {
+        self.inner.borrow_bytes(len)
+    }
+}
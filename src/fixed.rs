@@ -0,0 +1,182 @@
+//! Decimal fixed-point numbers with a scale known at compile time.
+//!
+//! A [`FixedPoint<SCALE>`] stores its value as an `i64` counting units of
+//! `10^-SCALE`, so `FixedPoint::<2>` can represent amounts of money down to
+//! the cent exactly. Only the raw `i64` is serialized, using the same encoding
+//! as a plain `i64` in the current [`Mode`].
+//!
+//! ```
+//! use binserde::fixed::FixedPoint;
+//!
+//! let price = FixedPoint::<2>::from_raw(1999);
+//! let vec = binserde::serialize(&(price + price)).unwrap();
+//!
+//! assert_eq!("39.98", binserde::deserialize::<FixedPoint<2>>(&vec).unwrap().to_string());
+//! ```
+//!
+//! [`Mode`]: crate::Mode
+
+use core::fmt;
+use core::fmt::{Display, Formatter};
+use core::ops::{Add, Neg, Sub};
+
+use crate::schema::Schema;
+use crate::{BinDeserialize, BinDeserializer, BinSerialize, BinSerializer, Result};
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct FixedPoint<const SCALE: u32>(i64);
+
+impl<const SCALE: u32> FixedPoint<SCALE> {
+    /// The raw value corresponding to 1.
+    pub const ONE: i64 = 10i64.pow(SCALE);
+
+    pub const fn from_raw(raw: i64) -> Self {
+        FixedPoint(raw)
+    }
+
+    pub const fn raw(self) -> i64 {
+        self.0
+    }
+
+    /// Returns `None` if `value` doesn't fit at this scale.
+    pub fn from_int(value: i64) -> Option<Self> {
+        value.checked_mul(Self::ONE).map(FixedPoint)
+    }
+
+    /// Converts `value` to the nearest representable number, saturating if
+    /// it is out of range.
+    pub fn from_f64(value: f64) -> Self {
+        FixedPoint(round(value * Self::ONE as f64))
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / Self::ONE as f64
+    }
+}
+
+/// Rounds half away from zero like `f64::round`, which needs std, saturating
+/// like an `as` cast.
+fn round(value: f64) -> i64 {
+    let int = value as i64;
+    let frac = value - int as f64;
+
+    if frac >= 0.5 {
+        int.saturating_add(1)
+    } else if frac <= -0.5 {
+        int.saturating_sub(1)
+    } else {
+        int
+    }
+}
+
+impl<const SCALE: u32> Add for FixedPoint<SCALE> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        FixedPoint(self.0 + rhs.0)
+    }
+}
+
+impl<const SCALE: u32> Sub for FixedPoint<SCALE> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        FixedPoint(self.0 - rhs.0)
+    }
+}
+
+impl<const SCALE: u32> Neg for FixedPoint<SCALE> {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        FixedPoint(-self.0)
+    }
+}
+
+impl<const SCALE: u32> Display for FixedPoint<SCALE> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let one = Self::ONE as u64;
+
+        if SCALE == 0 {
+            write!(f, "{}{}", sign, abs)
+        } else {
+            write!(
+                f,
+                "{}{}.{:0width$}",
+                sign,
+                abs / one,
+                abs % one,
+                width = SCALE as usize
+            )
+        }
+    }
+}
+
+impl<'de, const SCALE: u32> BinDeserialize<'de> for FixedPoint<SCALE> {
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        Ok(FixedPoint(i64::deserialize(deserializer)?))
+    }
+}
+
+impl<const SCALE: u32> BinSerialize for FixedPoint<SCALE> {
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        self.0.serialize(serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::I64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::fixed::FixedPoint;
+    use crate::{deserialize_with, serialize_with, Mode};
+
+    #[test]
+    fn round_trip() {
+        type Money = FixedPoint<2>;
+        type Coord = FixedPoint<7>;
+
+        let values = [
+            Money::from_raw(0),
+            Money::from_raw(1),
+            Money::from_raw(-1),
+            Money::from_int(1_000_000).unwrap(),
+            Money::from_raw(i64::MAX),
+            Money::from_raw(i64::MIN),
+        ];
+
+        for mode in [
+            Mode::default(),
+            Mode::default().with_fixed_size_use_varint(true),
+        ] {
+            for value in values {
+                let vec = serialize_with(&value, mode).unwrap();
+                assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+            }
+        }
+
+        let lat = Coord::from_f64(52.5200066);
+        assert_eq!(525200066, lat.raw());
+        let vec = serialize_with(&lat, Mode::default()).unwrap();
+        assert_eq!(lat, deserialize_with(&vec, Mode::default()).unwrap());
+        assert_eq!(52.5200066, lat.to_f64());
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = FixedPoint::<2>::from_raw(1050);
+        let b = FixedPoint::<2>::from_raw(-325);
+
+        assert_eq!("10.50", a.to_string());
+        assert_eq!("-3.25", b.to_string());
+        assert_eq!("7.25", (a + b).to_string());
+        assert_eq!("13.75", (a - b).to_string());
+        assert_eq!("3.25", (-b).to_string());
+        assert_eq!("5", FixedPoint::<0>::from_raw(5).to_string());
+        assert_eq!(None, FixedPoint::<18>::from_int(10));
+    }
+}
@@ -0,0 +1,64 @@
+//! Strings stored in a fixed number of bytes.
+//!
+//! Struct fields of type `String` or `&str` marked
+//! `#[binserde(fixed_len = n)]` are written as exactly `n` bytes with no
+//! length prefix, which is useful for columnar formats with fixed-width
+//! columns. Shorter strings are padded with zero bytes, which are stripped
+//! again when reading, and longer strings fail to serialize. Fixed-length
+//! strings are never deduplicated.
+//!
+//! ```
+//! use binserde_derive::{BinDeserialize, BinSerialize};
+//!
+//! #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+//! struct Row {
+//!     #[binserde(fixed_len = 4)]
+//!     code: String,
+//!     count: u8,
+//! }
+//!
+//! let row = Row { code: "AB".to_string(), count: 3 };
+//! let vec = binserde::serialize(&row).unwrap();
+//!
+//! assert_eq!(b"AB\0\0\x03", &*vec);
+//! assert_eq!(row, binserde::deserialize(&vec).unwrap());
+//! ```
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+
+use crate::io::{Read, Write};
+use crate::{BinDeserializer, BinSerializer, Error, Result};
+
+/// Writes `s` padded to `len` bytes. Used by the derive macro.
+pub fn write<S, T>(s: &T, len: usize, mut serializer: S) -> Result<()>
+where
+    S: BinSerializer,
+    T: AsRef<str> + ?Sized,
+{
+    let s = s.as_ref();
+
+    if s.len() > len {
+        return Err(Error::custom(format!(
+            "string of length {} doesn't fit in {} bytes",
+            s.len(),
+            len
+        )));
+    }
+
+    let pipe = serializer.pipe();
+    pipe.write_all(s.as_bytes())?;
+    pipe.write_all(&vec![0; len - s.len()])?;
+    Ok(())
+}
+
+/// Reads a string written by [`write()`]. Used by the derive macro.
+pub fn read<'de, D: BinDeserializer<'de>>(len: usize, mut deserializer: D) -> Result<String> {
+    let mut buf = vec![0; len];
+    deserializer.pipe().read_exact(&mut buf)?;
+
+    let end = buf.iter().rposition(|&b| b != 0).map_or(0, |pos| pos + 1);
+    buf.truncate(end);
+    Ok(String::from_utf8(buf)?)
+}
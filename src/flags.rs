@@ -0,0 +1,247 @@
+//! Sets of flags stored as the bits of a `u32`.
+//!
+//! [`BitFlags<E>`] holds any combination of the values of a flag type `E`,
+//! usually a fieldless enum with one bit set in each discriminant, and is
+//! serialized as the bitwise OR of the flags it contains, like the `u32`
+//! flags in a C struct. Bits that don't correspond to a known flag are
+//! preserved.
+//!
+//! ```
+//! use binserde::flags::BitFlags;
+//!
+//! #[derive(Debug, Clone, Copy)]
+//! #[repr(u32)]
+//! enum Perm {
+//!     Read = 0b001,
+//!     Write = 0b010,
+//!     Exec = 0b100,
+//! }
+//!
+//! impl From<Perm> for u32 {
+//!     fn from(perm: Perm) -> u32 {
+//!         perm as u32
+//!     }
+//! }
+//!
+//! let perms = BitFlags::from(Perm::Read) | Perm::Exec;
+//! let vec = binserde::serialize(&perms).unwrap();
+//!
+//! assert_eq!(&[0b101, 0, 0, 0], &*vec);
+//!
+//! let perms: BitFlags<Perm> = binserde::deserialize(&vec).unwrap();
+//!
+//! assert!(perms.contains(Perm::Exec));
+//! assert!(!perms.contains(Perm::Write));
+//! ```
+
+use core::fmt;
+use core::fmt::{Debug, Formatter};
+use core::iter::FromIterator;
+use core::marker::PhantomData;
+use core::ops::{BitOr, BitOrAssign};
+
+use crate::schema::Schema;
+use crate::{BinDeserialize, BinDeserializer, BinSerialize, BinSerializer, Result};
+
+pub struct BitFlags<E> {
+    bits: u32,
+    marker: PhantomData<E>,
+}
+
+impl<E> BitFlags<E> {
+    pub const fn empty() -> Self {
+        BitFlags::from_bits(0)
+    }
+
+    /// Creates a set from its raw bits, including ones that don't belong to
+    /// any flag.
+    pub const fn from_bits(bits: u32) -> Self {
+        BitFlags {
+            bits,
+            marker: PhantomData,
+        }
+    }
+
+    pub const fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+}
+
+impl<E> BitFlags<E>
+where
+    E: Into<u32>,
+{
+    pub fn insert(&mut self, flag: E) {
+        self.bits |= flag.into();
+    }
+
+    pub fn remove(&mut self, flag: E) {
+        self.bits &= !flag.into();
+    }
+
+    /// Returns whether all bits of `flag` are set.
+    pub fn contains(&self, flag: E) -> bool {
+        let bits = flag.into();
+        self.bits & bits == bits
+    }
+}
+
+// implemented manually so that these don't require E to implement them
+
+impl<E> Clone for BitFlags<E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<E> Copy for BitFlags<E> {}
+
+impl<E> PartialEq for BitFlags<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits == other.bits
+    }
+}
+
+impl<E> Eq for BitFlags<E> {}
+
+impl<E> Default for BitFlags<E> {
+    fn default() -> Self {
+        BitFlags::empty()
+    }
+}
+
+impl<E> Debug for BitFlags<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "BitFlags({:#b})", self.bits)
+    }
+}
+
+impl<E> From<E> for BitFlags<E>
+where
+    E: Into<u32>,
+{
+    fn from(flag: E) -> Self {
+        BitFlags::from_bits(flag.into())
+    }
+}
+
+impl<E> BitOr<E> for BitFlags<E>
+where
+    E: Into<u32>,
+{
+    type Output = Self;
+
+    fn bitor(mut self, rhs: E) -> Self::Output {
+        self.insert(rhs);
+        self
+    }
+}
+
+impl<E> BitOr for BitFlags<E> {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        BitFlags::from_bits(self.bits | rhs.bits)
+    }
+}
+
+impl<E> BitOrAssign<E> for BitFlags<E>
+where
+    E: Into<u32>,
+{
+    fn bitor_assign(&mut self, rhs: E) {
+        self.insert(rhs);
+    }
+}
+
+impl<E> FromIterator<E> for BitFlags<E>
+where
+    E: Into<u32>,
+{
+    fn from_iter<T: IntoIterator<Item = E>>(iter: T) -> Self {
+        let mut flags = BitFlags::empty();
+        iter.into_iter().for_each(|el| flags.insert(el));
+        flags
+    }
+}
+
+impl<'de, E> BinDeserialize<'de> for BitFlags<E> {
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        Ok(BitFlags::from_bits(u32::deserialize(deserializer)?))
+    }
+}
+
+impl<E> BinSerialize for BitFlags<E> {
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        self.bits.serialize(serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::U32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flags::BitFlags;
+    use crate::{deserialize_with, serialize_with, Mode};
+
+    #[derive(Debug, Clone, Copy)]
+    #[repr(u32)]
+    enum Flag {
+        A = 1 << 0,
+        B = 1 << 1,
+        C = 1 << 31,
+    }
+
+    impl From<Flag> for u32 {
+        fn from(flag: Flag) -> u32 {
+            flag as u32
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let values = [
+            BitFlags::empty(),
+            BitFlags::from(Flag::A),
+            BitFlags::from(Flag::C),
+            BitFlags::from(Flag::A) | Flag::B | Flag::C,
+            vec![Flag::B, Flag::C].into_iter().collect(),
+            BitFlags::from_bits(1 << 7),
+        ];
+
+        for mode in [
+            Mode::default(),
+            Mode::default().with_fixed_size_use_varint(true),
+        ] {
+            for value in values {
+                let vec = serialize_with(&value, mode).unwrap();
+                assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+            }
+        }
+
+        let vec = serialize_with(&values[3], Mode::default()).unwrap();
+        assert_eq!(&[0b11, 0, 0, 0x80], &*vec);
+    }
+
+    #[test]
+    fn operations() {
+        let mut flags = BitFlags::from(Flag::A) | Flag::C;
+
+        assert!(flags.contains(Flag::A));
+        assert!(!flags.contains(Flag::B));
+        assert!(flags.contains(Flag::C));
+
+        flags.remove(Flag::A);
+        flags |= Flag::B;
+
+        assert_eq!(BitFlags::from(Flag::B) | BitFlags::from(Flag::C), flags);
+        assert_eq!(0x8000_0002, flags.bits());
+        assert!(BitFlags::<Flag>::default().is_empty());
+    }
+}
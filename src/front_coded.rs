@@ -0,0 +1,179 @@
+//! Front coding for maps with sorted string keys.
+//!
+//! Consecutive keys of a [`BTreeMap`] often share a prefix, for example paths
+//! or hierarchical identifiers. Wrapping the map in [`FrontCoded`] writes
+//! every key as the length of the prefix it shares with the previous key,
+//! followed by the rest of it. Keys are never deduplicated, values are
+//! written as usual.
+//!
+//! ```
+//! use std::collections::BTreeMap;
+//!
+//! use binserde::front_coded::FrontCoded;
+//!
+//! let mut map = BTreeMap::new();
+//! map.insert("assets/textures/stone.png".to_string(), 1u8);
+//! map.insert("assets/textures/stone_bricks.png".to_string(), 2u8);
+//!
+//! let vec = binserde::serialize(&FrontCoded(map.clone())).unwrap();
+//! assert_eq!(1 + (2 + 25 + 1) + (2 + 11 + 1), vec.len());
+//!
+//! let out: FrontCoded<BTreeMap<String, u8>> = binserde::deserialize(&vec).unwrap();
+//! assert_eq!(map, out.0);
+//! ```
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+
+use crate::io::{Read, Write};
+use crate::util::{read_elements_len, read_len};
+use crate::{BinDeserialize, BinDeserializer, BinSerialize, BinSerializer, Error, Result};
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct FrontCoded<T>(pub T);
+
+impl<V> BinSerialize for FrontCoded<BTreeMap<String, V>>
+where
+    V: BinSerialize,
+{
+    fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
+        self.0.len().serialize(&mut serializer)?;
+        let mut prev = "";
+
+        for (key, value) in self.0.iter() {
+            let shared = shared_prefix(prev, key);
+            let suffix = &key.as_bytes()[shared..];
+
+            shared.serialize(&mut serializer)?;
+            suffix.len().serialize(&mut serializer)?;
+            serializer.pipe().write_all(suffix)?;
+            value.serialize(&mut serializer)?;
+
+            prev = key;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'de, V> BinDeserialize<'de> for FrontCoded<BTreeMap<String, V>>
+where
+    V: BinDeserialize<'de>,
+{
+    fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
+        let len = read_elements_len(&mut deserializer)?;
+        let mut map = BTreeMap::new();
+        let mut prev = String::new();
+
+        for _ in 0..len {
+            let shared = usize::deserialize(&mut deserializer)?;
+
+            if !prev.is_char_boundary(shared) {
+                return Err(Error::custom(format!(
+                    "shared prefix length {} is invalid for previous key of length {}",
+                    shared,
+                    prev.len()
+                )));
+            }
+
+            let suffix_len = read_len(&mut deserializer)?;
+            let mut key = prev.as_bytes()[..shared].to_vec();
+            let got = deserializer
+                .pipe()
+                .take(suffix_len as u64)
+                .read_to_end(&mut key)?;
+
+            if got != suffix_len {
+                return Err(Error::UnexpectedEof {
+                    expected: suffix_len,
+                    got,
+                });
+            }
+
+            let key = String::from_utf8(key)?;
+            let value = V::deserialize(&mut deserializer)?;
+            map.insert(key.clone(), value);
+            prev = key;
+        }
+
+        Ok(FrontCoded(map))
+    }
+}
+
+/// Returns the length in bytes of the longest common prefix of `a` and `b`
+/// which ends at a character boundary.
+fn shared_prefix(a: &str, b: &str) -> usize {
+    let mut len = a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count();
+
+    while !b.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    len
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use crate::front_coded::FrontCoded;
+    use crate::{deserialize_with, serialize_with, Mode};
+
+    #[test]
+    fn round_trip() {
+        let keys = [
+            "",
+            "a",
+            "ab",
+            "abc",
+            "b",
+            "com.example.app.main",
+            "com.example.app.main.window",
+            "com.example.lib",
+            "héllo",
+            "hêllo",
+            "hëllo wörld",
+        ];
+        let map: BTreeMap<String, u32> = keys
+            .iter()
+            .enumerate()
+            .map(|(idx, el)| (el.to_string(), idx as u32))
+            .collect();
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let vec = serialize_with(&FrontCoded(map.clone()), mode).unwrap();
+            let out: FrontCoded<BTreeMap<String, u32>> = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(map, out.0);
+        }
+
+        let empty = FrontCoded(BTreeMap::<String, u32>::new());
+        let vec = serialize_with(&empty, Mode::default()).unwrap();
+        assert_eq!(empty, deserialize_with(&vec, Mode::default()).unwrap());
+    }
+
+    #[test]
+    fn smaller_with_shared_prefixes() {
+        let map: BTreeMap<String, u8> = (0..1000)
+            .map(|idx| (format!("/usr/share/icons/hicolor/{:04}.png", idx), 0))
+            .collect();
+
+        let entries: Vec<_> = map.clone().into_iter().collect();
+        let plain = serialize_with(&entries, Mode::default()).unwrap();
+        let coded = serialize_with(&FrontCoded(map.clone()), Mode::default()).unwrap();
+        assert!(coded.len() * 4 < plain.len());
+
+        let out: FrontCoded<BTreeMap<String, u8>> =
+            deserialize_with(&coded, Mode::default()).unwrap();
+        assert_eq!(map, out.0);
+    }
+
+    #[test]
+    fn invalid_prefix() {
+        // the first key can't share a prefix with anything
+        let vec = [1, 1, 1, b'a', 0];
+        assert!(
+            deserialize_with::<FrontCoded<BTreeMap<String, u8>>>(&vec, Mode::default()).is_err()
+        );
+    }
+}
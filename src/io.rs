@@ -0,0 +1,413 @@
+//! The I/O traits values are read from and written to.
+//!
+//! With the `std` feature, which is enabled by default, these are the ones
+//! from [`std::io`], so any reader or writer from the standard library can be
+//! used. Without it, this module provides a minimal replacement for the parts
+//! of that API binserde needs. The [`Read`] trait is implemented for `&[u8]`,
+//! and [`Write`] for `Vec<u8>` and `&mut [u8]`, which is enough to serialize
+//! into a buffer and read it back:
+//!
+//! ```
+//! let mut buf = [0; 16];
+//! let mut out = &mut buf[..];
+//! binserde::serialize_into(&mut out, &vec![1u32, 2, 3]).unwrap();
+//! let len = 16 - out.len();
+//!
+//! let value: Vec<u32> = binserde::deserialize(&buf[..len]).unwrap();
+//! assert_eq!(vec![1, 2, 3], value);
+//! ```
+//!
+//! [`ReadBytesExt`] and [`WriteBytesExt`] read and write integers with a given
+//! byte order, like the traits of the same name from the `byteorder` crate,
+//! which are used with the `std` feature.
+
+#[cfg(feature = "std")]
+pub use byteorder::{ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Read, Result, Take, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::imp::*;
+
+#[cfg(not(feature = "std"))]
+mod imp {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use core::{cmp, fmt, mem};
+
+    use byteorder::ByteOrder;
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// The general category of an [`Error`], like [`std::io::ErrorKind`].
+    #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        UnexpectedEof,
+        WriteZero,
+        Interrupted,
+        InvalidData,
+        Other,
+    }
+
+    impl ErrorKind {
+        fn as_str(self) -> &'static str {
+            match self {
+                ErrorKind::UnexpectedEof => "unexpected end of file",
+                ErrorKind::WriteZero => "write zero",
+                ErrorKind::Interrupted => "operation interrupted",
+                ErrorKind::InvalidData => "invalid data",
+                ErrorKind::Other => "other error",
+            }
+        }
+    }
+
+    /// An error returned by a reader or writer, like [`std::io::Error`].
+    pub struct Error {
+        kind: ErrorKind,
+        error: Option<Box<dyn core::error::Error + Send + Sync>>,
+    }
+
+    impl Error {
+        pub fn new<E>(kind: ErrorKind, error: E) -> Self
+        where
+            E: Into<Box<dyn core::error::Error + Send + Sync>>,
+        {
+            Error {
+                kind,
+                error: Some(error.into()),
+            }
+        }
+
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+
+        pub fn get_ref(&self) -> Option<&(dyn core::error::Error + Send + Sync + 'static)> {
+            self.error.as_deref()
+        }
+
+        pub fn into_inner(self) -> Option<Box<dyn core::error::Error + Send + Sync>> {
+            self.error
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            Error { kind, error: None }
+        }
+    }
+
+    impl fmt::Debug for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Error")
+                .field("kind", &self.kind)
+                .field("error", &self.error)
+                .finish()
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match &self.error {
+                None => f.write_str(self.kind.as_str()),
+                Some(error) => error.fmt(f),
+            }
+        }
+    }
+
+    impl core::error::Error for Error {
+        fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+            match &self.error {
+                None => None,
+                Some(error) => error.source(),
+            }
+        }
+    }
+
+    /// A source of bytes, like [`std::io::Read`].
+    pub trait Read {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.read(buf) {
+                    Ok(0) => {
+                        return Err(Error::new(
+                            ErrorKind::UnexpectedEof,
+                            "failed to fill whole buffer",
+                        ))
+                    }
+                    Ok(n) => buf = &mut buf[n..],
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            Ok(())
+        }
+
+        fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+            let start = buf.len();
+            let mut chunk = [0; 64];
+
+            loop {
+                match self.read(&mut chunk) {
+                    Ok(0) => return Ok(buf.len() - start),
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        fn by_ref(&mut self) -> &mut Self
+        where
+            Self: Sized,
+        {
+            self
+        }
+
+        fn take(self, limit: u64) -> Take<Self>
+        where
+            Self: Sized,
+        {
+            Take { inner: self, limit }
+        }
+    }
+
+    /// A sink for bytes, like [`std::io::Write`].
+    pub trait Write {
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+        fn flush(&mut self) -> Result<()>;
+
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf) {
+                    Ok(0) => {
+                        return Err(Error::new(
+                            ErrorKind::WriteZero,
+                            "failed to write whole buffer",
+                        ))
+                    }
+                    Ok(n) => buf = &buf[n..],
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            Ok(())
+        }
+
+        fn by_ref(&mut self) -> &mut Self
+        where
+            Self: Sized,
+        {
+            self
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+
+        fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+            (**self).read_exact(buf)
+        }
+
+        fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+            (**self).read_to_end(buf)
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for Box<R> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let len = cmp::min(buf.len(), self.len());
+            let (a, b) = self.split_at(len);
+            buf[..len].copy_from_slice(a);
+            *self = b;
+            Ok(len)
+        }
+
+        fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+            let len = self.len();
+            buf.extend_from_slice(self);
+            *self = &[];
+            Ok(len)
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+
+        fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+            (**self).write_all(buf)
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for Box<W> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // Like the implementation in std, this advances the slice past the
+    // written bytes, so that the number of bytes written is the difference
+    // in length.
+    impl Write for &mut [u8] {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let len = cmp::min(buf.len(), self.len());
+            let (a, b) = mem::take(self).split_at_mut(len);
+            a.copy_from_slice(&buf[..len]);
+            *self = b;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A reader which reads at most a limited number of bytes from another
+    /// one, like [`std::io::Take`].
+    #[derive(Debug)]
+    pub struct Take<R> {
+        inner: R,
+        limit: u64,
+    }
+
+    impl<R> Take<R> {
+        pub fn limit(&self) -> u64 {
+            self.limit
+        }
+
+        pub fn set_limit(&mut self, limit: u64) {
+            self.limit = limit;
+        }
+
+        pub fn get_ref(&self) -> &R {
+            &self.inner
+        }
+
+        pub fn get_mut(&mut self) -> &mut R {
+            &mut self.inner
+        }
+
+        pub fn into_inner(self) -> R {
+            self.inner
+        }
+    }
+
+    impl<R: Read> Read for Take<R> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            if self.limit == 0 {
+                return Ok(0);
+            }
+
+            let max = cmp::min(buf.len() as u64, self.limit) as usize;
+            let len = self.inner.read(&mut buf[..max])?;
+            self.limit -= len as u64;
+            Ok(len)
+        }
+    }
+
+    macro_rules! read_int {
+        ($($name:ident: $ty:ty, $len:expr;)*) => {
+            $(
+                fn $name<T: ByteOrder>(&mut self) -> Result<$ty> {
+                    let mut buf = [0; $len];
+                    self.read_exact(&mut buf)?;
+                    Ok(T::$name(&buf))
+                }
+            )*
+        };
+    }
+
+    macro_rules! write_int {
+        ($($name:ident: $ty:ty, $len:expr;)*) => {
+            $(
+                fn $name<T: ByteOrder>(&mut self, n: $ty) -> Result<()> {
+                    let mut buf = [0; $len];
+                    T::$name(&mut buf, n);
+                    self.write_all(&buf)
+                }
+            )*
+        };
+    }
+
+    /// Reads integers with a given byte order, like `byteorder::ReadBytesExt`.
+    pub trait ReadBytesExt: Read {
+        fn read_u8(&mut self) -> Result<u8> {
+            let mut buf = [0; 1];
+            self.read_exact(&mut buf)?;
+            Ok(buf[0])
+        }
+
+        fn read_i8(&mut self) -> Result<i8> {
+            Ok(self.read_u8()? as i8)
+        }
+
+        read_int! {
+            read_u16: u16, 2;
+            read_i16: i16, 2;
+            read_u32: u32, 4;
+            read_i32: i32, 4;
+            read_u64: u64, 8;
+            read_i64: i64, 8;
+        }
+    }
+
+    impl<R: Read + ?Sized> ReadBytesExt for R {}
+
+    /// Writes integers with a given byte order, like
+    /// `byteorder::WriteBytesExt`.
+    pub trait WriteBytesExt: Write {
+        fn write_u8(&mut self, n: u8) -> Result<()> {
+            self.write_all(&[n])
+        }
+
+        fn write_i8(&mut self, n: i8) -> Result<()> {
+            self.write_u8(n as u8)
+        }
+
+        write_int! {
+            write_u16: u16, 2;
+            write_i16: i16, 2;
+            write_u32: u32, 4;
+            write_i32: i32, 4;
+            write_u64: u64, 8;
+            write_i64: i64, 8;
+        }
+    }
+
+    impl<W: Write + ?Sized> WriteBytesExt for W {}
+}
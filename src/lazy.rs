@@ -0,0 +1,239 @@
+//! Fields that are only decoded when they are first accessed.
+//!
+//! A [`Lazy<T>`] is written as the serialized `T` prefixed with its length.
+//! When deserializing, only the bytes are read and kept, and `T` is decoded
+//! from them on the first call to [`Lazy::get`]. This is useful for large
+//! values that often aren't needed. The value is serialized without
+//! deduplication so that it can be decoded on its own later.
+//!
+//! ```
+//! use binserde::lazy::Lazy;
+//!
+//! let value = (1u32, Lazy::new(vec!["large".to_string(); 100]));
+//! let vec = binserde::serialize(&value).unwrap();
+//!
+//! let (id, blob): (u32, Lazy<Vec<String>>) = binserde::deserialize(&vec).unwrap();
+//! assert_eq!(1, id);
+//! assert!(!blob.is_decoded());
+//! assert_eq!(100, blob.get().unwrap().len());
+//! ```
+
+use alloc::format;
+use alloc::vec::Vec;
+use core::cell::OnceCell;
+
+use crate::de::BinDeserializerBase;
+use crate::dedup::DedupContext;
+use crate::io::{Read, Write};
+use crate::ser::BinSerializerBase;
+use crate::util::read_len;
+use crate::{
+    BinDeserialize, BinDeserializeOwned, BinDeserializer, BinSerialize, BinSerializer, Error, Mode,
+    Result,
+};
+
+#[derive(Debug, Clone)]
+pub struct Lazy<T> {
+    value: OnceCell<T>,
+    /// The serialized value and the mode it was written with, if this was
+    /// deserialized.
+    raw: Option<(Vec<u8>, Mode)>,
+}
+
+impl<T> Lazy<T> {
+    pub fn new(value: T) -> Self {
+        Lazy {
+            value: OnceCell::from(value),
+            raw: None,
+        }
+    }
+
+    /// Returns whether the value has been decoded yet.
+    pub fn is_decoded(&self) -> bool {
+        self.value.get().is_some()
+    }
+
+    /// Returns the serialized value, if this was deserialized.
+    pub fn raw_bytes(&self) -> Option<&[u8]> {
+        self.raw.as_ref().map(|(bytes, _)| &**bytes)
+    }
+}
+
+impl<T> Lazy<T>
+where
+    T: BinDeserializeOwned,
+{
+    /// Returns the value, decoding it if this is the first access.
+    pub fn get(&self) -> Result<&T> {
+        if let Some(value) = self.value.get() {
+            return Ok(value);
+        }
+
+        let value = self.decode()?;
+        Ok(self.value.get_or_init(|| value))
+    }
+
+    pub fn into_inner(mut self) -> Result<T> {
+        match self.value.take() {
+            Some(value) => Ok(value),
+            None => self.decode(),
+        }
+    }
+
+    fn decode(&self) -> Result<T> {
+        let (bytes, mode) = self.raw.as_ref().expect("lazy value without bytes");
+        let mut input = &**bytes;
+        let dedup = DedupContext::new();
+        let value = T::deserialize(BinDeserializerBase::new(&mut input, &dedup).with_mode(*mode))?;
+
+        if !input.is_empty() {
+            return Err(Error::custom(format!(
+                "lazy value has {} bytes left over",
+                input.len()
+            )));
+        }
+
+        Ok(value)
+    }
+}
+
+fn value_mode(mode: Mode) -> Mode {
+    Mode {
+        use_dedup: false,
+        ..mode
+    }
+}
+
+impl<T> BinSerialize for Lazy<T>
+where
+    T: BinSerialize,
+{
+    fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
+        let mode = value_mode(serializer.mode());
+
+        let bytes = match (self.value.get(), &self.raw) {
+            // write back the original bytes if nothing would change
+            (None, Some((bytes, raw_mode))) if *raw_mode == mode => bytes.clone(),
+            (Some(value), _) => {
+                let mut buf = Vec::new();
+                value.serialize(BinSerializerBase::new(&mut buf).with_mode(mode))?;
+                buf
+            }
+            (None, _) => {
+                return Err(Error::custom(
+                    "can't serialize lazy value in a different mode without decoding it first",
+                ))
+            }
+        };
+
+        bytes.len().serialize(&mut serializer)?;
+        serializer.pipe().write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+impl<'de, T> BinDeserialize<'de> for Lazy<T> {
+    fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
+        let mode = value_mode(deserializer.mode());
+        let len = read_len(&mut deserializer)?;
+        let mut bytes = Vec::new();
+        let got = deserializer
+            .pipe()
+            .take(len as u64)
+            .read_to_end(&mut bytes)?;
+
+        if got != len {
+            return Err(Error::UnexpectedEof { expected: len, got });
+        }
+
+        Ok(Lazy {
+            value: OnceCell::new(),
+            raw: Some((bytes, mode)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use binserde_derive::{BinDeserialize, BinSerialize};
+
+    use crate::lazy::Lazy;
+    use crate::{deserialize_with, serialize_with, BinDeserializer, BinSerializer, Mode, Result};
+
+    thread_local! {
+        static DECODED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[derive(Debug, Eq, PartialEq)]
+    struct Blob(Vec<u8>);
+
+    impl crate::BinSerialize for Blob {
+        fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+            crate::BinSerialize::serialize(&self.0, serializer)
+        }
+    }
+
+    impl<'de> crate::BinDeserialize<'de> for Blob {
+        fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+            DECODED.with(|v| v.set(v.get() + 1));
+            Ok(Blob(crate::BinDeserialize::deserialize(deserializer)?))
+        }
+    }
+
+    #[derive(BinSerialize, BinDeserialize)]
+    struct Asset {
+        name: String,
+        data: Lazy<Blob>,
+        tag: String,
+    }
+
+    #[test]
+    fn decoded_on_access() {
+        let asset = Asset {
+            name: "icon".to_string(),
+            data: Lazy::new(Blob(vec![7; 1000])),
+            tag: "icon".to_string(),
+        };
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            DECODED.with(|v| v.set(0));
+
+            let vec = serialize_with(&asset, mode).unwrap();
+            let out: Asset = deserialize_with(&vec, mode).unwrap();
+
+            assert_eq!("icon", out.name);
+            assert_eq!("icon", out.tag);
+            assert!(!out.data.is_decoded());
+            assert_eq!(0, DECODED.with(|v| v.get()));
+
+            // writing it back doesn't decode it either
+            let again = serialize_with(&out, mode).unwrap();
+            assert_eq!(vec, again);
+            assert_eq!(0, DECODED.with(|v| v.get()));
+
+            assert_eq!(&Blob(vec![7; 1000]), out.data.get().unwrap());
+            assert_eq!(&Blob(vec![7; 1000]), out.data.get().unwrap());
+            assert!(out.data.is_decoded());
+            assert_eq!(1, DECODED.with(|v| v.get()));
+
+            assert_eq!(Blob(vec![7; 1000]), out.data.into_inner().unwrap());
+        }
+    }
+
+    #[test]
+    fn different_mode() {
+        let value = Lazy::new(vec![1u32, 2, 3]);
+        let vec = serialize_with(&value, Mode::default()).unwrap();
+        let out: Lazy<Vec<u32>> = deserialize_with(&vec, Mode::default()).unwrap();
+
+        let varint = Mode::default().with_fixed_size_use_varint(true);
+        assert!(serialize_with(&out, varint).is_err());
+
+        out.get().unwrap();
+        let vec = serialize_with(&out, varint).unwrap();
+        let out: Lazy<Vec<u32>> = deserialize_with(&vec, varint).unwrap();
+        assert_eq!(&vec![1, 2, 3], out.get().unwrap());
+    }
+}
@@ -1,16 +1,12 @@
 #![allow(incomplete_features)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 //! # binserde
 //!
 //! A crate similar to serde, but specialized for serializing into a compact
 //! binary format, including features like string deduplication.
 //!
-//! *This crate is very WIP.* Features currently not implemented but planned
-//! include incremental versioning support so that old formats can still be
-//! loaded when the data format changes, deduplication of arbitrary data
-//! structures, and explicit tagging (writing a struct or enum as a set of
-//! key/value pairs instead of serializing the items in order of declaration,
-//! for higher resistance to format changes at the expense of output size)
+//! *This crate is very WIP.*
 //!
 //! ## Usage
 //!
@@ -37,6 +33,24 @@
 //! assert_eq!(my_data, copy_of_my_data);
 //! ```
 //!
+//! ## `no_std`
+//!
+//! The `std` feature is enabled by default. Without it, the crate only needs
+//! `core` and `alloc`, and reads and writes through the minimal I/O traits in
+//! `binserde::io`. `HashMap`, `HashSet`, paths, `SystemTime`, serializer
+//! profiles and `Mode::with_time_epoch` are only available with `std`.
+//!
+//! ```toml
+//! binserde = { version = "0.1", default-features = false }
+//! ```
+//!
+//! ## Compression
+//!
+//! With the `compression` feature, `serialize_compressed` and
+//! `deserialize_compressed` compress the whole output, including the dedup
+//! table, as a single zlib stream. This helps with data that stays repetitive
+//! after deduplication.
+//!
 //! ## Macro Attributes
 //!
 //! `#[derive(BinSerialize)]` and `#[derive(BinDeserialize)]` allows using
@@ -49,6 +63,44 @@
 //! Skips the field when serializing. When deserializing, uses
 //! [`Default::default()`] instead of reading from the stream to fill the field.
 //!
+//! ### `#[binserde(skip_if = "path")]`
+//!
+//! Valid for: fields
+//!
+//! Calls the function `path` with a reference to the field before serializing it,
+//! and skips the field if it returns `true`. A bool is written in front of the
+//! field to mark whether it is present, and an absent field is set to its default
+//! value when deserializing. Fields marked `skip_if` can't be marked `skip`, `bits`
+//! or `rest`.
+//!
+//! ### `#[binserde(default = "path")]`
+//!
+//! Valid for: fields
+//!
+//! Calls the function `path` instead of [`Default::default()`] to create the
+//! value of the field whenever it isn't read from the stream: for fields marked
+//! `skip`, fields missing from a `tagged` or `count_fields` type, fields added
+//! in a newer version than the data with `since`, and fields left out with
+//! `skip_if`.
+//!
+//! ### `#[binserde(include = "field_a, field_b")]`
+//!
+//! Valid for: structs
+//!
+//! Serializes only the fields named in the comma-separated list, as if every
+//! other field was marked `skip`, so those are set to their default value when
+//! deserializing. Naming a field which doesn't exist is a compile error.
+//!
+//! ```compile_fail
+//! # use binserde::{BinDeserialize, BinSerialize};
+//! #[derive(BinSerialize, BinDeserialize)]
+//! #[binserde(include = "id, nmae")]
+//! struct Entry {
+//!     id: u32,
+//!     name: String,
+//! }
+//! ```
+//!
 //! ### `#[binserde(no_dedup)]`
 //!
 //! Valid for: fields
@@ -56,11 +108,246 @@
 //! Turns off deduplication for this field. See [Deduplication] for more
 //! information about how it works.
 //!
-//! ### `#[binserde(index = n)]`
+//! ### `#[binserde(skip_dedup_scan)]`
+//!
+//! Valid for: fields
+//!
+//! Like `no_dedup`, but additionally skips the field entirely while collecting
+//! the strings to deduplicate before serializing (see [`prescan`]), instead of
+//! visiting it only to find nothing. Use this for large fields known to contain
+//! no strings, such as opaque binary data. If the field does contain strings
+//! anyway, they are written in full where they occur, so the output is still
+//! valid.
+//!
+//! ### `#[binserde(soa)]`
+//!
+//! Valid for: structs
+//!
+//! Additionally implements the traits needed to serialize a `Vec` of the struct
+//! column by column using [`soa::Soa`]. Each column is written using the field
+//! type's own implementation, so fields can't have attributes that change how
+//! they are serialized, such as `with`, `bits` or `fixed_len`, and the struct
+//! can't be marked `pack_bools`.
+//!
+//! ```compile_fail
+//! # use binserde::{BinDeserialize, BinSerialize};
+//! #[derive(BinSerialize, BinDeserialize)]
+//! #[binserde(soa)]
+//! struct Entry {
+//!     #[binserde(fixed_len = 4)]
+//!     name: String,
+//! }
+//! ```
+//!
+//! ### `#[binserde(tag_variants_by_hash)]`
+//!
+//! Valid for: enums
+//!
+//! Identifies variants by a 64-bit hash of their name instead of their index,
+//! so that adding, removing or reordering variants doesn't change how the
+//! other variants are encoded. Renaming a variant does change its encoding.
+//! Two variants whose names hash to the same value are a compile error.
+//!
+//! ```compile_fail
+//! # use binserde::{BinDeserialize, BinSerialize};
+//! #[derive(BinSerialize, BinDeserialize)]
+//! #[binserde(tag_variants_by_hash)]
+//! enum Colliding {
+//!     Va85cfb84eadef993,
+//!     Vf7a240fb8fc0a8ed,
+//! }
+//! ```
+//!
+//! ### `#[binserde(repr = "type")]`
+//!
+//! Valid for: enums
+//!
+//! Writes variant tags as `type`, which is one of `u8`, `u16`, `u32` or `u64`,
+//! instead of as a `usize`. They are encoded like any other value of that type,
+//! so a `u8` tag always takes up a single byte regardless of the mode. Every tag
+//! must fit in the type. This can't be combined with `tag_variants_by_hash`.
+//!
+//! ### `#[binserde(tag = n)]`
+//!
+//! Valid for: enum variants
+//!
+//! Identifies the variant by `n` instead of its index, so that variants can be
+//! inserted and reordered without changing how existing ones are encoded.
+//! Variants without a tag keep using their index, and no two variants may end
+//! up with the same tag. Reading a tag that doesn't belong to any variant fails
+//! with `Error::UnknownVariant`. This can't be combined with
+//! `tag_variants_by_hash`.
+//!
+//! ### `#[binserde(from_discriminant = "n => Variant, ...")]`
+//!
+//! Valid for: enums
+//!
+//! Additionally reads the variant `Variant` when encountering the tag `n`, so
+//! that data written before a variant's tag was changed can still be read. Values
+//! are always written with the variant's current tag. Each `n` must be a tag no
+//! other variant uses, and must fit in the type given with `repr`.
+//!
+//! ### `#[binserde(tagged)]`
+//!
+//! Valid for: structs, enums
+//!
+//! Writes the fields as a set of key/value pairs instead of in order of
+//! declaration, for higher resistance to format changes at the expense of output
+//! size. Each field is written as its name, which is deduplicated like other
+//! strings, followed by its length-prefixed value. When deserializing, fields
+//! are matched by name, unknown fields are skipped and missing fields are set to
+//! their default value, so fields can be added, removed and reordered. Fields
+//! can't be marked `bits` or `rest`, and all fields must implement `Default`.
+//!
+//! ### `#[binserde(count_fields)]`
+//!
+//! Valid for: structs, enums
+//!
+//! Writes the number of fields as a `u32` in front of them, followed by their
+//! total length, so that fields can be added to the end of the type without
+//! breaking compatibility. When deserializing, fields beyond those the type
+//! knows about are skipped, and fields missing from the input are set to their
+//! default value. This is cheaper than `tagged`, but fields can't be removed or
+//! reordered. Fields can't be marked `bits`, and all fields must implement
+//! `Default`.
+//!
+//! ### `#[binserde(version = n)]`, `#[binserde(since = k)]`
+//!
+//! Valid for: structs, enums (`version`); fields (`since`)
+//!
+//! Writes the version `n` of the type as a varint in front of it, so that data
+//! written by older versions of the type can still be loaded after adding
+//! fields to it. Fields added in version `k` are marked `since = k`, and are
+//! set to their default value when reading data of an older version. Data of a
+//! newer version than `n` fails to deserialize. Fields marked `since` can't be
+//! marked `bits` or `rest`.
+//!
+//! ### `#[binserde(bits = n)]`
+//!
+//! Valid for: fields
+//!
+//! Packs the field into `n` bits, together with the fields marked this way
+//! directly before and after it. See [`bits`] for more information.
+//!
+//! ### `#[binserde(pack_bools)]`
+//!
+//! Valid for: structs, enums
+//!
+//! Packs runs of consecutive `bool` fields into bits, as if each of them was
+//! marked `#[binserde(bits = 1)]`, so that eight flags take up a single byte.
+//! Each run is written as a little-endian integer of as many bytes as needed,
+//! with the first field in the lowest bit. Any field of another type ends the
+//! run, as do `bool` fields marked `skip`, `skip_if`, `since`, `rest`,
+//! `fixed_len` or with custom (de)serialization functions, which are written as
+//! usual. A run can't be longer than 64 fields. This can't be combined with
+//! `tagged` or `count_fields`.
+//!
+//! ### `#[binserde(with = "module")]`
+//!
+//! Valid for: fields
+//!
+//! Serializes the field using `module::serialize` and deserializes it using
+//! `module::deserialize` instead of the field type's implementations. These
+//! have the signatures
+//!
+//! ```ignore
+//! fn serialize<S: BinSerializer>(value: &T, serializer: S) -> Result<()>;
+//! fn deserialize<'de, D: BinDeserializer<'de>>(deserializer: D) -> Result<T>;
+//! ```
+//!
+//! where `T` is the type of the field, which then doesn't need to implement
+//! [`BinSerialize`] or [`BinDeserialize`] itself. This can't be combined with
+//! `skip`, `bits` or `fixed_len`.
+//!
+//! ```compile_fail
+//! # use binserde::{BinDeserialize, BinSerialize};
+//! mod as_u8 {
+//!     use binserde::{BinDeserialize, BinDeserializer, BinSerialize, BinSerializer, Result};
+//!
+//!     pub fn serialize<S: BinSerializer>(value: &u32, serializer: S) -> Result<()> {
+//!         (*value as u8).serialize(serializer)
+//!     }
+//!
+//!     pub fn deserialize<'de, D: BinDeserializer<'de>>(deserializer: D) -> Result<u32> {
+//!         u8::deserialize(deserializer).map(u32::from)
+//!     }
+//! }
+//!
+//! #[derive(BinSerialize, BinDeserialize)]
+//! struct Entry {
+//!     #[binserde(with = "as_u8", skip)]
+//!     id: u32,
+//! }
+//! ```
+//!
+//! ### `#[binserde(serialize_with = "path")]`, `#[binserde(deserialize_with = "path")]`
+//!
+//! Valid for: fields
+//!
+//! Like `with`, but only replaces one direction, using the function at `path`.
+//!
+//! ### `#[binserde(niche)]`
 //!
 //! Valid for: fields
 //!
-//! **not implemented**
+//! Writes an `Option` of a `NonZero` integer type as the plain integer, with 0
+//! standing for `None`, instead of writing a tag in front of it. This is the same
+//! as `with = "binserde::niche"`, see [`niche`] for more information.
+//!
+//! ### `#[binserde(len = "type")]`
+//!
+//! Valid for: fields
+//!
+//! Writes the number of elements of a sequence or map field as `type`, which is
+//! one of `u8`, `u16`, `u32` or `u64`, instead of as a `usize`, followed by each
+//! of its elements. Serializing fails if the number doesn't fit in `type`. Only
+//! this field's length is affected, lengths inside of its elements are written
+//! as usual. Since the elements are written one at a time, encodings for whole
+//! sequences such as packed bools or deduplicated byte blobs don't apply to the
+//! field. The field's type must be a collection that can be iterated and
+//! collected from its elements, such as `Vec`, `VecDeque`, `HashMap` or
+//! `BTreeSet`.
+//!
+//! ### `#[binserde(fixed_len = n)]`
+//!
+//! Valid for: `String` and `&str` fields
+//!
+//! Writes the string as exactly `n` bytes without a length prefix. See
+//! [`fixed_str`] for more information.
+//!
+//! ### `#[binserde(rest)]`
+//!
+//! Valid for: the last serialized field, of type `Vec<u8>` or similar
+//!
+//! Writes the bytes of the field without a length prefix, and reads everything
+//! up to the end of the input into it when deserializing. This only works if
+//! the end of the value is known, for example with [`Mode::with_prefix_total_len`]
+//! or when the value is the last thing in the input. Combined with
+//! [`Mode::with_trailing_crc`], reading the checksum fails since it was consumed by
+//! the field.
+//!
+//! ### `#[binserde(repr_width = n)]`
+//!
+//! Valid for: enums without fields
+//!
+//! Additionally implements [`bits::BitField`] for the enum, so that it can be
+//! used in fields marked `#[binserde(bits = n)]`, storing the variant index in
+//! `n` bits.
+//!
+//! ### `#[repr(transparent)]`
+//!
+//! Valid for: structs
+//!
+//! Not an attribute of this crate, but structs with this representation are
+//! serialized exactly like their one field which isn't skipped or
+//! `PhantomData`, including their schema and without field tags (see
+//! [`Mode::with_tag_depth`]). This doesn't apply if that field has any of the
+//! attributes above. Other fields are set to their default value when
+//! deserializing.
+//!
+//! ### `#[binserde(index = n)]`
+//!
+//! Valid for: fields
 //!
 //! Moves the field and all following fields to the specified position `n` when
 //! serializing, shifting everything originally after that position to the
@@ -86,7 +373,7 @@
 //! ```
 //!
 //! The attribute moved `y` and `z` into position 0, pushing `w` and `x` back to
-//! positions 2 and 3 respectively.
+//! positions 2 and 3 respectively. Skipped fields don't take up a position.
 //!
 //! The attribute can be applied on more than one field, in which case moving
 //! operations will be evaluated from top to bottom. That means, the following
@@ -105,12 +392,19 @@
 //!     #[binserde(index = 0)]
 //!     z: u8,
 //! }
+//!
+//! let vec = binserde::serialize(&S { w: 0, x: 1, y: 2, z: 3 }).unwrap();
+//!
+//! assert_eq!(&[3, 1, 2, 0], &*vec);
 //! ```
 //!
 //! # Deduplication
 //!
-//! Deduplication is currently only implemented for strings. It works by taking
-//! any [`String`] or [`str`] that is serialized using its [`BinSerializer`]
+//! Deduplication is implemented for strings, and for byte blobs and values of
+//! any type wrapped in [`dedup::Dedup`], [`dedup::Shared`] or an `Arc` or `Rc`
+//! with [`Mode::with_dedup_bytes`], which also covers whole `Vec`s with
+//! [`Mode::with_deduplicate_identical_collections`]. It works by taking any
+//! [`String`] or [`str`] that is serialized using its [`BinSerializer`]
 //! implementation and adds it to a seperate list which is written to the
 //! beginning of the buffer given to [`serialize`] (or an equivalent function,
 //! after which the actual data follows. In that data, the string is replaced by
@@ -142,17 +436,35 @@
 //! serialized data structure when multiple occurrences of the same string
 //! appear.
 //!
+//! Since the list needs to be known before writing any data, this requires
+//! walking the value twice. [`Mode::with_inline_dedup`] instead writes every
+//! string in full the first time it appears and only refers back to it
+//! afterwards, which allows writing the output in a single pass.
+//!
+//! To share one list between several values, such as the records of a log
+//! file, serialize each of them with [`serialize_shared_into`], which adds
+//! the strings it needs to a common `DedupContext`, and write the list with
+//! [`DedupContext::write_header`] when done. When reading, get the list
+//! back with [`DedupContext::read_header`] and pass it to
+//! [`deserialize_shared_from`] for each value.
+//!
 
+extern crate alloc;
 extern crate self as binserde;
 
-use std::fmt::Display;
-use std::io;
-use std::io::{Cursor, Read, Write};
-use std::num::TryFromIntError;
-use std::string::FromUtf8Error;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{FromUtf8Error, String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::{Display, Formatter};
+use core::marker::PhantomData;
+use core::num::TryFromIntError;
+#[cfg(feature = "std")]
+use std::io::BufWriter;
 
 pub use binserde_derive::{BinDeserialize, BinSerialize};
-use thiserror::Error;
+use byteorder::LE;
 
 use de::BinDeserializeOwned;
 pub use de::{BinDeserialize, BinDeserializer};
@@ -160,19 +472,52 @@ use dedup::DedupContext;
 pub use ser::{BinSerialize, BinSerializer};
 pub use serde::Mode;
 
-use crate::de::BinDeserializerBase;
-use crate::ser::{BinSerializerBase, PrescanSerializer};
+use crate::crc::{Crc32, CrcReader, CrcWriter};
+use crate::de::{BinDeserializerBase, CountRead, SeqIter, SliceDeserializer, Truncated};
+use crate::io::{Read, ReadBytesExt, Take, Write, WriteBytesExt};
+use crate::schema::Schema;
+#[cfg(feature = "std")]
+use crate::ser::VectoredWriter;
+use crate::ser::{BinSerializerBase, CountWrite, PrescanSerializer};
+use crate::small::SmallBuf;
+use crate::write_ext::{ReadExt, WriteExt};
 
+pub mod bits;
+pub mod crc;
 pub mod de;
 pub mod dedup;
+mod erased;
+pub mod fixed;
+pub mod fixed_str;
+pub mod flags;
+pub mod front_coded;
+pub mod io;
+pub mod lazy;
+pub mod niche;
+#[cfg(feature = "std")]
+pub mod path;
+pub mod registry;
+pub mod schema;
 pub mod ser;
 pub mod serde;
 mod serdeimpl;
+pub mod small;
+pub mod soa;
 pub mod try_iter;
 pub mod util;
-mod varint;
+pub mod varint;
 mod write_ext;
 
+/// Items used by the derive macros, which can't refer to them through `std`
+/// since that isn't available to every crate using them.
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::boxed::Box;
+    pub use alloc::string::String;
+    pub use alloc::vec;
+    pub use alloc::vec::Vec;
+}
+
 pub fn serialize<T>(value: &T) -> Result<Vec<u8>>
 where
     T: BinSerialize + ?Sized,
@@ -192,22 +537,281 @@ pub fn serialize_with<T>(value: &T, mode: Mode) -> Result<Vec<u8>>
 where
     T: BinSerialize + ?Sized,
 {
-    let mut buf = Cursor::new(Vec::new());
+    let mut buf = Vec::with_capacity(value.size_hint(mode));
+    serialize_with_into(&mut buf, value, mode)?;
+    Ok(buf)
+}
+
+/// Like [`serialize_with`], but keeps the output inline instead of
+/// allocating if it is at most [`small::INLINE_CAP`] bytes long.
+pub fn serialize_small<T>(value: &T, mode: Mode) -> Result<SmallBuf>
+where
+    T: BinSerialize + ?Sized,
+{
+    let mut buf = SmallBuf::new();
     serialize_with_into(&mut buf, value, mode)?;
-    Ok(buf.into_inner())
+    Ok(buf)
+}
+
+pub fn serialize_with_into<W, T>(pipe: W, value: &T, mode: Mode) -> Result<()>
+where
+    W: Write,
+    T: BinSerialize + ?Sized,
+{
+    serialize_impl(pipe, value, mode, Table::Scan)
+}
+
+/// Like [`serialize_with_into`], but buffers writes to `pipe` in a buffer of
+/// `buf_size` bytes and flushes it at the end. Useful when writing directly
+/// to a file or socket.
+#[cfg(feature = "std")]
+pub fn serialize_into_buffered<W, T>(pipe: W, value: &T, mode: Mode, buf_size: usize) -> Result<()>
+where
+    W: Write,
+    T: BinSerialize + ?Sized,
+{
+    let mut pipe = BufWriter::with_capacity(buf_size, pipe);
+    serialize_with_into(&mut pipe, value, mode)?;
+    pipe.flush()?;
+    Ok(())
+}
+
+/// Like [`serialize_into_buffered`], but writes the buffer together with
+/// writes that don't fit in it, such as large strings and byte blobs, using
+/// [`Write::write_vectored`], instead of flushing the buffer first. This saves
+/// copying large values and reduces the number of writes to `pipe`, which
+/// helps for writers like sockets that support vectored writes.
+#[cfg(feature = "std")]
+pub fn serialize_into_vectored<W, T>(pipe: W, value: &T, mode: Mode, buf_size: usize) -> Result<()>
+where
+    W: Write,
+    T: BinSerialize + ?Sized,
+{
+    let mut pipe = VectoredWriter::with_capacity(buf_size, pipe);
+    serialize_with_into(&mut pipe, value, mode)?;
+    pipe.flush()?;
+    Ok(())
+}
+
+/// Like [`serialize_with_into`], but serializes `value` into a buffer first
+/// and writes it to `pipe` prefixed with its length in bytes as a
+/// little-endian `u64`. Nothing is written if serializing fails, and a frame
+/// that was cut short when writing is detected by [`deserialize_framed_from`]
+/// instead of being misread.
+pub fn serialize_framed_into<W, T>(mut pipe: W, value: &T, mode: Mode) -> Result<()>
+where
+    W: Write,
+    T: BinSerialize + ?Sized,
+{
+    let buf = serialize_with(value, mode)?;
+    pipe.write_u64::<LE>(buf.len() as u64)?;
+    pipe.write_all(&buf)?;
+    Ok(())
+}
+
+/// Serializes `value` like [`serialize_with`] and compresses the output,
+/// including the dedup table, as a single zlib stream. `level` ranges from 0
+/// (no compression) to 10 (best compression), 6 is a good default. Only
+/// available with the `compression` feature.
+#[cfg(feature = "compression")]
+pub fn serialize_compressed<T>(value: &T, mode: Mode, level: u8) -> Result<Vec<u8>>
+where
+    T: BinSerialize + ?Sized,
+{
+    let buf = serialize_with(value, mode)?;
+    Ok(miniz_oxide::deflate::compress_to_vec_zlib(&buf, level))
+}
+
+/// Collects the strings `value` would deduplicate when serialized with
+/// `mode`. The result can be passed to [`serialize_prescanned_into`] and
+/// [`serialized_size_prescanned`] to avoid scanning the same value again for
+/// every call.
+pub fn prescan<T>(value: &T, mode: Mode) -> Result<DedupContext>
+where
+    T: BinSerialize + ?Sized,
+{
+    let mut ps = PrescanSerializer::with_dedup(DedupContext::from_mode(mode)?);
+
+    if let Some(count) = mode.map_entry_count_hint {
+        ps.dedup().reserve_strings(count);
+    }
+
+    value.serialize((&mut ps).with_mode(mode))?;
+    Ok(ps.into_dedup())
+}
+
+/// Like [`serialize_with_into`], but uses the result of [`prescan`] instead
+/// of scanning `value` again. `dedup` must have been computed from the same
+/// value and mode.
+pub fn serialize_prescanned_into<W, T>(
+    pipe: W,
+    value: &T,
+    mode: Mode,
+    dedup: &DedupContext,
+) -> Result<()>
+where
+    W: Write,
+    T: BinSerialize + ?Sized,
+{
+    serialize_impl(pipe, value, mode, Table::Prescanned(dedup))
+}
+
+/// Returns the number of bytes [`serialize_with`] would produce for `value`,
+/// including the dedup table. The value is serialized into a writer that only
+/// counts the bytes, so no output is kept, except with
+/// [`Mode::with_prefix_total_len`], which needs the output to get its length.
+pub fn serialized_size<T>(value: &T, mode: Mode) -> Result<usize>
+where
+    T: BinSerialize + ?Sized,
+{
+    let mut counter = CountWrite::new();
+    serialize_impl(&mut counter, value, mode, Table::Scan)?;
+    Ok(counter.count())
+}
+
+/// Like [`serialized_size`], but uses the result of [`prescan`] instead of
+/// scanning `value` again.
+pub fn serialized_size_prescanned<T>(value: &T, mode: Mode, dedup: &DedupContext) -> Result<usize>
+where
+    T: BinSerialize + ?Sized,
+{
+    let mut counter = CountWrite::new();
+    serialize_impl(&mut counter, value, mode, Table::Prescanned(dedup))?;
+    Ok(counter.count())
+}
+
+/// Like [`serialize_with_into`], but doesn't write a dedup table in front of
+/// the value. Instead, the entries it needs are added to `dedup`, so that
+/// several values can share one table, which is written separately with
+/// [`DedupContext::write_header`]. Values serialized this way are read back
+/// with [`deserialize_shared_from`].
+pub fn serialize_shared_into<W, T>(
+    pipe: W,
+    value: &T,
+    mode: Mode,
+    dedup: &mut DedupContext,
+) -> Result<()>
+where
+    W: Write,
+    T: BinSerialize + ?Sized,
+{
+    serialize_impl(pipe, value, mode, Table::Shared(dedup))
+}
+
+/// Where to get the dedup table from when serializing.
+enum Table<'a> {
+    /// Collect the table from the value and write it in front of it.
+    Scan,
+    /// Write the given table in front of the value.
+    Prescanned(&'a DedupContext),
+    /// Add entries to the given table, which is written elsewhere.
+    Shared(&'a mut DedupContext),
+}
+
+fn serialize_impl<W, T>(mut pipe: W, value: &T, mode: Mode, dedup: Table) -> Result<()>
+where
+    W: Write,
+    T: BinSerialize + ?Sized,
+{
+    if mode.prefix_total_len {
+        let mut buf = Vec::with_capacity(value.size_hint(mode));
+        serialize_impl(&mut buf, value, mode.with_prefix_total_len(false), dedup)?;
+        pipe.write_varusize(buf.len())?;
+        pipe.write_all(&buf)?;
+        return Ok(());
+    }
+
+    if mode.trailing_crc {
+        let mut crc_pipe = CrcWriter::new(&mut pipe);
+        serialize_body_into(&mut crc_pipe, value, mode, dedup)?;
+        let crc = crc_pipe.crc();
+        pipe.write_u32::<LE>(crc)?;
+        Ok(())
+    } else {
+        serialize_body_into(pipe, value, mode, dedup)
+    }
+}
+
+/// Writes the header set with [`Mode::with_header`] and the byte written
+/// with [`Mode::with_mode_flags`], if any.
+pub(crate) fn write_preamble<W: Write>(mut pipe: W, mode: Mode) -> Result<()> {
+    if let Some((magic, version)) = mode.header {
+        pipe.write_all(&magic)?;
+        pipe.write_u16::<LE>(version)?;
+    }
+
+    if mode.mode_flags {
+        pipe.write_u8(mode.flags_byte())?;
+    }
+
+    Ok(())
+}
+
+/// Reads and checks the header set with [`Mode::with_header`] and the byte
+/// written with [`Mode::with_mode_flags`], if any.
+pub(crate) fn read_preamble<R: Read>(mut pipe: R, mode: Mode) -> Result<()> {
+    if let Some((magic, version)) = mode.header {
+        let mut found = [0; 4];
+        pipe.read_exact(&mut found)?;
+
+        if found != magic {
+            return Err(Error::BadHeader {
+                expected: magic,
+                found,
+            });
+        }
+
+        let found = pipe.read_u16::<LE>()?;
+
+        if found != version {
+            return Err(Error::VersionMismatch {
+                expected: version,
+                found,
+            });
+        }
+    }
+
+    if mode.mode_flags {
+        let expected = mode.flags_byte();
+        let found = pipe.read_u8()?;
+
+        if found != expected {
+            return Err(Error::ModeMismatch { expected, found });
+        }
+    }
+
+    Ok(())
 }
 
-pub fn serialize_with_into<W, T>(mut pipe: W, value: &T, mode: Mode) -> Result<()>
+fn serialize_body_into<W, T>(mut pipe: W, value: &T, mode: Mode, dedup: Table) -> Result<()>
 where
     W: Write,
     T: BinSerialize + ?Sized,
 {
-    if mode.use_dedup {
-        let mut ps = PrescanSerializer::new().with_mode(mode);
-        value.serialize(&mut ps)?;
-        ps.dedup().write_to(&mut pipe)?;
+    write_preamble(&mut pipe, mode)?;
+
+    if mode.write_schema_header {
+        T::schema().write_to(&mut pipe)?;
     }
-    let mut serializer = BinSerializerBase::new(pipe).with_mode(mode);
+
+    // Look up indices in the same table that gets written instead of
+    // rebuilding it while serializing, so that they match even if the table
+    // was collected in a different order than the value is serialized in.
+    let dedup = match dedup {
+        Table::Shared(dedup) => {
+            let mut serializer = BinSerializerBase::with_dedup(pipe, &mut *dedup);
+            let result = value.serialize((&mut serializer).with_mode(mode));
+            // the addresses of shared pointers in `value` may be reused once
+            // it is no longer borrowed
+            dedup.clear_pointers();
+            return result;
+        }
+        _ if !mode.use_dedup || mode.inline_dedup => DedupContext::new(),
+        Table::Scan => prescan(value, mode)?,
+        Table::Prescanned(dedup) => dedup.clone(),
+    };
+    dedup.write_header(&mut pipe, mode)?;
+    let mut serializer = BinSerializerBase::with_dedup(pipe, dedup).with_mode(mode);
     value.serialize(&mut serializer)?;
     Ok(())
 }
@@ -223,7 +827,19 @@ pub fn deserialize_with<T>(buf: &[u8], mode: Mode) -> Result<T>
 where
     T: BinDeserializeOwned,
 {
-    deserialize_with_from(Cursor::new(buf), mode)
+    deserialize_with_from(buf, mode)
+}
+
+/// Decompresses and deserializes a value written by
+/// [`serialize_compressed`]. Only available with the `compression` feature.
+#[cfg(feature = "compression")]
+pub fn deserialize_compressed<T>(buf: &[u8], mode: Mode) -> Result<T>
+where
+    T: BinDeserializeOwned,
+{
+    let buf = miniz_oxide::inflate::decompress_to_vec_zlib(buf)
+        .map_err(|e| Error::custom(format!("invalid compressed data: {}", e)))?;
+    deserialize_with(&buf, mode)
 }
 
 pub fn deserialize_from<R, T>(pipe: R) -> Result<T>
@@ -234,71 +850,698 @@ where
     deserialize_with_from(pipe, Mode::default())
 }
 
-pub fn deserialize_with_from<R, T>(mut pipe: R, mode: Mode) -> Result<T>
+pub fn deserialize_with_from<R, T>(pipe: R, mode: Mode) -> Result<T>
 where
     R: Read,
     T: BinDeserializeOwned,
 {
-    let context = if mode.use_dedup {
-        DedupContext::read_from(&mut pipe)?
-    } else {
-        DedupContext::new()
-    };
-    let deserializer = BinDeserializerBase::new(pipe, &context).with_mode(mode);
-    T::deserialize(deserializer)
+    deserialize_impl_from(pipe, mode, None, &mut Vec::new(), NewValue(PhantomData))
 }
 
-pub fn deserialize_in_place<R, T>(target: &mut T, mut pipe: R, mode: Mode) -> Result<()>
+/// Deserializes a value written by [`serialize_shared_into`], looking up
+/// deduplicated entries in `dedup`, which is usually read with
+/// [`DedupContext::read_header`].
+pub fn deserialize_shared_from<R, T>(pipe: R, mode: Mode, dedup: &DedupContext) -> Result<T>
 where
     R: Read,
     T: BinDeserializeOwned,
 {
-    let context = if mode.use_dedup {
-        DedupContext::read_from(&mut pipe)?
-    } else {
-        DedupContext::new()
-    };
-    let deserializer = BinDeserializerBase::new(pipe, &context).with_mode(mode);
-    target.deserialize_in_place(deserializer)
+    deserialize_impl_from(
+        pipe,
+        mode,
+        Some(dedup),
+        &mut Vec::new(),
+        NewValue(PhantomData),
+    )
+}
+
+/// Like [`deserialize_with`], but uses `scratch` for data that is only
+/// needed temporarily while deserializing, such as the fields of types marked
+/// `#[binserde(tagged)]` or `#[binserde(count_fields)]`. Passing the same
+/// buffer to every call in a loop avoids allocating a new one each time.
+pub fn deserialize_with_scratch<T>(buf: &[u8], mode: Mode, scratch: &mut Vec<u8>) -> Result<T>
+where
+    T: BinDeserializeOwned,
+{
+    deserialize_impl_from(buf, mode, None, scratch, NewValue(PhantomData))
 }
 
-pub type Result<T, E = Error> = std::result::Result<T, E>;
+/// What the `deserialize_*_from` functions do once they've read everything
+/// before the value itself, which is either reading a new value or reading
+/// into an existing one.
+trait ReadBody {
+    type Output;
 
-#[derive(Debug, Error)]
-pub enum Error {
-    #[error("I/O error: {0}")]
-    Io(#[from] io::Error),
-    #[error("string too long")]
-    TryFromInt(#[from] TryFromIntError),
-    #[error("invalid UTF-8 string")]
-    InvalidUtf8(#[from] FromUtf8Error),
-    #[error("indexed string out of range: {0}")]
-    StrOutOfRange(usize),
-    #[error("{0}")]
-    Custom(String),
+    fn read<'de, D: BinDeserializer<'de>>(self, deserializer: D) -> Result<Self::Output>;
 }
 
-impl Error {
-    pub fn custom<S: Display>(s: S) -> Self {
-        Error::Custom(s.to_string())
+struct NewValue<T>(PhantomData<T>);
+
+impl<T> ReadBody for NewValue<T>
+where
+    T: BinDeserializeOwned,
+{
+    type Output = T;
+
+    fn read<'de, D: BinDeserializer<'de>>(self, deserializer: D) -> Result<T> {
+        T::deserialize(deserializer)
     }
 }
 
-#[cfg(test)]
-mod test {
-    use binserde_derive::{BinDeserialize, BinSerialize};
+struct InPlace<'a, T>(&'a mut T);
 
-    use crate::{deserialize, deserialize_with, serialize, serialize_with, Mode};
+impl<T> ReadBody for InPlace<'_, T>
+where
+    T: BinDeserializeOwned,
+{
+    type Output = ();
 
-    #[test]
-    fn serialize_inline_test() {
-        use std::collections::{HashMap, HashSet};
+    fn read<'de, D: BinDeserializer<'de>>(self, deserializer: D) -> Result<()> {
+        self.0.deserialize_in_place(deserializer)
+    }
+}
 
-        #[derive(Debug, PartialEq, Eq, BinSerialize, BinDeserialize)]
-        struct Test {
-            vec: Vec<Test1>,
-            map_set: HashMap<String, HashSet<String>>,
-            test2: Vec<Test2>,
+fn deserialize_impl_from<R, B>(
+    pipe: R,
+    mode: Mode,
+    dedup: Option<&DedupContext>,
+    scratch: &mut Vec<u8>,
+    body: B,
+) -> Result<B::Output>
+where
+    R: Read,
+    B: ReadBody,
+{
+    if !mode.error_offsets {
+        return deserialize_prefixed_from(pipe, mode, dedup, scratch, body);
+    }
+
+    let mut pipe = CountRead::new(pipe);
+    deserialize_prefixed_from(&mut pipe, mode, dedup, scratch, body)
+        .map_err(|e| e.at_offset(pipe.count()))
+}
+
+fn deserialize_prefixed_from<R, B>(
+    mut pipe: R,
+    mode: Mode,
+    dedup: Option<&DedupContext>,
+    scratch: &mut Vec<u8>,
+    body: B,
+) -> Result<B::Output>
+where
+    R: Read,
+    B: ReadBody,
+{
+    if mode.prefix_total_len {
+        let len = pipe.read_varusize()?;
+        let mut pipe = pipe.take(len as u64);
+        let value = deserialize_checked_from(&mut pipe, mode, dedup, scratch, body)?;
+        check_fully_read(&pipe)?;
+        Ok(value)
+    } else {
+        deserialize_checked_from(pipe, mode, dedup, scratch, body)
+    }
+}
+
+/// Like [`deserialize_with_from`], but additionally reads the byte following
+/// the value and fails if it isn't `sentinel`. This is for formats where
+/// records are terminated by a marker byte instead of prefixed with their
+/// length.
+pub fn deserialize_until_sentinel_from<R, T>(mut pipe: R, sentinel: u8, mode: Mode) -> Result<T>
+where
+    R: Read,
+    T: BinDeserializeOwned,
+{
+    let value = deserialize_with_from(&mut pipe, mode)?;
+
+    match pipe.read_u8()? {
+        b if b == sentinel => Ok(value),
+        b => Err(Error::custom(format!(
+            "expected terminator {:#04x}, found {:#04x}",
+            sentinel, b
+        ))),
+    }
+}
+
+/// Reads a value written by [`serialize_framed_into`]. Fails with
+/// [`Error::TruncatedFrame`] if the input ends before the length given in
+/// the frame's prefix, and if the value doesn't take up the whole frame.
+pub fn deserialize_framed_from<R, T>(mut pipe: R, mode: Mode) -> Result<T>
+where
+    R: Read,
+    T: BinDeserializeOwned,
+{
+    let len = pipe.read_u64::<LE>()?;
+    let mut buf = Vec::new();
+    let got = pipe.take(len).read_to_end(&mut buf)? as u64;
+
+    if got < len {
+        return Err(Error::TruncatedFrame { expected: len, got });
+    }
+
+    let mut body = (&buf[..]).take(len);
+    let value = deserialize_with_from(&mut body, mode)?;
+    check_fully_read(&body)?;
+    Ok(value)
+}
+
+fn deserialize_checked_from<R, B>(
+    mut pipe: R,
+    mode: Mode,
+    dedup: Option<&DedupContext>,
+    scratch: &mut Vec<u8>,
+    body: B,
+) -> Result<B::Output>
+where
+    R: Read,
+    B: ReadBody,
+{
+    if mode.trailing_crc {
+        let mut crc_pipe = CrcReader::new(&mut pipe);
+        let value = deserialize_body_from(&mut crc_pipe, mode, dedup, scratch, body)?;
+        let crc = crc_pipe.crc();
+        check_crc(crc, pipe.read_u32::<LE>()?)?;
+        Ok(value)
+    } else {
+        deserialize_body_from(pipe, mode, dedup, scratch, body)
+    }
+}
+
+fn deserialize_body_from<R, B>(
+    mut pipe: R,
+    mode: Mode,
+    dedup: Option<&DedupContext>,
+    scratch: &mut Vec<u8>,
+    body: B,
+) -> Result<B::Output>
+where
+    R: Read,
+    B: ReadBody,
+{
+    read_preamble(&mut pipe, mode)?;
+
+    if mode.write_schema_header {
+        Schema::read_from(&mut pipe)?;
+    }
+
+    let read = |pipe, context: &DedupContext| {
+        context.swap_scratch(scratch);
+        let value = body.read(BinDeserializerBase::new(pipe, context).with_mode(mode));
+        context.swap_scratch(scratch);
+        value
+    };
+
+    match dedup {
+        None => {
+            let context = DedupContext::read_header(&mut pipe, mode)?;
+            read(pipe, &context)
+        }
+        Some(context) => read(pipe, context),
+    }
+}
+
+/// Deserializes a value that borrows from `buf`, such as a struct with
+/// `&'de str` or `&'de [u8]` fields, without copying the borrowed data.
+/// Borrowing deduplicated strings and byte blobs isn't possible, since these
+/// aren't stored in place; this fails when trying to do so.
+pub fn deserialize_borrowed<'de, T>(buf: &'de [u8], mode: Mode) -> Result<T>
+where
+    T: BinDeserialize<'de>,
+{
+    let mut input = buf;
+
+    match deserialize_borrowed_prefixed(&mut input, mode) {
+        Err(e) if mode.error_offsets => Err(e.at_offset((buf.len() - input.len()) as u64)),
+        x => x,
+    }
+}
+
+fn deserialize_borrowed_prefixed<'de, T>(input: &mut &'de [u8], mode: Mode) -> Result<T>
+where
+    T: BinDeserialize<'de>,
+{
+    if mode.prefix_total_len {
+        let len = input.read_varusize()?;
+
+        if len > input.len() {
+            return Err(Error::UnexpectedEof {
+                expected: len,
+                got: input.len(),
+            });
+        }
+
+        let mut inner = &input[..len];
+        let value = deserialize_borrowed_checked(&mut inner, mode);
+        *input = &input[len - inner.len()..];
+        let value = value?;
+
+        if !inner.is_empty() {
+            return Err(Error::custom(format!(
+                "{} unread bytes left in length-prefixed value",
+                inner.len()
+            )));
+        }
+
+        Ok(value)
+    } else {
+        deserialize_borrowed_checked(input, mode)
+    }
+}
+
+fn deserialize_borrowed_checked<'de, T>(input: &mut &'de [u8], mode: Mode) -> Result<T>
+where
+    T: BinDeserialize<'de>,
+{
+    let start = *input;
+    let value = deserialize_borrowed_body(input, mode)?;
+
+    if mode.trailing_crc {
+        let mut crc = Crc32::new();
+        crc.update(&start[..start.len() - input.len()]);
+        check_crc(crc.finish(), input.read_u32::<LE>()?)?;
+    }
+
+    Ok(value)
+}
+
+fn deserialize_borrowed_body<'de, T>(input: &mut &'de [u8], mode: Mode) -> Result<T>
+where
+    T: BinDeserialize<'de>,
+{
+    read_preamble(&mut *input, mode)?;
+
+    if mode.write_schema_header {
+        Schema::read_from(&mut *input)?;
+    }
+
+    let context = DedupContext::read_header(&mut *input, mode)?;
+    let mut deserializer = SliceDeserializer::new(input, &context);
+    let value = T::deserialize((&mut deserializer).with_mode(mode));
+    *input = deserializer.remaining();
+    value
+}
+
+/// Like [`deserialize_with_from`], but reads into `target` instead of
+/// returning a new value, reusing its allocations where possible.
+pub fn deserialize_in_place<R, T>(target: &mut T, pipe: R, mode: Mode) -> Result<()>
+where
+    R: Read,
+    T: BinDeserializeOwned,
+{
+    deserialize_impl_from(pipe, mode, None, &mut Vec::new(), InPlace(target))
+}
+
+/// Reads a sequence of `T` from `pipe` and appends its elements to `target`,
+/// keeping the elements already in it.
+pub fn deserialize_extend_from<R, T>(target: &mut Vec<T>, pipe: R, mode: Mode) -> Result<()>
+where
+    R: Read,
+    T: BinDeserializeOwned,
+{
+    let mut items: Vec<T> = deserialize_with_from(pipe, mode)?;
+    target.append(&mut items);
+    Ok(())
+}
+
+pub fn deserialize_seq_from<R, T>(pipe: R) -> SeqIter<R, T>
+where
+    R: Read,
+    T: BinDeserializeOwned,
+{
+    deserialize_seq_with_from(pipe, Mode::default())
+}
+
+/// Reads a `Vec<T>` or slice written by [`serialize_with_into`] one element at
+/// a time, instead of collecting all of them first. Nothing is read from
+/// `pipe` until the first call to `next`. The iterator stops after the first
+/// error, such as the input ending early.
+///
+/// Types that encode sequences of themselves differently from their elements
+/// one by one can't be read this way, which applies to `bool` with
+/// [`Mode::with_pack_bools`], `u32` and `u64` with
+/// [`Mode::with_varint_group_encoding`], and `u8` with
+/// [`Mode::with_dedup_bytes`]. Values written with
+/// [`Mode::with_prefix_total_len`], [`Mode::with_trailing_crc`] or
+/// [`Mode::with_deduplicate_identical_collections`] aren't supported either.
+pub fn deserialize_seq_with_from<R, T>(pipe: R, mode: Mode) -> SeqIter<R, T>
+where
+    R: Read,
+    T: BinDeserializeOwned,
+{
+    SeqIter::new(pipe, mode)
+}
+
+/// Serializes each of `items` separately, so that they can be read back with
+/// [`deserialize_batch`] even if some of them fail to deserialize. With
+/// [`Mode::with_inline_dedup`], strings are only deduplicated within each
+/// item.
+pub fn serialize_batch<T>(items: &[T], mode: Mode) -> Result<Vec<u8>>
+where
+    T: BinSerialize,
+{
+    let mut buf = Vec::new();
+    write_preamble(&mut buf, mode)?;
+
+    let dedup = if mode.use_dedup && !mode.inline_dedup {
+        let mut ps = PrescanSerializer::with_dedup(DedupContext::from_mode(mode)?);
+        items
+            .iter()
+            .try_for_each(|el| el.serialize((&mut ps).with_mode(mode)))?;
+        let dedup = ps.into_dedup();
+        dedup.write_header(&mut buf, mode)?;
+        dedup
+    } else {
+        DedupContext::new()
+    };
+
+    buf.write_varusize(items.len())?;
+
+    let mut serializer = BinSerializerBase::with_dedup(Vec::new(), dedup).with_mode(mode);
+
+    for item in items {
+        // with inline dedup, strings can only refer back to earlier ones in
+        // the same item, so that every item can be read on its own
+        if mode.inline_dedup {
+            *serializer.dedup() = DedupContext::new();
+        }
+
+        item.serialize(&mut serializer)?;
+        let item_buf = core::mem::take(serializer.pipe());
+        buf.write_varusize(item_buf.len())?;
+        buf.write_all(&item_buf)?;
+    }
+
+    Ok(buf)
+}
+
+/// Reads items written by [`serialize_batch`]. If [`Mode::fail_fast`] is
+/// enabled, returns the error of the first item that fails to deserialize, so
+/// every returned item is `Ok`. Otherwise returns the result of deserializing
+/// each item. An item which doesn't use up all of its bytes fails to
+/// deserialize.
+pub fn deserialize_batch<T>(buf: &[u8], mode: Mode) -> Result<Vec<Result<T>>>
+where
+    T: BinDeserializeOwned,
+{
+    let mut pipe = buf;
+    read_preamble(&mut pipe, mode)?;
+    let context = DedupContext::read_header(&mut pipe, mode)?;
+
+    let len = pipe.read_varusize()?;
+    let mut items = Vec::new();
+
+    for _ in 0..len {
+        let item_len = pipe.read_varusize()?;
+
+        if item_len > pipe.len() {
+            return Err(Error::custom("batch item extends past end of buffer"));
+        }
+
+        let (mut item_buf, rest) = pipe.split_at(item_len);
+        pipe = rest;
+
+        let inline_context;
+        let context = if mode.inline_dedup {
+            inline_context = DedupContext::new();
+            &inline_context
+        } else {
+            &context
+        };
+
+        let deserializer = BinDeserializerBase::new(&mut item_buf, context).with_mode(mode);
+        let item = T::deserialize(deserializer).and_then(|item| {
+            if item_buf.is_empty() {
+                Ok(item)
+            } else {
+                Err(Error::custom(format!(
+                    "{} unread bytes left in batch item",
+                    item_buf.len()
+                )))
+            }
+        });
+
+        if mode.fail_fast {
+            items.push(Ok(item?));
+        } else {
+            items.push(item);
+        }
+    }
+
+    Ok(items)
+}
+
+fn check_crc(actual: u32, expected: u32) -> Result<()> {
+    if actual != expected {
+        Err(Error::custom(format!(
+            "CRC mismatch: expected {:08X}, got {:08X}",
+            expected, actual
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_fully_read<R>(pipe: &Take<R>) -> Result<()> {
+    if pipe.limit() != 0 {
+        Err(Error::custom(format!(
+            "{} unread bytes left in length-prefixed value",
+            pipe.limit()
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The input ended in the middle of a value. Unlike other errors, this
+    /// means that the data read so far was valid, and more of it is needed.
+    UnexpectedEof {
+        expected: usize,
+        got: usize,
+    },
+    TryFromInt(TryFromIntError),
+    InvalidUtf8(FromUtf8Error),
+    StrOutOfRange(usize),
+    BytesOutOfRange(usize),
+    UnknownTypeId(u32),
+    /// An enum variant tag which doesn't belong to any variant of the type.
+    UnknownVariant(u64),
+    TooManyMapEntries(usize, usize),
+    ContainerTooLong(usize, usize),
+    /// The collections in the input have more elements in total than
+    /// allowed by [`Mode::max_total_elements`].
+    TooManyElements(usize),
+    /// Values are nested more deeply than allowed by [`Mode::max_depth`].
+    RecursionLimit(usize),
+    ZeroValue(&'static str),
+    BadHeader {
+        expected: [u8; 4],
+        found: [u8; 4],
+    },
+    VersionMismatch {
+        expected: u16,
+        found: u16,
+    },
+    /// The input was written with different options than it is being read
+    /// with, as recorded by [`Mode::with_mode_flags`].
+    ModeMismatch {
+        expected: u8,
+        found: u8,
+    },
+    /// The input ended before the end of a frame written by
+    /// [`serialize_framed_into`].
+    TruncatedFrame {
+        expected: u64,
+        got: u64,
+    },
+    UsizeOverflow(u64),
+    IsizeOverflow(i64),
+    InField {
+        path: String,
+        source: Box<Error>,
+    },
+    /// An error together with the number of bytes read when it occurred and
+    /// the path of the field being read, if any, see
+    /// [`Mode::with_error_offsets`].
+    Context {
+        offset: u64,
+        field: Option<String>,
+        source: Box<Error>,
+    },
+    Custom(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::UnexpectedEof { expected, got } => write!(
+                f,
+                "unexpected end of input: expected {} bytes, got {}",
+                expected, got
+            ),
+            Error::TryFromInt(_) => write!(f, "string too long"),
+            Error::InvalidUtf8(_) => write!(f, "invalid UTF-8 string"),
+            Error::StrOutOfRange(idx) => write!(f, "indexed string out of range: {}", idx),
+            Error::BytesOutOfRange(idx) => write!(f, "indexed byte blob out of range: {}", idx),
+            Error::UnknownTypeId(id) => write!(f, "unknown type id: {}", id),
+            Error::UnknownVariant(tag) => write!(f, "unknown enum variant tag: {}", tag),
+            Error::TooManyMapEntries(len, max) => {
+                write!(f, "map has {} entries, more than the allowed {}", len, max)
+            }
+            Error::ContainerTooLong(len, max) => write!(
+                f,
+                "container has length {}, more than the allowed {}",
+                len, max
+            ),
+            Error::TooManyElements(max) => write!(
+                f,
+                "collections have more than the allowed {} elements in total",
+                max
+            ),
+            Error::RecursionLimit(max) => {
+                write!(f, "values are nested more than {} levels deep", max)
+            }
+            Error::ZeroValue(ty) => write!(f, "zero value for {}", ty),
+            Error::BadHeader { expected, found } => write!(
+                f,
+                "invalid header: expected magic {:02x?}, found {:02x?}",
+                expected, found
+            ),
+            Error::VersionMismatch { expected, found } => write!(
+                f,
+                "unsupported format version {}, expected {}",
+                found, expected
+            ),
+            Error::ModeMismatch { expected, found } => write!(
+                f,
+                "input was written with mode flags {:#010b}, expected {:#010b}",
+                found, expected
+            ),
+            Error::TruncatedFrame { expected, got } => write!(
+                f,
+                "truncated frame: expected {} bytes, got {}",
+                expected, got
+            ),
+            Error::UsizeOverflow(num) => {
+                write!(f, "{} doesn't fit in usize on this platform", num)
+            }
+            Error::IsizeOverflow(num) => {
+                write!(f, "{} doesn't fit in isize on this platform", num)
+            }
+            Error::InField { path, source } => write!(f, "in field `{}`: {}", path, source),
+            Error::Context {
+                offset,
+                field: None,
+                source,
+            } => write!(f, "at byte {}: {}", offset, source),
+            Error::Context {
+                offset,
+                field: Some(field),
+                source,
+            } => write!(f, "at byte {} in field `{}`: {}", offset, field, source),
+            Error::Custom(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::TryFromInt(e) => Some(e),
+            Error::InvalidUtf8(e) => Some(e),
+            Error::InField { source, .. } => Some(source),
+            Error::Context { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<TryFromIntError> for Error {
+    fn from(e: TryFromIntError) -> Self {
+        Error::TryFromInt(e)
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(e: FromUtf8Error) -> Self {
+        Error::InvalidUtf8(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        let truncated = e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<Truncated>());
+
+        match truncated {
+            Some(t) => Error::UnexpectedEof {
+                expected: t.expected,
+                got: t.got,
+            },
+            None => Error::Io(e),
+        }
+    }
+}
+
+impl Error {
+    pub fn custom<S: Display>(s: S) -> Self {
+        Error::Custom(s.to_string())
+    }
+
+    /// Marks this error as having occurred in `field`, prepending it to the
+    /// path if this error already has field context.
+    pub fn in_field(self, field: &str) -> Self {
+        match self {
+            Error::InField { path, source } => Error::InField {
+                path: format!("{}.{}", field, path),
+                source,
+            },
+            e => Error::InField {
+                path: field.to_string(),
+                source: Box::new(e),
+            },
+        }
+    }
+
+    /// Wraps this error in [`Error::Context`] with the given offset, taking
+    /// the field path from it if it has one.
+    pub(crate) fn at_offset(self, offset: u64) -> Self {
+        match self {
+            Error::InField { path, source } => Error::Context {
+                offset,
+                field: Some(path),
+                source,
+            },
+            e => Error::Context {
+                offset,
+                field: None,
+                source: Box::new(e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use binserde_derive::{BinDeserialize, BinSerialize};
+
+    use crate::{deserialize, deserialize_with, serialize, serialize_with, Mode};
+
+    #[test]
+    fn serialize_inline_test() {
+        use std::collections::{HashMap, HashSet};
+
+        #[derive(Debug, PartialEq, Eq, BinSerialize, BinDeserialize)]
+        struct Test {
+            vec: Vec<Test1>,
+            map_set: HashMap<String, HashSet<String>>,
+            test2: Vec<Test2>,
         }
 
         #[derive(Debug, PartialEq, Eq, BinSerialize, BinDeserialize)]
@@ -359,6 +1602,50 @@ mod test {
         }
     }
 
+    #[test]
+    fn string_len_prefix() {
+        use crate::serde::UsizeLen;
+
+        let mode = Mode::default()
+            .with_usize_len(UsizeLen::U32)
+            .with_string_len_prefix(Some(UsizeLen::Variable));
+
+        assert_eq!(&[3, 97, 98, 99], &*serialize_with("abc", mode).unwrap());
+        assert_eq!(
+            &[2, 0, 0, 0, 1, 2],
+            &*serialize_with(&vec![1u8, 2], mode).unwrap()
+        );
+        assert_eq!(
+            &[1, 0, 0, 0, 1, 120],
+            &*serialize_with(&vec!["x"], mode).unwrap()
+        );
+
+        let buf = serialize_with(&vec!["x", "yz"], mode).unwrap();
+        assert_eq!(
+            vec!["x".to_string(), "yz".to_string()],
+            deserialize_with::<Vec<String>>(&buf, mode).unwrap()
+        );
+    }
+
+    #[test]
+    fn fixed_signed() {
+        let mode = Mode::default()
+            .with_fixed_size_use_varint(true)
+            .with_fixed_signed(true);
+
+        assert_eq!(&[0xFF, 0xFF, 0xFF, 0xFF], &*serialize(&-1i32).unwrap());
+        assert_eq!(
+            &[0xFF, 0xFF, 0xFF, 0xFF],
+            &*serialize_with(&-1i32, mode).unwrap()
+        );
+        assert_eq!(&[0xFE, 0xFF], &*serialize_with(&-2i16, mode).unwrap());
+        assert_eq!(&[0x01], &*serialize_with(&1u32, mode).unwrap());
+        assert_eq!(
+            -1i32,
+            deserialize_with(&[0xFF, 0xFF, 0xFF, 0xFF], mode).unwrap()
+        );
+    }
+
     #[test]
     fn serialize_constant_output() {
         assert_eq!(&[3, 97, 98, 99], &*serialize(&"abc").unwrap());
@@ -375,4 +1662,4379 @@ mod test {
             .unwrap()
         );
     }
+
+    #[test]
+    fn prefix_total_len_concatenated() {
+        use std::io::Cursor;
+
+        use crate::deserialize_with_from;
+
+        let mode = Mode::dedup().with_prefix_total_len(true);
+
+        let mut buf = serialize_with(&("abc", 5u32), mode).unwrap();
+        buf.extend(serialize_with(&vec!["x".to_string(), "x".to_string()], mode).unwrap());
+
+        let mut cursor = Cursor::new(&buf);
+        let first: (String, u32) = deserialize_with_from(&mut cursor, mode).unwrap();
+        let second: Vec<String> = deserialize_with_from(&mut cursor, mode).unwrap();
+
+        assert_eq!(("abc".to_string(), 5), first);
+        assert_eq!(vec!["x".to_string(), "x".to_string()], second);
+        assert_eq!(buf.len() as u64, cursor.position());
+    }
+
+    #[test]
+    fn unit_map_matches_set() {
+        use std::collections::{HashMap, HashSet};
+
+        let set: HashSet<String> = vec!["abc".to_string()].into_iter().collect();
+        let map: HashMap<String, ()> = vec![("abc".to_string(), ())].into_iter().collect();
+
+        assert_eq!(serialize(&set).unwrap(), serialize(&map).unwrap());
+
+        // with more than one entry, the iteration order of each collection
+        // is unspecified, so check that each one reads back as the other
+        let set: HashSet<String> = vec!["a", "b", "c"].into_iter().map(String::from).collect();
+        let map: HashMap<String, ()> = set.iter().map(|el| (el.clone(), ())).collect();
+
+        let map_as_set: HashSet<String> = deserialize(&serialize(&map).unwrap()).unwrap();
+        let set_as_map: HashMap<String, ()> = deserialize(&serialize(&set).unwrap()).unwrap();
+        assert_eq!(set, map_as_set);
+        assert_eq!(map, set_as_map);
+
+        let mode = Mode::default().with_deterministic(true);
+        assert_eq!(
+            serialize_with(&set, mode).unwrap(),
+            serialize_with(&map, mode).unwrap()
+        );
+
+        // the values of such maps aren't visited at all
+        struct Nothing;
+
+        impl crate::BinSerialize for Nothing {
+            fn serialize<S: crate::BinSerializer>(&self, _serializer: S) -> crate::Result<()> {
+                panic!("value of a map written like a set was serialized");
+            }
+
+            fn serializes_to_nothing() -> bool {
+                true
+            }
+        }
+
+        let map: HashMap<String, Nothing> = set.iter().map(|el| (el.clone(), Nothing)).collect();
+        assert_eq!(
+            serialize_with(&set, mode).unwrap(),
+            serialize_with(&map, mode).unwrap()
+        );
+
+        let map: std::collections::BTreeMap<_, _> =
+            set.iter().map(|el| (el.clone(), Nothing)).collect();
+        let btree_set: std::collections::BTreeSet<_> = set.iter().cloned().collect();
+        assert_eq!(serialize(&btree_set).unwrap(), serialize(&map).unwrap());
+    }
+
+    #[test]
+    fn mode_profiles() {
+        assert_eq!(None, Mode::profile("test_disk"));
+
+        let disk = Mode::dedup().with_fixed_size_use_varint(true);
+        Mode::register_profile("test_disk", disk);
+        assert_eq!(Some(disk), Mode::profile("test_disk"));
+
+        Mode::register_profile("test_disk", Mode::default());
+        assert_eq!(Some(Mode::default()), Mode::profile("test_disk"));
+    }
+
+    #[test]
+    fn extend_vec() {
+        use crate::deserialize_extend_from;
+
+        let mode = Mode::dedup();
+        let first = serialize_with(&["a", "b"] as &[&str], mode).unwrap();
+        let second = serialize_with(&["c", "a"] as &[&str], mode).unwrap();
+
+        let mut target = vec!["z".to_string()];
+        deserialize_extend_from(&mut target, &*first, mode).unwrap();
+        deserialize_extend_from(&mut target, &*second, mode).unwrap();
+
+        assert_eq!(vec!["z", "a", "b", "c", "a"], target);
+    }
+
+    #[test]
+    fn soa_round_trip() {
+        use crate::soa::Soa;
+
+        #[derive(Debug, PartialEq, Eq, BinSerialize, BinDeserialize)]
+        #[binserde(soa)]
+        struct Row {
+            id: u16,
+            name: String,
+            #[binserde(skip)]
+            cached: u32,
+            flag: bool,
+        }
+
+        let rows = vec![
+            Row {
+                id: 1,
+                name: "a".to_string(),
+                cached: 0,
+                flag: true,
+            },
+            Row {
+                id: 2,
+                name: "bb".to_string(),
+                cached: 0,
+                flag: false,
+            },
+            Row {
+                id: 3,
+                name: "a".to_string(),
+                cached: 0,
+                flag: true,
+            },
+        ];
+
+        let soa = Soa(rows);
+        let buf = serialize(&soa).unwrap();
+        assert_eq!(
+            &[3, 1, 0, 2, 0, 3, 0, 1, 97, 2, 98, 98, 1, 97, 0xFF, 0x00, 0xFF],
+            &*buf
+        );
+        assert_eq!(soa, deserialize(&buf).unwrap());
+
+        // same size as the row-oriented layout, just reordered
+        assert_eq!(serialize(&soa.0).unwrap().len(), buf.len());
+
+        let mode = Mode::dedup();
+        let buf = serialize_with(&soa, mode).unwrap();
+        assert_eq!(soa, deserialize_with(&buf, mode).unwrap());
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let too_big_varint = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F];
+        assert!(deserialize::<usize>(&too_big_varint).is_err());
+
+        let unchecked = Mode::default().with_checked_arithmetic(false);
+        assert_eq!(
+            usize::MAX,
+            deserialize_with::<usize>(&too_big_varint, unchecked).unwrap()
+        );
+
+        let mode = Mode::default().with_fixed_size_use_varint(true);
+        let buf = serialize_with(&0x12345u32, mode).unwrap();
+        assert!(deserialize_with::<u16>(&buf, mode).is_err());
+        assert_eq!(
+            0x2345u16,
+            deserialize_with(&buf, mode.with_checked_arithmetic(false)).unwrap()
+        );
+    }
+
+    #[test]
+    fn inline_dedup() {
+        let mode = Mode::dedup().with_inline_dedup(true);
+        let value = ("ab", "c", "ab", vec!["c".to_string(), "d".to_string()]);
+
+        let buf = serialize_with(&value, mode).unwrap();
+
+        // no string table up front, strings are written where they first
+        // appear
+        assert_eq!(&[0, 2, 97, 98, 0, 1, 99, 1, 2, 2, 0, 1, 100], &*buf);
+
+        let copy: (String, String, String, Vec<String>) = deserialize_with(&buf, mode).unwrap();
+        assert_eq!(("ab", "c", "ab"), (&*copy.0, &*copy.1, &*copy.2));
+        assert_eq!(value.3, copy.3);
+    }
+
+    #[test]
+    fn pack_bools() {
+        let mode = Mode::default().with_pack_bools(true);
+        let value: Vec<bool> = (0..17).map(|el| el % 3 == 0).collect();
+
+        let buf = serialize_with(&value, mode).unwrap();
+        assert_eq!(&[17, 0b01001001, 0b10010010, 0b00000000], &*buf);
+        assert_eq!(value, deserialize_with::<Vec<bool>>(&buf, mode).unwrap());
+
+        assert_eq!(18, serialize(&value).unwrap().len());
+    }
+
+    #[test]
+    fn dedup_fields() {
+        use crate::BinSerialize;
+
+        #[allow(dead_code)]
+        #[derive(BinSerialize)]
+        struct Test {
+            name: String,
+            #[binserde(no_dedup)]
+            raw_name: String,
+            id: u32,
+            tags: Vec<Option<String>>,
+            #[binserde(skip)]
+            cached: String,
+            pair: (u8, &'static str),
+        }
+
+        #[allow(dead_code)]
+        #[derive(BinSerialize)]
+        enum TestEnum {
+            A(String, u8),
+            B { x: u8, y: std::path::PathBuf },
+        }
+
+        type Name = String;
+
+        // only the declared types are looked at, so strings behind an alias
+        // or another struct aren't recognized
+        #[allow(dead_code)]
+        #[derive(BinSerialize)]
+        struct Outer {
+            inner: Test,
+            alias: Name,
+            label: crate::dedup::Dedup<String>,
+        }
+
+        assert_eq!(&["name", "tags", "pair"], Test::dedup_fields());
+        assert_eq!(&["A.0", "B.y"], TestEnum::dedup_fields());
+        assert_eq!(&["label"], Outer::dedup_fields());
+        assert!(String::dedup_fields().is_empty());
+    }
+
+    #[test]
+    fn batch_collect_all() {
+        use crate::{deserialize_batch, serialize_batch, Error};
+
+        // written as byte vectors, read back as strings
+        let items = vec![
+            b"ab".to_vec(),
+            vec![0xC3, 0x28],
+            b"c".to_vec(),
+            vec![0xFF],
+            b"".to_vec(),
+        ];
+
+        let buf = serialize_batch(&items, Mode::default()).unwrap();
+
+        let all: Vec<crate::Result<String>> =
+            deserialize_batch(&buf, Mode::default().with_fail_fast(false)).unwrap();
+        assert_eq!(5, all.len());
+        assert_eq!("ab", all[0].as_ref().unwrap());
+        assert!(matches!(all[1], Err(Error::InvalidUtf8(_))));
+        assert_eq!("c", all[2].as_ref().unwrap());
+        assert!(matches!(all[3], Err(Error::InvalidUtf8(_))));
+        assert_eq!("", all[4].as_ref().unwrap());
+
+        assert!(matches!(
+            deserialize_batch::<String>(&buf, Mode::default()),
+            Err(Error::InvalidUtf8(_))
+        ));
+
+        let mode = Mode::dedup();
+        let strs = vec!["x".to_string(), "y".to_string(), "x".to_string()];
+        let buf = serialize_batch(&strs, mode).unwrap();
+        let copy: Vec<String> = deserialize_batch(&buf, mode)
+            .unwrap()
+            .into_iter()
+            .collect::<crate::Result<_>>()
+            .unwrap();
+        assert_eq!(strs, copy);
+
+        // items are read as u8, leaving the rest of the u32 unread
+        let buf = serialize_batch(&[1u32, 2], Mode::default()).unwrap();
+        let all: Vec<crate::Result<u8>> =
+            deserialize_batch(&buf, Mode::default().with_fail_fast(false)).unwrap();
+        assert_eq!(2, all.len());
+        assert!(all.iter().all(|el| el.is_err()));
+        assert!(deserialize_batch::<u8>(&buf, Mode::default()).is_err());
+
+        // a corrupted item doesn't affect later ones repeating its string
+        let mode = Mode::dedup().with_inline_dedup(true).with_fail_fast(false);
+        let strs = vec!["a".to_string(), "a".to_string(), "b".to_string()];
+        let mut buf = serialize_batch(&strs, mode).unwrap();
+        assert_eq!(&[3, 3, 0, 1, 97, 3, 0, 1, 97, 3, 0, 1, 98], &*buf);
+
+        buf[4] = 0xFF;
+        let all: Vec<crate::Result<String>> = deserialize_batch(&buf, mode).unwrap();
+        assert!(matches!(all[0], Err(Error::InvalidUtf8(_))));
+        assert_eq!("a", all[1].as_ref().unwrap());
+        assert_eq!("b", all[2].as_ref().unwrap());
+    }
+
+    #[test]
+    fn option_nonzero() {
+        use std::num::NonZeroU32;
+
+        let none: Option<NonZeroU32> = None;
+        let buf = serialize(&none).unwrap();
+        assert_eq!(&[0], &*buf);
+        assert_eq!(none, deserialize(&buf).unwrap());
+
+        let max = NonZeroU32::new(u32::MAX);
+        let buf = serialize(&max).unwrap();
+        assert_eq!(&[1, 0xFF, 0xFF, 0xFF, 0xFF], &*buf);
+        assert_eq!(max, deserialize(&buf).unwrap());
+
+        let one = NonZeroU32::new(1);
+        let buf = serialize(&one).unwrap();
+        assert_eq!(&[1, 1, 0, 0, 0], &*buf);
+        assert_eq!(one, deserialize(&buf).unwrap());
+
+        assert!(deserialize::<Option<NonZeroU32>>(&[1, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn ordering() {
+        use std::cmp::Ordering;
+
+        for (byte, ord) in [Ordering::Less, Ordering::Equal, Ordering::Greater]
+            .iter()
+            .enumerate()
+        {
+            let buf = serialize(ord).unwrap();
+            assert_eq!(&[byte as u8], &*buf);
+            assert_eq!(*ord, deserialize::<Ordering>(&buf).unwrap());
+        }
+
+        assert!(deserialize::<Ordering>(&[3]).is_err());
+    }
+
+    #[test]
+    fn str_out_of_range_context() {
+        use crate::Error;
+
+        #[derive(Debug, BinDeserialize)]
+        struct Outer {
+            _name: String,
+            _inner: Inner,
+        }
+
+        #[derive(Debug, BinDeserialize)]
+        struct Inner(String);
+
+        // string table with one entry, then indices 0 and 5
+        let buf = [1, 1, 97, 0, 5];
+
+        let mode = Mode::dedup().with_error_context(true);
+        match deserialize_with::<Outer>(&buf, mode) {
+            Err(Error::InField { path, source }) => {
+                assert_eq!("_inner.0", path);
+                assert!(matches!(*source, Error::StrOutOfRange(5)));
+            }
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        let err = deserialize_with::<Outer>(&buf, mode).unwrap_err();
+        assert_eq!(
+            "in field `_inner.0`: indexed string out of range: 5",
+            err.to_string()
+        );
+
+        assert!(matches!(
+            deserialize_with::<Outer>(&buf, Mode::dedup()),
+            Err(Error::StrOutOfRange(5))
+        ));
+    }
+
+    #[test]
+    fn trailing_crc() {
+        use std::io::Cursor;
+
+        use crate::{deserialize_with_from, serialize_with_into};
+
+        let mode = Mode::dedup().with_trailing_crc(true);
+        let value: Vec<(u32, String)> = (0..100_000)
+            .map(|el| (el, format!("item {}", el % 100)))
+            .collect();
+
+        let mut buf = Cursor::new(Vec::new());
+        serialize_with_into(&mut buf, &value, mode).unwrap();
+        let mut buf = buf.into_inner();
+
+        let copy: Vec<(u32, String)> = deserialize_with_from(Cursor::new(&buf), mode).unwrap();
+        assert_eq!(value, copy);
+
+        let len = buf.len();
+        buf[len / 2] ^= 0x01;
+        assert!(deserialize_with::<Vec<(u32, String)>>(&buf, mode).is_err());
+
+        let buf = serialize_with(&5u32, Mode::default().with_trailing_crc(true)).unwrap();
+        assert_eq!(&[5, 0, 0, 0, 0x2E, 0x2F, 0x9A, 0x16], &*buf);
+    }
+
+    #[test]
+    fn large_byte_vec() {
+        use crate::util::serialize_iter;
+        use crate::{BinSerializer, Result};
+
+        // writes every byte separately, like other element types
+        struct PerElement<'a>(&'a [u8]);
+
+        impl crate::BinSerialize for PerElement<'_> {
+            fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+                serialize_iter(self.0.iter(), serializer)
+            }
+        }
+
+        let value: Vec<u8> = (0..1 << 20).map(|el| el as u8).collect();
+
+        let buf = serialize(&value).unwrap();
+        let copy: Vec<u8> = deserialize(&buf).unwrap();
+
+        assert_eq!(&[0x80, 0x80, 0x40], &buf[..3]);
+        assert_eq!(&value[..], &buf[3..]);
+        assert_eq!(value, copy);
+        assert_eq!(buf, serialize(&PerElement(&value)).unwrap());
+        assert_eq!(buf, serialize(&value[..]).unwrap());
+
+        let borrowed: &[u8] = crate::deserialize_borrowed(&buf, Mode::default()).unwrap();
+        assert_eq!(&value[..], borrowed);
+
+        // the same encoding as any other sequence
+        let generic: Vec<u16> = deserialize_with(
+            &serialize(&vec![1u8, 2, 3]).unwrap(),
+            Mode::default().with_fixed_size_use_varint(true),
+        )
+        .unwrap();
+        assert_eq!(vec![1, 2, 3], generic);
+
+        assert!(deserialize::<Vec<u8>>(&[5, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn reuse_prescan() {
+        use crate::{
+            prescan, serialize_prescanned_into, serialized_size, serialized_size_prescanned,
+        };
+
+        let mode = Mode::dedup();
+        let value = vec!["a", "b", "a", "c"];
+
+        let dedup = prescan(&value, mode).unwrap();
+        let size = serialized_size_prescanned(&value, mode, &dedup).unwrap();
+
+        let mut buf = Vec::new();
+        serialize_prescanned_into(&mut buf, &value, mode, &dedup).unwrap();
+
+        assert_eq!(serialize_with(&value, mode).unwrap(), buf);
+        assert_eq!(buf.len(), size);
+        assert_eq!(size, serialized_size(&value, mode).unwrap());
+    }
+
+    #[test]
+    fn phantom_data() {
+        use std::marker::PhantomData;
+
+        #[derive(Debug, PartialEq, Eq, BinSerialize, BinDeserialize)]
+        struct Ref<'a> {
+            id: u32,
+            _marker: PhantomData<&'a ()>,
+        }
+
+        // NotSerializable doesn't implement the traits, which is fine since
+        // it's only used as a marker
+        #[derive(Debug, PartialEq, Eq)]
+        struct NotSerializable;
+
+        #[derive(Debug, PartialEq, Eq, BinSerialize, BinDeserialize)]
+        struct Typed<'a, T, U> {
+            value: U,
+            _marker: PhantomData<&'a T>,
+        }
+
+        let r = Ref {
+            id: 5,
+            _marker: PhantomData,
+        };
+        let buf = serialize(&r).unwrap();
+        assert_eq!(&[5, 0, 0, 0], &*buf);
+        assert_eq!(r, deserialize(&buf).unwrap());
+
+        let t: Typed<NotSerializable, u8> = Typed {
+            value: 3,
+            _marker: PhantomData,
+        };
+        let buf = serialize(&t).unwrap();
+        assert_eq!(&[3], &*buf);
+        assert_eq!(t, deserialize(&buf).unwrap());
+    }
+
+    #[test]
+    fn dedup_bytes() {
+        use std::collections::HashMap;
+
+        let blob = vec![0xAB; 64];
+        let mut manifest: HashMap<String, Vec<u8>> = HashMap::new();
+        for i in 0..8 {
+            manifest.insert(format!("some/long/cache/path/{}", i), blob.clone());
+        }
+        let keys: Vec<String> = manifest.keys().cloned().collect();
+        let value = (manifest, keys);
+
+        let plain = serialize_with(&value, Mode::default()).unwrap();
+        let strings = serialize_with(&value, Mode::dedup()).unwrap();
+        let mode = Mode::dedup().with_dedup_bytes(true);
+        let both = serialize_with(&value, mode).unwrap();
+
+        assert!(strings.len() < plain.len());
+        assert!(both.len() < strings.len());
+
+        let de: (HashMap<String, Vec<u8>>, Vec<String>) = deserialize_with(&both, mode).unwrap();
+        assert_eq!(value, de);
+    }
+
+    #[test]
+    fn deny_dedup_in_keys() {
+        use std::collections::HashMap;
+
+        let mut map = HashMap::new();
+        map.insert("key".to_string(), "value".to_string());
+        let value = (map, "value".to_string());
+
+        let mode = Mode::dedup().with_deny_dedup_in_keys(true);
+        let vec = serialize_with(&value, mode).unwrap();
+
+        // the table only contains the value, the key is written inline while
+        // both occurrences of the value refer to the table
+        assert_eq!(
+            &[1, 5, b'v', b'a', b'l', b'u', b'e', 1, 3, b'k', b'e', b'y', 0, 0],
+            &*vec
+        );
+        assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+    }
+
+    #[test]
+    fn serialize_into_buffered() {
+        use std::fs::File;
+
+        let value: Vec<String> = (0..1000).map(|i| format!("entry {}", i)).collect();
+        let path = std::env::temp_dir().join(format!(
+            "binserde-serialize-into-buffered-{}.bin",
+            std::process::id()
+        ));
+
+        let file = File::create(&path).unwrap();
+        crate::serialize_into_buffered(file, &value, Mode::dedup(), 64).unwrap();
+
+        let buf = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(serialize_with(&value, Mode::dedup()).unwrap(), buf);
+        assert_eq!(
+            value,
+            deserialize_with::<Vec<String>>(&buf, Mode::dedup()).unwrap()
+        );
+    }
+
+    #[test]
+    fn zero_sized_vec() {
+        let value = vec![(); 1000];
+        let vec = serialize(&value).unwrap();
+
+        // just the length
+        assert_eq!(&[0xE8, 0x07], &*vec);
+        assert_eq!(value, deserialize::<Vec<()>>(&vec).unwrap());
+
+        let mut target = vec![(); 5];
+        crate::deserialize_extend_from(&mut target, &*vec, Mode::default()).unwrap();
+        assert_eq!(1005, target.len());
+    }
+
+    #[test]
+    fn explicit_option_tags() {
+        let mode = Mode::default().with_explicit_option_tag_byte(0xFF, 0x80);
+        let value = (Some(7u8), None::<u8>);
+
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(&[0x80, 7, 0xFF], &*vec);
+        assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+
+        assert!(deserialize_with::<Option<u8>>(&[0], mode).is_err());
+    }
+
+    #[test]
+    fn tag_variants_by_hash() {
+        #[derive(Debug, PartialEq, Eq, BinSerialize, BinDeserialize)]
+        #[binserde(tag_variants_by_hash)]
+        enum Old {
+            Start,
+            Move(i32, i32),
+            Stop { reason: String },
+        }
+
+        #[derive(Debug, PartialEq, Eq, BinSerialize, BinDeserialize)]
+        #[binserde(tag_variants_by_hash)]
+        enum New {
+            Pause,
+            Stop { reason: String },
+            Start,
+            Move(i32, i32),
+        }
+
+        let old = vec![
+            Old::Start,
+            Old::Move(3, -4),
+            Old::Stop {
+                reason: "done".to_string(),
+            },
+        ];
+        let vec = serialize(&old).unwrap();
+
+        let new: Vec<New> = deserialize(&vec).unwrap();
+        assert_eq!(
+            vec![
+                New::Start,
+                New::Move(3, -4),
+                New::Stop {
+                    reason: "done".to_string()
+                },
+            ],
+            new
+        );
+
+        let vec = serialize(&New::Pause).unwrap();
+        assert!(deserialize::<Old>(&vec).is_err());
+    }
+
+    #[test]
+    fn nested_dedup() {
+        use std::collections::HashMap;
+
+        let value: Vec<HashMap<String, Vec<String>>> = (0..20)
+            .map(|i| {
+                (0..i % 5)
+                    .map(|j| {
+                        let values = (0..j).map(|k| format!("v{}", (i + k) % 7)).collect();
+                        (format!("k{}", (i * j) % 11), values)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let vec = serialize_with(&value, Mode::dedup()).unwrap();
+        let de: Vec<HashMap<String, Vec<String>>> = deserialize_with(&vec, Mode::dedup()).unwrap();
+        assert_eq!(value, de);
+
+        // a table that starts with unrelated strings, so the indices don't
+        // line up with the order in which the value visits its strings
+        let mut dedup = crate::prescan(&["v6", "unused", "k3"], Mode::dedup()).unwrap();
+        dedup
+            .merge(&crate::prescan(&value, Mode::dedup()).unwrap())
+            .unwrap();
+
+        let mut vec = Vec::new();
+        crate::serialize_prescanned_into(&mut vec, &value, Mode::dedup(), &dedup).unwrap();
+        let de: Vec<HashMap<String, Vec<String>>> = deserialize_with(&vec, Mode::dedup()).unwrap();
+        assert_eq!(value, de);
+    }
+
+    #[test]
+    fn schema_header() {
+        use std::collections::HashMap;
+
+        use crate::schema::{deserialize_dynamic, Value};
+
+        #[derive(Debug, PartialEq, Eq, BinSerialize, BinDeserialize)]
+        struct Manifest {
+            name: String,
+            files: Vec<File>,
+            tags: HashMap<String, u8>,
+            #[binserde(skip)]
+            cached: bool,
+        }
+
+        #[derive(Debug, PartialEq, Eq, BinSerialize, BinDeserialize)]
+        enum File {
+            Missing,
+            Present(String, Option<u32>),
+            Link { target: String },
+        }
+
+        let mut tags = HashMap::new();
+        tags.insert("x".to_string(), 7);
+
+        let manifest = Manifest {
+            name: "pkg".to_string(),
+            files: vec![
+                File::Missing,
+                File::Present("pkg/a".to_string(), Some(3)),
+                File::Link {
+                    target: "pkg/a".to_string(),
+                },
+            ],
+            tags,
+            cached: false,
+        };
+
+        let mode = Mode::dedup().with_write_schema_header(true);
+        let vec = serialize_with(&manifest, mode).unwrap();
+        assert_eq!(manifest, deserialize_with(&vec, mode).unwrap());
+
+        let (_, value) = deserialize_dynamic(&vec, mode).unwrap();
+        let s = |s: &str| Value::String(s.to_string());
+        assert_eq!(
+            Value::Struct(vec![
+                ("name".to_string(), s("pkg")),
+                (
+                    "files".to_string(),
+                    Value::Seq(vec![
+                        Value::Variant("Missing".to_string(), Box::new(Value::Struct(vec![]))),
+                        Value::Variant(
+                            "Present".to_string(),
+                            Box::new(Value::Struct(vec![
+                                ("0".to_string(), s("pkg/a")),
+                                (
+                                    "1".to_string(),
+                                    Value::Option(Some(Box::new(Value::UInt(3))))
+                                ),
+                            ]))
+                        ),
+                        Value::Variant(
+                            "Link".to_string(),
+                            Box::new(Value::Struct(vec![("target".to_string(), s("pkg/a"))]))
+                        ),
+                    ])
+                ),
+                (
+                    "tags".to_string(),
+                    Value::Map(vec![(s("x"), Value::UInt(7))])
+                ),
+            ]),
+            value
+        );
+    }
+
+    #[test]
+    fn schema_unknown_fields() {
+        use crate::schema::{deserialize_dynamic, Schema};
+        use crate::BinSerialize as _;
+
+        #[derive(BinSerialize)]
+        struct Bits {
+            #[binserde(bits = 3)]
+            a: u8,
+            #[binserde(bits = 5)]
+            b: u8,
+            c: u16,
+        }
+
+        #[derive(BinSerialize)]
+        #[binserde(pack_bools)]
+        struct Packed {
+            a: bool,
+            b: bool,
+            c: u16,
+        }
+
+        let expected = Schema::Struct(vec![
+            ("a".to_string(), Schema::Unknown),
+            ("b".to_string(), Schema::Unknown),
+            ("c".to_string(), Schema::U16),
+        ]);
+        #[derive(BinSerialize)]
+        struct Fixed {
+            #[binserde(fixed_len = 4)]
+            a: String,
+            #[binserde(fixed_len = 4)]
+            b: String,
+            c: u16,
+        }
+
+        assert_eq!(expected, Bits::schema());
+        assert_eq!(expected, Packed::schema());
+        assert_eq!(expected, Fixed::schema());
+
+        let mode = Mode::default().with_write_schema_header(true);
+        let vec = serialize_with(&Bits { a: 1, b: 2, c: 3 }, mode).unwrap();
+        let err = deserialize_dynamic(&vec, mode).unwrap_err();
+        assert_eq!("can't read value of unknown type", err.to_string());
+
+        let value = Fixed {
+            a: "a".to_string(),
+            b: "b".to_string(),
+            c: 3,
+        };
+        let vec = serialize_with(&value, mode).unwrap();
+        let err = deserialize_dynamic(&vec, mode).unwrap_err();
+        assert_eq!("can't read value of unknown type", err.to_string());
+    }
+
+    #[test]
+    fn schema_no_dedup() {
+        use crate::schema::{deserialize_dynamic, Value};
+
+        #[derive(BinSerialize)]
+        struct Names {
+            #[binserde(no_dedup)]
+            a: String,
+            b: String,
+            #[binserde(skip_dedup_scan)]
+            c: Vec<String>,
+        }
+
+        let value = Names {
+            a: "x".to_string(),
+            b: "y".to_string(),
+            c: vec!["x".to_string()],
+        };
+
+        let mode = Mode::dedup().with_write_schema_header(true);
+        let vec = serialize_with(&value, mode).unwrap();
+        let (_, value) = deserialize_dynamic(&vec, mode).unwrap();
+        let s = |s: &str| Value::String(s.to_string());
+        assert_eq!(
+            Value::Struct(vec![
+                ("a".to_string(), s("x")),
+                ("b".to_string(), s("y")),
+                ("c".to_string(), Value::Seq(vec![s("x")])),
+            ]),
+            value
+        );
+    }
+
+    #[test]
+    fn schema_floats() {
+        use crate::schema::{deserialize_dynamic, Value};
+
+        #[derive(BinSerialize)]
+        struct Point {
+            x: f64,
+            y: f32,
+        }
+
+        let base = Mode::default().with_write_schema_header(true);
+        let modes = [
+            base,
+            base.with_big_endian(true),
+            base.with_stable_float_repr(true),
+        ];
+
+        for mode in modes {
+            let vec = serialize_with(&Point { x: -1.5, y: 0.25 }, mode).unwrap();
+            let (_, value) = deserialize_dynamic(&vec, mode).unwrap();
+            assert_eq!(
+                Value::Struct(vec![
+                    ("x".to_string(), Value::Float(-1.5)),
+                    ("y".to_string(), Value::Float(0.25)),
+                ]),
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn schema_shared_values() {
+        use std::rc::Rc;
+        use std::sync::Arc;
+
+        use crate::dedup::{Dedup, Shared};
+        use crate::schema::{deserialize_dynamic, Value};
+
+        let a = Arc::new(5u32);
+        let value = (
+            a.clone(),
+            a,
+            Rc::new("x".to_string()),
+            Dedup(7u16),
+            Shared::new(vec![1u32]),
+        );
+
+        let expected = Value::Tuple(vec![
+            Value::UInt(5),
+            Value::UInt(5),
+            Value::String("x".to_string()),
+            Value::UInt(7),
+            Value::Seq(vec![Value::UInt(1)]),
+        ]);
+
+        let base = Mode::default().with_write_schema_header(true);
+        let modes = [
+            base,
+            Mode::dedup().with_write_schema_header(true),
+            Mode::dedup()
+                .with_dedup_bytes(true)
+                .with_write_schema_header(true),
+        ];
+
+        for mode in modes {
+            let vec = serialize_with(&value, mode).unwrap();
+            let (_, dynamic) = deserialize_dynamic(&vec, mode).unwrap();
+            assert_eq!(expected, dynamic, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn schema_dedup_collections() {
+        use crate::schema::{deserialize_dynamic, Value};
+
+        let value = (
+            vec![1u32, 2],
+            vec![1u32, 2],
+            vec!["a".to_string()],
+            vec![vec![3u8], vec![3u8]],
+        );
+
+        let int = Value::UInt;
+        let expected = Value::Tuple(vec![
+            Value::Seq(vec![int(1), int(2)]),
+            Value::Seq(vec![int(1), int(2)]),
+            Value::Seq(vec![Value::String("a".to_string())]),
+            Value::Seq(vec![Value::Bytes(vec![3]), Value::Bytes(vec![3])]),
+        ]);
+
+        let modes = [
+            Mode::dedup()
+                .with_dedup_bytes(true)
+                .with_deduplicate_identical_collections(true),
+            Mode::compact(),
+        ];
+
+        for mode in modes {
+            let mode = mode.with_write_schema_header(true);
+            let vec = serialize_with(&value, mode).unwrap();
+            let (_, dynamic) = deserialize_dynamic(&vec, mode).unwrap();
+            assert_eq!(expected, dynamic, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn schema_compact_options() {
+        use crate::schema::{deserialize_dynamic, Value};
+
+        let mode = Mode::default()
+            .with_compact_collection_options(true)
+            .with_write_schema_header(true);
+
+        let vec = serialize_with(&(Some(vec![1u8, 2]), 7u8), mode).unwrap();
+        assert!(deserialize_dynamic(&vec, mode).is_err());
+
+        // options of other types are still written with a tag
+        let vec = serialize_with(&(Some(1u8), 7u8), mode).unwrap();
+        let (_, value) = deserialize_dynamic(&vec, mode).unwrap();
+        assert_eq!(
+            Value::Tuple(vec![
+                Value::Option(Some(Box::new(Value::UInt(1)))),
+                Value::UInt(7)
+            ]),
+            value
+        );
+    }
+
+    #[test]
+    fn deterministic() {
+        use std::collections::{HashMap, HashSet};
+
+        let words = [
+            "one", "two", "three", "four", "five", "six", "seven", "eight",
+        ];
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let mode = mode.with_deterministic(true);
+
+            // separately created sets have different hash seeds, so they
+            // iterate in different orders
+            let a: HashSet<String> = words.iter().map(|s| s.to_string()).collect();
+            let b: HashSet<String> = words.iter().rev().map(|s| s.to_string()).collect();
+            let vec = serialize_with(&a, mode).unwrap();
+            assert_eq!(vec, serialize_with(&b, mode).unwrap());
+            assert_eq!(a, deserialize_with(&vec, mode).unwrap());
+
+            let a: HashMap<String, usize> =
+                words.iter().map(|s| (s.to_string(), s.len())).collect();
+            let b: HashMap<String, usize> = words
+                .iter()
+                .rev()
+                .map(|s| (s.to_string(), s.len()))
+                .collect();
+            let vec = serialize_with(&a, mode).unwrap();
+            assert_eq!(vec, serialize_with(&b, mode).unwrap());
+            assert_eq!(a, deserialize_with(&vec, mode).unwrap());
+        }
+    }
+
+    #[test]
+    fn float_bit_patterns() {
+        let f32s = [
+            0x0000_0000, // 0.0
+            0x8000_0000, // -0.0
+            0x0000_0001, // smallest subnormal
+            0x807F_FFFF, // largest negative subnormal
+            0x0080_0000, // smallest normal
+            0x7F7F_FFFF, // max
+            0x7F80_0000, // infinity
+            0xFF80_0000, // -infinity
+            0x7FC0_0000, // canonical NaN
+            0x7FA0_1234, // signaling NaN with payload
+            0xFFC0_BEEF, // negative quiet NaN with payload
+        ];
+
+        for bits in f32s {
+            let vec = serialize(&f32::from_bits(bits)).unwrap();
+            assert_eq!(bits.to_le_bytes(), &*vec);
+            assert_eq!(bits, deserialize::<f32>(&vec).unwrap().to_bits());
+        }
+
+        let f64s = [
+            0x0000_0000_0000_0000,
+            0x8000_0000_0000_0000,
+            0x0000_0000_0000_0001,
+            0x800F_FFFF_FFFF_FFFF,
+            0x7FEF_FFFF_FFFF_FFFF,
+            0xFFF0_0000_0000_0000,
+            0x7FF8_0000_0000_0000,
+            0x7FF4_0000_DEAD_BEEF,
+            0xFFF8_0000_0000_0001,
+        ];
+
+        let values: Vec<f64> = f64s.iter().map(|el| f64::from_bits(*el)).collect();
+        let mode = Mode::default().with_fixed_size_use_varint(true);
+        let vec = serialize_with(&values, mode).unwrap();
+        let de: Vec<f64> = deserialize_with(&vec, mode).unwrap();
+        assert_eq!(
+            &f64s[..],
+            &*de.iter().map(|el| el.to_bits()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn dedup_eq_by_content_hash() {
+        let value: Vec<String> = (0..100).map(|i| format!("s{}", i % 13)).collect();
+        let blobs: Vec<Vec<u8>> = (0..20).map(|i| vec![i % 3; 16]).collect();
+        let value = (value, blobs);
+
+        let mode = Mode::dedup().with_dedup_bytes(true);
+        let vec = serialize_with(&value, mode).unwrap();
+        let hashed = serialize_with(&value, mode.with_dedup_eq_by_content_hash(true)).unwrap();
+
+        assert_eq!(vec, hashed);
+        assert_eq!(value, deserialize_with(&hashed, mode).unwrap());
+    }
+
+    #[test]
+    fn cow_key_dedup() {
+        use std::borrow::Cow;
+        use std::collections::HashMap;
+
+        let mut map: HashMap<Cow<str>, String> = HashMap::new();
+        map.insert(Cow::Borrowed("shared"), "shared".to_string());
+
+        let vec = serialize_with(&map, Mode::dedup()).unwrap();
+
+        // a single table entry used by both the key and the value
+        assert_eq!(&[1, 6, b's', b'h', b'a', b'r', b'e', b'd', 1, 0, 0], &*vec);
+
+        let de: HashMap<Cow<str>, String> = deserialize_with(&vec, Mode::dedup()).unwrap();
+        assert_eq!(map, de);
+    }
+
+    #[test]
+    fn cow_fields() {
+        use std::borrow::Cow;
+
+        use crate::BinSerialize as _;
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        struct Entry<'a> {
+            name: String,
+            label: Cow<'a, str>,
+            #[binserde(no_dedup)]
+            raw_label: Cow<'a, str>,
+            data: Cow<'a, [u16]>,
+        }
+
+        let data = [1, 2, 3];
+        let borrowed = Entry {
+            name: "shared".to_string(),
+            label: Cow::Borrowed("shared"),
+            raw_label: Cow::Borrowed("shared"),
+            data: Cow::Borrowed(&data),
+        };
+        let owned = Entry {
+            name: "shared".to_string(),
+            label: Cow::Owned("shared".to_string()),
+            raw_label: Cow::Owned("shared".to_string()),
+            data: Cow::Owned(data.to_vec()),
+        };
+
+        assert_eq!(&["name", "label"], Entry::dedup_fields());
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let vec = serialize_with(&borrowed, mode).unwrap();
+            assert_eq!(vec, serialize_with(&owned, mode).unwrap());
+
+            let out: Entry = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(borrowed, out);
+            assert!(matches!(out.label, Cow::Owned(_)));
+        }
+
+        // the String and the Cow<str> share one table entry, the field marked
+        // no_dedup is written in place
+        let vec = serialize_with(&borrowed, Mode::dedup()).unwrap();
+        let header = [1, 6, b's', b'h', b'a', b'r', b'e', b'd'];
+        assert_eq!(&header, &vec[..8]);
+        assert_eq!(&[0, 0], &vec[8..10]);
+        assert_eq!(&header[1..], &vec[10..17]);
+        assert_eq!(&[3, 1, 0, 2, 0, 3, 0], &vec[17..]);
+    }
+
+    #[test]
+    fn bit_packed_fields() {
+        use crate::bits::BitField;
+
+        #[derive(Debug, Clone, Copy, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        #[binserde(repr_width = 2)]
+        enum Color {
+            Red,
+            Green,
+            Blue,
+        }
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Pixel {
+            #[binserde(bits = 1)]
+            visible: bool,
+            #[binserde(bits = 2)]
+            color: Color,
+            #[binserde(bits = 1)]
+            dirty: bool,
+            #[binserde(bits = 5)]
+            alpha: u8,
+            layer: u8,
+        }
+
+        let pixel = Pixel {
+            visible: true,
+            color: Color::Blue,
+            dirty: false,
+            alpha: 31,
+            layer: 7,
+        };
+
+        let vec = serialize(&pixel).unwrap();
+        assert_eq!(&[0b1111_0101, 0b1, 7], &*vec);
+        assert_eq!(pixel, deserialize(&vec).unwrap());
+
+        let mut target = Pixel {
+            visible: false,
+            color: Color::Red,
+            dirty: true,
+            alpha: 0,
+            layer: 0,
+        };
+        crate::deserialize_in_place(&mut target, &*vec, Mode::default()).unwrap();
+        assert_eq!(pixel, target);
+
+        assert_eq!(1, Color::Green.to_bits());
+        assert!(Color::from_bits(3).is_err());
+
+        let too_wide = Pixel { alpha: 32, ..pixel };
+        assert!(serialize(&too_wide).is_err());
+    }
+
+    #[test]
+    fn fixed_len_strings() {
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Row {
+            #[binserde(fixed_len = 3)]
+            code: String,
+            name: String,
+        }
+
+        #[derive(BinSerialize)]
+        struct BorrowedRow<'a> {
+            #[binserde(fixed_len = 3)]
+            code: &'a str,
+        }
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let exact = Row {
+                code: "EUR".to_string(),
+                name: "Euro".to_string(),
+            };
+            let vec = serialize_with(&exact, mode).unwrap();
+            assert_eq!(exact, deserialize_with(&vec, mode).unwrap());
+
+            let short = Row {
+                code: "A".to_string(),
+                name: "A".to_string(),
+            };
+            let vec = serialize_with(&short, mode).unwrap();
+            assert_eq!(short, deserialize_with(&vec, mode).unwrap());
+
+            let mut target = Row {
+                code: "XYZ".to_string(),
+                name: String::new(),
+            };
+            crate::deserialize_in_place(&mut target, &*vec, mode).unwrap();
+            assert_eq!(short, target);
+
+            let long = Row {
+                code: "EURO".to_string(),
+                name: "Euro".to_string(),
+            };
+            assert!(serialize_with(&long, mode).is_err());
+        }
+
+        assert_eq!(b"AB\0", &*serialize(&BorrowedRow { code: "AB" }).unwrap());
+        assert!(serialize(&BorrowedRow { code: "ABCD" }).is_err());
+    }
+
+    #[test]
+    fn phantom_pinned() {
+        use std::marker::PhantomPinned;
+
+        #[derive(Debug, BinSerialize, BinDeserialize)]
+        struct Node {
+            value: u32,
+            _pin: PhantomPinned,
+        }
+
+        let vec = serialize(&Node {
+            value: 5,
+            _pin: PhantomPinned,
+        })
+        .unwrap();
+        assert_eq!(&[5, 0, 0, 0], &*vec);
+
+        let node: Node = deserialize(&vec).unwrap();
+        assert_eq!(5, node.value);
+    }
+
+    #[test]
+    fn tag_depth() {
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Inner {
+            a: u8,
+        }
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Outer {
+            id: u8,
+            inner: Inner,
+        }
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Swapped {
+            inner: Inner,
+            id: u8,
+        }
+
+        let value = Outer {
+            id: 1,
+            inner: Inner { a: 2 },
+        };
+
+        let mode = Mode::default().with_tag_depth(1);
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(
+            &[2, b'i', b'd', 1, 5, b'i', b'n', b'n', b'e', b'r', 2],
+            &*vec
+        );
+        assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+
+        let mut target = Outer {
+            id: 0,
+            inner: Inner { a: 0 },
+        };
+        crate::deserialize_in_place(&mut target, &*vec, mode).unwrap();
+        assert_eq!(value, target);
+
+        // a positional read would succeed here since both fields are bytes
+        assert!(deserialize_with::<Swapped>(&vec, mode).is_err());
+
+        let mode = Mode::default().with_tag_depth(2);
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(
+            &[2, b'i', b'd', 1, 5, b'i', b'n', b'n', b'e', b'r', 1, b'a', 2],
+            &*vec
+        );
+        assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+    }
+
+    #[test]
+    fn max_map_entries() {
+        use std::collections::HashMap;
+
+        let mode = Mode::default().with_max_map_entries(Some(2));
+
+        let map: HashMap<u8, u8> = vec![(1, 2), (3, 4)].into_iter().collect();
+        let vec = serialize_with(&map, mode).unwrap();
+        assert_eq!(map, deserialize_with(&vec, mode).unwrap());
+
+        // claims u32::MAX entries, followed by a single one
+        let crafted = [0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 1, 2];
+        assert!(matches!(
+            deserialize_with::<HashMap<u8, u8>>(&crafted, mode),
+            Err(crate::Error::TooManyMapEntries(0xFFFF_FFFF, 2))
+        ));
+
+        // other collections aren't affected
+        let vec = serialize_with(&vec![1u8, 2, 3], mode).unwrap();
+        assert_eq!(
+            vec![1u8, 2, 3],
+            deserialize_with::<Vec<u8>>(&vec, mode).unwrap()
+        );
+    }
+
+    #[test]
+    fn sentinel_delimited_records() {
+        use crate::deserialize_until_sentinel_from;
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Record {
+            id: u16,
+            name: String,
+        }
+
+        let records = [
+            Record {
+                id: 1,
+                name: "a".to_string(),
+            },
+            Record {
+                id: 2,
+                name: "bc".to_string(),
+            },
+        ];
+
+        let mut buf = Vec::new();
+
+        for record in records.iter() {
+            buf.extend(serialize(record).unwrap());
+            buf.push(0x00);
+        }
+
+        let mut pipe = &*buf;
+
+        for record in records.iter() {
+            let read: Record =
+                deserialize_until_sentinel_from(&mut pipe, 0x00, Mode::default()).unwrap();
+            assert_eq!(record, &read);
+        }
+
+        assert!(pipe.is_empty());
+
+        // missing terminator
+        let vec = serialize(&records[0]).unwrap();
+        assert!(
+            deserialize_until_sentinel_from::<_, Record>(&*vec, 0x00, Mode::default()).is_err()
+        );
+
+        // value followed by more data before the terminator
+        let buf = [1, 2, 3, 0];
+        assert!(
+            deserialize_until_sentinel_from::<_, (u8, u8)>(&buf[..], 0x00, Mode::default())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn optimize_for() {
+        use crate::serde::{OptimizeFor, UsizeLen};
+
+        let base = Mode::dedup()
+            .with_pack_bools(true)
+            .with_fixed_size_use_varint(true)
+            .with_varint_group_encoding(true)
+            .with_compact_collection_options(true)
+            .with_dedup_bytes(true)
+            .with_deduplicate_identical_collections(true)
+            .with_trailing_crc(true);
+        let speed = base.with_optimize_for(OptimizeFor::Speed);
+
+        assert_eq!(
+            Mode {
+                usize_len: UsizeLen::U64,
+                fixed_size_use_varint: false,
+                pack_bools: false,
+                varint_group_encoding: false,
+                compact_options: false,
+                dedup_bytes: false,
+                dedup_collections: false,
+                use_dedup: false,
+                ..base
+            },
+            speed
+        );
+
+        let size = Mode::default()
+            .with_fixed_signed(true)
+            .with_usize_len(UsizeLen::U32)
+            .with_error_context(true)
+            .with_optimize_for(OptimizeFor::Size);
+
+        assert_eq!(
+            Mode {
+                error_context: true,
+                fixed_size_use_varint: true,
+                pack_bools: true,
+                varint_group_encoding: true,
+                compact_options: true,
+                dedup_bytes: true,
+                dedup_collections: true,
+                use_dedup: true,
+                ..Mode::default()
+            },
+            size
+        );
+
+        let value = (
+            vec!["a".to_string(), "a".to_string()],
+            -1i64,
+            300u32,
+            Some(vec![1u32, 2, 3]),
+        );
+
+        for mode in [speed, size] {
+            let vec = serialize_with(&value, mode).unwrap();
+            assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+        }
+
+        assert!(
+            serialize_with(&value, size).unwrap().len()
+                < serialize_with(&value, speed).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn dedup_table_validated_once() {
+        // the table is validated as a whole when it is read, so an invalid
+        // entry fails even if nothing refers to it
+        let buf = [1, 1, 0xFF, 7];
+        assert!(matches!(
+            deserialize_with::<u8>(&buf, Mode::dedup()),
+            Err(crate::Error::InvalidUtf8(_))
+        ));
+
+        // references only copy the already validated entry
+        let buf = [1, 2, b'h', b'i', 3, 0, 0, 0];
+        assert_eq!(
+            vec!["hi".to_string(); 3],
+            deserialize_with::<Vec<String>>(&buf, Mode::dedup()).unwrap()
+        );
+    }
+
+    #[test]
+    fn field_index() {
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Moved {
+            a: u8,
+            #[binserde(index = 0)]
+            b: u8,
+            #[binserde(skip)]
+            skipped: u8,
+            c: u16,
+            #[binserde(index = 1)]
+            d: u8,
+            e: u8,
+        }
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Tuple(u8, #[binserde(index = 0)] u8, u8);
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        enum E {
+            V {
+                x: u8,
+                #[binserde(index = 0)]
+                y: u8,
+            },
+        }
+
+        let value = Moved {
+            a: 1,
+            b: 2,
+            skipped: 0,
+            c: 3,
+            d: 4,
+            e: 5,
+        };
+
+        // b, c, d, e moved before a, then d, e moved to position 1; the
+        // skipped field isn't counted
+        let vec = serialize(&value).unwrap();
+        assert_eq!(&[2, 4, 5, 3, 0, 1], &*vec);
+        assert_eq!(value, deserialize(&vec).unwrap());
+
+        let mut target = Moved {
+            a: 0,
+            b: 0,
+            skipped: 9,
+            c: 0,
+            d: 0,
+            e: 0,
+        };
+        crate::deserialize_in_place(&mut target, &*vec, Mode::default()).unwrap();
+        assert_eq!(value, target);
+
+        let vec = serialize(&Tuple(1, 2, 3)).unwrap();
+        assert_eq!(&[2, 3, 1], &*vec);
+        assert_eq!(Tuple(1, 2, 3), deserialize(&vec).unwrap());
+
+        let value = E::V { x: 1, y: 2 };
+        let vec = serialize(&value).unwrap();
+        assert_eq!(&[0, 2, 1], &*vec);
+        assert_eq!(value, deserialize(&vec).unwrap());
+    }
+
+    #[test]
+    fn custom_field_functions() {
+        use crate::{BinDeserialize, BinDeserializer, BinSerialize, BinSerializer, Result};
+
+        use instant_secs::Instant;
+
+        mod instant_secs {
+            use crate::{BinDeserialize, BinDeserializer, BinSerialize, BinSerializer, Result};
+
+            // doesn't implement the traits
+            #[derive(Debug, Eq, PartialEq)]
+            pub struct Instant(pub u64);
+
+            pub fn serialize<S: BinSerializer>(value: &Instant, serializer: S) -> Result<()> {
+                ((value.0 / 1000) as u32).serialize(serializer)
+            }
+
+            pub fn deserialize<'de, D: BinDeserializer<'de>>(deserializer: D) -> Result<Instant> {
+                Ok(Instant(u32::deserialize(deserializer)? as u64 * 1000))
+            }
+        }
+
+        fn write_reversed<S: BinSerializer>(value: &str, serializer: S) -> Result<()> {
+            value
+                .chars()
+                .rev()
+                .collect::<String>()
+                .serialize(serializer)
+        }
+
+        fn read_reversed<'de, D: BinDeserializer<'de>>(deserializer: D) -> Result<String> {
+            Ok(String::deserialize(deserializer)?.chars().rev().collect())
+        }
+
+        fn read_lenient_bool<'de, D: BinDeserializer<'de>>(deserializer: D) -> Result<bool> {
+            Ok(u8::deserialize(deserializer)? != 0)
+        }
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Event {
+            #[binserde(with = "instant_secs")]
+            at: Instant,
+            #[binserde(serialize_with = "write_reversed", deserialize_with = "read_reversed")]
+            name: String,
+            #[binserde(deserialize_with = "read_lenient_bool")]
+            flag: bool,
+        }
+
+        let value = Event {
+            at: Instant(5000),
+            name: "ab".to_string(),
+            flag: true,
+        };
+
+        let vec = serialize(&value).unwrap();
+        assert_eq!(&[5, 0, 0, 0, 2, b'b', b'a', 0xFF], &*vec);
+        assert_eq!(value, deserialize(&vec).unwrap());
+
+        let mut target = Event {
+            at: Instant(0),
+            name: String::new(),
+            flag: false,
+        };
+        crate::deserialize_in_place(
+            &mut target,
+            &[5, 0, 0, 0, 2, b'b', b'a', 7][..],
+            Mode::default(),
+        )
+        .unwrap();
+        assert_eq!(value, target);
+
+        let vec = serialize_with(&value, Mode::dedup()).unwrap();
+        assert_eq!(value, deserialize_with(&vec, Mode::dedup()).unwrap());
+    }
+
+    #[test]
+    fn skip_dedup_scan() {
+        use std::cell::Cell;
+
+        use crate::{BinSerializer, Result};
+
+        struct Opaque {
+            visits: Cell<u32>,
+        }
+
+        impl crate::BinSerialize for Opaque {
+            fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+                self.visits.set(self.visits.get() + 1);
+                [1u8, 2, 3].serialize(serializer)
+            }
+        }
+
+        #[derive(BinSerialize)]
+        struct S {
+            name: String,
+            #[binserde(skip_dedup_scan)]
+            opaque: Opaque,
+            #[binserde(skip_dedup_scan)]
+            note: String,
+        }
+
+        let value = S {
+            name: "a".to_string(),
+            opaque: Opaque {
+                visits: Cell::new(0),
+            },
+            note: "a".to_string(),
+        };
+
+        let dedup = crate::prescan(&value, Mode::dedup()).unwrap();
+        assert_eq!(0, value.opaque.visits.get());
+        assert_eq!(Some("a"), dedup.get_str(0));
+        assert_eq!(None, dedup.get_str(1));
+
+        let vec = serialize_with(&value, Mode::dedup()).unwrap();
+        assert_eq!(1, value.opaque.visits.get());
+        // the note is written inline even though it is in the table
+        assert_eq!(&[1, 1, b'a', 0, 1, 2, 3, 1, b'a'], &*vec);
+        assert_eq!(&["name"], <S as crate::BinSerialize>::dedup_fields());
+    }
+
+    #[test]
+    fn byte_map_keys() {
+        use std::collections::HashMap;
+
+        let map: HashMap<Vec<u8>, u8> = vec![(vec![1, 2, 3], 4)].into_iter().collect();
+
+        // written as length and bytes, not element by element
+        let vec = serialize(&map).unwrap();
+        assert_eq!(&[1, 3, 1, 2, 3, 4], &*vec);
+        assert_eq!(map, deserialize(&vec).unwrap());
+
+        let mode = Mode::dedup().with_dedup_bytes(true);
+        let maps = vec![map.clone(), map.clone()];
+        let vec = serialize_with(&maps, mode).unwrap();
+        // empty string table, one byte blob, then both maps referring to it
+        assert_eq!(&[0, 1, 3, 1, 2, 3, 2, 1, 0, 4, 1, 0, 4], &*vec);
+        assert_eq!(maps, deserialize_with::<Vec<_>>(&vec, mode).unwrap());
+
+        let big: HashMap<Vec<u8>, u32> = (0..10_000u32)
+            .map(|el| (el.to_le_bytes().repeat(8), el))
+            .collect();
+
+        for mode in [Mode::default(), mode] {
+            let vec = serialize_with(&big, mode).unwrap();
+            assert_eq!(big, deserialize_with(&vec, mode).unwrap());
+        }
+
+        // 32 bytes per key plus its length and a 4 byte value
+        let vec = serialize(&big).unwrap();
+        assert_eq!(2 + 10_000 * (1 + 32 + 4), vec.len());
+    }
+
+    #[test]
+    fn portable_pointer_sized_ints() {
+        use crate::serde::UsizeLen;
+
+        let large = 1u64 << 40;
+
+        for mode in [
+            Mode::default(),
+            Mode::default().with_usize_len(UsizeLen::U64),
+        ] {
+            // usize is written with the same bytes as u64 when the length is
+            // fixed, and as a varint otherwise
+            let vec = serialize_with(&(large as usize), mode).unwrap();
+
+            if mode.usize_len == UsizeLen::U64 {
+                assert_eq!(large, deserialize_with::<u64>(&vec, mode).unwrap());
+            }
+
+            #[cfg(target_pointer_width = "64")]
+            assert_eq!(
+                large as usize,
+                deserialize_with::<usize>(&vec, mode).unwrap()
+            );
+
+            #[cfg(target_pointer_width = "32")]
+            assert!(matches!(
+                deserialize_with::<usize>(&vec, mode),
+                Err(crate::Error::UsizeOverflow(v)) if v == large
+            ));
+        }
+
+        for mode in [
+            Mode::default(),
+            Mode::default().with_fixed_size_use_varint(true),
+        ] {
+            let vec = serialize_with(&-5isize, mode).unwrap();
+            assert_eq!(serialize_with(&-5i64, mode).unwrap(), vec);
+            assert_eq!(-5isize, deserialize_with(&vec, mode).unwrap());
+        }
+    }
+
+    #[test]
+    fn shared_dedup_table() {
+        use crate::dedup::DedupContext;
+        use crate::{deserialize_shared_from, serialize_shared_into};
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Record {
+            host: String,
+            message: String,
+        }
+
+        let first = Record {
+            host: "build-server-01".to_string(),
+            message: "started".to_string(),
+        };
+        let second = Record {
+            host: "build-server-01".to_string(),
+            message: "finished".to_string(),
+        };
+
+        for mode in [
+            Mode::dedup(),
+            Mode::dedup().with_dedup_bytes(true),
+            Mode::dedup().with_prefix_total_len(true),
+        ] {
+            let mut dedup = DedupContext::new();
+            let mut records = Vec::new();
+            serialize_shared_into(&mut records, &first, mode, &mut dedup).unwrap();
+            serialize_shared_into(&mut records, &second, mode, &mut dedup).unwrap();
+
+            let mut file = Vec::new();
+            dedup.write_header(&mut file, mode).unwrap();
+            file.extend_from_slice(&records);
+
+            let host = b"build-server-01";
+            let count = file.windows(host.len()).filter(|w| w == host).count();
+            assert_eq!(1, count);
+
+            let mut pipe = &*file;
+            let dedup = DedupContext::read_header(&mut pipe, mode).unwrap();
+            let a: Record = deserialize_shared_from(&mut pipe, mode, &dedup).unwrap();
+            let b: Record = deserialize_shared_from(&mut pipe, mode, &dedup).unwrap();
+            assert_eq!(first, a);
+            assert_eq!(second, b);
+            assert!(pipe.is_empty());
+        }
+    }
+
+    #[test]
+    fn borrowed() {
+        use crate::deserialize_borrowed;
+        use crate::serde::UsizeLen;
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct View<'a> {
+            id: u32,
+            name: &'a str,
+            data: &'a [u8],
+            tags: Vec<&'a str>,
+            owned: String,
+        }
+
+        let view = View {
+            id: 7,
+            name: "borrowed",
+            data: &[1, 2, 3],
+            tags: vec!["a", "b"],
+            owned: "owned".to_string(),
+        };
+
+        for mode in [
+            Mode::default(),
+            Mode::default().with_string_len_prefix(Some(UsizeLen::U8)),
+            Mode::default().with_prefix_total_len(true),
+            Mode::default().with_trailing_crc(true),
+            Mode::default().with_write_schema_header(true),
+        ] {
+            let buf = serialize_with(&view, mode).unwrap();
+            let out: View = deserialize_borrowed(&buf, mode).unwrap();
+            assert_eq!(view, out);
+
+            let range = buf.as_ptr_range();
+            assert!(range.contains(&out.name.as_ptr()));
+            assert!(range.contains(&out.data.as_ptr()));
+        }
+
+        let mode = Mode::dedup();
+        let buf = serialize_with(&view, mode).unwrap();
+        assert!(deserialize_borrowed::<View>(&buf, mode).is_err());
+
+        // owned values still work with dedup
+        let buf = serialize_with(&(view.owned.clone(), view.owned.clone()), mode).unwrap();
+        let out: (String, String) = deserialize_borrowed(&buf, mode).unwrap();
+        assert_eq!((view.owned.clone(), view.owned.clone()), out);
+
+        let mode = Mode::default().with_trailing_crc(true);
+        let mut buf = serialize_with(&view, mode).unwrap();
+        buf[6] ^= 1;
+        assert!(deserialize_borrowed::<View>(&buf, mode).is_err());
+        assert!(deserialize_borrowed::<View>(&buf[..10], Mode::default()).is_err());
+    }
+
+    #[test]
+    fn rest_field() {
+        use crate::{deserialize_with_from, serialize_with_into};
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Packet {
+            kind: u8,
+            #[binserde(rest)]
+            payload: Vec<u8>,
+        }
+
+        let first = Packet {
+            kind: 1,
+            payload: vec![0xAA, 0xBB, 0xCC],
+        };
+        let second = Packet {
+            kind: 2,
+            payload: vec![],
+        };
+
+        let mode = Mode::default().with_prefix_total_len(true);
+        let mut buf = Vec::new();
+        serialize_with_into(&mut buf, &first, mode).unwrap();
+        serialize_with_into(&mut buf, &second, mode).unwrap();
+        assert_eq!(&[4, 1, 0xAA, 0xBB, 0xCC, 1, 2], &*buf);
+
+        let mut pipe = &*buf;
+        let a: Packet = deserialize_with_from(&mut pipe, mode).unwrap();
+        let b: Packet = deserialize_with_from(&mut pipe, mode).unwrap();
+        assert_eq!(first, a);
+        assert_eq!(second, b);
+        assert!(pipe.is_empty());
+
+        // without framing, the rest of the input is consumed
+        let buf = serialize(&first).unwrap();
+        assert_eq!(first, deserialize(&buf).unwrap());
+    }
+
+    #[test]
+    fn repr_transparent() {
+        use std::marker::PhantomData;
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        #[repr(transparent)]
+        struct Name(String);
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        #[repr(transparent)]
+        struct Meters<T> {
+            value: u32,
+            _unit: PhantomData<T>,
+        }
+
+        // without the repr, the field is tagged like in any other struct
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Plain(String);
+
+        let name = Name("name".to_string());
+        let meters: Meters<()> = Meters {
+            value: 12,
+            _unit: PhantomData,
+        };
+
+        for mode in [
+            Mode::default(),
+            Mode::dedup(),
+            Mode::default().with_tag_depth(1),
+            Mode::default().with_write_schema_header(true),
+        ] {
+            let vec = serialize_with(&name, mode).unwrap();
+            assert_eq!(serialize_with(&name.0, mode).unwrap(), vec);
+            assert_eq!(name, deserialize_with(&vec, mode).unwrap());
+
+            let vec = serialize_with(&meters, mode).unwrap();
+            assert_eq!(serialize_with(&12u32, mode).unwrap(), vec);
+            assert_eq!(meters, deserialize_with(&vec, mode).unwrap());
+        }
+
+        let mode = Mode::default().with_tag_depth(1);
+        assert_ne!(
+            serialize_with(&name.0, mode).unwrap(),
+            serialize_with(&Plain(name.0.clone()), mode).unwrap()
+        );
+        assert_eq!(
+            <String as crate::BinSerialize>::schema(),
+            <Name as crate::BinSerialize>::schema()
+        );
+    }
+
+    #[test]
+    fn big_endian() {
+        use crate::serde::UsizeLen;
+
+        let be = Mode::default().with_big_endian(true);
+
+        assert_eq!(&[0x02, 0x01], &*serialize(&0x0102u16).unwrap());
+        assert_eq!(&[0x01, 0x02], &*serialize_with(&0x0102u16, be).unwrap());
+        assert_eq!(
+            &[0xFF, 0xFF, 0xFF, 0xFE],
+            &*serialize_with(&-2i32, be).unwrap()
+        );
+        assert_eq!(
+            &[0x3F, 0xF0, 0, 0, 0, 0, 0, 0],
+            &*serialize_with(&1.0f64, be).unwrap()
+        );
+
+        let mode = be.with_usize_len(UsizeLen::U16);
+        assert_eq!(
+            &[0, 2, 7, 8],
+            &*serialize_with(&vec![7u8, 8], mode).unwrap()
+        );
+
+        // varints have no byte order
+        let mode = be.with_fixed_size_use_varint(true);
+        assert_eq!(
+            serialize_with(&300u32, Mode::default().with_fixed_size_use_varint(true)).unwrap(),
+            serialize_with(&300u32, mode).unwrap()
+        );
+
+        let value = (0x0102u16, -5i64, 2.5f32, vec![1u32, 2], "be".to_string());
+
+        for mode in [
+            be,
+            be.with_usize_len(UsizeLen::U64),
+            be.with_dedup_bytes(true),
+        ] {
+            let vec = serialize_with(&value, mode).unwrap();
+            assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+        }
+    }
+
+    #[test]
+    fn dedup_index_width() {
+        use crate::serde::UsizeLen;
+        use crate::Error;
+
+        let mode = Mode::dedup().with_dedup_index_width(UsizeLen::U32);
+        let value = vec!["a", "b", "a"];
+        let vec = serialize_with(&value, mode).unwrap();
+
+        assert_eq!(
+            &[
+                0x02, 0x01, b'a', 0x01, b'b', // table
+                0x03, // length
+                0x00, 0x00, 0x00, 0x00, // indices
+                0x01, 0x00, 0x00, 0x00, //
+                0x00, 0x00, 0x00, 0x00, //
+            ],
+            &*vec
+        );
+        assert_eq!(value, deserialize_with::<Vec<String>>(&vec, mode).unwrap());
+
+        let mode = Mode::dedup().with_dedup_index_width(UsizeLen::U8);
+        let value: Vec<String> = (0..257).map(|el| el.to_string()).collect();
+
+        assert!(serialize_with(&value[..256], mode).is_ok());
+        assert!(matches!(
+            serialize_with(&value, mode),
+            Err(Error::TryFromInt(_))
+        ));
+    }
+
+    #[test]
+    fn bogus_container_len() {
+        use std::collections::HashMap;
+
+        use crate::Error;
+
+        // a length of u32::MAX with nothing following it
+        let crafted = [0xFF, 0xFF, 0xFF, 0xFF, 0x0F];
+        let packed = Mode::default().with_pack_bools(true);
+
+        assert!(matches!(
+            deserialize::<Vec<u64>>(&crafted),
+            Err(Error::UnexpectedEof {
+                expected: 8,
+                got: 0
+            })
+        ));
+        assert!(matches!(
+            deserialize_with::<Vec<bool>>(&crafted, packed),
+            Err(Error::UnexpectedEof {
+                expected: 0x2000_0000,
+                got: 0
+            })
+        ));
+        assert!(matches!(
+            deserialize::<String>(&crafted),
+            Err(Error::UnexpectedEof {
+                expected: 0xFFFF_FFFF,
+                got: 0
+            })
+        ));
+
+        let mode = Mode::default().with_max_container_len(Some(1000));
+
+        assert!(matches!(
+            deserialize_with::<Vec<u64>>(&crafted, mode),
+            Err(Error::ContainerTooLong(0xFFFF_FFFF, 1000))
+        ));
+        assert!(matches!(
+            deserialize_with::<Vec<bool>>(&crafted, mode.with_pack_bools(true)),
+            Err(Error::ContainerTooLong(0xFFFF_FFFF, 1000))
+        ));
+        assert!(matches!(
+            deserialize_with::<String>(&crafted, mode),
+            Err(Error::ContainerTooLong(0xFFFF_FFFF, 1000))
+        ));
+        assert!(matches!(
+            deserialize_with::<HashMap<u8, u8>>(&crafted, mode),
+            Err(Error::ContainerTooLong(0xFFFF_FFFF, 1000))
+        ));
+
+        let value = vec![1u64; 1000];
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(value, deserialize_with::<Vec<u64>>(&vec, mode).unwrap());
+    }
+
+    #[test]
+    fn arrays() {
+        use std::cell::Cell;
+
+        use crate::{BinDeserialize, BinDeserializer, Result};
+
+        let hash = [0xAB; 32];
+        let vec = serialize(&hash).unwrap();
+        assert_eq!(32, vec.len());
+        assert_eq!(hash, deserialize::<[u8; 32]>(&vec).unwrap());
+
+        let strings = ["a".to_string(), "b".to_string(), "a".to_string()];
+        for mode in [Mode::default(), Mode::dedup()] {
+            let vec = serialize_with(&strings, mode).unwrap();
+            assert_eq!(
+                strings,
+                deserialize_with::<[String; 3]>(&vec, mode).unwrap()
+            );
+        }
+
+        thread_local! {
+            static LIVE: Cell<usize> = const { Cell::new(0) };
+        }
+
+        struct Counted;
+
+        impl<'de> BinDeserialize<'de> for Counted {
+            fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+                u8::deserialize(deserializer)?;
+                LIVE.with(|v| v.set(v.get() + 1));
+                Ok(Counted)
+            }
+        }
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                LIVE.with(|v| v.set(v.get() - 1));
+            }
+        }
+
+        // the third element is missing, the first two must be dropped
+        assert!(deserialize::<[Counted; 3]>(&[1, 2]).is_err());
+        assert_eq!(0, LIVE.with(|v| v.get()));
+
+        let arr = deserialize::<[Counted; 3]>(&[1, 2, 3]).unwrap();
+        assert_eq!(3, LIVE.with(|v| v.get()));
+        drop(arr);
+        assert_eq!(0, LIVE.with(|v| v.get()));
+    }
+
+    #[test]
+    fn truncated_and_invalid() {
+        use crate::Error;
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        struct Record {
+            id: u32,
+            name: String,
+            ok: Option<u8>,
+        }
+
+        let value = Record {
+            id: 7,
+            name: "record".to_string(),
+            ok: Some(1),
+        };
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let vec = serialize_with(&value, mode).unwrap();
+
+            for len in 0..vec.len() {
+                assert!(
+                    matches!(
+                        deserialize_with::<Record>(&vec[..len], mode),
+                        Err(Error::UnexpectedEof { .. })
+                    ),
+                    "prefix of length {} in {:?}",
+                    len,
+                    mode
+                );
+            }
+        }
+
+        // the first three bytes of the id are there
+        let vec = serialize(&value).unwrap();
+        assert!(matches!(
+            deserialize::<Record>(&vec[..3]),
+            Err(Error::UnexpectedEof {
+                expected: 4,
+                got: 3
+            })
+        ));
+
+        // an invalid option tag is corrupted data, not missing data
+        let mut vec = vec;
+        let tag = vec.len() - 2;
+        vec[tag] = 5;
+        let result = deserialize::<Record>(&vec);
+        assert!(result.is_err());
+        assert!(!matches!(result, Err(Error::UnexpectedEof { .. })));
+
+        // the same for borrowed deserialization
+        let vec = serialize(&(1u32, "abc")).unwrap();
+        assert!(matches!(
+            crate::deserialize_borrowed::<(u32, &str)>(&vec[..6], Mode::default()),
+            Err(Error::UnexpectedEof {
+                expected: 3,
+                got: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn time_and_nonzero() {
+        use std::num::{
+            NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU16, NonZeroU32,
+            NonZeroU64, NonZeroU8, NonZeroUsize,
+        };
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        use crate::Error;
+
+        let modes = [
+            Mode::default(),
+            Mode::default().with_fixed_size_use_varint(true),
+            Mode::default().with_big_endian(true),
+        ];
+
+        let durations = [Duration::ZERO, Duration::new(1, 500_000_000), Duration::MAX];
+        let times = [
+            UNIX_EPOCH,
+            UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789),
+            SystemTime::now(),
+        ];
+
+        for mode in modes {
+            for d in durations {
+                let vec = serialize_with(&d, mode).unwrap();
+                assert_eq!(d, deserialize_with::<Duration>(&vec, mode).unwrap());
+            }
+
+            for t in times {
+                let vec = serialize_with(&t, mode).unwrap();
+                assert_eq!(t, deserialize_with::<SystemTime>(&vec, mode).unwrap());
+            }
+
+            let values = (
+                NonZeroU8::new(1).unwrap(),
+                NonZeroU16::new(300).unwrap(),
+                NonZeroU32::new(u32::MAX).unwrap(),
+                NonZeroU64::new(1 << 40).unwrap(),
+                NonZeroUsize::new(7).unwrap(),
+            );
+            let vec = serialize_with(&values, mode).unwrap();
+            assert_eq!(values, deserialize_with(&vec, mode).unwrap());
+
+            let values = (
+                NonZeroI8::new(-1).unwrap(),
+                NonZeroI16::new(-300).unwrap(),
+                NonZeroI32::new(i32::MIN).unwrap(),
+                NonZeroI64::new(1 << 40).unwrap(),
+                NonZeroIsize::new(-7).unwrap(),
+            );
+            let vec = serialize_with(&values, mode).unwrap();
+            assert_eq!(values, deserialize_with(&vec, mode).unwrap());
+        }
+
+        assert_eq!(4, serialize(&NonZeroU32::new(1).unwrap()).unwrap().len());
+        let varint = Mode::default().with_fixed_size_use_varint(true);
+        assert_eq!(
+            1,
+            serialize_with(&NonZeroU32::new(1).unwrap(), varint)
+                .unwrap()
+                .len()
+        );
+
+        assert!(matches!(
+            deserialize::<NonZeroU32>(&[0, 0, 0, 0]),
+            Err(Error::ZeroValue("NonZeroU32"))
+        ));
+        assert!(matches!(
+            deserialize_with::<NonZeroU32>(&[0], varint),
+            Err(Error::ZeroValue("NonZeroU32"))
+        ));
+
+        let before_epoch = UNIX_EPOCH - Duration::from_secs(1);
+        assert!(serialize(&before_epoch).is_err());
+
+        let vec = serialize(&(0u64, 1_000_000_000u32)).unwrap();
+        assert!(deserialize::<Duration>(&vec).is_err());
+    }
+
+    #[test]
+    fn varint_group_encoding() {
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        struct Postings {
+            doc_ids: Vec<u32>,
+            offsets: Vec<u64>,
+            fixed: [u32; 2],
+        }
+
+        let value = Postings {
+            doc_ids: (0..1000).map(|i| i * 37 % 300).collect(),
+            offsets: vec![0, 1, 300, 1 << 20, 1 << 40, u64::MAX],
+            fixed: [1, 2],
+        };
+
+        let mode = Mode::default().with_varint_group_encoding(true);
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+
+        // 250 control bytes, 1 or 2 bytes per id instead of 4
+        let plain = serialize(&value).unwrap();
+        assert!(vec.len() < plain.len() / 2);
+
+        let mode = mode.with_fixed_size_use_varint(true).with_big_endian(true);
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+
+        let mode = Mode::default()
+            .with_varint_group_encoding(true)
+            .with_write_schema_header(true);
+        let vec = serialize_with(&value.doc_ids, mode).unwrap();
+        let (_, dynamic) = crate::schema::deserialize_dynamic(&vec, mode).unwrap();
+        let expected: Vec<_> = value
+            .doc_ids
+            .iter()
+            .map(|&v| crate::schema::Value::UInt(v.into()))
+            .collect();
+        assert_eq!(crate::schema::Value::Seq(expected), dynamic);
+    }
+
+    #[test]
+    fn tagged() {
+        use crate::dedup::DedupContext;
+
+        mod v1 {
+            use binserde_derive::{BinDeserialize, BinSerialize};
+
+            #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+            #[binserde(tagged)]
+            pub struct Config {
+                pub name: String,
+                pub retries: u32,
+                pub hosts: Vec<String>,
+            }
+
+            #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+            #[binserde(tagged)]
+            pub enum Event {
+                Start { at: u64 },
+                Stop,
+            }
+        }
+
+        mod v2 {
+            use binserde_derive::{BinDeserialize, BinSerialize};
+
+            // fields reordered, `retries` removed and `timeout` added
+            #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+            #[binserde(tagged)]
+            pub struct Config {
+                pub timeout: Option<u32>,
+                pub hosts: Vec<String>,
+                pub name: String,
+            }
+
+            #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+            #[binserde(tagged)]
+            pub enum Event {
+                Start { reason: String, at: u64 },
+                Stop,
+            }
+        }
+
+        let old = v1::Config {
+            name: "server".to_string(),
+            retries: 3,
+            hosts: vec!["server".to_string(), "backup".to_string()],
+        };
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let vec = serialize_with(&old, mode).unwrap();
+            assert_eq!(old, deserialize_with(&vec, mode).unwrap());
+
+            let new: v2::Config = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(
+                v2::Config {
+                    timeout: None,
+                    hosts: old.hosts.clone(),
+                    name: old.name.clone(),
+                },
+                new
+            );
+
+            let vec = serialize_with(&new, mode).unwrap();
+            let back: v1::Config = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(0, back.retries);
+            assert_eq!(old.hosts, back.hosts);
+
+            let vec = serialize_with(&v1::Event::Start { at: 5 }, mode).unwrap();
+            assert_eq!(
+                v2::Event::Start {
+                    reason: String::new(),
+                    at: 5
+                },
+                deserialize_with(&vec, mode).unwrap()
+            );
+            let vec = serialize_with(&v1::Event::Stop, mode).unwrap();
+            assert_eq!(v2::Event::Stop, deserialize_with(&vec, mode).unwrap());
+        }
+
+        // field names are stored in the string table with deduplication
+        let mode = Mode::dedup();
+        let vec = serialize_with(&old, mode).unwrap();
+        let dedup = DedupContext::read_header(&*vec, mode).unwrap();
+        let strings: Vec<_> = (0..).map_while(|idx| dedup.get_str(idx)).collect();
+        assert!(strings.contains(&"retries"));
+
+        let mut target = v2::Config {
+            timeout: Some(10),
+            hosts: Vec::new(),
+            name: String::new(),
+        };
+        crate::deserialize_in_place(&mut target, &*vec, mode).unwrap();
+        assert_eq!(None, target.timeout);
+        assert_eq!(old.name, target.name);
+    }
+
+    #[test]
+    fn versioned() {
+        mod v1 {
+            use binserde_derive::{BinDeserialize, BinSerialize};
+
+            #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+            #[binserde(version = 1)]
+            pub struct Save {
+                pub name: String,
+                pub level: u32,
+            }
+        }
+
+        mod v3 {
+            use binserde_derive::{BinDeserialize, BinSerialize};
+
+            #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+            #[binserde(version = 3)]
+            pub struct Save {
+                pub name: String,
+                pub level: u32,
+                #[binserde(since = 2)]
+                pub gold: u64,
+                #[binserde(since = 3)]
+                pub achievements: Vec<String>,
+            }
+
+            #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+            #[binserde(version = 2)]
+            pub enum Slot {
+                Empty,
+                Used {
+                    save: Save,
+                    #[binserde(since = 2)]
+                    pinned: bool,
+                },
+            }
+        }
+
+        let old = v1::Save {
+            name: "hero".to_string(),
+            level: 12,
+        };
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let vec = serialize_with(&old, mode).unwrap();
+            assert_eq!(old, deserialize_with(&vec, mode).unwrap());
+
+            let new: v3::Save = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(
+                v3::Save {
+                    name: "hero".to_string(),
+                    level: 12,
+                    gold: 0,
+                    achievements: vec![],
+                },
+                new
+            );
+
+            let mut target = v3::Save {
+                name: String::new(),
+                level: 0,
+                gold: 100,
+                achievements: vec!["first".to_string()],
+            };
+            crate::deserialize_in_place(&mut target, &*vec, mode).unwrap();
+            assert_eq!(new, target);
+
+            // newer data can't be read with an older type
+            let vec = serialize_with(&new, mode).unwrap();
+            assert!(deserialize_with::<v1::Save>(&vec, mode).is_err());
+            assert_eq!(new, deserialize_with(&vec, mode).unwrap());
+
+            let slot = v3::Slot::Used {
+                save: new,
+                pinned: true,
+            };
+            let vec = serialize_with(&slot, mode).unwrap();
+            assert_eq!(slot, deserialize_with(&vec, mode).unwrap());
+        }
+
+        // the version is written in front of the fields
+        let vec = serialize(&old).unwrap();
+        assert_eq!(&[1, 4, b'h', b'e', b'r', b'o', 12, 0, 0, 0], &*vec);
+    }
+    #[test]
+    fn skip_if_and_default() {
+        fn default_port() -> u16 {
+            8080
+        }
+
+        fn is_default_port(port: &u16) -> bool {
+            *port == default_port()
+        }
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        struct Plain {
+            name: String,
+            tags: Vec<String>,
+            port: u16,
+        }
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        struct Sparse {
+            name: String,
+            #[binserde(skip_if = "Vec::is_empty")]
+            tags: Vec<String>,
+            #[binserde(skip_if = "is_default_port", default = "default_port")]
+            port: u16,
+            #[binserde(skip, default = "default_port")]
+            fallback: u16,
+        }
+
+        let absent = Sparse {
+            name: "web".to_string(),
+            tags: vec![],
+            port: 8080,
+            fallback: 8080,
+        };
+        let present = Sparse {
+            name: "web".to_string(),
+            tags: vec!["a".to_string()],
+            port: 80,
+            fallback: 8080,
+        };
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            for value in [&absent, &present] {
+                let vec = serialize_with(value, mode).unwrap();
+                assert_eq!(value, &deserialize_with::<Sparse>(&vec, mode).unwrap());
+
+                let mut target = Sparse {
+                    name: String::new(),
+                    tags: vec!["old".to_string()],
+                    port: 1,
+                    fallback: 1,
+                };
+                crate::deserialize_in_place(&mut target, &*vec, mode).unwrap();
+                assert_eq!(value, &target);
+            }
+        }
+
+        // an absent field takes one byte instead of the value
+        let plain = Plain {
+            name: "web".to_string(),
+            tags: vec![],
+            port: 8080,
+        };
+        let vec = serialize(&absent).unwrap();
+        assert_eq!(&[3, b'w', b'e', b'b', 0, 0], &*vec);
+        assert_eq!(serialize(&plain).unwrap().len() - 1, vec.len());
+    }
+    #[test]
+    fn size_hint() {
+        use std::collections::HashMap;
+
+        use crate::serde::UsizeLen;
+        use crate::{BinSerializer, Result};
+
+        // doesn't override size_hint, like custom impls written before it
+        struct Unhinted<'a>(&'a HashMap<String, u32>);
+
+        impl crate::BinSerialize for Unhinted<'_> {
+            fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+                crate::BinSerialize::serialize(self.0, serializer)
+            }
+        }
+
+        #[derive(BinSerialize)]
+        struct Index {
+            name: String,
+            entries: HashMap<String, u32>,
+            #[binserde(skip)]
+            _cache: Vec<u8>,
+        }
+
+        let map: HashMap<String, u32> = (0..10000)
+            .map(|idx| (format!("entry-{}", idx), idx))
+            .collect();
+
+        let modes = [
+            Mode::default(),
+            Mode::default().with_fixed_size_use_varint(true),
+            Mode::default().with_usize_len(UsizeLen::U32),
+        ];
+
+        for mode in modes {
+            let hinted = serialize_with(&map, mode).unwrap();
+            let unhinted = serialize_with(&Unhinted(&map), mode).unwrap();
+            assert_eq!(hinted, unhinted);
+
+            // the hint is exact here, so the buffer was allocated once and
+            // never grew
+            assert_eq!(hinted.len(), crate::BinSerialize::size_hint(&map, mode));
+            assert_eq!(hinted.len(), hinted.capacity());
+            assert!(unhinted.capacity() > unhinted.len());
+
+            let index = Index {
+                name: "index".to_string(),
+                entries: map.clone(),
+                _cache: vec![0; 100],
+            };
+            let vec = serialize_with(&index, mode).unwrap();
+            assert_eq!(vec.len(), crate::BinSerialize::size_hint(&index, mode));
+        }
+
+        // the hint only pre-sizes buffers and doesn't change the output
+        let mode = Mode::dedup();
+        let vec = serialize_with(&map, mode).unwrap();
+        let hinted = serialize_with(&map, mode.with_map_entry_count_hint(Some(map.len()))).unwrap();
+        assert_eq!(
+            map,
+            deserialize_with::<HashMap<String, u32>>(&hinted, mode).unwrap()
+        );
+        assert_eq!(vec.len(), hinted.len());
+    }
+    #[test]
+    fn deserialize_seq() {
+        use crate::{deserialize_seq_from, deserialize_seq_with_from, Error};
+
+        let items: Vec<u32> = (0..100).collect();
+        let vec = serialize(&items).unwrap();
+
+        // only the elements taken so far are read
+        let mut input = &*vec;
+        let first: Vec<u32> = deserialize_seq_from(&mut input)
+            .take(3)
+            .collect::<crate::Result<_>>()
+            .unwrap();
+        assert_eq!(vec![0, 1, 2], first);
+        assert_eq!(vec.len() - 1 - 3 * 4, input.len());
+
+        let all: Vec<u32> = deserialize_seq_from(&*vec)
+            .collect::<crate::Result<_>>()
+            .unwrap();
+        assert_eq!(items, all);
+
+        let mut iter = deserialize_seq_from::<_, u32>(&vec[..vec.len() - 2]);
+
+        for idx in 0..99 {
+            assert_eq!(idx, iter.next().unwrap().unwrap());
+        }
+
+        assert!(matches!(
+            iter.next(),
+            Some(Err(Error::UnexpectedEof {
+                expected: 4,
+                got: 2
+            }))
+        ));
+        assert!(iter.next().is_none());
+
+        // strings are looked up in the table read before the first element
+        let names = vec!["a".to_string(), "b".to_string(), "a".to_string()];
+        let mode = Mode::dedup();
+        let vec = serialize_with(&names, mode).unwrap();
+        let out: Vec<String> = deserialize_seq_with_from(&*vec, mode)
+            .collect::<crate::Result<_>>()
+            .unwrap();
+        assert_eq!(names, out);
+
+        assert!(deserialize_seq_from::<_, u32>(&[][..])
+            .next()
+            .unwrap()
+            .is_err());
+    }
+    #[test]
+    fn hex_bytes() {
+        use crate::schema::{deserialize_dynamic, Value};
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        struct Fixture {
+            id: [u8; 4],
+            payload: Vec<u8>,
+            words: [u16; 2],
+            name: String,
+        }
+
+        let fixture = Fixture {
+            id: [0xde, 0xad, 0xbe, 0xef],
+            payload: vec![0x00, 0x7f, 0xff],
+            words: [1, 2],
+            name: "fx".to_string(),
+        };
+
+        let mode = Mode::default().with_hex_bytes(true);
+        let vec = serialize_with(&fixture, mode).unwrap();
+        // strings are still written as text
+        assert_eq!(b"\x08deadbeef\x06007fff\x01\x00\x02\x00\x02fx", &*vec);
+        assert_eq!(fixture, deserialize_with(&vec, mode).unwrap());
+
+        let mut target = Fixture {
+            id: [0; 4],
+            payload: vec![1, 2, 3, 4, 5],
+            words: [0; 2],
+            name: String::new(),
+        };
+        crate::deserialize_in_place(&mut target, &*vec, mode).unwrap();
+        assert_eq!(fixture, target);
+
+        // hand-edited fixtures may use uppercase digits
+        let edited = b"\x08DEADBEEF\x02ab\x01\x00\x02\x00\x02fx";
+        let out: Fixture = deserialize_with(edited, mode).unwrap();
+        assert_eq!([0xde, 0xad, 0xbe, 0xef], out.id);
+        assert_eq!(vec![0xab], out.payload);
+
+        assert!(deserialize_with::<Vec<u8>>(b"\x03abc", mode).is_err());
+        assert!(deserialize_with::<Vec<u8>>(b"\x02zz", mode).is_err());
+        assert!(deserialize_with::<[u8; 2]>(b"\x02ab", mode).is_err());
+
+        // the dedup setting doesn't apply to hex strings
+        let dedup = Mode::dedup().with_dedup_bytes(true).with_hex_bytes(true);
+        let vec = serialize_with(&fixture, dedup).unwrap();
+        assert_eq!(fixture, deserialize_with(&vec, dedup).unwrap());
+
+        let schema_mode = mode.with_write_schema_header(true);
+        let vec = serialize_with(&fixture, schema_mode).unwrap();
+        let (_, value) = deserialize_dynamic(&vec, schema_mode).unwrap();
+        assert_eq!(
+            Value::Struct(vec![
+                (
+                    "id".to_string(),
+                    Value::Seq(
+                        [0xde, 0xad, 0xbe, 0xef]
+                            .iter()
+                            .map(|&v| Value::UInt(v))
+                            .collect()
+                    )
+                ),
+                ("payload".to_string(), Value::Bytes(vec![0x00, 0x7f, 0xff])),
+                (
+                    "words".to_string(),
+                    Value::Seq(vec![Value::UInt(1), Value::UInt(2)])
+                ),
+                ("name".to_string(), Value::String("fx".to_string())),
+            ]),
+            value
+        );
+    }
+    #[test]
+    fn time_epoch() {
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        // 2024-01-01T00:00:00Z
+        let epoch = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let times = [
+            epoch,
+            epoch + Duration::new(60, 0),
+            epoch + Duration::new(86_400 * 7, 250_000_000),
+        ];
+
+        let unix = Mode::default().with_fixed_size_use_varint(true);
+        let custom = unix.with_time_epoch(epoch);
+
+        let vec = serialize_with(&times, custom).unwrap();
+        assert_eq!(
+            times,
+            deserialize_with::<[SystemTime; 3]>(&vec, custom).unwrap()
+        );
+
+        let unix_vec = serialize_with(&times, unix).unwrap();
+        assert_eq!(
+            times,
+            deserialize_with::<[SystemTime; 3]>(&unix_vec, unix).unwrap()
+        );
+
+        // 5 bytes of seconds since 1970 versus 1 to 3 bytes since the custom
+        // epoch
+        assert_eq!(3 * 5 + 1 + 1 + 4, unix_vec.len());
+        assert_eq!(1 + 1 + 1 + 1 + 3 + 4, vec.len());
+
+        // the same data means something else with another epoch
+        let other: [SystemTime; 3] = deserialize_with(&vec, unix).unwrap();
+        assert_eq!(UNIX_EPOCH, other[0]);
+
+        assert!(serialize_with(&UNIX_EPOCH, custom).is_err());
+    }
+    #[test]
+    fn skip_value() {
+        use std::collections::HashMap;
+
+        use crate::de::BinDeserializerBase;
+        use crate::dedup::DedupContext;
+        use crate::BinDeserializer;
+
+        #[derive(BinSerialize)]
+        struct Record {
+            id: u32,
+            attrs: HashMap<String, u32>,
+            name: String,
+        }
+
+        let record = Record {
+            id: 7,
+            attrs: (0..10).map(|idx| (format!("attr{}", idx), idx)).collect(),
+            name: "attr3".to_string(),
+        };
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let vec = serialize_with(&record, mode).unwrap();
+            let mut input = &*vec;
+            let dedup = DedupContext::read_header(&mut input, mode).unwrap();
+            let mut de = BinDeserializerBase::new(input, &dedup).with_mode(mode);
+
+            assert_eq!(
+                7,
+                <u32 as crate::BinDeserialize>::deserialize(&mut de).unwrap()
+            );
+            de.skip::<HashMap<String, u32>>().unwrap();
+            assert_eq!(
+                "attr3",
+                <String as crate::BinDeserialize>::deserialize(&mut de).unwrap()
+            );
+            assert!(de.skip::<u8>().is_err());
+        }
+    }
+    #[test]
+    fn file_header() {
+        use crate::Error;
+
+        let value = vec!["alpha".to_string(), "alpha".to_string()];
+        let mode = Mode::dedup().with_header(*b"BSDT", 3);
+
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(b"BSDT\x03\x00", &vec[..6]);
+        // followed by the dedup table and the value
+        assert_eq!(&[1, 5, b'a', b'l', b'p', b'h', b'a', 2, 0, 0], &vec[6..]);
+
+        assert_eq!(value, deserialize_with::<Vec<String>>(&vec, mode).unwrap());
+        assert_eq!(
+            value,
+            crate::deserialize_borrowed::<Vec<String>>(&vec, mode).unwrap()
+        );
+
+        let mut corrupted = vec.clone();
+        corrupted[1] = b'X';
+        assert!(matches!(
+            deserialize_with::<Vec<String>>(&corrupted, mode),
+            Err(Error::BadHeader {
+                expected: [b'B', b'S', b'D', b'T'],
+                found: [b'B', b'X', b'D', b'T'],
+            })
+        ));
+
+        let newer = mode.with_header(*b"BSDT", 4);
+        assert!(matches!(
+            deserialize_with::<Vec<String>>(&vec, newer),
+            Err(Error::VersionMismatch {
+                expected: 4,
+                found: 3
+            })
+        ));
+
+        // the header is part of the checksummed and length-prefixed data
+        let mode = mode.with_trailing_crc(true).with_prefix_total_len(true);
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(b"BSDT", &vec[1..5]);
+        assert_eq!(value, deserialize_with::<Vec<String>>(&vec, mode).unwrap());
+    }
+    #[test]
+    fn unit_enum_map_keys() {
+        use std::collections::HashMap;
+
+        #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, BinSerialize, BinDeserialize)]
+        enum Suit {
+            Clubs,
+            Diamonds,
+            Hearts,
+            Spades,
+        }
+
+        let map: HashMap<Suit, u8> = [
+            (Suit::Clubs, 1),
+            (Suit::Diamonds, 2),
+            (Suit::Hearts, 3),
+            (Suit::Spades, 4),
+        ]
+        .iter()
+        .copied()
+        .collect();
+
+        for mode in [
+            Mode::default(),
+            Mode::dedup(),
+            Mode::default().with_deterministic(true),
+        ] {
+            let vec = serialize_with(&map, mode).unwrap();
+            let body = &vec[vec.len() - 9..];
+
+            // the entry count, then one byte for each key and value
+            assert_eq!(4, body[0]);
+            let mut entries: Vec<_> = body[1..].chunks(2).map(|el| (el[0], el[1])).collect();
+            entries.sort_unstable();
+            assert_eq!(vec![(0, 1), (1, 2), (2, 3), (3, 4)], entries);
+
+            assert_eq!(map, deserialize_with(&vec, mode).unwrap());
+        }
+    }
+
+    #[test]
+    fn in_place_reuses_allocations() {
+        use std::collections::HashMap;
+
+        #[derive(Debug, Default, PartialEq, BinSerialize, BinDeserialize)]
+        struct Record {
+            values: Vec<u32>,
+            name: String,
+            attrs: HashMap<u32, u32>,
+        }
+
+        let big = Record {
+            values: (0..100).collect(),
+            name: "a fairly long record name".to_string(),
+            attrs: (0..50).map(|idx| (idx, idx * 2)).collect(),
+        };
+        let small = Record {
+            values: vec![7, 8, 9],
+            name: "short".to_string(),
+            attrs: (0..3).map(|idx| (idx, idx)).collect(),
+        };
+
+        for mode in [
+            Mode::default(),
+            Mode::dedup(),
+            Mode::dedup().with_inline_dedup(true),
+        ] {
+            let mut target = Record::default();
+            crate::deserialize_in_place(&mut target, &*serialize_with(&big, mode).unwrap(), mode)
+                .unwrap();
+            assert_eq!(big, target);
+
+            let values = (target.values.as_ptr(), target.values.capacity());
+            let name = target.name.capacity();
+            let attrs = target.attrs.capacity();
+
+            let vec = serialize_with(&small, mode).unwrap();
+            crate::deserialize_in_place(&mut target, &*vec, mode).unwrap();
+            assert_eq!(small, target);
+
+            assert_eq!(values, (target.values.as_ptr(), target.values.capacity()));
+            assert_eq!(attrs, target.attrs.capacity());
+
+            if !mode.inline_dedup {
+                assert_eq!(name, target.name.capacity());
+            }
+        }
+    }
+
+    #[test]
+    fn dedup_collections() {
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Frames {
+            first: Vec<u32>,
+            second: Vec<u32>,
+            other: Vec<u32>,
+            nested: Vec<Vec<u32>>,
+        }
+
+        let values: Vec<u32> = (0..1000).map(|idx| idx * 7919).collect();
+        let frames = Frames {
+            first: values.clone(),
+            second: values.clone(),
+            other: vec![1, 2, 3],
+            nested: vec![values.clone(), vec![], vec![1, 2, 3]],
+        };
+
+        let plain_mode = Mode::dedup().with_dedup_bytes(true);
+        let mode = plain_mode.with_deduplicate_identical_collections(true);
+
+        let plain = serialize_with(&frames, plain_mode).unwrap();
+        let vec = serialize_with(&frames, mode).unwrap();
+        assert_eq!(frames, deserialize_with(&vec, mode).unwrap());
+
+        // the large vector is stored once instead of three times
+        let once = serialize_with(&values, Mode::default()).unwrap().len();
+        assert!(plain.len() > 3 * once);
+        assert!(vec.len() < once + 100);
+
+        // without a byte blob table the flag has no effect
+        let no_table = Mode::dedup().with_deduplicate_identical_collections(true);
+        assert_eq!(
+            serialize_with(&frames, Mode::dedup()).unwrap(),
+            serialize_with(&frames, no_table).unwrap()
+        );
+    }
+
+    #[test]
+    fn paths_and_addresses() {
+        use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+        use std::path::PathBuf;
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Config {
+            root: PathBuf,
+            v4: Ipv4Addr,
+            v6: Ipv6Addr,
+            bind: IpAddr,
+            listen: SocketAddr,
+            upstream: SocketAddr,
+        }
+
+        let config = Config {
+            root: PathBuf::from("/srv/my files/data dir"),
+            v4: Ipv4Addr::new(192, 168, 1, 20),
+            v6: "2001:db8::8a2e:370:7334".parse().unwrap(),
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            listen: "127.0.0.1:8443".parse().unwrap(),
+            upstream: "[::1]:60001".parse().unwrap(),
+        };
+
+        for mode in [
+            Mode::default(),
+            Mode::dedup(),
+            Mode::default().with_fixed_size_use_varint(true),
+            Mode::default().with_hex_bytes(true),
+        ] {
+            let vec = serialize_with(&config, mode).unwrap();
+            assert_eq!(config, deserialize_with(&vec, mode).unwrap());
+        }
+
+        let addr: SocketAddr = "10.0.0.1:9000".parse().unwrap();
+        let vec = serialize_with(&addr, Mode::default()).unwrap();
+        assert_eq!(&[0, 10, 0, 0, 1, 0x28, 0x23], &*vec);
+
+        let vec = serialize_with(&IpAddr::V6(Ipv6Addr::LOCALHOST), Mode::default()).unwrap();
+        assert_eq!(17, vec.len());
+    }
+
+    #[test]
+    fn count_fields() {
+        mod v1 {
+            use binserde_derive::{BinDeserialize, BinSerialize};
+
+            #[derive(Debug, Default, PartialEq, BinSerialize, BinDeserialize)]
+            #[binserde(count_fields)]
+            pub struct Player {
+                pub name: String,
+                pub score: u32,
+            }
+
+            #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+            #[binserde(count_fields)]
+            pub enum Event {
+                Join(Player),
+                Leave { name: String },
+            }
+        }
+
+        mod v2 {
+            use binserde_derive::{BinDeserialize, BinSerialize};
+
+            #[derive(Debug, Clone, Default, PartialEq, BinSerialize, BinDeserialize)]
+            #[binserde(count_fields)]
+            pub struct Player {
+                pub name: String,
+                pub score: u32,
+                #[binserde(skip)]
+                pub online: bool,
+                pub titles: Vec<String>,
+            }
+
+            #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+            #[binserde(count_fields)]
+            pub enum Event {
+                Join(Player),
+                Leave { name: String, reason: String },
+            }
+        }
+
+        let new = v2::Player {
+            name: "alice".to_string(),
+            score: 40,
+            online: false,
+            titles: vec!["alice".to_string(), "champion".to_string()],
+        };
+        let old = v1::Player {
+            name: "alice".to_string(),
+            score: 40,
+        };
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let vec = serialize_with(&new, mode).unwrap();
+            assert_eq!(new, deserialize_with(&vec, mode).unwrap());
+
+            // an old reader skips the field it doesn't know
+            let out: v1::Player = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(old, out);
+
+            // and a new reader sets it to its default value
+            let vec = serialize_with(&old, mode).unwrap();
+            let out: v2::Player = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(
+                v2::Player {
+                    titles: vec![],
+                    ..new.clone()
+                },
+                out
+            );
+
+            // the same works for enum variants
+            let event = v2::Event::Leave {
+                name: "bob".to_string(),
+                reason: "timeout".to_string(),
+            };
+            let vec = serialize_with(&(event, 7u8), mode).unwrap();
+            let (out, after): (v1::Event, u8) = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(
+                v1::Event::Leave {
+                    name: "bob".to_string()
+                },
+                out
+            );
+            assert_eq!(7, after);
+        }
+
+        // the field count, the length of the fields, then the fields
+        let vec = serialize(&old).unwrap();
+        assert_eq!(
+            &[2, 0, 0, 0, 10, 5, b'a', b'l', b'i', b'c', b'e', 40, 0, 0, 0],
+            &*vec
+        );
+
+        // data left over after all known fields is an error
+        let mut vec = vec;
+        vec[4] += 1;
+        vec.push(0);
+        assert!(deserialize::<v1::Player>(&vec).is_err());
+    }
+
+    #[test]
+    fn explicit_variant_tags() {
+        mod v1 {
+            use binserde_derive::{BinDeserialize, BinSerialize};
+
+            #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+            pub enum Shape {
+                Point,
+                Circle(u32),
+                Rect { w: u32, h: u32 },
+            }
+        }
+
+        mod v2 {
+            use binserde_derive::{BinDeserialize, BinSerialize};
+
+            #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+            pub enum Shape {
+                Point,
+                #[binserde(tag = 3)]
+                Line(u32, u32),
+                #[binserde(tag = 1)]
+                Circle(u32),
+                #[binserde(tag = 2)]
+                Rect {
+                    w: u32,
+                    h: u32,
+                },
+            }
+        }
+
+        let old = vec![
+            v1::Shape::Rect { w: 3, h: 4 },
+            v1::Shape::Point,
+            v1::Shape::Circle(5),
+        ];
+        let new = vec![
+            v2::Shape::Rect { w: 3, h: 4 },
+            v2::Shape::Point,
+            v2::Shape::Circle(5),
+        ];
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            // adding a variant in the middle doesn't change the encoding of
+            // the existing ones
+            let vec = serialize_with(&old, mode).unwrap();
+            assert_eq!(vec, serialize_with(&new, mode).unwrap());
+            assert_eq!(new, deserialize_with::<Vec<v2::Shape>>(&vec, mode).unwrap());
+
+            let vec = serialize_with(&v2::Shape::Line(1, 2), mode).unwrap();
+            assert_eq!(&[3, 1, 0, 0, 0, 2, 0, 0, 0], &vec[vec.len() - 9..]);
+            assert_eq!(v2::Shape::Line(1, 2), deserialize_with(&vec, mode).unwrap());
+
+            match deserialize_with::<v1::Shape>(&vec, mode) {
+                Err(crate::Error::UnknownVariant(3)) => {}
+                x => panic!("unexpected result {:?}", x),
+            }
+        }
+    }
+
+    #[test]
+    fn error_on_nan() {
+        let mode = Mode::default().with_error_on_nan(true);
+
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(serialize_with(&value, mode).is_err());
+            assert!(serialize_with(&(value as f32), mode).is_err());
+            assert!(serialize_with(&vec![1.0, value], mode).is_err());
+
+            // still written and read as is without the check
+            let vec = serialize_with(&value, Mode::default()).unwrap();
+            let out: f64 = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(value.to_bits(), out.to_bits());
+        }
+
+        let values = (0.0f64, -0.0f32, 1.5e300f64, f32::MIN_POSITIVE, f64::MAX);
+        let vec = serialize_with(&values, mode).unwrap();
+        assert_eq!(serialize_with(&values, Mode::default()).unwrap(), vec);
+        assert_eq!(values, deserialize_with(&vec, mode).unwrap());
+    }
+
+    #[test]
+    fn variant_tag_repr() {
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        #[binserde(repr = "u8")]
+        enum Toggle {
+            Off,
+            On(u16),
+        }
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        #[binserde(repr = "u32")]
+        enum Wide {
+            A,
+            #[binserde(tag = 70000)]
+            B,
+        }
+
+        let mode = Mode::default().with_usize_len(crate::serde::UsizeLen::U32);
+
+        let vec = serialize_with(&Toggle::Off, mode).unwrap();
+        assert_eq!(&[0], &*vec);
+        assert_eq!(Toggle::Off, deserialize_with(&vec, mode).unwrap());
+
+        let vec = serialize_with(&Toggle::On(9), mode).unwrap();
+        assert_eq!(&[1, 9, 0], &*vec);
+        assert_eq!(Toggle::On(9), deserialize_with(&vec, mode).unwrap());
+
+        let vec = serialize_with(&Wide::B, mode).unwrap();
+        assert_eq!(&70000u32.to_le_bytes(), &*vec);
+        assert_eq!(Wide::B, deserialize_with(&vec, mode).unwrap());
+
+        for vec in [&[2][..], &[255]] {
+            match deserialize_with::<Toggle>(vec, mode) {
+                Err(crate::Error::UnknownVariant(x)) => assert_eq!(vec[0] as u64, x),
+                x => panic!("unexpected result {:?}", x),
+            }
+        }
+
+        assert!(deserialize_with::<Wide>(&[1, 0, 0, 0], mode).is_err());
+    }
+
+    #[test]
+    fn compact_collection_options() {
+        let mode = Mode::default().with_compact_collection_options(true);
+
+        let values: [Option<Box<[u8]>>; 3] = [None, Some(Box::new([])), Some(Box::new([1, 2, 3]))];
+        let expected: [&[u8]; 3] = [&[0], &[1], &[4, 1, 2, 3]];
+
+        for (value, expected) in values.iter().zip(expected.iter()) {
+            let vec = serialize_with(value, mode).unwrap();
+            assert_eq!(*expected, &*vec);
+            assert_eq!(
+                *value,
+                deserialize_with::<Option<Box<[u8]>>>(&vec, mode).unwrap()
+            );
+        }
+
+        // without the flag, presence is written separately
+        let vec = serialize_with(&values, Mode::default()).unwrap();
+        assert_eq!(&[0, 1, 0, 1, 3, 1, 2, 3], &*vec);
+
+        let strings = (Some("abc".to_string()), None::<String>, Some(vec![1u32, 2]));
+        let vec = serialize_with(&strings, mode).unwrap();
+        assert_eq!(&[4, b'a', b'b', b'c', 0, 3, 1, 0, 0, 0, 2, 0, 0, 0], &*vec);
+        assert_eq!(strings, deserialize_with(&vec, mode).unwrap());
+
+        let borrowed: (Option<&str>, Option<&[u8]>) =
+            crate::deserialize_borrowed(&vec[..6], mode).unwrap();
+        assert_eq!((Some("abc"), None), borrowed);
+
+        // deduplicated strings start with their index instead
+        let mode = Mode::dedup().with_compact_collection_options(true);
+        let value = vec![Some("abc".to_string()), None, Some("abc".to_string())];
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(
+            value,
+            deserialize_with::<Vec<Option<String>>>(&vec, mode).unwrap()
+        );
+
+        let mut map = std::collections::HashMap::new();
+        map.insert(1u8, "a".to_string());
+        let value = (Some(map), Some(std::collections::HashSet::<u8>::new()));
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+
+        // lengths and indices written differently can't be combined
+        let mode = mode.with_dedup_index_width(crate::serde::UsizeLen::U16);
+        let value = Some(vec![1u8]);
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(
+            serialize_with(&value, Mode::default()).unwrap()[..],
+            vec[vec.len() - 3..]
+        );
+        assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+    }
+
+    #[test]
+    fn max_total_elements() {
+        use std::collections::HashMap;
+
+        // 100 vecs of 100 vecs of 100 units each, which stay under the
+        // per-container limit but add up to over a million elements
+        let mut crafted = vec![100];
+        for _ in 0..100 {
+            crafted.push(100);
+            crafted.extend([100; 100]);
+        }
+
+        let mode = Mode::default().with_max_container_len(Some(100));
+        let out: Vec<Vec<Vec<()>>> = deserialize_with(&crafted, mode).unwrap();
+        assert_eq!(1_000_000, out.iter().flatten().map(Vec::len).sum::<usize>());
+
+        let mode = mode.with_max_total_elements(Some(10_000));
+        assert!(matches!(
+            deserialize_with::<Vec<Vec<Vec<()>>>>(&crafted, mode),
+            Err(crate::Error::TooManyElements(10_000))
+        ));
+
+        // the count starts over for each value
+        let value = vec![vec![1u16; 50]; 100];
+        let vec = serialize_with(&value, mode).unwrap();
+        for _ in 0..3 {
+            assert_eq!(
+                value,
+                deserialize_with::<Vec<Vec<u16>>>(&vec, mode).unwrap()
+            );
+        }
+
+        // maps count their entries and the elements of their values
+        let mode = Mode::default().with_max_total_elements(Some(10_000));
+        let value: HashMap<u8, Vec<u16>> = (0..20).map(|i| (i, vec![0; 600])).collect();
+        let vec = serialize_with(&value, mode).unwrap();
+        assert!(matches!(
+            deserialize_with::<HashMap<u8, Vec<u16>>>(&vec, mode),
+            Err(crate::Error::TooManyElements(10_000))
+        ));
+    }
+
+    #[test]
+    fn manual_impl() {
+        use crate::{BinDeserializer, BinSerializer};
+
+        #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+        struct Derived {
+            name: String,
+            tags: Vec<String>,
+            id: u32,
+        }
+
+        #[derive(Debug, Eq, PartialEq)]
+        struct Manual {
+            name: String,
+            tags: Vec<String>,
+            id: u32,
+        }
+
+        impl crate::BinSerialize for Manual {
+            fn serialize<S: BinSerializer>(&self, serializer: S) -> crate::Result<()> {
+                let mut s = serializer.serialize_struct(3)?;
+                s.serialize_field("name", &self.name)?;
+                s.serialize_field("tags", &Tags(&self.tags))?;
+                s.serialize_field("id", &self.id)?;
+                s.end()
+            }
+        }
+
+        struct Tags<'a>(&'a [String]);
+
+        impl crate::BinSerialize for Tags<'_> {
+            fn serialize<S: BinSerializer>(&self, serializer: S) -> crate::Result<()> {
+                let mut seq = serializer.serialize_seq(self.0.len())?;
+
+                for tag in self.0 {
+                    seq.serialize_element(tag)?;
+                }
+
+                seq.end()
+            }
+        }
+
+        impl<'de> crate::BinDeserialize<'de> for Manual {
+            fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> crate::Result<Self> {
+                let mut s = deserializer.deserialize_struct(3)?;
+                let name = s.deserialize_field("name")?;
+                let OwnedTags(tags) = s.deserialize_field("tags")?;
+                let id = s.deserialize_field("id")?;
+                s.end()?;
+                Ok(Manual { name, tags, id })
+            }
+        }
+
+        struct OwnedTags(Vec<String>);
+
+        impl<'de> crate::BinDeserialize<'de> for OwnedTags {
+            fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> crate::Result<Self> {
+                let mut seq = deserializer.deserialize_seq()?;
+                let mut tags = Vec::with_capacity(seq.remaining());
+
+                while let Some(tag) = seq.next_element()? {
+                    tags.push(tag);
+                }
+
+                seq.end()?;
+                Ok(OwnedTags(tags))
+            }
+        }
+
+        let derived = Derived {
+            name: "apple".to_string(),
+            tags: vec!["fruit".to_string(), "apple".to_string()],
+            id: 12,
+        };
+        let manual = Manual {
+            name: derived.name.clone(),
+            tags: derived.tags.clone(),
+            id: derived.id,
+        };
+
+        for mode in [
+            Mode::default(),
+            Mode::dedup(),
+            Mode::default().with_tag_depth(1),
+        ] {
+            let vec = serialize_with(&manual, mode).unwrap();
+            assert_eq!(serialize_with(&derived, mode).unwrap(), vec);
+            assert_eq!(manual, deserialize_with::<Manual>(&vec, mode).unwrap());
+        }
+
+        // the declared number of fields and elements has to match
+        struct Short;
+
+        impl crate::BinSerialize for Short {
+            fn serialize<S: BinSerializer>(&self, serializer: S) -> crate::Result<()> {
+                let mut seq = serializer.serialize_seq(2)?;
+                seq.serialize_element(&1u8)?;
+                seq.end()
+            }
+        }
+
+        assert!(serialize(&Short).is_err());
+    }
+
+    #[test]
+    fn dedup_cow_bytes() {
+        use std::borrow::Cow;
+
+        let blob = [0xAB; 4];
+        let value: Vec<Cow<[u8]>> = vec![
+            Cow::Borrowed(&blob[..]),
+            Cow::Owned(blob.to_vec()),
+            Cow::Borrowed(&blob[..]),
+            Cow::Borrowed(&[1, 2, 3]),
+        ];
+
+        // borrowed and owned blobs share one entry in the byte table
+        let mode = Mode::dedup().with_dedup_bytes(true);
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(
+            &[0, 2, 4, 0xAB, 0xAB, 0xAB, 0xAB, 3, 1, 2, 3, 4, 0, 0, 0, 1],
+            &*vec
+        );
+        assert_eq!(
+            value,
+            deserialize_with::<Vec<Cow<[u8]>>>(&vec, mode).unwrap()
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed() {
+        let value: Vec<(String, u32)> = (0..1000)
+            .map(|i| (format!("entry {}", i % 10), i % 7))
+            .collect();
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let raw = serialize_with(&value, mode).unwrap();
+            let vec = crate::serialize_compressed(&value, mode, 6).unwrap();
+            assert!(vec.len() < raw.len());
+
+            let out: Vec<(String, u32)> = crate::deserialize_compressed(&vec, mode).unwrap();
+            assert_eq!(value, out);
+        }
+
+        assert!(crate::deserialize_compressed::<String>(&[1, 2, 3], Mode::default()).is_err());
+    }
+
+    #[test]
+    fn stable_float_repr() {
+        let mode = Mode::default().with_stable_float_repr(true);
+
+        let values = [
+            0.1,
+            -0.0,
+            1e300,
+            f64::MIN_POSITIVE,
+            5e-324,
+            f64::MAX,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            1.0 / 3.0,
+        ];
+        let vec = serialize_with(&values, mode).unwrap();
+        let out: [f64; 9] = deserialize_with(&vec, mode).unwrap();
+        assert_eq!(values.map(f64::to_bits), out.map(f64::to_bits));
+
+        let values = (0.1f32, f32::MIN_POSITIVE, 16777217.0f32);
+        let vec = serialize_with(&values, mode).unwrap();
+        assert_eq!(values, deserialize_with(&vec, mode).unwrap());
+
+        let vec = serialize_with(&f64::NAN, mode).unwrap();
+        assert!(deserialize_with::<f64>(&vec, mode).unwrap().is_nan());
+
+        // the textual form is a plain string
+        let vec = serialize_with(&0.1f64, mode).unwrap();
+        assert_eq!(&[3, b'0', b'.', b'1'], &*vec);
+        assert_eq!("0.1", deserialize::<String>(&vec).unwrap());
+
+        let vec = serialize(&"2.5e-8").unwrap();
+        assert_eq!(2.5e-8, deserialize_with::<f64>(&vec, mode).unwrap());
+        let vec = serialize(&"one").unwrap();
+        assert!(deserialize_with::<f64>(&vec, mode).is_err());
+
+        // and is deduplicated
+        let values = vec![0.25f64; 8];
+        let mode = Mode::dedup().with_stable_float_repr(true);
+        let vec = serialize_with(&values, mode).unwrap();
+        assert_eq!(
+            &[1, 4, b'0', b'.', b'2', b'5', 8, 0, 0, 0, 0, 0, 0, 0, 0],
+            &*vec
+        );
+        assert_eq!(values, deserialize_with::<Vec<f64>>(&vec, mode).unwrap());
+    }
+
+    #[test]
+    fn recursive_types() {
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        enum Expr {
+            Lit(i64),
+            Add(Box<Expr>, Box<Expr>),
+            Neg(Box<Expr>),
+        }
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        struct Node {
+            name: String,
+            next: Option<Box<Node>>,
+        }
+
+        let expr = Expr::Add(
+            Box::new(Expr::Lit(1)),
+            Box::new(Expr::Neg(Box::new(Expr::Lit(2)))),
+        );
+        let list = Node {
+            name: "a".to_string(),
+            next: Some(Box::new(Node {
+                name: "a".to_string(),
+                next: None,
+            })),
+        };
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let vec = serialize_with(&expr, mode).unwrap();
+            assert_eq!(expr, deserialize_with(&vec, mode).unwrap());
+            let vec = serialize_with(&list, mode).unwrap();
+            assert_eq!(list, deserialize_with(&vec, mode).unwrap());
+        }
+
+        // a chain of negations far deeper than the stack could handle
+        let mut crafted = vec![2; 1_000_000];
+        crafted.extend([0; 9]);
+        let mode = Mode::default().with_max_depth(Some(100));
+        assert!(matches!(
+            deserialize_with::<Expr>(&crafted, mode),
+            Err(crate::Error::RecursionLimit(100))
+        ));
+
+        let mut vec = vec![2; 100];
+        vec.extend([0; 9]);
+        assert!(deserialize_with::<Expr>(&vec, mode).is_ok());
+        vec.insert(0, 2);
+        assert!(matches!(
+            deserialize_with::<Expr>(&vec, mode),
+            Err(crate::Error::RecursionLimit(100))
+        ));
+
+        // each box only counts while it is being read
+        let value = vec![Box::new(1u8); 200];
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(value, deserialize_with::<Vec<Box<u8>>>(&vec, mode).unwrap());
+    }
+
+    #[test]
+    fn tuples_of_references() {
+        let name = "apple".to_string();
+        let count = 12u32;
+        let bytes = vec![1u8, 2, 3];
+
+        for mode in [Mode::default(), Mode::dedup().with_dedup_bytes(true)] {
+            let vec = serialize_with(&(name.as_str(), &count), mode).unwrap();
+            assert_eq!(serialize_with(&(name.clone(), count), mode).unwrap(), vec);
+
+            let value = (name.as_str(), bytes.as_slice(), &count);
+            let vec = serialize_with(&value, mode).unwrap();
+            assert_eq!(
+                serialize_with(&(name.clone(), bytes.clone(), count), mode).unwrap(),
+                vec
+            );
+        }
+
+        // and can be read back without copying
+        let vec = serialize(&(name.as_str(), bytes.as_slice())).unwrap();
+        let out: (&str, &[u8]) = crate::deserialize_borrowed(&vec, Mode::default()).unwrap();
+        assert_eq!((name.as_str(), bytes.as_slice()), out);
+    }
+
+    #[test]
+    fn btree_collections() {
+        use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+        let keys = ["pear", "apple", "fig", "banana", "cherry"];
+        let a: BTreeMap<String, usize> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (k.to_string(), i))
+            .collect();
+        let b: BTreeMap<String, usize> = keys
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, k)| (k.to_string(), i))
+            .collect();
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            // the same contents always produce the same bytes
+            let vec = serialize_with(&a, mode).unwrap();
+            assert_eq!(vec, serialize_with(&a, mode).unwrap());
+            assert_eq!(vec, serialize_with(&b, mode).unwrap());
+
+            let out: BTreeMap<String, usize> = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(a, out);
+            assert!(out
+                .keys()
+                .eq(["apple", "banana", "cherry", "fig", "pear"].iter()));
+
+            // and can be read as a HashMap, and the other way around
+            let hash: HashMap<String, usize> = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(a.len(), hash.len());
+            let vec = serialize_with(&hash, mode).unwrap();
+            assert_eq!(a, deserialize_with::<BTreeMap<_, _>>(&vec, mode).unwrap());
+        }
+
+        let set: BTreeSet<u16> = [300, 1, 20].iter().copied().collect();
+        let vec = serialize(&set).unwrap();
+        assert_eq!(&[3, 1, 0, 20, 0, 44, 1], &*vec);
+        assert_eq!(set, deserialize::<BTreeSet<u16>>(&vec).unwrap());
+    }
+
+    #[test]
+    fn deserialize_with_scratch() {
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        #[binserde(tagged)]
+        struct Record {
+            name: String,
+            values: Vec<u32>,
+        }
+
+        let value = Record {
+            name: "record".to_string(),
+            values: (0..100).collect(),
+        };
+        let vec = serialize(&value).unwrap();
+
+        // the buffer for reading each field comes from the scratch buffer,
+        // and is handed back afterwards
+        let mut scratch = Vec::new();
+        let out: Record =
+            crate::deserialize_with_scratch(&vec, Mode::default(), &mut scratch).unwrap();
+        assert_eq!(value, out);
+        assert!(scratch.capacity() >= 400);
+
+        let ptr = scratch.as_ptr();
+        let capacity = scratch.capacity();
+
+        for _ in 0..3 {
+            let out: Record =
+                crate::deserialize_with_scratch(&vec, Mode::default(), &mut scratch).unwrap();
+            assert_eq!(value, out);
+            assert_eq!(ptr, scratch.as_ptr());
+            assert_eq!(capacity, scratch.capacity());
+        }
+
+        // hex strings are read into it as well
+        let mode = Mode::default().with_hex_bytes(true);
+        let bytes = vec![0xAB; 300];
+        let vec = serialize_with(&bytes, mode).unwrap();
+        let out: Vec<u8> = crate::deserialize_with_scratch(&vec, mode, &mut scratch).unwrap();
+        assert_eq!(bytes, out);
+        assert!(scratch.capacity() >= 600);
+    }
+
+    #[test]
+    fn custom_hasher() {
+        use std::collections::{HashMap, HashSet};
+        use std::hash::{BuildHasherDefault, Hasher};
+
+        #[derive(Default)]
+        struct Fnv(u64);
+
+        impl Hasher for Fnv {
+            fn finish(&self) -> u64 {
+                self.0
+            }
+
+            fn write(&mut self, bytes: &[u8]) {
+                for b in bytes {
+                    self.0 = (self.0 ^ *b as u64).wrapping_mul(0x100000001b3);
+                }
+            }
+        }
+
+        type FnvMap<K, V> = HashMap<K, V, BuildHasherDefault<Fnv>>;
+        type FnvSet<T> = HashSet<T, BuildHasherDefault<Fnv>>;
+
+        let map: FnvMap<String, u32> = (0..20).map(|i| (format!("key {}", i), i)).collect();
+        let set: FnvSet<u32> = (0..20).collect();
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let vec = serialize_with(&(&map, &set), mode).unwrap();
+            let out: (FnvMap<String, u32>, FnvSet<u32>) = deserialize_with(&vec, mode).unwrap();
+            assert_eq!((&map, &set), (&out.0, &out.1));
+
+            // the hasher doesn't affect the encoding
+            let mode = mode.with_deterministic(true);
+            let std_map: HashMap<String, u32> = map.clone().into_iter().collect();
+            assert_eq!(
+                serialize_with(&std_map, mode).unwrap(),
+                serialize_with(&map, mode).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn include_fields() {
+        fn default_count() -> Option<u32> {
+            Some(7)
+        }
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        #[binserde(include = "id , name")]
+        struct Entry {
+            id: u32,
+            cache: Vec<u8>,
+            name: String,
+            #[binserde(default = "default_count", skip_if = "Option::is_none")]
+            count: Option<u32>,
+        }
+
+        let value = Entry {
+            id: 3,
+            cache: vec![1, 2, 3],
+            name: "entry".to_string(),
+            count: Some(1),
+        };
+
+        let vec = serialize(&value).unwrap();
+        assert_eq!(&[3, 0, 0, 0, 5, b'e', b'n', b't', b'r', b'y'], &*vec);
+
+        let out: Entry = deserialize(&vec).unwrap();
+        assert_eq!(
+            Entry {
+                id: 3,
+                cache: vec![],
+                name: "entry".to_string(),
+                count: Some(7),
+            },
+            out
+        );
+    }
+
+    #[test]
+    fn mode_flags() {
+        let value = vec!["a".to_string(), "a".to_string()];
+
+        let vec = serialize_with(&value, Mode::dedup().with_mode_flags(true)).unwrap();
+        assert_eq!(1, vec[0]);
+
+        let out: Vec<String> = deserialize_with(&vec, Mode::dedup().with_mode_flags(true)).unwrap();
+        assert_eq!(value, out);
+
+        let result: crate::Result<Vec<String>> =
+            deserialize_with(&vec, Mode::default().with_mode_flags(true));
+        assert!(matches!(
+            result,
+            Err(crate::Error::ModeMismatch {
+                expected: 0,
+                found: 1
+            })
+        ));
+
+        // the flags come after the header
+        let mode = Mode::default()
+            .with_header(*b"TEST", 1)
+            .with_mode_flags(true)
+            .with_big_endian(true);
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(&[b'T', b'E', b'S', b'T', 1, 0, 8], &vec[..7]);
+        let out: Vec<String> = deserialize_with(&vec, mode).unwrap();
+        assert_eq!(value, out);
+    }
+
+    #[test]
+    fn dictionary_from_bytes() {
+        use crate::dedup::DedupContext;
+
+        let mut dictionary = DedupContext::new();
+        dictionary.put_str("GET");
+        dictionary.put_str("POST");
+        dictionary.put_str("/index.html");
+        let mut bytes = Vec::new();
+        dictionary.write_to(&mut bytes).unwrap();
+        let bytes: &'static [u8] = Box::leak(bytes.into_boxed_slice());
+
+        let mode = Mode::dedup().with_dictionary_from_bytes(bytes);
+
+        // every string is in the dictionary, so only indices are written
+        let value = vec![
+            ("POST".to_string(), "/index.html".to_string()),
+            ("GET".to_string(), "/index.html".to_string()),
+        ];
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(&[0, 2, 1, 2, 0, 2], &*vec);
+
+        let out: Vec<(String, String)> = deserialize_with(&vec, mode).unwrap();
+        assert_eq!(value, out);
+
+        // other strings are added to the table after the dictionary
+        let value = vec!["GET".to_string(), "/about.html".to_string()];
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(&[1, 11], &vec[..2]);
+        assert_eq!(&[2, 0, 3], &vec[13..]);
+
+        let out: Vec<String> = deserialize_with(&vec, mode).unwrap();
+        assert_eq!(value, out);
+
+        let out: Vec<String> = crate::deserialize_borrowed(&vec, mode).unwrap();
+        assert_eq!(value, out);
+    }
+
+    #[test]
+    fn error_offsets() {
+        #[derive(Debug, Default, BinDeserialize)]
+        struct Outer {
+            _id: u32,
+            _inner: Inner,
+        }
+
+        #[derive(Debug, Default, BinDeserialize)]
+        struct Inner {
+            _flag: bool,
+            _name: String,
+        }
+
+        // id, flag, then a string of length 2 that isn't valid UTF-8
+        let buf = [1, 0, 0, 0, 1, 2, 0xff, 0xfe];
+
+        let mode = Mode::default().with_error_offsets(true);
+        match deserialize_with::<Outer>(&buf, mode) {
+            Err(crate::Error::Context {
+                offset,
+                field,
+                source,
+            }) => {
+                assert_eq!(8, offset);
+                assert_eq!(Some("_inner._name"), field.as_deref());
+                assert!(matches!(*source, crate::Error::InvalidUtf8(_)));
+            }
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        let err = crate::deserialize_borrowed::<Outer>(&buf, mode).unwrap_err();
+        assert_eq!(
+            "at byte 8 in field `_inner._name`: invalid UTF-8 string",
+            err.to_string()
+        );
+
+        let mut target = Outer::default();
+        let err = crate::deserialize_in_place(&mut target, &buf[..], mode).unwrap_err();
+        assert_eq!(
+            "at byte 8 in field `_inner._name`: invalid UTF-8 string",
+            err.to_string()
+        );
+
+        // the offset counts from the start of the input, including the
+        // length prefix
+        let mode = mode.with_prefix_total_len(true);
+        let buf = [3, 1, 0, 0];
+        let err = deserialize_with::<Outer>(&buf, mode).unwrap_err();
+        assert_eq!(
+            "at byte 4 in field `_id`: unexpected end of input: expected 4 bytes, got 3",
+            err.to_string()
+        );
+        let err = crate::deserialize_borrowed::<Outer>(&buf, mode).unwrap_err();
+        assert!(matches!(err, crate::Error::Context { offset: 4, .. }));
+    }
+
+    #[test]
+    fn from_discriminant() {
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        #[binserde(repr = "u8", from_discriminant = "3 => Circle, 4 => Square")]
+        enum Shape {
+            Point,
+            #[binserde(tag = 5)]
+            Circle(u8),
+            #[binserde(tag = 6)]
+            Square(u8),
+        }
+
+        // legacy data with the old tags
+        let out: Vec<Shape> = deserialize(&[3, 3, 2, 4, 7, 0, 0]).unwrap();
+        assert_eq!(vec![Shape::Circle(2), Shape::Square(7), Shape::Point], out);
+
+        // the current tags are still read and written
+        let vec = serialize(&out).unwrap();
+        assert_eq!(&[3, 5, 2, 6, 7, 0], &*vec);
+        assert_eq!(out, deserialize::<Vec<Shape>>(&vec).unwrap());
+
+        assert!(matches!(
+            deserialize::<Shape>(&[1]),
+            Err(crate::Error::UnknownVariant(1))
+        ));
+    }
+
+    #[test]
+    fn serialized_size_matches() {
+        use std::collections::BTreeMap;
+
+        use crate::serialized_size;
+
+        #[derive(BinSerialize)]
+        struct Entry {
+            name: String,
+            tags: Vec<String>,
+            score: Option<f32>,
+        }
+
+        fn check<T: crate::BinSerialize + ?Sized>(value: &T) {
+            let modes = [
+                Mode::default(),
+                Mode::dedup(),
+                Mode::dedup().with_dedup_bytes(true),
+                Mode::dedup().with_inline_dedup(true),
+                Mode::default().with_trailing_crc(true),
+                Mode::dedup().with_prefix_total_len(true),
+            ];
+
+            for mode in modes.iter().copied() {
+                assert_eq!(
+                    serialize_with(value, mode).unwrap().len(),
+                    serialized_size(value, mode).unwrap()
+                );
+            }
+        }
+
+        check(&0u64);
+        check("a string");
+        check(&vec![1u16, 2, 3]);
+        check(&[true, false, true][..]);
+
+        let map: BTreeMap<String, u32> = (0..10).map(|i| (format!("{}", i % 3), i)).collect();
+        check(&map);
+
+        let entries: Vec<Entry> = (0..5)
+            .map(|i| Entry {
+                name: "entry".to_string(),
+                tags: vec!["a".to_string(), format!("t{}", i)],
+                score: if i % 2 == 0 { Some(i as f32) } else { None },
+            })
+            .collect();
+        check(&entries);
+        assert!(
+            serialized_size(&entries, Mode::dedup()).unwrap()
+                < serialized_size(&entries, Mode::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn serialize_into_vectored() {
+        use std::io::{self, IoSlice, Write};
+
+        #[derive(Default)]
+        struct Recorder {
+            out: Vec<u8>,
+            writes: usize,
+            vectored: Vec<Vec<usize>>,
+        }
+
+        impl Write for Recorder {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.writes += 1;
+                self.out.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+                self.vectored.push(bufs.iter().map(|el| el.len()).collect());
+                // only take part of the data to check that the rest is retried
+                let buf = bufs.iter().find(|el| !el.is_empty()).unwrap();
+                let len = buf.len().min(5000);
+                self.out.extend_from_slice(&buf[..len]);
+                Ok(len)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let value = (vec!["x".to_string(); 10], vec![0xAB_u8; 10000], 7u32);
+        let mode = Mode::dedup();
+
+        let mut rec = Recorder::default();
+        crate::serialize_into_vectored(&mut rec, &value, mode, 64).unwrap();
+
+        assert_eq!(serialize_with(&value, mode).unwrap(), rec.out);
+
+        // the buffered table and strings go out together with the blob
+        assert_eq!(vec![vec![16, 10000], vec![10000], vec![5000]], rec.vectored);
+
+        // the trailing u32 is written when flushing
+        assert_eq!(1, rec.writes);
+    }
+
+    #[test]
+    fn pack_bool_fields() {
+        #[derive(Debug, Default, PartialEq, BinSerialize, BinDeserialize)]
+        #[binserde(pack_bools)]
+        struct Flags {
+            a: bool,
+            b: bool,
+            c: bool,
+            d: bool,
+            e: bool,
+            f: bool,
+            g: bool,
+            h: bool,
+            i: bool,
+            j: bool,
+        }
+
+        let value = Flags {
+            a: true,
+            c: true,
+            h: true,
+            j: true,
+            ..Default::default()
+        };
+
+        let vec = serialize(&value).unwrap();
+        assert_eq!(&[0b1000_0101, 0b10], &*vec);
+        assert_eq!(value, deserialize(&vec).unwrap());
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        #[binserde(pack_bools)]
+        struct Mixed {
+            a: bool,
+            b: bool,
+            id: u8,
+            c: bool,
+            #[binserde(skip_if = "is_false")]
+            d: bool,
+            e: bool,
+        }
+
+        fn is_false(v: &bool) -> bool {
+            !*v
+        }
+
+        // the u8 and the field marked skip_if end the runs
+        let value = Mixed {
+            a: false,
+            b: true,
+            id: 9,
+            c: true,
+            d: true,
+            e: true,
+        };
+        let vec = serialize(&value).unwrap();
+        assert_eq!(&[0b10, 9, 0b1, 0xFF, 0xFF, 0b1], &*vec);
+        assert_eq!(value, deserialize(&vec).unwrap());
+    }
+
+    #[test]
+    fn niche_and_result() {
+        use std::num::{NonZeroI16, NonZeroI8, NonZeroIsize, NonZeroU32};
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        struct Plain {
+            parent: Option<NonZeroU32>,
+        }
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        struct Niched {
+            #[binserde(niche)]
+            parent: Option<NonZeroU32>,
+            #[binserde(niche)]
+            offset: Option<NonZeroI16>,
+            #[binserde(niche)]
+            delta: Option<NonZeroI8>,
+            #[binserde(niche)]
+            index: Option<NonZeroIsize>,
+        }
+
+        let parent = NonZeroU32::new(300);
+        let plain = serialize(&Plain { parent }).unwrap();
+        assert_eq!(&[1, 44, 1, 0, 0], &*plain);
+
+        let value = Niched {
+            parent,
+            offset: NonZeroI16::new(-2),
+            delta: NonZeroI8::new(-3),
+            index: NonZeroIsize::new(-1),
+        };
+        let vec = serialize(&value).unwrap();
+        assert_eq!(
+            &[44, 1, 0, 0, 0xFE, 0xFF, 0xFD, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+            &*vec
+        );
+        assert_eq!(value, deserialize(&vec).unwrap());
+
+        let value = Niched {
+            parent: None,
+            offset: None,
+            delta: None,
+            index: None,
+        };
+        let vec = serialize(&value).unwrap();
+        assert_eq!(&[0; 15], &*vec);
+        assert_eq!(value, deserialize(&vec).unwrap());
+
+        // Option still writes a tag, and the value only if present
+        assert_eq!(&[0], &*serialize(&None::<u32>).unwrap());
+        assert_eq!(&[1, 5, 0, 0, 0], &*serialize(&Some(5u32)).unwrap());
+
+        let ok: Result<u32, String> = Ok(5);
+        let vec = serialize(&ok).unwrap();
+        assert_eq!(&[0, 5, 0, 0, 0], &*vec);
+        assert_eq!(ok, deserialize(&vec).unwrap());
+
+        let err: Result<u32, String> = Err("failed".to_string());
+        let vec = serialize(&err).unwrap();
+        assert_eq!(&[1, 6, b'f', b'a', b'i', b'l', b'e', b'd'], &*vec);
+        assert_eq!(err, deserialize(&vec).unwrap());
+
+        assert!(deserialize::<Result<u32, String>>(&[2]).is_err());
+    }
+
+    #[test]
+    fn len_width() {
+        use std::collections::BTreeMap;
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        struct Packet {
+            #[binserde(len = "u8")]
+            data: Vec<u16>,
+            #[binserde(len = "u16")]
+            names: BTreeMap<String, Vec<u8>>,
+        }
+
+        let mut names = BTreeMap::new();
+        names.insert("a".to_string(), vec![1; 3]);
+
+        let value = Packet {
+            data: vec![1, 2],
+            names,
+        };
+
+        // nested lengths are still written as a usize
+        let mode = Mode::default().with_fixed_size_use_varint(true);
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(&[2, 1, 2, 1, 0, 1, b'a', 3, 1, 1, 1], &*vec);
+        assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+
+        let too_long = Packet {
+            data: vec![0; 300],
+            names: BTreeMap::new(),
+        };
+        assert!(matches!(
+            serialize(&too_long),
+            Err(crate::Error::TryFromInt(_))
+        ));
+    }
+
+    #[test]
+    fn mode_presets() {
+        use std::collections::HashMap;
+
+        let compact = Mode::compact();
+        let portable = Mode::portable();
+        assert_ne!(compact.fingerprint(), portable.fingerprint());
+        assert_ne!(Mode::default().fingerprint(), Mode::dedup().fingerprint());
+
+        // options that don't affect the format don't change the fingerprint
+        assert_eq!(
+            Mode::default().fingerprint(),
+            Mode::default()
+                .with_max_depth(Some(8))
+                .with_error_context(true)
+                .with_deterministic(true)
+                .fingerprint()
+        );
+        assert_eq!(Mode::compact().fingerprint(), compact.fingerprint());
+
+        // the same value is checked without std in tests/no_std.rs
+        assert_eq!(2902819308366345267, Mode::dedup().fingerprint());
+
+        let map: HashMap<String, Option<Vec<u32>>> = (0..10)
+            .map(|i| (format!("key {}", i % 4), Some(vec![i; 3])))
+            .collect();
+
+        for mode in [compact, portable].iter().copied() {
+            let vec = serialize_with(&map, mode).unwrap();
+            let out: HashMap<String, Option<Vec<u32>>> = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(map, out);
+        }
+
+        assert!(
+            serialize_with(&map, compact).unwrap().len()
+                < serialize_with(&map, portable).unwrap().len()
+        );
+
+        // the recorded options catch mismatched modes
+        let vec = serialize_with(&map, portable).unwrap();
+        assert!(deserialize_with::<HashMap<String, Option<Vec<u32>>>>(
+            &vec,
+            portable.with_big_endian(true)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn large_tuples() {
+        use std::marker::PhantomData;
+
+        type Sixteen = (
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+        );
+        type Twelve = (u8, u16, u32, u64, u8, i16, i32, i64, bool, u32, String, ());
+        let value: Twelve = (1, 2, 3, 4, 5, -2, -3, -4, true, 6, "twelve".to_string(), ());
+        let vec = serialize(&value).unwrap();
+        assert_eq!(1 + 2 + 4 + 8 + 1 + 2 + 4 + 8 + 1 + 4 + 7, vec.len());
+        assert_eq!(value, deserialize::<Twelve>(&vec).unwrap());
+
+        let value = (
+            1u8, 2u8, 3u8, 4u8, 5u8, 6u8, 7u8, 8u8, 9u8, 10u8, 11u8, 12u8, 13u8, 14u8, 15u8, 16u8,
+        );
+        let vec = serialize(&value).unwrap();
+        assert_eq!(&(1..=16).collect::<Vec<u8>>(), &vec);
+        // std only implements PartialEq/Debug for tuples up to 12 elements
+        let back: Sixteen = deserialize(&vec).unwrap();
+        assert_eq!((value.0, value.15), (back.0, back.15));
+        assert_eq!(vec, serialize(&back).unwrap());
+
+        let value = (7u32,);
+        let vec = serialize(&value).unwrap();
+        assert_eq!(&[7, 0, 0, 0], &*vec);
+        assert_eq!(value, deserialize(&vec).unwrap());
+
+        // markers and unit values take up no space
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        struct Marked {
+            id: u32,
+            marker: PhantomData<u32>,
+            unit: (),
+        }
+
+        let value = Marked {
+            id: 9,
+            marker: PhantomData,
+            unit: (),
+        };
+        let vec = serialize(&value).unwrap();
+        assert_eq!(&[9, 0, 0, 0], &*vec);
+        assert_eq!(value, deserialize(&vec).unwrap());
+    }
+
+    #[test]
+    fn framed() {
+        use crate::{deserialize_framed_from, serialize_framed_into};
+
+        #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+        struct Record {
+            id: u32,
+            name: String,
+        }
+
+        let first = Record {
+            id: 1,
+            name: "first".to_string(),
+        };
+        let second = Record {
+            id: 2,
+            name: "second".to_string(),
+        };
+
+        let mut vec = Vec::new();
+        serialize_framed_into(&mut vec, &first, Mode::default()).unwrap();
+        let first_len = vec.len();
+        serialize_framed_into(&mut vec, &second, Mode::default()).unwrap();
+
+        let body_len = serialize(&first).unwrap().len();
+        assert_eq!(8 + body_len, first_len);
+        assert_eq!(&(body_len as u64).to_le_bytes(), &vec[..8]);
+
+        let mut pipe = &vec[..];
+        assert_eq!(
+            first,
+            deserialize_framed_from::<_, Record>(&mut pipe, Mode::default()).unwrap()
+        );
+        assert_eq!(
+            second,
+            deserialize_framed_from::<_, Record>(&mut pipe, Mode::default()).unwrap()
+        );
+        assert!(pipe.is_empty());
+
+        // cut off in the middle of the second frame
+        let mut pipe = &vec[..vec.len() - 3];
+        deserialize_framed_from::<_, Record>(&mut pipe, Mode::default()).unwrap();
+        let err = deserialize_framed_from::<_, Record>(&mut pipe, Mode::default()).unwrap_err();
+        let second_len = (vec.len() - first_len - 8) as u64;
+        match err {
+            crate::Error::TruncatedFrame { expected, got } => {
+                assert_eq!(second_len, expected);
+                assert_eq!(second_len - 3, got);
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn mode_builder() {
+        use crate::serde::OptimizeFor;
+
+        let mode = Mode::builder()
+            .optimize_for(OptimizeFor::Size)
+            .build()
+            .unwrap();
+        assert_eq!(Mode::compact(), mode);
+        assert_eq!(Mode::compact().fingerprint(), mode.fingerprint());
+
+        let mode = Mode::builder()
+            .deterministic(true)
+            .mode_flags(true)
+            .build()
+            .unwrap();
+        assert_eq!(Mode::portable(), mode);
+
+        let mode = Mode::builder()
+            .dedup(true)
+            .inline_dedup(true)
+            .header(*b"TEST", 2)
+            .explicit_option_tag_byte(0xAA, 0xBB)
+            .max_depth(Some(4))
+            .build()
+            .unwrap();
+        let expected = Mode::dedup()
+            .with_inline_dedup(true)
+            .with_header(*b"TEST", 2)
+            .with_explicit_option_tag_byte(0xAA, 0xBB)
+            .with_max_depth(Some(4));
+        assert_eq!(expected, mode);
+
+        let value = (Some("a".to_string()), vec!["a".to_string(); 3]);
+        let vec = serialize_with(&value, mode).unwrap();
+        assert_eq!(value, deserialize_with(&vec, mode).unwrap());
+
+        // options that would be ignored because of others are rejected
+        for builder in [
+            Mode::builder().inline_dedup(true),
+            Mode::builder().dedup_bytes(true),
+            Mode::builder()
+                .dedup(true)
+                .deduplicate_identical_collections(true),
+            Mode::builder()
+                .dedup(true)
+                .inline_dedup(true)
+                .dedup_bytes(true),
+            Mode::builder().dictionary_from_bytes(&[0]),
+            Mode::builder().explicit_option_tag_byte(7, 7),
+        ] {
+            assert!(builder.build().is_err(), "{:?}", builder);
+        }
+    }
 }
@@ -0,0 +1,90 @@
+//! Optional values which use a value their type can't otherwise have to
+//! represent `None`, instead of writing a separate tag.
+//!
+//! `Option<T>` is normally written as a tag followed by the value if it is
+//! `Some`. For the `NonZero` integer types, zero is free to mean `None`, so
+//! fields marked `#[binserde(niche)]` are written as the plain integer, with
+//! 0 for `None`, saving the tag. The functions in this module can also be
+//! used with `#[binserde(with = "binserde::niche")]` or in custom
+//! implementations.
+//!
+//! ```
+//! use std::num::NonZeroU32;
+//!
+//! use binserde_derive::{BinDeserialize, BinSerialize};
+//!
+//! #[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+//! struct Node {
+//!     #[binserde(niche)]
+//!     parent: Option<NonZeroU32>,
+//! }
+//!
+//! let node = Node { parent: NonZeroU32::new(7) };
+//! let vec = binserde::serialize(&node).unwrap();
+//! assert_eq!(&[7, 0, 0, 0], &*vec);
+//!
+//! let root: Node = binserde::deserialize(&[0, 0, 0, 0]).unwrap();
+//! assert_eq!(None, root.parent);
+//! ```
+
+use core::num::{
+    NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU16, NonZeroU32,
+    NonZeroU64, NonZeroU8, NonZeroUsize,
+};
+
+use crate::de::BinDeserializeOwned;
+use crate::{BinDeserialize, BinDeserializer, BinSerialize, BinSerializer, Result};
+
+/// A type with a value it can't represent, which is used to store `None`
+/// when serializing an `Option` of it.
+pub trait Niche: Sized {
+    /// The type written in place of the `Option`.
+    type Raw: BinSerialize + BinDeserializeOwned;
+
+    fn to_raw(value: Option<&Self>) -> Self::Raw;
+
+    fn from_raw(raw: Self::Raw) -> Option<Self>;
+}
+
+macro_rules! impl_nonzero {
+    ($type:ty, $inner:ty) => {
+        impl Niche for $type {
+            type Raw = $inner;
+
+            fn to_raw(value: Option<&Self>) -> $inner {
+                value.map_or(0, |v| v.get())
+            }
+
+            fn from_raw(raw: $inner) -> Option<Self> {
+                <$type>::new(raw)
+            }
+        }
+    };
+}
+
+impl_nonzero! { NonZeroU8, u8 }
+impl_nonzero! { NonZeroU16, u16 }
+impl_nonzero! { NonZeroU32, u32 }
+impl_nonzero! { NonZeroU64, u64 }
+impl_nonzero! { NonZeroUsize, usize }
+impl_nonzero! { NonZeroI8, i8 }
+impl_nonzero! { NonZeroI16, i16 }
+impl_nonzero! { NonZeroI32, i32 }
+impl_nonzero! { NonZeroI64, i64 }
+impl_nonzero! { NonZeroIsize, isize }
+
+pub fn serialize<T, S>(value: &Option<T>, serializer: S) -> Result<()>
+where
+    T: Niche,
+    S: BinSerializer,
+{
+    T::to_raw(value.as_ref()).serialize(serializer)
+}
+
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>>
+where
+    T: Niche,
+    D: BinDeserializer<'de>,
+{
+    Ok(T::from_raw(T::Raw::deserialize(deserializer)?))
+}
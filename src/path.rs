@@ -0,0 +1,169 @@
+//! Paths encoded as a list of their components.
+//!
+//! [`PathBuf`] itself is serialized as a single string, which contains the
+//! separators of the platform it was written on. Wrapping it in
+//! [`ComponentPath`] instead writes the number of components followed by
+//! each of them, with the names of normal components written as strings so
+//! that they are deduplicated. When deserializing, the path is rebuilt from
+//! its components using the separators of the current platform.
+//!
+//! ```
+//! use std::path::PathBuf;
+//!
+//! use binserde::path::ComponentPath;
+//!
+//! let path: PathBuf = ["assets", "..", "textures", "stone.png"].iter().collect();
+//! let vec = binserde::serialize(&ComponentPath(path.clone())).unwrap();
+//!
+//! let out: ComponentPath = binserde::deserialize(&vec).unwrap();
+//! assert_eq!(path, out.0);
+//! ```
+//!
+//! Each component is a tag, followed by the name for normal components and
+//! Windows path prefixes such as `C:`. Prefixes are read back as normal
+//! components on other platforms. Names which contain a separator of the
+//! current platform are rejected when deserializing, since they would
+//! otherwise turn into several components.
+
+use std::path::{is_separator, Component, Path, PathBuf};
+
+use crate::util::read_elements_len;
+use crate::{BinDeserialize, BinDeserializer, BinSerialize, BinSerializer, Error, Result};
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ComponentPath(pub PathBuf);
+
+const TAG_PREFIX: u8 = 0;
+const TAG_ROOT_DIR: u8 = 1;
+const TAG_CUR_DIR: u8 = 2;
+const TAG_PARENT_DIR: u8 = 3;
+const TAG_NORMAL: u8 = 4;
+
+impl BinSerialize for ComponentPath {
+    fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
+        self.0.components().count().serialize(&mut serializer)?;
+
+        for component in self.0.components() {
+            match component {
+                Component::Prefix(prefix) => {
+                    TAG_PREFIX.serialize(&mut serializer)?;
+                    Path::new(prefix.as_os_str()).serialize(&mut serializer)?;
+                }
+                Component::RootDir => TAG_ROOT_DIR.serialize(&mut serializer)?,
+                Component::CurDir => TAG_CUR_DIR.serialize(&mut serializer)?,
+                Component::ParentDir => TAG_PARENT_DIR.serialize(&mut serializer)?,
+                Component::Normal(name) => {
+                    TAG_NORMAL.serialize(&mut serializer)?;
+                    Path::new(name).serialize(&mut serializer)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'de> BinDeserialize<'de> for ComponentPath {
+    fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
+        let len = read_elements_len(&mut deserializer)?;
+        let mut path = PathBuf::new();
+
+        for _ in 0..len {
+            match u8::deserialize(&mut deserializer)? {
+                TAG_ROOT_DIR => path.push(Component::RootDir),
+                TAG_CUR_DIR => path.push(Component::CurDir),
+                TAG_PARENT_DIR => path.push(Component::ParentDir),
+                TAG_PREFIX | TAG_NORMAL => {
+                    let name = String::deserialize(&mut deserializer)?;
+
+                    if name.is_empty() || name.chars().any(is_separator) {
+                        return Err(Error::custom(format!("invalid path component {:?}", name)));
+                    }
+
+                    path.push(name);
+                }
+                x => return Err(Error::custom(format!("invalid enum variant index {}", x))),
+            }
+        }
+
+        Ok(ComponentPath(path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Component, PathBuf};
+
+    use crate::path::ComponentPath;
+    use crate::{deserialize_with, serialize_with, Mode};
+
+    #[test]
+    fn round_trip() {
+        let paths = [
+            PathBuf::new(),
+            PathBuf::from("file name.txt"),
+            [
+                Component::RootDir.as_os_str(),
+                "usr".as_ref(),
+                "lib".as_ref(),
+            ]
+            .iter()
+            .collect(),
+            [".", "..", "..", "src", "lib.rs"].iter().collect(),
+            [
+                Component::RootDir.as_os_str(),
+                "..".as_ref(),
+                "etc".as_ref(),
+            ]
+            .iter()
+            .collect(),
+        ];
+
+        for mode in [Mode::default(), Mode::dedup()] {
+            let wrapped: Vec<_> = paths.iter().cloned().map(ComponentPath).collect();
+            let vec = serialize_with(&wrapped, mode).unwrap();
+            let out: Vec<ComponentPath> = deserialize_with(&vec, mode).unwrap();
+            assert_eq!(wrapped, out);
+        }
+    }
+
+    #[test]
+    fn platform_independent() {
+        // `/srv/../data`, and `C:data` as written on Windows, which don't
+        // contain any separators in the encoded form
+        let vec = [
+            4, 1, 4, 3, b's', b'r', b'v', 3, 4, 4, b'd', b'a', b't', b'a',
+        ];
+        let out: ComponentPath = deserialize_with(&vec, Mode::default()).unwrap();
+        let expected: PathBuf = [
+            Component::RootDir.as_os_str(),
+            "srv".as_ref(),
+            "..".as_ref(),
+            "data".as_ref(),
+        ]
+        .iter()
+        .collect();
+        assert_eq!(expected, out.0);
+
+        let vec = [2, 0, 2, b'C', b':', 4, 4, b'd', b'a', b't', b'a'];
+        let out: ComponentPath = deserialize_with(&vec, Mode::default()).unwrap();
+        assert_eq!(2, out.0.components().count());
+
+        // a separator in a name can't be represented as one component
+        let vec = [1, 4, 3, b'a', b'/', b'b'];
+        assert!(deserialize_with::<ComponentPath>(&vec, Mode::default()).is_err());
+    }
+
+    #[test]
+    fn dedups_names() {
+        let path: PathBuf = ["data", "data", "data", "data"].iter().collect();
+        let vec = serialize_with(&ComponentPath(path.clone()), Mode::dedup()).unwrap();
+        assert_eq!(
+            &[1, 4, b'd', b'a', b't', b'a', 4, 4, 0, 4, 0, 4, 0, 4, 0],
+            &*vec
+        );
+
+        let out: ComponentPath = deserialize_with(&vec, Mode::dedup()).unwrap();
+        assert_eq!(path, out.0);
+    }
+}
@@ -0,0 +1,140 @@
+//! Deserializing values whose type is only known at runtime.
+//!
+//! Values are written with [`serialize_tagged`], which prefixes them with a
+//! numeric type id as a little-endian `u32`. A [`TypeRegistry`] maps these ids
+//! back to the type to deserialize and returns the value as a `Box<dyn Any>`.
+//!
+//! ```
+//! use binserde::registry::{serialize_tagged, TypeRegistry};
+//! use binserde::Mode;
+//!
+//! let mut registry = TypeRegistry::new();
+//! registry.register::<String>(1);
+//!
+//! let vec = serialize_tagged(1, "hello", Mode::default()).unwrap();
+//! let value = registry.deserialize(&vec, Mode::default()).unwrap();
+//!
+//! assert_eq!(Some("hello"), value.downcast_ref::<String>().map(|s| &**s));
+//! ```
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::any::Any;
+
+use byteorder::LE;
+
+use crate::io::{Read, ReadBytesExt, Write, WriteBytesExt};
+use crate::{
+    deserialize_with_from, serialize_with_into, BinDeserializeOwned, BinSerialize, Error, Mode,
+    Result,
+};
+
+type DeserializeFn = Box<dyn Fn(&mut dyn Read, Mode) -> Result<Box<dyn Any>>>;
+
+#[derive(Default)]
+pub struct TypeRegistry {
+    types: BTreeMap<u32, DeserializeFn>,
+}
+
+impl TypeRegistry {
+    pub fn new() -> Self {
+        TypeRegistry::default()
+    }
+
+    /// Makes values tagged with `id` deserialize as `T`, replacing the type
+    /// previously registered for `id`, if any.
+    pub fn register<T>(&mut self, id: u32)
+    where
+        T: BinDeserializeOwned + Any,
+    {
+        self.types.insert(
+            id,
+            Box::new(|pipe, mode| {
+                let value: T = deserialize_with_from(pipe, mode)?;
+                Ok(Box::new(value))
+            }),
+        );
+    }
+
+    pub fn deserialize(&self, buf: &[u8], mode: Mode) -> Result<Box<dyn Any>> {
+        self.deserialize_from(buf, mode)
+    }
+
+    pub fn deserialize_from<R: Read>(&self, mut pipe: R, mode: Mode) -> Result<Box<dyn Any>> {
+        let id = pipe.read_u32::<LE>()?;
+
+        match self.types.get(&id) {
+            None => Err(Error::UnknownTypeId(id)),
+            Some(f) => f(&mut pipe, mode),
+        }
+    }
+}
+
+/// Serializes `value` prefixed with the type id `id`, to be read back with
+/// [`TypeRegistry::deserialize`].
+pub fn serialize_tagged<T>(id: u32, value: &T, mode: Mode) -> Result<Vec<u8>>
+where
+    T: BinSerialize + ?Sized,
+{
+    let mut buf = Vec::new();
+    serialize_tagged_into(&mut buf, id, value, mode)?;
+    Ok(buf)
+}
+
+pub fn serialize_tagged_into<W, T>(mut pipe: W, id: u32, value: &T, mode: Mode) -> Result<()>
+where
+    W: Write,
+    T: BinSerialize + ?Sized,
+{
+    pipe.write_u32::<LE>(id)?;
+    serialize_with_into(pipe, value, mode)
+}
+
+#[cfg(test)]
+mod test {
+    use binserde_derive::{BinDeserialize, BinSerialize};
+
+    use crate::registry::{serialize_tagged, TypeRegistry};
+    use crate::{Error, Mode};
+
+    #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+    struct Load {
+        name: String,
+        priority: u32,
+    }
+
+    #[derive(Debug, Eq, PartialEq, BinSerialize, BinDeserialize)]
+    enum Unload {
+        All,
+        Only(Vec<String>),
+    }
+
+    #[test]
+    fn dispatch() {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Load>(1);
+        registry.register::<Unload>(2);
+
+        let mode = Mode::dedup();
+        let load = Load {
+            name: "plugin".to_string(),
+            priority: 3,
+        };
+        let unload = Unload::Only(vec!["plugin".to_string()]);
+
+        let buf = serialize_tagged(1, &load, mode).unwrap();
+        let value = registry.deserialize(&buf, mode).unwrap();
+        assert_eq!(Some(&load), value.downcast_ref::<Load>());
+
+        let buf = serialize_tagged(2, &unload, mode).unwrap();
+        let value = registry.deserialize(&buf, mode).unwrap();
+        assert_eq!(Some(&unload), value.downcast_ref::<Unload>());
+
+        let buf = serialize_tagged(3, &unload, mode).unwrap();
+        assert!(matches!(
+            registry.deserialize(&buf, mode),
+            Err(Error::UnknownTypeId(3))
+        ));
+    }
+}
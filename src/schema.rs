@@ -0,0 +1,570 @@
+//! Self-describing output.
+//!
+//! With [`Mode::with_write_schema_header`], the serialized data is preceded by
+//! a [`Schema`] describing the structure of the serialized type, as returned
+//! by [`BinSerialize::schema`]. This allows [`deserialize_dynamic`] to decode
+//! the data into a generic [`Value`] without access to the original type.
+//!
+//! ```
+//! use binserde::schema::{deserialize_dynamic, Value};
+//! use binserde::Mode;
+//! use binserde_derive::BinSerialize;
+//!
+//! #[derive(BinSerialize)]
+//! struct Entry {
+//!     name: String,
+//!     size: u32,
+//! }
+//!
+//! let mode = Mode::default().with_write_schema_header(true);
+//! let entry = Entry { name: "a.txt".to_string(), size: 12 };
+//! let vec = binserde::serialize_with(&entry, mode).unwrap();
+//!
+//! let (_, value) = deserialize_dynamic(&vec, mode).unwrap();
+//! assert_eq!(
+//!     Value::Struct(vec![
+//!         ("name".to_string(), Value::String("a.txt".to_string())),
+//!         ("size".to_string(), Value::UInt(12)),
+//!     ]),
+//!     value,
+//! );
+//! ```
+//!
+//! Schemas of recursive types are infinitely large, so these can't be
+//! serialized with a schema header.
+//!
+//! [`Mode::with_write_schema_header`]: crate::Mode::with_write_schema_header
+//! [`BinSerialize::schema`]: crate::BinSerialize::schema
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::de::BinDeserializerBase;
+use crate::dedup::{
+    read_entry, read_value_idx, use_collection_table, use_value_table, DedupContext,
+};
+use crate::io::{Read, ReadBytesExt, Write, WriteBytesExt};
+use crate::ser::use_compact_options;
+use crate::write_ext::{ReadExt, WriteExt};
+use crate::{read_preamble, BinDeserialize, BinDeserializer, Error, Mode, Result};
+
+/// Describes how a type is serialized.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Schema {
+    /// A type that doesn't provide a schema. Data containing it can't be
+    /// decoded generically.
+    Unknown,
+    Unit,
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    Usize,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    String,
+    Option(Box<Schema>),
+    /// A length-prefixed sequence.
+    Seq(Box<Schema>),
+    /// A sequence of fixed length, without a length prefix.
+    Array(usize, Box<Schema>),
+    /// A length-prefixed sequence of key-value pairs. Sets are maps with
+    /// [`Schema::Unit`] values.
+    Map(Box<Schema>, Box<Schema>),
+    Tuple(Vec<Schema>),
+    /// Named fields in the order they are serialized in. Fields of tuple
+    /// structs are named by their position.
+    Struct(Vec<(String, Schema)>),
+    /// A tag of type `tag` followed by the data of the variant with that
+    /// tag.
+    Enum {
+        tag: Box<Schema>,
+        variants: Vec<(u64, String, Schema)>,
+    },
+    /// A value written without deduplicating the strings in it, such as a
+    /// field marked `#[binserde(no_dedup)]`.
+    NoDedup(Box<Schema>),
+    /// A value which is written to the byte blob table in modes that use it,
+    /// such as [`Dedup`](crate::dedup::Dedup) or an `Arc`.
+    Dedup(Box<Schema>),
+}
+
+impl Schema {
+    pub fn write_to<W: Write>(&self, mut pipe: W) -> Result<()> {
+        self.write_to_impl(&mut pipe)
+    }
+
+    // Takes the pipe by reference so that recursive calls don't instantiate
+    // this function for ever more deeply nested references.
+    fn write_to_impl<W: Write>(&self, pipe: &mut W) -> Result<()> {
+        match self {
+            Schema::Unknown => pipe.write_u8(0)?,
+            Schema::Unit => pipe.write_u8(1)?,
+            Schema::Bool => pipe.write_u8(2)?,
+            Schema::U8 => pipe.write_u8(3)?,
+            Schema::U16 => pipe.write_u8(4)?,
+            Schema::U32 => pipe.write_u8(5)?,
+            Schema::U64 => pipe.write_u8(6)?,
+            Schema::Usize => pipe.write_u8(7)?,
+            // these were added after the others, so they got the next free
+            // codes
+            Schema::I8 => pipe.write_u8(19)?,
+            Schema::F32 => pipe.write_u8(21)?,
+            Schema::F64 => pipe.write_u8(22)?,
+            Schema::I16 => pipe.write_u8(8)?,
+            Schema::I32 => pipe.write_u8(9)?,
+            Schema::I64 => pipe.write_u8(10)?,
+            Schema::String => pipe.write_u8(11)?,
+            Schema::Option(inner) => {
+                pipe.write_u8(12)?;
+                inner.write_to_impl(pipe)?;
+            }
+            Schema::Seq(inner) => {
+                pipe.write_u8(13)?;
+                inner.write_to_impl(pipe)?;
+            }
+            Schema::Array(len, inner) => {
+                pipe.write_u8(14)?;
+                pipe.write_varusize(*len)?;
+                inner.write_to_impl(pipe)?;
+            }
+            Schema::Map(key, value) => {
+                pipe.write_u8(15)?;
+                key.write_to_impl(pipe)?;
+                value.write_to_impl(pipe)?;
+            }
+            Schema::Tuple(items) => {
+                pipe.write_u8(16)?;
+                pipe.write_varusize(items.len())?;
+
+                for item in items {
+                    item.write_to_impl(pipe)?;
+                }
+            }
+            Schema::Struct(fields) => {
+                pipe.write_u8(17)?;
+                pipe.write_varusize(fields.len())?;
+
+                for (name, schema) in fields {
+                    write_name(&mut *pipe, name)?;
+                    schema.write_to_impl(pipe)?;
+                }
+            }
+            Schema::Enum { tag, variants } => {
+                pipe.write_u8(18)?;
+                tag.write_to_impl(pipe)?;
+                pipe.write_varusize(variants.len())?;
+
+                for (tag, name, schema) in variants {
+                    pipe.write_varuint(*tag)?;
+                    write_name(&mut *pipe, name)?;
+                    schema.write_to_impl(pipe)?;
+                }
+            }
+            Schema::NoDedup(inner) => {
+                pipe.write_u8(20)?;
+                inner.write_to_impl(pipe)?;
+            }
+            Schema::Dedup(inner) => {
+                pipe.write_u8(23)?;
+                inner.write_to_impl(pipe)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(mut pipe: R) -> Result<Self> {
+        Schema::read_from_impl(&mut pipe)
+    }
+
+    fn read_from_impl<R: Read>(pipe: &mut R) -> Result<Self> {
+        let schema = match pipe.read_u8()? {
+            0 => Schema::Unknown,
+            1 => Schema::Unit,
+            2 => Schema::Bool,
+            3 => Schema::U8,
+            4 => Schema::U16,
+            5 => Schema::U32,
+            6 => Schema::U64,
+            7 => Schema::Usize,
+            8 => Schema::I16,
+            9 => Schema::I32,
+            10 => Schema::I64,
+            11 => Schema::String,
+            12 => Schema::Option(Box::new(Schema::read_from_impl(pipe)?)),
+            13 => Schema::Seq(Box::new(Schema::read_from_impl(pipe)?)),
+            14 => {
+                let len = pipe.read_varusize()?;
+                Schema::Array(len, Box::new(Schema::read_from_impl(pipe)?))
+            }
+            15 => {
+                let key = Schema::read_from_impl(pipe)?;
+                let value = Schema::read_from_impl(pipe)?;
+                Schema::Map(Box::new(key), Box::new(value))
+            }
+            16 => {
+                let len = pipe.read_varusize()?;
+                let items = (0..len)
+                    .map(|_| Schema::read_from_impl(pipe))
+                    .collect::<Result<_>>()?;
+                Schema::Tuple(items)
+            }
+            17 => {
+                let len = pipe.read_varusize()?;
+                let fields = (0..len)
+                    .map(|_| Ok((read_name(&mut *pipe)?, Schema::read_from_impl(pipe)?)))
+                    .collect::<Result<_>>()?;
+                Schema::Struct(fields)
+            }
+            18 => {
+                let tag = Schema::read_from_impl(pipe)?;
+                let len = pipe.read_varusize()?;
+                let variants = (0..len)
+                    .map(|_| {
+                        let tag = pipe.read_varuint()?;
+                        let name = read_name(&mut *pipe)?;
+                        Ok((tag, name, Schema::read_from_impl(pipe)?))
+                    })
+                    .collect::<Result<_>>()?;
+                Schema::Enum {
+                    tag: Box::new(tag),
+                    variants,
+                }
+            }
+            19 => Schema::I8,
+            20 => Schema::NoDedup(Box::new(Schema::read_from_impl(pipe)?)),
+            21 => Schema::F32,
+            22 => Schema::F64,
+            23 => Schema::Dedup(Box::new(Schema::read_from_impl(pipe)?)),
+            x => return Err(Error::custom(format!("invalid schema type code {}", x))),
+        };
+
+        Ok(schema)
+    }
+}
+
+fn write_name<W: Write>(mut pipe: W, name: &str) -> Result<()> {
+    pipe.write_varusize(name.len())?;
+    pipe.write_all(name.as_bytes())?;
+    Ok(())
+}
+
+fn read_name<R: Read>(pipe: R) -> Result<String> {
+    let mut pipe = pipe;
+    let len = pipe.read_varusize()?;
+    let mut buf = Vec::new();
+
+    let read = pipe.take(len as u64).read_to_end(&mut buf)?;
+
+    if read != len {
+        return Err(Error::UnexpectedEof {
+            expected: len,
+            got: read,
+        });
+    }
+
+    Ok(String::from_utf8(buf)?)
+}
+
+/// A value decoded using a [`Schema`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unit,
+    Bool(bool),
+    UInt(u64),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Option(Option<Box<Value>>),
+    Seq(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Tuple(Vec<Value>),
+    Struct(Vec<(String, Value)>),
+    Variant(String, Box<Value>),
+}
+
+/// Decodes data serialized with [`Mode::with_write_schema_header`] using the
+/// schema in its header, returning the schema and the decoded value.
+///
+/// [`Mode::prefix_total_len`] and [`Mode::trailing_crc`] aren't supported,
+/// and neither are optional collections written with
+/// [`Mode::with_compact_collection_options`].
+pub fn deserialize_dynamic(buf: &[u8], mode: Mode) -> Result<(Schema, Value)> {
+    if !mode.write_schema_header {
+        return Err(Error::custom("mode has no schema header"));
+    }
+
+    if mode.prefix_total_len || mode.trailing_crc {
+        return Err(Error::custom(
+            "dynamic deserialization doesn't support length prefix or CRC",
+        ));
+    }
+
+    if mode.tag_depth > 0 {
+        return Err(Error::custom(
+            "dynamic deserialization doesn't support tagged fields",
+        ));
+    }
+
+    let mut pipe = buf;
+    read_preamble(&mut pipe, mode)?;
+    let schema = Schema::read_from(&mut pipe)?;
+
+    if use_compact_options(mode) && has_collection_option(&schema) {
+        return Err(Error::custom(
+            "dynamic deserialization doesn't support compact collection options",
+        ));
+    }
+
+    let context = DedupContext::read_header(&mut pipe, mode)?;
+    let deserializer = BinDeserializerBase::new(pipe, &context).with_mode(mode);
+    let value = read_value(&schema, deserializer)?;
+    Ok((schema, value))
+}
+
+/// Returns whether `schema` contains an optional collection, which is
+/// written differently with [`Mode::with_compact_collection_options`].
+fn has_collection_option(schema: &Schema) -> bool {
+    match schema {
+        Schema::Option(inner) => {
+            matches!(**inner, Schema::String | Schema::Seq(_) | Schema::Map(..))
+                || has_collection_option(inner)
+        }
+        Schema::Seq(inner)
+        | Schema::Array(_, inner)
+        | Schema::NoDedup(inner)
+        | Schema::Dedup(inner) => has_collection_option(inner),
+        Schema::Map(key, value) => has_collection_option(key) || has_collection_option(value),
+        Schema::Tuple(items) => items.iter().any(has_collection_option),
+        Schema::Struct(fields) => fields.iter().any(|(_, el)| has_collection_option(el)),
+        Schema::Enum { variants, .. } => variants.iter().any(|el| has_collection_option(&el.2)),
+        _ => false,
+    }
+}
+
+/// Decodes a single value described by `schema`.
+pub fn read_value<'de, D: BinDeserializer<'de>>(
+    schema: &Schema,
+    mut deserializer: D,
+) -> Result<Value> {
+    let mode = deserializer.mode();
+    read_value_impl(schema, &mut deserializer, mode)
+}
+
+// Like Schema::read_from_impl, takes the deserializer by reference and passes
+// the mode separately to avoid instantiating this for nested wrappers.
+fn read_value_impl<'de, D: BinDeserializer<'de>>(
+    schema: &Schema,
+    de: &mut D,
+    mode: Mode,
+) -> Result<Value> {
+    let value = match schema {
+        Schema::Unknown => return Err(Error::custom("can't read value of unknown type")),
+        Schema::Unit => Value::Unit,
+        Schema::Bool => Value::Bool(bool::deserialize((&mut *de).with_mode(mode))?),
+        Schema::U8 => Value::UInt(u8::deserialize((&mut *de).with_mode(mode))?.into()),
+        Schema::U16 => Value::UInt(u16::deserialize((&mut *de).with_mode(mode))?.into()),
+        Schema::U32 => Value::UInt(u32::deserialize((&mut *de).with_mode(mode))?.into()),
+        Schema::U64 => Value::UInt(u64::deserialize((&mut *de).with_mode(mode))?),
+        Schema::Usize => Value::UInt(usize::deserialize((&mut *de).with_mode(mode))? as u64),
+        Schema::I8 => Value::Int(i8::deserialize((&mut *de).with_mode(mode))?.into()),
+        Schema::I16 => Value::Int(i16::deserialize((&mut *de).with_mode(mode))?.into()),
+        Schema::I32 => Value::Int(i32::deserialize((&mut *de).with_mode(mode))?.into()),
+        Schema::I64 => Value::Int(i64::deserialize((&mut *de).with_mode(mode))?),
+        Schema::F32 => Value::Float(f32::deserialize((&mut *de).with_mode(mode))?.into()),
+        Schema::F64 => Value::Float(f64::deserialize((&mut *de).with_mode(mode))?),
+        Schema::String => Value::String(String::deserialize((&mut *de).with_mode(mode))?),
+        Schema::Option(inner) => {
+            let (none, some) = mode.option_tags;
+
+            match u8::deserialize((&mut *de).with_mode(mode))? {
+                x if x == none => Value::Option(None),
+                x if x == some => Value::Option(Some(Box::new(read_value_impl(inner, de, mode)?))),
+                x => return Err(Error::custom(format!("invalid enum variant index {}", x))),
+            }
+        }
+        // these have their own encoding for sequences, see
+        // BinSerialize::serialize_slice
+        Schema::Seq(inner) if **inner == Schema::U8 => {
+            Value::Bytes(Vec::<u8>::deserialize((&mut *de).with_mode(mode))?)
+        }
+        Schema::Seq(inner) if **inner == Schema::Bool => Value::Seq(
+            Vec::<bool>::deserialize((&mut *de).with_mode(mode))?
+                .into_iter()
+                .map(Value::Bool)
+                .collect(),
+        ),
+        Schema::Seq(inner) if **inner == Schema::U32 => Value::Seq(
+            Vec::<u32>::deserialize((&mut *de).with_mode(mode))?
+                .into_iter()
+                .map(|v| Value::UInt(v.into()))
+                .collect(),
+        ),
+        Schema::Seq(inner) if **inner == Schema::U64 => Value::Seq(
+            Vec::<u64>::deserialize((&mut *de).with_mode(mode))?
+                .into_iter()
+                .map(Value::UInt)
+                .collect(),
+        ),
+        Schema::Seq(inner) if **inner == Schema::Unit => Value::Seq(
+            Vec::<()>::deserialize((&mut *de).with_mode(mode))?
+                .into_iter()
+                .map(|_| Value::Unit)
+                .collect(),
+        ),
+        // see Mode::with_deduplicate_identical_collections
+        Schema::Seq(inner) if use_collection_table(mode) => {
+            let idx = read_value_idx((&mut *de).with_mode(mode))?;
+            read_entry(idx, mode, de.dedup(), |mut de| {
+                read_seq_items(inner, &mut de, mode)
+            })?
+        }
+        Schema::Seq(inner) => read_seq_items(inner, de, mode)?,
+        // written as a single hex string, see Mode::with_hex_bytes
+        Schema::Array(len, inner) if **inner == Schema::U8 && mode.hex_bytes => {
+            let bytes = Vec::<u8>::deserialize((&mut *de).with_mode(mode))?;
+
+            if bytes.len() != *len {
+                return Err(Error::custom(format!(
+                    "expected {} bytes for array, found {}",
+                    len,
+                    bytes.len()
+                )));
+            }
+
+            Value::Seq(bytes.into_iter().map(|v| Value::UInt(v.into())).collect())
+        }
+        Schema::Array(len, inner) => {
+            let items = (0..*len)
+                .map(|_| read_value_impl(inner, de, mode))
+                .collect::<Result<_>>()?;
+            Value::Seq(items)
+        }
+        Schema::Map(key, value) => {
+            let len = usize::deserialize((&mut *de).with_mode(mode))?;
+            let items = (0..len)
+                .map(|_| {
+                    let mut key_mode = mode;
+
+                    if mode.deny_dedup_in_keys {
+                        key_mode.use_dedup = false;
+                    }
+
+                    let k = read_value_impl(key, de, key_mode)?;
+                    Ok((k, read_value_impl(value, de, mode)?))
+                })
+                .collect::<Result<_>>()?;
+            Value::Map(items)
+        }
+        Schema::Tuple(items) => {
+            let items = items
+                .iter()
+                .map(|el| read_value_impl(el, de, mode))
+                .collect::<Result<_>>()?;
+            Value::Tuple(items)
+        }
+        Schema::Struct(fields) => {
+            let fields = fields
+                .iter()
+                .map(|(name, el)| Ok((name.clone(), read_value_impl(el, de, mode)?)))
+                .collect::<Result<_>>()?;
+            Value::Struct(fields)
+        }
+        Schema::Enum { tag, variants } => {
+            let tag = match read_value_impl(tag, de, mode)? {
+                Value::UInt(tag) => tag,
+                _ => return Err(Error::custom("enum tag is not an unsigned integer")),
+            };
+
+            match variants.iter().find(|el| el.0 == tag) {
+                None => return Err(Error::UnknownVariant(tag)),
+                Some((_, name, schema)) => {
+                    Value::Variant(name.clone(), Box::new(read_value_impl(schema, de, mode)?))
+                }
+            }
+        }
+        Schema::NoDedup(inner) => {
+            let mut mode = mode;
+            mode.use_dedup = false;
+            read_value_impl(inner, de, mode)?
+        }
+        Schema::Dedup(inner) if use_value_table(mode) => {
+            let idx = read_value_idx((&mut *de).with_mode(mode))?;
+            read_entry(idx, mode, de.dedup(), |mut de| {
+                read_value_impl(inner, &mut de, mode)
+            })?
+        }
+        Schema::Dedup(inner) => read_value_impl(inner, de, mode)?,
+    };
+
+    Ok(value)
+}
+
+/// Reads the length and elements of a sequence of `inner`.
+fn read_seq_items<'de, D: BinDeserializer<'de>>(
+    inner: &Schema,
+    de: &mut D,
+    mode: Mode,
+) -> Result<Value> {
+    let len = usize::deserialize((&mut *de).with_mode(mode))?;
+    let items = (0..len)
+        .map(|_| read_value_impl(inner, de, mode))
+        .collect::<Result<_>>()?;
+    Ok(Value::Seq(items))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::schema::Schema;
+
+    #[test]
+    fn schema_round_trip() {
+        let schema = Schema::Struct(vec![
+            (
+                "a".to_string(),
+                Schema::Seq(Box::new(Schema::Dedup(Box::new(Schema::String)))),
+            ),
+            (
+                "b".to_string(),
+                Schema::Enum {
+                    tag: Box::new(Schema::Usize),
+                    variants: vec![
+                        (0, "X".to_string(), Schema::Struct(vec![])),
+                        (
+                            1,
+                            "Y".to_string(),
+                            Schema::Tuple(vec![
+                                Schema::I32,
+                                Schema::I8,
+                                Schema::F64,
+                                Schema::Array(3, Box::new(Schema::U8)),
+                            ]),
+                        ),
+                    ],
+                },
+            ),
+            (
+                "c".to_string(),
+                Schema::Map(
+                    Box::new(Schema::U64),
+                    Box::new(Schema::Option(Box::new(Schema::NoDedup(Box::new(
+                        Schema::Unknown,
+                    ))))),
+                ),
+            ),
+        ]);
+
+        let mut buf = Vec::new();
+        schema.write_to(&mut buf).unwrap();
+        assert_eq!(schema, Schema::read_from(&*buf).unwrap());
+    }
+}
@@ -1,12 +1,260 @@
-use std::io;
-use std::io::Write;
+use alloc::format;
+use alloc::vec::Vec;
+use core::borrow::BorrowMut;
+#[cfg(feature = "std")]
+use std::io::IoSlice;
 
+use crate::de::{BinDeserializer, BinDeserializerBase};
 use crate::dedup::DedupContext;
-use crate::serde::Mode;
-use crate::Result;
+use crate::io::{self, Write};
+use crate::schema::Schema;
+use crate::serde::{Mode, UsizeLen};
+use crate::util::serialize_iter;
+use crate::write_ext::WriteExt;
+use crate::{BinDeserialize, Error, Result};
 
 pub trait BinSerialize {
     fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()>;
+
+    /// Serializes a slice of this type. Used by the slice and `Vec`
+    /// implementations, which allows types to provide a more compact encoding
+    /// for sequences of themselves.
+    fn serialize_slice<S: BinSerializer>(slice: &[Self], serializer: S) -> Result<()>
+    where
+        Self: Sized,
+    {
+        serialize_iter(slice.iter(), serializer)
+    }
+
+    /// Serializes a fixed-size array of this type, without a length prefix.
+    /// Used by the array implementations, like
+    /// [`BinSerialize::serialize_slice`].
+    fn serialize_array<S: BinSerializer>(array: &[Self], mut serializer: S) -> Result<()>
+    where
+        Self: Sized,
+    {
+        for el in array {
+            el.serialize(&mut serializer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Serializes an optional value of this type. Used by the `Option`
+    /// implementation, which allows collections to write `None` and their
+    /// length as a single number with
+    /// [`Mode::with_compact_collection_options`].
+    fn serialize_option<S: BinSerializer>(value: Option<&Self>, serializer: S) -> Result<()> {
+        write_tagged_option(value, serializer)
+    }
+
+    /// Returns the names of the fields of this type which take part in string
+    /// deduplication. For derived types, these are the fields not marked
+    /// `#[binserde(no_dedup)]`, `#[binserde(skip_dedup_scan)]` or
+    /// `#[binserde(skip)]` whose declared type literally names `String`,
+    /// `str`, `Path` or `PathBuf`, also as a type argument like in
+    /// `Vec<String>`. Fields of enum variants are listed as `Variant.field`.
+    ///
+    /// Since this only looks at how the type of a field is written, fields
+    /// whose strings are hidden behind another type, such as a derived struct
+    /// or a type alias, aren't listed. Neither are fields that are only
+    /// deduplicated with [`Mode::with_dedup_bytes`], such as byte blobs or
+    /// [`Dedup`], [`Shared`], `Arc` and `Rc` values without strings.
+    ///
+    /// [`Dedup`]: crate::dedup::Dedup
+    /// [`Shared`]: crate::dedup::Shared
+    fn dedup_fields() -> &'static [&'static str]
+    where
+        Self: Sized,
+    {
+        &[]
+    }
+
+    /// Returns whether serializing a value of this type never writes
+    /// anything, like for `()`. Maps with values of such a type are written
+    /// like sets of their keys, without visiting the values at all. The
+    /// default returns false.
+    fn serializes_to_nothing() -> bool
+    where
+        Self: Sized,
+    {
+        false
+    }
+
+    /// Describes how this type is serialized, for use with
+    /// [`Mode::with_write_schema_header`]. Derived for structs and enums.
+    fn schema() -> Schema {
+        Schema::Unknown
+    }
+
+    /// Returns an estimate of how many bytes serializing this value in `mode`
+    /// writes, used to pre-size output buffers. It doesn't need to be exact
+    /// and should be cheap to compute. The default returns 0.
+    fn size_hint(&self, mode: Mode) -> usize {
+        let _ = mode;
+        0
+    }
+}
+
+/// Used by the derive macro, see [`Mode::with_tag_depth`].
+pub fn enter_struct<S: BinSerializer>(serializer: &mut S) -> bool {
+    let tag_depth = serializer.mode().tag_depth;
+    serializer.dedup().enter_struct(tag_depth)
+}
+
+/// Used by the derive macro.
+pub fn exit_struct<S: BinSerializer>(serializer: &mut S) {
+    serializer.dedup().exit_struct();
+}
+
+/// Writes the name of a field when its struct is tagged. Used by the derive
+/// macro.
+pub fn write_field_tag<S: BinSerializer>(name: &str, serializer: S) -> Result<()> {
+    name.serialize(serializer.disable_dedup())
+}
+
+/// Writes the bytes of a field marked `#[binserde(rest)]` without a length
+/// prefix. Used by the derive macro.
+pub fn write_rest<S, T>(value: &T, mut serializer: S) -> Result<()>
+where
+    S: BinSerializer,
+    T: AsRef<[u8]> + ?Sized,
+{
+    serializer.pipe().write_all(value.as_ref())?;
+    Ok(())
+}
+
+/// Writes the number of elements of a field marked `#[binserde(len = "type")]`
+/// as an integer of the given width, followed by each of its elements, and
+/// fails if the number doesn't fit. Used by the derive macro.
+pub fn write_with_len<'a, S, T>(width: UsizeLen, value: &'a T, mut serializer: S) -> Result<()>
+where
+    S: BinSerializer,
+    T: ?Sized,
+    &'a T: IntoIterator,
+    <&'a T as IntoIterator>::IntoIter: ExactSizeIterator,
+    <&'a T as IntoIterator>::Item: BinSerialize,
+{
+    let iter = value.into_iter();
+    iter.len()
+        .serialize((&mut serializer).change_mode(|mode| mode.usize_len = width))?;
+
+    for item in iter {
+        item.serialize(&mut serializer)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the version of a type marked `#[binserde(version = n)]` as a
+/// varint. Used by the derive macro.
+pub fn write_version<S: BinSerializer>(version: u32, mut serializer: S) -> Result<()> {
+    serializer.pipe().write_varuint(version.into())?;
+    Ok(())
+}
+
+/// Writes a field of a struct or enum variant marked `#[binserde(tagged)]`
+/// as its name followed by its value, which is written by `write`, prefixed
+/// with its length. Used by the derive macro.
+pub fn write_tagged_field<S, F>(name: &str, mut serializer: S, write: F) -> Result<()>
+where
+    S: BinSerializer,
+    F: FnOnce(&mut BufSerializer) -> Result<()>,
+{
+    name.serialize(&mut serializer)?;
+
+    let mode = serializer.mode();
+    let prescan = serializer.is_prescan();
+    let mut buf = BufSerializer::new(serializer.dedup(), mode);
+    buf.prescan = prescan;
+    write(&mut buf)?;
+    let bytes = buf.into_inner();
+
+    bytes.len().serialize(&mut serializer)?;
+    serializer.pipe().write_all(&bytes)?;
+    Ok(())
+}
+
+/// Writes the fields of a struct or enum variant marked
+/// `#[binserde(count_fields)]`, which are written by `write`, as their number
+/// followed by their length-prefixed data. Used by the derive macro.
+pub fn write_counted_fields<S, F>(count: u32, mut serializer: S, write: F) -> Result<()>
+where
+    S: BinSerializer,
+    F: FnOnce(&mut BufSerializer) -> Result<()>,
+{
+    count.serialize(&mut serializer)?;
+
+    let mode = serializer.mode();
+    let prescan = serializer.is_prescan();
+    let mut buf = BufSerializer::new(serializer.dedup(), mode);
+    buf.prescan = prescan;
+    write(&mut buf)?;
+    let bytes = buf.into_inner();
+
+    bytes.len().serialize(&mut serializer)?;
+    serializer.pipe().write_all(&bytes)?;
+    Ok(())
+}
+
+/// Writes an optional value as one of [`Mode::option_tags`] followed by the
+/// value, if there is one.
+fn write_tagged_option<T, S>(value: Option<&T>, mut serializer: S) -> Result<()>
+where
+    T: BinSerialize + ?Sized,
+    S: BinSerializer,
+{
+    let (none, some) = serializer.mode().option_tags;
+
+    match value {
+        None => none.serialize(&mut serializer),
+        Some(v) => {
+            some.serialize(&mut serializer)?;
+            v.serialize(&mut serializer)
+        }
+    }
+}
+
+/// Returns whether optional collections are written as their length plus
+/// one, see [`Mode::with_compact_collection_options`]. This requires every
+/// number a collection can start with to be written the same way.
+pub(crate) fn use_compact_options(mode: Mode) -> bool {
+    mode.compact_options
+        && mode.dedup_idx == mode.usize_len
+        && mode.string_len.unwrap_or(mode.usize_len) == mode.usize_len
+}
+
+/// Writes an optional collection. If [`use_compact_options`] is true, the
+/// number its encoding starts with, which is its length or a dedup index, is
+/// written plus one followed by the rest of it, and `None` is written as 0.
+pub(crate) fn write_collection_option<T, S>(value: Option<&T>, mut serializer: S) -> Result<()>
+where
+    T: BinSerialize + ?Sized,
+    S: BinSerializer,
+{
+    if !use_compact_options(serializer.mode()) {
+        return write_tagged_option(value, serializer);
+    }
+
+    let value = match value {
+        None => return 0usize.serialize(serializer),
+        // the length doesn't matter when only collecting strings
+        Some(value) if serializer.is_prescan() => return value.serialize(serializer),
+        Some(value) => value,
+    };
+
+    let mode = serializer.mode();
+    let mut buf = BufSerializer::new(serializer.dedup(), mode);
+    value.serialize(&mut buf)?;
+    let bytes = buf.into_inner();
+
+    let mut rest = &*bytes;
+    let dedup = DedupContext::new();
+    let len = usize::deserialize(BinDeserializerBase::new(&mut rest, &dedup).with_mode(mode))?;
+
+    (len + 1).serialize(&mut serializer)?;
+    serializer.pipe().write_all(rest)?;
+    Ok(())
 }
 
 pub trait BinSerializer: Sized {
@@ -18,6 +266,15 @@ pub trait BinSerializer: Sized {
 
     fn mode(&self) -> Mode;
 
+    /// Returns whether this serializer only collects the strings to
+    /// deduplicate, see [`prescan`]. Values that don't contain any may skip
+    /// serializing themselves in this case.
+    ///
+    /// [`prescan`]: crate::prescan
+    fn is_prescan(&self) -> bool {
+        false
+    }
+
     fn with_mode(self, mode: Mode) -> WithMode<Self> {
         WithMode {
             serializer: self,
@@ -37,6 +294,37 @@ pub trait BinSerializer: Sized {
     fn disable_dedup(self) -> WithMode<Self> {
         self.change_mode(|mode| mode.use_dedup = false)
     }
+
+    /// Starts writing a sequence of `len` elements by hand, for values whose
+    /// shape is only known at runtime. The elements are then written using
+    /// [`SeqSerializer::serialize_element`]. This produces the same output as
+    /// a `Vec` of them, unless the element type has its own encoding for
+    /// sequences, like `bool` with [`Mode::with_pack_bools`], or collections
+    /// are deduplicated.
+    fn serialize_seq(mut self, len: usize) -> Result<SeqSerializer<Self>> {
+        len.serialize(&mut self)?;
+
+        Ok(SeqSerializer {
+            serializer: self,
+            len,
+            written: 0,
+        })
+    }
+
+    /// Starts writing a struct with `n_fields` fields by hand, which are then
+    /// written in order using [`StructSerializer::serialize_field`]. This
+    /// produces the same output as deriving [`BinSerialize`] for a struct
+    /// with those fields and no attributes.
+    fn serialize_struct(mut self, n_fields: usize) -> Result<StructSerializer<Self>> {
+        let tagged = enter_struct(&mut self);
+
+        Ok(StructSerializer {
+            serializer: self,
+            tagged,
+            n_fields,
+            written: 0,
+        })
+    }
 }
 
 impl<T> BinSerializer for &mut T
@@ -56,11 +344,15 @@ where
     fn mode(&self) -> Mode {
         (**self).mode()
     }
+
+    fn is_prescan(&self) -> bool {
+        (**self).is_prescan()
+    }
 }
 
-pub struct BinSerializerBase<W> {
+pub struct BinSerializerBase<W, C = DedupContext> {
     pipe: W,
-    dedup: DedupContext,
+    dedup: C,
 }
 
 impl<W> BinSerializerBase<W> {
@@ -70,15 +362,26 @@ impl<W> BinSerializerBase<W> {
             dedup: DedupContext::new(),
         }
     }
+}
+
+impl<W, C> BinSerializerBase<W, C> {
+    /// Creates a serializer that looks up dedup indices in `dedup`, which
+    /// should be the table written in front of the value. Passing a
+    /// `&mut DedupContext` lets several values share one table, which new
+    /// entries are added to as they are serialized.
+    pub fn with_dedup(pipe: W, dedup: C) -> Self {
+        BinSerializerBase { pipe, dedup }
+    }
 
     pub fn into_pipe(self) -> W {
         self.pipe
     }
 }
 
-impl<W> BinSerializer for BinSerializerBase<W>
+impl<W, C> BinSerializer for BinSerializerBase<W, C>
 where
     W: Write,
+    C: BorrowMut<DedupContext>,
 {
     type Pipe = W;
     fn pipe(&mut self) -> &mut Self::Pipe {
@@ -86,7 +389,7 @@ where
     }
 
     fn dedup(&mut self) -> &mut DedupContext {
-        &mut self.dedup
+        self.dedup.borrow_mut()
     }
 
     fn mode(&self) -> Mode {
@@ -94,6 +397,49 @@ where
     }
 }
 
+/// Serializes into a buffer, sharing the dedup context of another serializer.
+pub struct BufSerializer<'a> {
+    pipe: Vec<u8>,
+    dedup: &'a mut DedupContext,
+    mode: Mode,
+    prescan: bool,
+}
+
+impl<'a> BufSerializer<'a> {
+    pub(crate) fn new(dedup: &'a mut DedupContext, mode: Mode) -> Self {
+        BufSerializer {
+            pipe: Vec::new(),
+            dedup,
+            mode,
+            prescan: false,
+        }
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.pipe
+    }
+}
+
+impl BinSerializer for BufSerializer<'_> {
+    type Pipe = Vec<u8>;
+
+    fn pipe(&mut self) -> &mut Self::Pipe {
+        &mut self.pipe
+    }
+
+    fn dedup(&mut self) -> &mut DedupContext {
+        self.dedup
+    }
+
+    fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    fn is_prescan(&self) -> bool {
+        self.prescan
+    }
+}
+
 pub struct WithMode<S> {
     serializer: S,
     mode: Mode,
@@ -116,6 +462,83 @@ where
     fn mode(&self) -> Mode {
         self.mode
     }
+
+    fn is_prescan(&self) -> bool {
+        self.serializer.is_prescan()
+    }
+}
+
+/// Writes the elements of a sequence, see [`BinSerializer::serialize_seq`].
+pub struct SeqSerializer<S> {
+    serializer: S,
+    len: usize,
+    written: usize,
+}
+
+impl<S> SeqSerializer<S>
+where
+    S: BinSerializer,
+{
+    pub fn serialize_element<T: BinSerialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.written += 1;
+        value.serialize(&mut self.serializer)
+    }
+
+    /// Finishes the sequence, failing if the number of elements written
+    /// doesn't match the length it was started with.
+    pub fn end(self) -> Result<()> {
+        if self.written != self.len {
+            return Err(Error::custom(format!(
+                "sequence of length {} has {} elements",
+                self.len, self.written
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the fields of a struct, see [`BinSerializer::serialize_struct`].
+pub struct StructSerializer<S> {
+    serializer: S,
+    tagged: bool,
+    n_fields: usize,
+    written: usize,
+}
+
+impl<S> StructSerializer<S>
+where
+    S: BinSerializer,
+{
+    /// Writes the next field. `name` is only written if the struct is
+    /// tagged according to [`Mode::with_tag_depth`].
+    pub fn serialize_field<T: BinSerialize + ?Sized>(
+        &mut self,
+        name: &str,
+        value: &T,
+    ) -> Result<()> {
+        if self.tagged {
+            write_field_tag(name, &mut self.serializer)?;
+        }
+
+        self.written += 1;
+        value.serialize(&mut self.serializer)
+    }
+
+    /// Finishes the struct, failing if the number of fields written doesn't
+    /// match the number it was started with.
+    pub fn end(mut self) -> Result<()> {
+        exit_struct(&mut self.serializer);
+
+        if self.written != self.n_fields {
+            return Err(Error::custom(format!(
+                "struct with {} fields has {} fields written",
+                self.n_fields, self.written
+            )));
+        }
+
+        Ok(())
+    }
 }
 
 pub struct PrescanSerializer {
@@ -123,6 +546,12 @@ pub struct PrescanSerializer {
     dedup: DedupContext,
 }
 
+impl Default for PrescanSerializer {
+    fn default() -> Self {
+        PrescanSerializer::new()
+    }
+}
+
 impl PrescanSerializer {
     pub fn new() -> Self {
         PrescanSerializer {
@@ -130,6 +559,19 @@ impl PrescanSerializer {
             dedup: DedupContext::new(),
         }
     }
+
+    /// Creates a serializer which adds strings to `dedup` instead of an
+    /// empty context.
+    pub(crate) fn with_dedup(dedup: DedupContext) -> Self {
+        PrescanSerializer {
+            pipe: NullWrite,
+            dedup,
+        }
+    }
+
+    pub fn into_dedup(self) -> DedupContext {
+        self.dedup
+    }
 }
 
 impl BinSerializer for PrescanSerializer {
@@ -146,6 +588,10 @@ impl BinSerializer for PrescanSerializer {
     fn mode(&self) -> Mode {
         Mode::default()
     }
+
+    fn is_prescan(&self) -> bool {
+        true
+    }
 }
 
 pub struct NullWrite;
@@ -159,3 +605,101 @@ impl Write for NullWrite {
         Ok(())
     }
 }
+
+/// A writer discarding its input, only counting how many bytes were written.
+#[derive(Debug, Default)]
+pub struct CountWrite {
+    count: usize,
+}
+
+impl CountWrite {
+    pub fn new() -> Self {
+        CountWrite { count: 0 }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl Write for CountWrite {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.count += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Buffers small writes like [`std::io::BufWriter`], but writes the buffer
+/// together with writes too large to fit in it using a single vectored write,
+/// instead of flushing the buffer first and then writing the data on its own.
+/// Large byte blobs and strings are written without being copied.
+#[cfg(feature = "std")]
+pub(crate) struct VectoredWriter<W> {
+    inner: W,
+    buf: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> VectoredWriter<W> {
+    pub fn with_capacity(capacity: usize, inner: W) -> Self {
+        VectoredWriter {
+            inner,
+            buf: Vec::with_capacity(capacity),
+        }
+    }
+
+    fn write_all_vectored(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut slices = [IoSlice::new(&self.buf), IoSlice::new(data)];
+        let mut slices = &mut slices[..];
+        // drops the buffer if it is empty
+        IoSlice::advance_slices(&mut slices, 0);
+
+        while !slices.is_empty() {
+            match self.inner.write_vectored(slices) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(n) => IoSlice::advance_slices(&mut slices, n),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.buf.clear();
+        Ok(())
+    }
+
+    fn flush_buf(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Write for VectoredWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buf.len() + data.len() <= self.buf.capacity() {
+            self.buf.extend_from_slice(data);
+        } else if data.len() < self.buf.capacity() {
+            self.flush_buf()?;
+            self.buf.extend_from_slice(data);
+        } else {
+            self.write_all_vectored(data)?;
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buf()?;
+        self.inner.flush()
+    }
+}
@@ -1,8 +1,54 @@
+use alloc::format;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Error, Result};
+
+#[cfg(feature = "std")]
+static PROFILES: Mutex<Vec<(String, Mode)>> = Mutex::new(Vec::new());
+
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub struct Mode {
     pub usize_len: UsizeLen,
     pub dedup_idx: UsizeLen,
+    pub string_len: Option<UsizeLen>,
     pub fixed_size_use_varint: bool,
+    pub prefix_total_len: bool,
+    pub checked_arithmetic: bool,
+    pub inline_dedup: bool,
+    pub pack_bools: bool,
+    pub fail_fast: bool,
+    pub error_context: bool,
+    pub trailing_crc: bool,
+    pub fixed_signed: bool,
+    pub dedup_bytes: bool,
+    pub deny_dedup_in_keys: bool,
+    pub option_tags: (u8, u8),
+    pub write_schema_header: bool,
+    pub deterministic: bool,
+    pub dedup_eq_by_content_hash: bool,
+    pub tag_depth: usize,
+    pub max_map_entries: Option<usize>,
+    pub big_endian: bool,
+    pub max_container_len: Option<usize>,
+    pub varint_group_encoding: bool,
+    pub map_entry_count_hint: Option<usize>,
+    pub hex_bytes: bool,
+    #[cfg(feature = "std")]
+    pub time_epoch: SystemTime,
+    pub header: Option<([u8; 4], u16)>,
+    pub dedup_collections: bool,
+    pub error_on_nan: bool,
+    pub compact_options: bool,
+    pub max_total_elements: Option<usize>,
+    pub stable_float_repr: bool,
+    pub max_depth: Option<usize>,
+    pub mode_flags: bool,
+    pub dictionary: Option<&'static [u8]>,
+    pub error_offsets: bool,
 
     // Do not flip this on if it's off
     pub use_dedup: bool,
@@ -13,7 +59,41 @@ impl Default for Mode {
         Mode {
             usize_len: UsizeLen::Variable,
             dedup_idx: UsizeLen::Variable,
+            string_len: None,
             fixed_size_use_varint: false,
+            prefix_total_len: false,
+            checked_arithmetic: true,
+            inline_dedup: false,
+            pack_bools: false,
+            fail_fast: true,
+            error_context: false,
+            trailing_crc: false,
+            fixed_signed: false,
+            dedup_bytes: false,
+            deny_dedup_in_keys: false,
+            option_tags: (0, 1),
+            write_schema_header: false,
+            deterministic: false,
+            dedup_eq_by_content_hash: false,
+            tag_depth: 0,
+            max_map_entries: None,
+            big_endian: false,
+            max_container_len: None,
+            varint_group_encoding: false,
+            map_entry_count_hint: None,
+            hex_bytes: false,
+            #[cfg(feature = "std")]
+            time_epoch: UNIX_EPOCH,
+            header: None,
+            dedup_collections: false,
+            error_on_nan: false,
+            compact_options: false,
+            max_total_elements: None,
+            stable_float_repr: false,
+            max_depth: None,
+            mode_flags: false,
+            dictionary: None,
+            error_offsets: false,
             use_dedup: false,
         }
     }
@@ -27,15 +107,708 @@ impl Mode {
         }
     }
 
+    /// A preset for output that is as small as possible, see
+    /// [`OptimizeFor::Size`].
+    pub fn compact() -> Self {
+        Mode::default().with_optimize_for(OptimizeFor::Size)
+    }
+
+    /// A preset for data exchanged between programs: the default encoding,
+    /// which doesn't depend on the platform, with maps and sets written in a
+    /// deterministic order (see [`Mode::with_deterministic`]) and the options
+    /// recorded in the output (see [`Mode::with_mode_flags`]), so that
+    /// reading it with a different mode fails early.
+    pub fn portable() -> Self {
+        Mode::default()
+            .with_deterministic(true)
+            .with_mode_flags(true)
+    }
+
+    /// Returns a [`ModeBuilder`] starting from the default mode, which sets
+    /// the same options as the `with_*` methods, but checks when building
+    /// that they don't contradict each other.
+    pub fn builder() -> ModeBuilder {
+        ModeBuilder(Mode::default())
+    }
+
+    /// Registers `mode` under `name` so it can later be retrieved using
+    /// [`Mode::profile`]. Replaces any profile previously registered under the
+    /// same name. Only available with the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn register_profile<S: Into<String>>(name: S, mode: Mode) {
+        let name = name.into();
+        let mut profiles = PROFILES.lock().unwrap_or_else(|e| e.into_inner());
+
+        match profiles.iter_mut().find(|el| el.0 == name) {
+            None => profiles.push((name, mode)),
+            Some(el) => el.1 = mode,
+        }
+    }
+
+    /// Returns the profile registered under `name` using
+    /// [`Mode::register_profile`], if any.
+    #[cfg(feature = "std")]
+    pub fn profile(name: &str) -> Option<Mode> {
+        let profiles = PROFILES.lock().unwrap_or_else(|e| e.into_inner());
+        profiles.iter().find(|el| el.0 == name).map(|el| el.1)
+    }
+
     pub fn with_usize_len(mut self, usize_len: UsizeLen) -> Self {
         self.usize_len = usize_len;
         self
     }
 
+    /// Sets the encoding of references to entries in the dedup tables, for
+    /// example to give them a fixed width. Serializing fails if the tables
+    /// have more entries than fit in the chosen width.
+    pub fn with_dedup_index_width(mut self, width: UsizeLen) -> Self {
+        self.dedup_idx = width;
+        self
+    }
+
+    /// Sets the encoding of string lengths separately from other lengths,
+    /// which are set using [`Mode::with_usize_len`]. `None` uses the same
+    /// encoding for both, which is the default.
+    pub fn with_string_len_prefix(mut self, string_len: Option<UsizeLen>) -> Self {
+        self.string_len = string_len;
+        self
+    }
+
+    /// Writes integers wider than a byte as LEB128 varints. Signed integers
+    /// are zigzag encoded first, so that small negative numbers are short as
+    /// well.
     pub fn with_fixed_size_use_varint(mut self, enabled: bool) -> Self {
         self.fixed_size_use_varint = enabled;
         self
     }
+
+    /// Instead of collecting all deduplicated strings into a table at the
+    /// start of the output, writes each string in full where it first occurs
+    /// and refers back to it afterwards. This allows serializing in a single
+    /// pass, without scanning the value for strings beforehand. Only has an
+    /// effect in combination with [`Mode::dedup`].
+    pub fn with_inline_dedup(mut self, enabled: bool) -> Self {
+        self.inline_dedup = enabled;
+        self
+    }
+
+    /// Packs sequences of `bool`s into 8 values per byte.
+    pub fn with_pack_bools(mut self, enabled: bool) -> Self {
+        self.pack_bools = enabled;
+        self
+    }
+
+    /// Makes [`deserialize_batch`] stop at the first element that fails to
+    /// deserialize instead of reading all of them. Enabled by default.
+    ///
+    /// [`deserialize_batch`]: crate::deserialize_batch
+    pub fn with_fail_fast(mut self, enabled: bool) -> Self {
+        self.fail_fast = enabled;
+        self
+    }
+
+    /// Makes errors returned from deserializing derived types include the
+    /// path of the field that failed to deserialize.
+    pub fn with_error_context(mut self, enabled: bool) -> Self {
+        self.error_context = enabled;
+        self
+    }
+
+    /// Makes errors returned from deserializing report the number of bytes
+    /// read from the input when they occurred, together with the path of the
+    /// field that failed like [`Mode::with_error_context`], as
+    /// [`Error::Context`]. This doesn't apply to reading sequences one element
+    /// at a time or to [`deserialize_in_place`].
+    ///
+    /// [`Error::Context`]: crate::Error::Context
+    /// [`deserialize_in_place`]: crate::deserialize_in_place
+    pub fn with_error_offsets(mut self, enabled: bool) -> Self {
+        self.error_offsets = enabled;
+        self
+    }
+
+    /// Appends a CRC-32 checksum of the output, which is computed while
+    /// writing and checked after reading the value back.
+    pub fn with_trailing_crc(mut self, enabled: bool) -> Self {
+        self.trailing_crc = enabled;
+        self
+    }
+
+    /// Always writes signed integers as fixed-size two's complement values,
+    /// even if [`Mode::with_fixed_size_use_varint`] is enabled. This matches
+    /// the layout of C's `intN_t` types.
+    pub fn with_fixed_signed(mut self, enabled: bool) -> Self {
+        self.fixed_signed = enabled;
+        self
+    }
+
+    /// Also deduplicates byte blobs (`[u8]`, `Vec<u8>` and `Cow<[u8]>`) when
+    /// dedup is enabled, storing them in a second table after the string table. Has
+    /// no effect with [`Mode::with_inline_dedup`].
+    pub fn with_dedup_bytes(mut self, enabled: bool) -> Self {
+        self.dedup_bytes = enabled;
+        self
+    }
+
+    /// Also deduplicates whole `Vec`s and slices when byte blobs are
+    /// deduplicated (see [`Mode::with_dedup_bytes`]), so that a collection
+    /// appearing several times is only stored once. Each one is serialized on
+    /// its own and stored in the byte blob table, like [`Dedup`] values, and
+    /// written as its index there. Collections are compared by their
+    /// serialized representation.
+    ///
+    /// [`Dedup`]: crate::dedup::Dedup
+    pub fn with_deduplicate_identical_collections(mut self, enabled: bool) -> Self {
+        self.dedup_collections = enabled;
+        self
+    }
+
+    /// Writes byte blobs (`[u8]`, `Vec<u8>` and `[u8; N]`) as strings of
+    /// lowercase hex digits instead of raw bytes. This doubles their size, but
+    /// makes them readable in the output, which helps when inspecting or
+    /// editing test fixtures by hand, especially together with
+    /// [`Mode::with_write_schema_header`]. Byte blobs written this way are
+    /// never deduplicated.
+    pub fn with_hex_bytes(mut self, enabled: bool) -> Self {
+        self.hex_bytes = enabled;
+        self
+    }
+
+    /// Writes [`SystemTime`]s as the duration since `epoch` instead of since
+    /// [`UNIX_EPOCH`]. Choosing an epoch close to the stored times keeps the
+    /// durations small, which takes up less space together with
+    /// [`Mode::with_fixed_size_use_varint`]. Times before `epoch` can't be
+    /// serialized, and data must be read back with the same epoch.
+    #[cfg(feature = "std")]
+    pub fn with_time_epoch(mut self, epoch: SystemTime) -> Self {
+        self.time_epoch = epoch;
+        self
+    }
+
+    /// Writes `magic` and the format `version` (as a little-endian `u16`) in
+    /// front of everything else, including the schema and dedup table. When
+    /// reading, input that doesn't start with the same magic and version is
+    /// rejected before reading anything else, with [`Error::BadHeader`] or
+    /// [`Error::VersionMismatch`] respectively. Useful for files, to reject
+    /// garbage or data written by another version of the program up front.
+    ///
+    /// [`Error::BadHeader`]: crate::Error::BadHeader
+    /// [`Error::VersionMismatch`]: crate::Error::VersionMismatch
+    pub fn with_header(mut self, magic: [u8; 4], version: u16) -> Self {
+        self.header = Some((magic, version));
+        self
+    }
+
+    /// Fails to serialize `f32` and `f64` values that are NaN or infinite,
+    /// for data where these only ever show up because of a bug. Deserializing
+    /// isn't affected.
+    pub fn with_error_on_nan(mut self, enabled: bool) -> Self {
+        self.error_on_nan = enabled;
+        self
+    }
+
+    /// Writes `f32` and `f64` values as the shortest decimal string that
+    /// parses back to the same value, such as `0.1` or `1e300`, instead of
+    /// their bit pattern. The strings are deduplicated like any other. Every
+    /// value round-trips exactly except for NaN, whose payload and sign are
+    /// lost.
+    pub fn with_stable_float_repr(mut self, enabled: bool) -> Self {
+        self.stable_float_repr = enabled;
+        self
+    }
+
+    /// Writes optional collections (`Option<Vec<T>>`, `Option<String>`,
+    /// `Option<Box<[u8]>>`, maps, sets, and so on) as their length plus one,
+    /// or 0 for `None`, instead of an [`Option`] tag followed by the length.
+    /// This saves a byte for every such value. It only applies when lengths,
+    /// string lengths and dedup indices are written the same way (see
+    /// [`Mode::with_string_len_prefix`] and [`Mode::with_dedup_index_width`]),
+    /// since deduplicated values start with an index instead of a length.
+    /// Otherwise, these are written like other options.
+    pub fn with_compact_collection_options(mut self, enabled: bool) -> Self {
+        self.compact_options = enabled;
+        self
+    }
+
+    /// Always writes the keys of maps and sets inline instead of as dedup
+    /// indices, so that they can be compared directly on the wire. Values
+    /// are still deduplicated.
+    pub fn with_deny_dedup_in_keys(mut self, enabled: bool) -> Self {
+        self.deny_dedup_in_keys = enabled;
+        self
+    }
+
+    /// Sets the bytes written before `None` and `Some` values respectively.
+    /// The default is 0 for `None` and 1 for `Some`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `none` and `some` are the same.
+    pub fn with_explicit_option_tag_byte(mut self, none: u8, some: u8) -> Self {
+        assert_ne!(none, some, "Option tags must be distinct");
+        self.option_tags = (none, some);
+        self
+    }
+
+    /// Writes a description of the serialized type's structure in front of
+    /// the data, which allows decoding it without knowing the type using
+    /// [`schema::deserialize_dynamic`]. Only applies to [`serialize_with`]
+    /// and related functions, not to [`serialize_batch`].
+    ///
+    /// [`schema::deserialize_dynamic`]: crate::schema::deserialize_dynamic
+    /// [`serialize_with`]: crate::serialize_with
+    /// [`serialize_batch`]: crate::serialize_batch
+    pub fn with_write_schema_header(mut self, enabled: bool) -> Self {
+        self.write_schema_header = enabled;
+        self
+    }
+
+    /// Writes the entries of hash maps and sets ordered by their serialized
+    /// representation instead of in iteration order, which differs between
+    /// instances. This makes the output reproducible at the cost of
+    /// serializing each entry one extra time.
+    ///
+    /// `BTreeMap` and `BTreeSet` are always written in key order and don't
+    /// need this, so prefer them where the output has to be reproducible.
+    pub fn with_deterministic(mut self, enabled: bool) -> Self {
+        self.deterministic = enabled;
+        self
+    }
+
+    /// Finds repeated strings and byte blobs while building the dedup table
+    /// by their hash instead of by comparing them with the entries in the
+    /// table, which is faster for large tables. Entries with the same hash
+    /// are still compared, so collisions don't merge different values. The
+    /// output is the same either way.
+    pub fn with_dedup_eq_by_content_hash(mut self, enabled: bool) -> Self {
+        self.dedup_eq_by_content_hash = enabled;
+        self
+    }
+
+    /// Prefixes the fields of derived structs and enum variants with their
+    /// name, which is checked when reading them back, as long as they are
+    /// nested at most `depth` levels deep. This catches data written with a
+    /// different field order while keeping deeply nested values compact. `0`,
+    /// the default, writes all fields positionally. Bit-packed fields are
+    /// never prefixed.
+    pub fn with_tag_depth(mut self, depth: usize) -> Self {
+        self.tag_depth = depth;
+        self
+    }
+
+    /// Fails deserializing maps that declare more than `max` entries, before
+    /// reading any of them. Useful for untrusted input, since a crafted entry
+    /// count is otherwise only caught once the input runs out.
+    pub fn with_max_map_entries(mut self, max: Option<usize>) -> Self {
+        self.max_map_entries = max;
+        self
+    }
+
+    /// Fails deserializing sequences, strings and maps whose length is more
+    /// than `max`, before reading their contents. Like
+    /// [`Mode::with_max_map_entries`], this is meant for untrusted input.
+    pub fn with_max_container_len(mut self, max: Option<usize>) -> Self {
+        self.max_container_len = max;
+        self
+    }
+
+    /// Fails deserializing once the sequences, sets and maps read so far
+    /// together declare more than `max` elements. Unlike
+    /// [`Mode::with_max_container_len`], this also catches deeply nested
+    /// collections which each stay under the limit, such as a
+    /// `Vec<Vec<Vec<()>>>` that is cheap to encode but huge in memory. String
+    /// and byte lengths don't count towards it.
+    pub fn with_max_total_elements(mut self, max: Option<usize>) -> Self {
+        self.max_total_elements = max;
+        self
+    }
+
+    /// Fails deserializing values nested inside more than `max` boxes with
+    /// [`Error::RecursionLimit`]. Recursive types such as trees go through
+    /// `Box`, so this keeps crafted input describing a very deep tree from
+    /// overflowing the stack.
+    ///
+    /// [`Error::RecursionLimit`]: crate::Error::RecursionLimit
+    pub fn with_max_depth(mut self, max: Option<usize>) -> Self {
+        self.max_depth = max;
+        self
+    }
+
+    /// Writes a byte in front of the output recording which options that
+    /// change the layout of the data are enabled, such as deduplication. When
+    /// reading, input written with different options is rejected with
+    /// [`Error::ModeMismatch`] instead of being misread. Both sides need this
+    /// enabled; it is off by default to keep the headerless format.
+    ///
+    /// [`Error::ModeMismatch`]: crate::Error::ModeMismatch
+    pub fn with_mode_flags(mut self, enabled: bool) -> Self {
+        self.mode_flags = enabled;
+        self
+    }
+
+    /// Uses the strings in `dictionary`, written with
+    /// [`DedupContext::write_to`], as the start of the dedup table. Both
+    /// sides need the same dictionary, such as one included in the program
+    /// with `include_bytes!`. Strings found in it are referred to by their
+    /// index without being written, and only the strings it doesn't have are
+    /// written in the table in front of the value. The dictionary is read
+    /// again for every value. Contexts passed to
+    /// [`serialize_shared_into`](crate::serialize_shared_into) should start
+    /// out with the dictionary's strings, as returned by
+    /// [`DedupContext::read_from`].
+    ///
+    /// [`DedupContext::write_to`]: crate::dedup::DedupContext::write_to
+    /// [`DedupContext::read_from`]: crate::dedup::DedupContext::read_from
+    pub fn with_dictionary_from_bytes(mut self, dictionary: &'static [u8]) -> Self {
+        self.dictionary = Some(dictionary);
+        self
+    }
+
+    /// Writes fixed-size integers, lengths and floats in big-endian instead of
+    /// little-endian byte order, for example to match the layout of a
+    /// network protocol. Varints are unaffected.
+    pub fn with_big_endian(mut self, enabled: bool) -> Self {
+        self.big_endian = enabled;
+        self
+    }
+
+    /// Writes sequences of `u32` and `u64` using group varint encoding, which
+    /// is faster to decode than encoding every element as a varint, and
+    /// usually just as compact for small numbers. This applies to `Vec` and
+    /// slices, but not arrays, and regardless of
+    /// [`Mode::with_fixed_size_use_varint`].
+    pub fn with_varint_group_encoding(mut self, enabled: bool) -> Self {
+        self.varint_group_encoding = enabled;
+        self
+    }
+
+    /// Hints that the value contains maps with about `count` entries in
+    /// total. When deduplicating, the string table is pre-sized for as many
+    /// strings before collecting them, since map keys are usually distinct.
+    /// The output buffer of [`serialize_with`] is pre-sized separately using
+    /// [`BinSerialize::size_hint`].
+    ///
+    /// [`serialize_with`]: crate::serialize_with
+    /// [`BinSerialize::size_hint`]: crate::BinSerialize::size_hint
+    pub fn with_map_entry_count_hint(mut self, count: Option<usize>) -> Self {
+        self.map_entry_count_hint = count;
+        self
+    }
+
+    /// Sets the flags affecting how compact the output is according to
+    /// `goal`, leaving all others as they are:
+    ///
+    /// - [`OptimizeFor::Speed`] writes lengths and integers with a fixed
+    ///   size and disables dedup.
+    /// - [`OptimizeFor::Size`] enables dedup of strings, byte blobs and
+    ///   collections, variable length lengths and integers, including signed
+    ///   ones, group varint encoding, compact optional collections and packed
+    ///   `bool`s.
+    ///
+    /// Flags added in the future will be set here if they affect this
+    /// trade-off.
+    pub fn with_optimize_for(mut self, goal: OptimizeFor) -> Self {
+        match goal {
+            OptimizeFor::Speed => {
+                self.usize_len = UsizeLen::U64;
+                self.string_len = None;
+                self.fixed_size_use_varint = false;
+                self.pack_bools = false;
+                self.varint_group_encoding = false;
+                self.compact_options = false;
+                self.inline_dedup = false;
+                self.dedup_bytes = false;
+                self.dedup_collections = false;
+                self.use_dedup = false;
+            }
+            OptimizeFor::Size => {
+                self.usize_len = UsizeLen::Variable;
+                self.dedup_idx = UsizeLen::Variable;
+                self.string_len = None;
+                self.fixed_size_use_varint = true;
+                self.fixed_signed = false;
+                self.pack_bools = true;
+                self.varint_group_encoding = true;
+                self.compact_options = true;
+                self.dedup_bytes = true;
+                self.dedup_collections = true;
+                self.use_dedup = true;
+            }
+        }
+
+        self
+    }
+
+    /// Makes decoding varints and fitting decoded integers into their target
+    /// type fail with an error when they overflow, instead of silently
+    /// truncating them. Enabled by default.
+    pub fn with_checked_arithmetic(mut self, enabled: bool) -> Self {
+        self.checked_arithmetic = enabled;
+        self
+    }
+
+    /// Prefixes the top-level output with its total length in bytes, so that
+    /// multiple serialized values can be concatenated and read back one by
+    /// one.
+    pub fn with_prefix_total_len(mut self, enabled: bool) -> Self {
+        self.prefix_total_len = enabled;
+        self
+    }
+
+    /// Returns a hash of the options that affect the format of the output,
+    /// such as the encoding of lengths and whether dedup is enabled, but not
+    /// limits, error reporting or the order of map entries, which don't
+    /// affect what a reader needs to know. Two modes with the same
+    /// fingerprint read each other's output, so this can be stored alongside
+    /// data and compared before reading it. The hash is computed the same
+    /// way on every platform, but may change when options are added.
+    pub fn fingerprint(&self) -> u64 {
+        fn usize_len(len: UsizeLen) -> u8 {
+            match len {
+                UsizeLen::U8 => 1,
+                UsizeLen::U16 => 2,
+                UsizeLen::U32 => 3,
+                UsizeLen::U64 => 4,
+                UsizeLen::Variable => 5,
+            }
+        }
+
+        let mut buf = Vec::new();
+        buf.push(usize_len(self.usize_len));
+        buf.push(usize_len(self.dedup_idx));
+        buf.push(self.string_len.map_or(0, usize_len));
+        buf.extend_from_slice(&[
+            self.fixed_size_use_varint as u8,
+            self.prefix_total_len as u8,
+            self.inline_dedup as u8,
+            self.pack_bools as u8,
+            self.trailing_crc as u8,
+            self.fixed_signed as u8,
+            self.dedup_bytes as u8,
+            self.deny_dedup_in_keys as u8,
+            self.option_tags.0,
+            self.option_tags.1,
+            self.write_schema_header as u8,
+            self.big_endian as u8,
+            self.varint_group_encoding as u8,
+            self.hex_bytes as u8,
+            self.dedup_collections as u8,
+            self.compact_options as u8,
+            self.stable_float_repr as u8,
+            self.mode_flags as u8,
+            self.use_dedup as u8,
+        ]);
+        buf.extend_from_slice(&(self.tag_depth as u64).to_le_bytes());
+
+        #[cfg(feature = "std")]
+        match self.time_epoch.duration_since(UNIX_EPOCH) {
+            Ok(d) => {
+                buf.push(0);
+                buf.extend_from_slice(&d.as_nanos().to_le_bytes());
+            }
+            Err(e) => {
+                buf.push(1);
+                buf.extend_from_slice(&e.duration().as_nanos().to_le_bytes());
+            }
+        }
+
+        // times are always relative to the Unix epoch without std, so hash
+        // it the same way as it would be with std
+        #[cfg(not(feature = "std"))]
+        {
+            buf.push(0);
+            buf.extend_from_slice(&0u128.to_le_bytes());
+        }
+
+        match self.header {
+            None => buf.push(0),
+            Some((magic, version)) => {
+                buf.push(1);
+                buf.extend_from_slice(&magic);
+                buf.extend_from_slice(&version.to_le_bytes());
+            }
+        }
+
+        match self.dictionary {
+            None => buf.push(0),
+            Some(dictionary) => {
+                buf.push(1);
+                buf.extend_from_slice(&(dictionary.len() as u64).to_le_bytes());
+                buf.extend_from_slice(dictionary);
+            }
+        }
+
+        // 64-bit FNV-1a, which unlike the hashers in std is guaranteed to
+        // stay the same
+        buf.iter().fold(0xcbf29ce484222325, |acc, &b| {
+            (acc ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    /// The byte written by [`Mode::with_mode_flags`], one bit per option.
+    pub(crate) fn flags_byte(&self) -> u8 {
+        [
+            self.use_dedup,
+            self.inline_dedup,
+            self.dedup_bytes,
+            self.big_endian,
+            self.fixed_size_use_varint,
+            self.pack_bools,
+            self.compact_options,
+            self.varint_group_encoding,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (idx, &set)| acc | (set as u8) << idx)
+    }
+
+    pub(crate) fn int_use_varint(&self, signed: bool) -> bool {
+        self.fixed_size_use_varint && !(signed && self.fixed_signed)
+    }
+}
+
+/// Builds a [`Mode`] option by option, see [`Mode::builder`]. Each method
+/// sets the same option as the `with_*` method of the same name on [`Mode`].
+/// [`ModeBuilder::build`] fails if options are combined in a way where some
+/// of them would silently have no effect, such as enabling
+/// [`Mode::with_dedup_bytes`] without enabling dedup.
+///
+/// ```
+/// use binserde::serde::UsizeLen;
+/// use binserde::Mode;
+///
+/// let mode = Mode::builder()
+///     .dedup(true)
+///     .dedup_bytes(true)
+///     .usize_len(UsizeLen::U32)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(
+///     Mode::dedup().with_dedup_bytes(true).with_usize_len(UsizeLen::U32),
+///     mode
+/// );
+///
+/// assert!(Mode::builder().dedup_bytes(true).build().is_err());
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct ModeBuilder(Mode);
+
+macro_rules! builder_options {
+    ($($(#[$attr:meta])* $name:ident => $with:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            $(#[$attr])*
+            #[doc = concat!("See [`Mode::", stringify!($with), "`].")]
+            pub fn $name(self, $($arg: $ty),*) -> Self {
+                ModeBuilder(self.0.$with($($arg),*))
+            }
+        )*
+    };
+}
+
+impl ModeBuilder {
+    /// Enables deduplication, like [`Mode::dedup`].
+    pub fn dedup(mut self, enabled: bool) -> Self {
+        self.0.use_dedup = enabled;
+        self
+    }
+
+    /// See [`Mode::with_explicit_option_tag_byte`]. Unlike that, passing the
+    /// same value twice doesn't panic, but makes [`build`](Self::build) fail.
+    pub fn explicit_option_tag_byte(mut self, none: u8, some: u8) -> Self {
+        self.0.option_tags = (none, some);
+        self
+    }
+
+    builder_options! {
+        usize_len => with_usize_len(usize_len: UsizeLen);
+        dedup_index_width => with_dedup_index_width(width: UsizeLen);
+        string_len_prefix => with_string_len_prefix(string_len: Option<UsizeLen>);
+        fixed_size_use_varint => with_fixed_size_use_varint(enabled: bool);
+        inline_dedup => with_inline_dedup(enabled: bool);
+        pack_bools => with_pack_bools(enabled: bool);
+        fail_fast => with_fail_fast(enabled: bool);
+        error_context => with_error_context(enabled: bool);
+        error_offsets => with_error_offsets(enabled: bool);
+        trailing_crc => with_trailing_crc(enabled: bool);
+        fixed_signed => with_fixed_signed(enabled: bool);
+        dedup_bytes => with_dedup_bytes(enabled: bool);
+        deduplicate_identical_collections => with_deduplicate_identical_collections(enabled: bool);
+        hex_bytes => with_hex_bytes(enabled: bool);
+        #[cfg(feature = "std")]
+        time_epoch => with_time_epoch(epoch: SystemTime);
+        header => with_header(magic: [u8; 4], version: u16);
+        error_on_nan => with_error_on_nan(enabled: bool);
+        stable_float_repr => with_stable_float_repr(enabled: bool);
+        compact_collection_options => with_compact_collection_options(enabled: bool);
+        deny_dedup_in_keys => with_deny_dedup_in_keys(enabled: bool);
+        write_schema_header => with_write_schema_header(enabled: bool);
+        deterministic => with_deterministic(enabled: bool);
+        dedup_eq_by_content_hash => with_dedup_eq_by_content_hash(enabled: bool);
+        tag_depth => with_tag_depth(depth: usize);
+        max_map_entries => with_max_map_entries(max: Option<usize>);
+        max_container_len => with_max_container_len(max: Option<usize>);
+        max_total_elements => with_max_total_elements(max: Option<usize>);
+        max_depth => with_max_depth(max: Option<usize>);
+        mode_flags => with_mode_flags(enabled: bool);
+        dictionary_from_bytes => with_dictionary_from_bytes(dictionary: &'static [u8]);
+        big_endian => with_big_endian(enabled: bool);
+        varint_group_encoding => with_varint_group_encoding(enabled: bool);
+        map_entry_count_hint => with_map_entry_count_hint(count: Option<usize>);
+        optimize_for => with_optimize_for(goal: OptimizeFor);
+        checked_arithmetic => with_checked_arithmetic(enabled: bool);
+        prefix_total_len => with_prefix_total_len(enabled: bool);
+    }
+
+    /// Returns the mode, or an error naming the first option that would have
+    /// no effect because of another one.
+    pub fn build(self) -> Result<Mode> {
+        let mode = self.0;
+
+        let needs_dedup = [
+            (mode.inline_dedup, "inline_dedup"),
+            (mode.dedup_bytes, "dedup_bytes"),
+            (mode.dedup_eq_by_content_hash, "dedup_eq_by_content_hash"),
+            (mode.deny_dedup_in_keys, "deny_dedup_in_keys"),
+            (mode.dictionary.is_some(), "dictionary_from_bytes"),
+        ];
+
+        if let Some((_, name)) = needs_dedup.iter().find(|el| el.0 && !mode.use_dedup) {
+            return Err(Error::custom(format!("{} requires dedup", name)));
+        }
+
+        if mode.dedup_collections && !mode.dedup_bytes {
+            return Err(Error::custom(
+                "deduplicate_identical_collections requires dedup_bytes",
+            ));
+        }
+
+        if mode.inline_dedup && mode.dedup_bytes {
+            return Err(Error::custom("dedup_bytes has no effect with inline_dedup"));
+        }
+
+        if mode.option_tags.0 == mode.option_tags.1 {
+            return Err(Error::custom(
+                "explicit_option_tag_byte needs distinct tags for None and Some",
+            ));
+        }
+
+        if mode.inline_dedup && mode.dictionary.is_some() {
+            return Err(Error::custom(
+                "dictionary_from_bytes has no effect with inline_dedup",
+            ));
+        }
+
+        Ok(mode)
+    }
+}
+
+/// What [`Mode::with_optimize_for`] should optimize the output for.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[non_exhaustive]
+pub enum OptimizeFor {
+    Speed,
+    Size,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
@@ -1,18 +1,48 @@
-use std::borrow::Cow;
-use std::cell::{Cell, RefCell};
+use alloc::borrow::Cow;
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::cmp::Ordering;
+use core::convert::{Infallible, TryInto};
+#[cfg(feature = "std")]
+use core::hash::{BuildHasher, Hash};
+use core::marker::{PhantomData, PhantomPinned};
+use core::mem::{self, MaybeUninit};
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use core::num::{
+    NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU16, NonZeroU32,
+    NonZeroU64, NonZeroU8, NonZeroUsize,
+};
+use core::time::Duration;
+#[cfg(feature = "std")]
 use std::collections::{HashMap, HashSet};
-use std::convert::{Infallible, TryInto};
-use std::hash::Hash;
-use std::mem::MaybeUninit;
+#[cfg(feature = "std")]
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
 
-use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use byteorder::{BE, LE};
 
-use crate::serde::UsizeLen;
+use crate::de::{read_boxed, read_collection_option};
+use crate::dedup::{read_collection, use_collection_table, write_collection, DedupContext};
+use crate::erased::DynSerializer;
+use crate::io::{Read, ReadBytesExt, Write, WriteBytesExt};
+use crate::schema::Schema;
+use crate::ser::{write_collection_option, CountWrite};
+use crate::serde::{Mode, UsizeLen};
 use crate::try_iter::try_iter;
-use crate::util::{serialize_iter, VecLikeIter};
+use crate::util::{read_elements_len, read_len, serialize_iter, VecLikeIter};
+use crate::varint::{
+    group_varint_read, group_varint_write, varint_len, GROUP_SIZES_U32, GROUP_SIZES_U64,
+};
 use crate::write_ext::{ReadExt, WriteExt};
+#[cfg(feature = "std")]
+use crate::BinSerializerBase;
 use crate::{BinDeserialize, BinDeserializer, BinSerialize, BinSerializer};
 use crate::{Error, Result};
 
@@ -23,6 +53,18 @@ where
     fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
         (*self).serialize(serializer)
     }
+
+    fn serialize_option<S: BinSerializer>(value: Option<&Self>, serializer: S) -> Result<()> {
+        T::serialize_option(value.copied(), serializer)
+    }
+
+    fn schema() -> Schema {
+        T::schema()
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        (*self).size_hint(mode)
+    }
 }
 
 impl<'de> BinDeserialize<'de> for bool {
@@ -30,6 +72,34 @@ impl<'de> BinDeserialize<'de> for bool {
         let v = deserializer.pipe().read_u8()?;
         Ok(v != 0)
     }
+
+    fn deserialize_extend<D: BinDeserializer<'de>>(
+        target: &mut Vec<Self>,
+        mut deserializer: D,
+    ) -> Result<()> {
+        if deserializer.mode().pack_bools {
+            let len = read_elements_len(&mut deserializer)?;
+            let byte_len = len.div_ceil(8);
+            let mut bytes = Vec::new();
+            let read = deserializer
+                .pipe()
+                .take(byte_len as u64)
+                .read_to_end(&mut bytes)?;
+
+            if read != byte_len {
+                return Err(Error::UnexpectedEof {
+                    expected: byte_len,
+                    got: read,
+                });
+            }
+
+            target.extend((0..len).map(|idx| bytes[idx / 8] & (1 << (idx % 8)) != 0));
+            Ok(())
+        } else {
+            let iter = VecLikeIter::<_, bool>::new(deserializer)?;
+            try_iter(iter, |iter| target.extend(iter))
+        }
+    }
 }
 
 impl BinSerialize for bool {
@@ -38,90 +108,509 @@ impl BinSerialize for bool {
             .pipe()
             .write_u8(if *self { u8::MAX } else { u8::MIN })?)
     }
+
+    fn serialize_slice<S: BinSerializer>(slice: &[Self], mut serializer: S) -> Result<()> {
+        if serializer.mode().pack_bools {
+            slice.len().serialize(&mut serializer)?;
+
+            for chunk in slice.chunks(8) {
+                let byte = chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |acc, (idx, el)| acc | (*el as u8) << idx);
+                serializer.pipe().write_u8(byte)?;
+            }
+
+            Ok(())
+        } else {
+            serialize_iter(slice.iter(), serializer)
+        }
+    }
+
+    fn schema() -> Schema {
+        Schema::Bool
+    }
+
+    fn size_hint(&self, _mode: Mode) -> usize {
+        1
+    }
+}
+
+/// Calls the byteorder method `$m` on `$pipe` with the byte order given by
+/// `$mode`.
+macro_rules! with_endian {
+    ($mode:expr, $pipe:expr, $m:ident ( $( $arg:expr ),* )) => {
+        if $mode.big_endian {
+            $pipe.$m::<BE>($( $arg ),*)
+        } else {
+            $pipe.$m::<LE>($( $arg ),*)
+        }
+    };
 }
 
+// usize is decoded as a u64 and then checked to fit, so that values written on
+// a 64 bit target that are too large for a 32 bit one fail to read there with
+// Error::UsizeOverflow instead of being truncated, unless checked arithmetic
+// is disabled
 impl<'de> BinDeserialize<'de> for usize {
     fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
-        match deserializer.mode().usize_len {
-            UsizeLen::U8 => Ok(deserializer.pipe().read_u8()? as usize),
-            UsizeLen::U16 => Ok(deserializer.pipe().read_u16::<LE>()? as usize),
-            UsizeLen::U32 => Ok(deserializer.pipe().read_u32::<LE>()?.try_into()?),
-            UsizeLen::U64 => Ok(deserializer.pipe().read_u64::<LE>()?.try_into()?),
-            UsizeLen::Variable => deserializer.pipe().read_varusize(),
+        let mode = deserializer.mode();
+        let pipe = deserializer.pipe();
+
+        match mode.usize_len {
+            UsizeLen::U8 => Ok(pipe.read_u8()? as usize),
+            UsizeLen::U16 => Ok(with_endian!(mode, pipe, read_u16())? as usize),
+            UsizeLen::U32 => Ok(with_endian!(mode, pipe, read_u32())?.try_into()?),
+            UsizeLen::U64 => {
+                let num = with_endian!(mode, pipe, read_u64())?;
+                num.try_into().map_err(|_| Error::UsizeOverflow(num))
+            }
+            UsizeLen::Variable if mode.checked_arithmetic => pipe.read_varusize(),
+            UsizeLen::Variable => Ok(pipe.read_varuint_wrapping()? as usize),
         }
     }
 }
 
 impl BinSerialize for usize {
     fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
-        match serializer.mode().usize_len {
-            UsizeLen::U8 => serializer.pipe().write_u8((*self).try_into()?)?,
-            UsizeLen::U16 => serializer.pipe().write_u8((*self).try_into()?)?,
-            UsizeLen::U32 => serializer.pipe().write_u8((*self).try_into()?)?,
-            UsizeLen::U64 => serializer.pipe().write_u8((*self).try_into()?)?,
+        let mode = serializer.mode();
+        let pipe = serializer.pipe();
+
+        match mode.usize_len {
+            UsizeLen::U8 => pipe.write_u8((*self).try_into()?)?,
+            UsizeLen::U16 => with_endian!(mode, pipe, write_u16((*self).try_into()?))?,
+            UsizeLen::U32 => with_endian!(mode, pipe, write_u32((*self).try_into()?))?,
+            UsizeLen::U64 => with_endian!(mode, pipe, write_u64((*self).try_into()?))?,
             UsizeLen::Variable => {
-                serializer.pipe().write_varusize(*self)?;
+                pipe.write_varusize(*self)?;
             }
         };
 
         Ok(())
     }
+
+    fn schema() -> Schema {
+        Schema::Usize
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        usize_len_hint(*self, mode.usize_len)
+    }
+}
+
+/// Returns the number of bytes `value` takes when written with `len`.
+fn usize_len_hint(value: usize, len: UsizeLen) -> usize {
+    match len {
+        UsizeLen::U8 => 1,
+        UsizeLen::U16 => 2,
+        UsizeLen::U32 => 4,
+        UsizeLen::U64 => 8,
+        UsizeLen::Variable => varint_len(value as u64),
+    }
+}
+
+// isize is encoded like i64 regardless of the platform's pointer width, so
+// that it reads back the same on 32 and 64 bit targets
+impl<'de> BinDeserialize<'de> for isize {
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        let num = i64::deserialize(deserializer)?;
+        num.try_into().map_err(|_| Error::IsizeOverflow(num))
+    }
+}
+
+impl BinSerialize for isize {
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        (*self as i64).serialize(serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::I64
+    }
+}
+
+/// Writes `bytes` as a string of hex digits, see [`Mode::with_hex_bytes`].
+fn write_hex<S: BinSerializer>(bytes: &[u8], mut serializer: S) -> Result<()> {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let hex: Vec<u8> = bytes
+        .iter()
+        .flat_map(|b| [DIGITS[(b >> 4) as usize], DIGITS[(b & 0xF) as usize]])
+        .collect();
+    hex.len()
+        .serialize((&mut serializer).change_mode(use_string_len))?;
+    serializer.pipe().write_all(&hex)?;
+    Ok(())
+}
+
+/// Reads a string of hex digits written by [`write_hex`], appending the
+/// bytes to `target`.
+fn read_hex<'de, D: BinDeserializer<'de>>(target: &mut Vec<u8>, mut deserializer: D) -> Result<()> {
+    let len = read_len((&mut deserializer).change_mode(use_string_len))?;
+    let mut hex = deserializer.dedup().take_scratch();
+    let read = deserializer.pipe().take(len as u64).read_to_end(&mut hex)?;
+
+    if read != len {
+        return Err(Error::UnexpectedEof {
+            expected: len,
+            got: read,
+        });
+    }
+
+    if len % 2 != 0 {
+        return Err(Error::custom(format!("odd number of hex digits: {}", len)));
+    }
+
+    let digit = |c: u8| match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err(Error::custom(format!("invalid hex digit {:?}", c as char))),
+    };
+
+    for pair in hex.chunks(2) {
+        target.push(digit(pair[0])? << 4 | digit(pair[1])?);
+    }
+
+    deserializer.dedup().put_scratch(hex);
+    Ok(())
 }
 
 impl<'de> BinDeserialize<'de> for u8 {
     fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
         Ok(deserializer.pipe().read_u8()?)
     }
+
+    fn deserialize_extend<D: BinDeserializer<'de>>(
+        target: &mut Vec<Self>,
+        mut deserializer: D,
+    ) -> Result<()> {
+        let mode = deserializer.mode();
+
+        if mode.hex_bytes {
+            return read_hex(target, deserializer);
+        }
+
+        if mode.use_dedup && mode.dedup_bytes && !mode.inline_dedup {
+            let idx = usize::deserialize(
+                (&mut deserializer).change_mode(|mode| mode.usize_len = mode.dedup_idx),
+            )?;
+            let bytes = deserializer
+                .dedup()
+                .get_bytes(idx)
+                .ok_or(Error::BytesOutOfRange(idx))?;
+            target.extend_from_slice(bytes);
+            return Ok(());
+        }
+
+        let len = read_len(&mut deserializer)?;
+
+        // read_to_end only grows the buffer as data actually arrives, so a
+        // bogus length can't make this allocate more than the input size
+        let read = deserializer.pipe().take(len as u64).read_to_end(target)?;
+
+        if read != len {
+            return Err(Error::UnexpectedEof {
+                expected: len,
+                got: read,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn deserialize_array<D: BinDeserializer<'de>, const LEN: usize>(
+        deserializer: D,
+    ) -> Result<[Self; LEN]> {
+        if !deserializer.mode().hex_bytes {
+            return read_array(deserializer);
+        }
+
+        let mut bytes = Vec::new();
+        read_hex(&mut bytes, deserializer)?;
+        let len = bytes.len();
+
+        bytes
+            .try_into()
+            .map_err(|_| Error::custom(format!("expected {} bytes for array, found {}", LEN, len)))
+    }
 }
 
 impl BinSerialize for u8 {
     fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
         Ok(serializer.pipe().write_u8(*self)?)
     }
+
+    fn serialize_slice<S: BinSerializer>(slice: &[Self], mut serializer: S) -> Result<()> {
+        let mode = serializer.mode();
+
+        if mode.hex_bytes {
+            return write_hex(slice, serializer);
+        }
+
+        if mode.use_dedup && mode.dedup_bytes && !mode.inline_dedup {
+            let pos = if mode.dedup_eq_by_content_hash {
+                serializer.dedup().put_bytes_by_hash(slice)
+            } else {
+                serializer.dedup().put_bytes(slice)
+            };
+            return pos.serialize(serializer.change_mode(|mode| mode.usize_len = mode.dedup_idx));
+        }
+
+        slice.len().serialize(&mut serializer)?;
+        serializer.pipe().write_all(slice)?;
+        Ok(())
+    }
+
+    fn serialize_array<S: BinSerializer>(array: &[Self], mut serializer: S) -> Result<()> {
+        if serializer.mode().hex_bytes {
+            return write_hex(array, serializer);
+        }
+
+        Ok(serializer.pipe().write_all(array)?)
+    }
+
+    fn schema() -> Schema {
+        Schema::U8
+    }
+
+    fn size_hint(&self, _mode: Mode) -> usize {
+        1
+    }
+}
+
+// Like u8, i8 is always written as a single byte
+impl<'de> BinDeserialize<'de> for i8 {
+    fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
+        Ok(deserializer.pipe().read_u8()? as i8)
+    }
+}
+
+impl BinSerialize for i8 {
+    fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
+        Ok(serializer.pipe().write_u8(*self as u8)?)
+    }
+
+    fn schema() -> Schema {
+        Schema::I8
+    }
+
+    fn size_hint(&self, _mode: Mode) -> usize {
+        1
+    }
 }
 
 macro_rules! impl_int {
-    ($type:ty, $rm:ident, $wm:ident, $rvm:ident, $wvm:ident, $varint_type:ty) => {
+    ($type:ty, $rm:ident, $wm:ident, $rvm:ident, $rvwm:ident, $wvm:ident, $varint_type:ty, $schema:ident $(, group: $group_sizes:expr)?) => {
         impl<'de> BinDeserialize<'de> for $type {
             fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
-                if deserializer.mode().fixed_size_use_varint {
+                let mode = deserializer.mode();
+                let use_varint = mode.int_use_varint(<$type>::MIN != 0);
+
+                if use_varint && mode.checked_arithmetic {
                     Ok(deserializer.pipe().$rvm()?.try_into()?)
+                } else if use_varint {
+                    Ok(deserializer.pipe().$rvwm()? as $type)
                 } else {
-                    Ok(deserializer.pipe().$rm::<LE>()?)
+                    Ok(with_endian!(mode, deserializer.pipe(), $rm())?)
                 }
             }
+
+            $(
+                fn deserialize_extend<D: BinDeserializer<'de>>(
+                    target: &mut Vec<Self>,
+                    mut deserializer: D,
+                ) -> Result<()> {
+                    if !deserializer.mode().varint_group_encoding {
+                        let iter = VecLikeIter::<_, Self>::new(deserializer)?;
+                        return try_iter(iter, |iter| target.extend(iter));
+                    }
+
+                    let len = read_elements_len(&mut deserializer)?;
+                    group_varint_read(len, &$group_sizes, deserializer.pipe(), target)?;
+                    Ok(())
+                }
+            )?
         }
 
         impl BinSerialize for $type {
             fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
-                if serializer.mode().fixed_size_use_varint {
+                let mode = serializer.mode();
+
+                if mode.int_use_varint(<$type>::MIN != 0) {
                     serializer.pipe().$wvm(*self as $varint_type)?;
                 } else {
-                    serializer.pipe().$wm::<LE>(*self)?;
+                    with_endian!(mode, serializer.pipe(), $wm(*self))?;
                 }
 
                 Ok(())
             }
+
+            $(
+                fn serialize_slice<S: BinSerializer>(slice: &[Self], mut serializer: S) -> Result<()> {
+                    if !serializer.mode().varint_group_encoding {
+                        return serialize_iter(slice.iter(), serializer);
+                    }
+
+                    slice.len().serialize(&mut serializer)?;
+                    group_varint_write(slice, &$group_sizes, serializer.pipe())?;
+                    Ok(())
+                }
+            )?
+
+            fn schema() -> Schema {
+                Schema::$schema
+            }
+
+            fn size_hint(&self, mode: Mode) -> usize {
+                if mode.int_use_varint(<$type>::MIN != 0) {
+                    CountWrite::new().$wvm(*self as $varint_type).unwrap_or(0)
+                } else {
+                    mem::size_of::<$type>()
+                }
+            }
         }
     };
 }
 
-impl From<std::convert::Infallible> for crate::Error {
+impl From<Infallible> for crate::Error {
     fn from(_: Infallible) -> Self {
         unreachable!()
     }
 }
 
-impl_int!(u16, read_u16, write_u16, read_varuint, write_varuint, u64);
-impl_int!(u32, read_u32, write_u32, read_varuint, write_varuint, u64);
-impl_int!(u64, read_u64, write_u64, read_varuint, write_varuint, u64);
-impl_int!(i16, read_i16, write_i16, read_varint, write_varint, i64);
-impl_int!(i32, read_i32, write_i32, read_varint, write_varint, i64);
-impl_int!(i64, read_i64, write_i64, read_varint, write_varint, i64);
+impl_int! { u16, read_u16, write_u16, read_varuint, read_varuint_wrapping, write_varuint, u64, U16 }
+impl_int! { u32, read_u32, write_u32, read_varuint, read_varuint_wrapping, write_varuint, u64, U32, group: GROUP_SIZES_U32 }
+impl_int! { u64, read_u64, write_u64, read_varuint, read_varuint_wrapping, write_varuint, u64, U64, group: GROUP_SIZES_U64 }
+impl_int! { i16, read_i16, write_i16, read_varint, read_varint_wrapping, write_varint, i64, I16 }
+impl_int! { i32, read_i32, write_i32, read_varint, read_varint_wrapping, write_varint, i64, I32 }
+impl_int! { i64, read_i64, write_i64, read_varint, read_varint_wrapping, write_varint, i64, I64 }
+
+// Floats are written as their IEEE 754 bit pattern, so every value including
+// -0.0, subnormals and NaN payloads round-trips exactly. With
+// Mode::stable_float_repr, they are written using their Debug representation
+// instead, which is the shortest string that parses back to the same value.
+macro_rules! impl_float {
+    ($type:ty, $rm:ident, $wm:ident, $schema:ident) => {
+        impl<'de> BinDeserialize<'de> for $type {
+            fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
+                let mode = deserializer.mode();
+
+                if mode.stable_float_repr {
+                    let s = String::deserialize(deserializer)?;
+                    return s
+                        .parse()
+                        .map_err(|_| Error::custom(format!("invalid float {:?}", s)));
+                }
+
+                Ok(<$type>::from_bits(with_endian!(
+                    mode,
+                    deserializer.pipe(),
+                    $rm()
+                )?))
+            }
+        }
+
+        impl BinSerialize for $type {
+            fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
+                let mode = serializer.mode();
+
+                if mode.error_on_nan && !self.is_finite() {
+                    return Err(Error::custom(format!(
+                        "can't serialize non-finite float {}",
+                        self
+                    )));
+                }
+
+                if mode.stable_float_repr {
+                    return format!("{:?}", self).serialize(serializer);
+                }
+
+                Ok(with_endian!(mode, serializer.pipe(), $wm(self.to_bits()))?)
+            }
+
+            fn schema() -> Schema {
+                Schema::$schema
+            }
+
+            fn size_hint(&self, _mode: Mode) -> usize {
+                mem::size_of::<$type>()
+            }
+        }
+    };
+}
+
+impl_float! { f32, read_u32, write_u32, F32 }
+impl_float! { f64, read_u64, write_u64, F64 }
+
+macro_rules! impl_nonzero {
+    ($type:ty, $inner:ty) => {
+        impl<'de> BinDeserialize<'de> for $type {
+            fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+                <$type>::new(<$inner>::deserialize(deserializer)?)
+                    .ok_or(Error::ZeroValue(stringify!($type)))
+            }
+        }
+
+        impl BinSerialize for $type {
+            fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+                self.get().serialize(serializer)
+            }
+
+            fn schema() -> Schema {
+                <$inner>::schema()
+            }
+        }
+    };
+}
+
+impl_nonzero! { NonZeroU8, u8 }
+impl_nonzero! { NonZeroU16, u16 }
+impl_nonzero! { NonZeroU32, u32 }
+impl_nonzero! { NonZeroU64, u64 }
+impl_nonzero! { NonZeroUsize, usize }
+impl_nonzero! { NonZeroI8, i8 }
+impl_nonzero! { NonZeroI16, i16 }
+impl_nonzero! { NonZeroI32, i32 }
+impl_nonzero! { NonZeroI64, i64 }
+impl_nonzero! { NonZeroIsize, isize }
+
+/// Sets up `mode` for writing the bytes of a string, which are always written
+/// as they are, even with [`Mode::hex_bytes`].
+fn use_string_len(mode: &mut Mode) {
+    if let Some(string_len) = mode.string_len {
+        mode.usize_len = string_len;
+    }
+
+    mode.hex_bytes = false;
+}
 
 impl<'de> BinDeserialize<'de> for String {
     fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
-        if deserializer.mode().use_dedup {
+        let mode = deserializer.mode();
+
+        if mode.use_dedup && mode.inline_dedup {
+            // 0 marks a new string, anything else is one past the index of a
+            // string seen before
+            let idx = usize::deserialize(
+                (&mut deserializer).change_mode(|mode| mode.usize_len = mode.dedup_idx),
+            )?;
+
+            if idx == 0 {
+                let s = String::from_utf8(Vec::deserialize((&mut deserializer).change_mode(
+                    |mode| {
+                        mode.use_dedup = false;
+                        use_string_len(mode);
+                    },
+                ))?)?;
+                deserializer.dedup().push_inline(s.clone());
+                Ok(s)
+            } else {
+                deserializer
+                    .dedup()
+                    .get_inline(idx - 1)
+                    .ok_or(Error::StrOutOfRange(idx - 1))
+            }
+        } else if mode.use_dedup {
             let idx = usize::deserialize(
                 (&mut deserializer).change_mode(|mode| mode.usize_len = mode.dedup_idx),
             )?;
@@ -129,10 +618,42 @@ impl<'de> BinDeserialize<'de> for String {
                 .dedup()
                 .get_str(idx)
                 .map(|s| s.to_string())
-                .ok_or_else(|| Error::custom(format!("index {} not in string table", idx)))
+                .ok_or(Error::StrOutOfRange(idx))
         } else {
-            Ok(String::from_utf8(Vec::deserialize(deserializer)?)?)
+            Ok(String::from_utf8(Vec::deserialize(
+                deserializer.change_mode(use_string_len),
+            )?)?)
+        }
+    }
+
+    fn deserialize_in_place<D: BinDeserializer<'de>>(&mut self, mut deserializer: D) -> Result<()> {
+        let mode = deserializer.mode();
+
+        if mode.use_dedup && mode.inline_dedup {
+            *self = String::deserialize(deserializer)?;
+            return Ok(());
+        } else if mode.use_dedup {
+            let idx = usize::deserialize(
+                (&mut deserializer).change_mode(|mode| mode.usize_len = mode.dedup_idx),
+            )?;
+            let s = deserializer
+                .dedup()
+                .get_str(idx)
+                .ok_or(Error::StrOutOfRange(idx))?;
+            self.clear();
+            self.push_str(s);
+            return Ok(());
         }
+
+        let mut bytes = mem::take(self).into_bytes();
+        bytes.clear();
+        u8::deserialize_extend(&mut bytes, deserializer.change_mode(use_string_len))?;
+        *self = String::from_utf8(bytes)?;
+        Ok(())
+    }
+
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        read_collection_option(deserializer)
     }
 }
 
@@ -140,15 +661,110 @@ impl BinSerialize for String {
     fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
         (**self).serialize(serializer)
     }
+
+    fn serialize_option<S: BinSerializer>(value: Option<&Self>, serializer: S) -> Result<()> {
+        write_collection_option(value, serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::String
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        (**self).size_hint(mode)
+    }
+}
+
+impl<'de: 'a, 'a> BinDeserialize<'de> for &'a str {
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        if deserializer.mode().use_dedup {
+            return Err(Error::custom(
+                "can't borrow strings when deduplication is enabled",
+            ));
+        }
+
+        let bytes = <&[u8]>::deserialize(deserializer.change_mode(use_string_len))?;
+
+        match core::str::from_utf8(bytes) {
+            Ok(s) => Ok(s),
+            // only copy in the error case, to report it the same way as for
+            // owned strings
+            Err(_) => Err(String::from_utf8(bytes.to_vec()).unwrap_err().into()),
+        }
+    }
+
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        read_collection_option(deserializer)
+    }
+}
+
+impl<'de: 'a, 'a> BinDeserialize<'de> for &'a [u8] {
+    fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
+        let mode = deserializer.mode();
+
+        if mode.use_dedup && mode.dedup_bytes && !mode.inline_dedup {
+            return Err(Error::custom(
+                "can't borrow byte blobs when deduplicating them",
+            ));
+        }
+
+        let len = read_len(&mut deserializer)?;
+        deserializer.borrow_bytes(len)
+    }
+
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        read_collection_option(deserializer)
+    }
+}
+
+fn insert_str(dedup: &mut DedupContext, s: &str, mode: Mode) -> (usize, bool) {
+    if mode.dedup_eq_by_content_hash {
+        dedup.insert_str_by_hash(s)
+    } else {
+        dedup.insert_str(s)
+    }
 }
 
 impl BinSerialize for str {
     fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
-        if serializer.mode().use_dedup {
-            let pos = serializer.dedup().put_str(self);
+        let mode = serializer.mode();
+
+        if mode.use_dedup && mode.inline_dedup {
+            match insert_str(serializer.dedup(), self, mode) {
+                (_, true) => {
+                    0usize.serialize(
+                        (&mut serializer).change_mode(|mode| mode.usize_len = mode.dedup_idx),
+                    )?;
+                    self.as_bytes().serialize(serializer.change_mode(|mode| {
+                        mode.use_dedup = false;
+                        use_string_len(mode);
+                    }))
+                }
+                (pos, false) => (pos + 1)
+                    .serialize(serializer.change_mode(|mode| mode.usize_len = mode.dedup_idx)),
+            }
+        } else if mode.use_dedup {
+            let (pos, _) = insert_str(serializer.dedup(), self, mode);
             pos.serialize(serializer.change_mode(|mode| mode.usize_len = mode.dedup_idx))
         } else {
-            self.as_bytes().serialize(serializer)
+            self.as_bytes()
+                .serialize(serializer.change_mode(use_string_len))
+        }
+    }
+
+    fn serialize_option<S: BinSerializer>(value: Option<&Self>, serializer: S) -> Result<()> {
+        write_collection_option(value, serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::String
+    }
+    fn size_hint(&self, mode: Mode) -> usize {
+        if mode.use_dedup && !mode.inline_dedup {
+            // only the index is written, assume it's small
+            1
+        } else {
+            usize_len_hint(self.len(), mode.string_len.unwrap_or(mode.usize_len)) + self.len()
         }
     }
 }
@@ -158,14 +774,23 @@ where
     T: BinDeserialize<'de>,
 {
     fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
-        let iter = VecLikeIter::new(deserializer)?;
-        try_iter(iter, |iter| iter.collect())
+        let mut vec = Vec::new();
+        vec.deserialize_in_place(deserializer)?;
+        Ok(vec)
     }
 
     fn deserialize_in_place<D: BinDeserializer<'de>>(&mut self, deserializer: D) -> Result<()> {
         self.clear();
-        let iter = VecLikeIter::new(deserializer)?;
-        try_iter(iter, |iter| self.extend(iter))
+
+        if use_collection_table(deserializer.mode()) {
+            read_collection(self, deserializer)
+        } else {
+            T::deserialize_extend(self, deserializer)
+        }
+    }
+
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        read_collection_option(deserializer)
     }
 }
 
@@ -176,6 +801,18 @@ where
     fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
         (**self).serialize(serializer)
     }
+
+    fn serialize_option<S: BinSerializer>(value: Option<&Self>, serializer: S) -> Result<()> {
+        write_collection_option(value, serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Seq(Box::new(T::schema()))
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        (**self).size_hint(mode)
+    }
 }
 
 impl<T> BinSerialize for [T]
@@ -183,7 +820,24 @@ where
     T: BinSerialize,
 {
     fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
-        serialize_iter(self.iter(), serializer)
+        if use_collection_table(serializer.mode()) {
+            write_collection(self, serializer)
+        } else {
+            T::serialize_slice(self, serializer)
+        }
+    }
+
+    fn serialize_option<S: BinSerializer>(value: Option<&Self>, serializer: S) -> Result<()> {
+        write_collection_option(value, serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Seq(Box::new(T::schema()))
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        usize_len_hint(self.len(), mode.usize_len)
+            + self.iter().map(|el| el.size_hint(mode)).sum::<usize>()
     }
 }
 
@@ -191,92 +845,435 @@ impl<T, const LEN: usize> BinSerialize for [T; LEN]
 where
     T: BinSerialize,
 {
-    fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
-        for el in self.iter() {
-            el.serialize(&mut serializer)?;
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        T::serialize_array(self, serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Array(LEN, Box::new(T::schema()))
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        self.iter().map(|el| el.size_hint(mode)).sum()
+    }
+}
+
+/// Drops the elements of a partially deserialized array if deserializing
+/// one of the remaining elements fails.
+struct ArrayGuard<'a, T> {
+    arr: &'a mut [MaybeUninit<T>],
+    init: usize,
+}
+
+impl<T> Drop for ArrayGuard<'_, T> {
+    fn drop(&mut self) {
+        for el in &mut self.arr[..self.init] {
+            // this is safe since the first `init` elements have been
+            // initialized
+            unsafe { el.assume_init_drop() };
         }
+    }
+}
 
-        Ok(())
+/// Reads the elements of an array one by one. This is the default
+/// implementation of [`BinDeserialize::deserialize_array`].
+pub(crate) fn read_array<'de, D, T, const LEN: usize>(mut deserializer: D) -> Result<[T; LEN]>
+where
+    D: BinDeserializer<'de>,
+    T: BinDeserialize<'de>,
+{
+    // this is safe since an array of MaybeUninit<T> doesn't need to be
+    // initialized
+    // TODO: https://github.com/rust-lang/rust/issues/61956
+    let mut arr: [MaybeUninit<T>; LEN] = unsafe { MaybeUninit::uninit().assume_init() };
+
+    let mut guard = ArrayGuard {
+        arr: &mut arr,
+        init: 0,
+    };
+
+    while guard.init < LEN {
+        guard.arr[guard.init] = MaybeUninit::new(T::deserialize(&mut deserializer)?);
+        guard.init += 1;
     }
+
+    mem::forget(guard);
+
+    // this is safe since [MaybeUninit<T>; LEN] doesn't do anything on drop,
+    // since MaybeUninit<T>'s Drop is a no-op
+    Ok(unsafe { mem::transmute_copy(&arr) })
 }
 
 impl<'de, T, const LEN: usize> BinDeserialize<'de> for [T; LEN]
 where
     T: BinDeserialize<'de> + Sized,
 {
-    fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
-        // this is safe since MaybeUninit<T>'s Drop is a no-op
-        // TODO: https://github.com/rust-lang/rust/issues/61956
-        let mut arr: [MaybeUninit<T>; LEN] =
-            unsafe { std::mem::transmute_copy(&MaybeUninit::<T>::uninit()) };
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        T::deserialize_array(deserializer)
+    }
+
+    fn deserialize_in_place<D: BinDeserializer<'de>>(&mut self, mut deserializer: D) -> Result<()> {
+        // arrays of bytes aren't written element by element then
+        if deserializer.mode().hex_bytes {
+            *self = T::deserialize_array(deserializer)?;
+            return Ok(());
+        }
+
+        for el in self.iter_mut() {
+            *el = T::deserialize(&mut deserializer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Serializes a map or set key, keeping it out of the dedup table if
+/// [`Mode::deny_dedup_in_keys`] is set.
+struct MapKey<T>(T);
+
+fn use_key_mode(mode: &mut Mode) {
+    if mode.deny_dedup_in_keys {
+        mode.use_dedup = false;
+    }
+}
+
+impl<T> BinSerialize for MapKey<T>
+where
+    T: BinSerialize,
+{
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        self.0.serialize(serializer.change_mode(use_key_mode))
+    }
+
+    fn schema() -> Schema {
+        T::schema()
+    }
+
+    fn size_hint(&self, mut mode: Mode) -> usize {
+        use_key_mode(&mut mode);
+        self.0.size_hint(mode)
+    }
+}
+
+impl<'de, T> BinDeserialize<'de> for MapKey<T>
+where
+    T: BinDeserialize<'de>,
+{
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        Ok(MapKey(T::deserialize(
+            deserializer.change_mode(use_key_mode),
+        )?))
+    }
+}
+
+/// Collects `iter` ordered by the serialized representation of `key` for each
+/// item, for [`Mode::deterministic`]. Dedup is turned off for this so that
+/// the order doesn't depend on which strings happened to be seen first.
+#[cfg(feature = "std")]
+fn sorted_by_bytes<I, F, K>(iter: I, key: F, mode: Mode) -> Result<Vec<I::Item>>
+where
+    I: Iterator,
+    F: Fn(&I::Item) -> &K,
+    K: BinSerialize + ?Sized,
+{
+    let mode = Mode {
+        use_dedup: false,
+        ..mode
+    };
+
+    let mut items = iter
+        .map(|el| {
+            let mut buf = Vec::new();
+            key(&el).serialize(BinSerializerBase::new(&mut buf).with_mode(mode))?;
+            Ok((buf, el))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    items.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    Ok(items.into_iter().map(|el| el.1).collect())
+}
+
+// Since () serializes to nothing, a HashMap<K, ()> has the same encoding as
+// the equivalent HashSet<K>, and the two can be read back as each other. Such
+// maps are written through the same code as sets, which skips the values.
+#[cfg(feature = "std")]
+impl<K, V, H> BinSerialize for HashMap<K, V, H>
+where
+    K: BinSerialize,
+    V: BinSerialize,
+{
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        if V::serializes_to_nothing() {
+            serialize_hash_set(self.keys(), serializer)
+        } else if serializer.mode().deterministic {
+            let entries = sorted_by_bytes(self.iter(), |(k, _)| *k, serializer.mode())?;
+            serialize_iter(entries.into_iter().map(|(k, v)| (MapKey(k), v)), serializer)
+        } else {
+            serialize_iter(self.iter().map(|(k, v)| (MapKey(k), v)), serializer)
+        }
+    }
+
+    fn serialize_option<S: BinSerializer>(value: Option<&Self>, serializer: S) -> Result<()> {
+        write_collection_option(value, serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Map(Box::new(K::schema()), Box::new(V::schema()))
+    }
 
-        for idx in 0..LEN {
-            arr[idx] = MaybeUninit::new(T::deserialize(&mut deserializer)?);
+    fn size_hint(&self, mode: Mode) -> usize {
+        if V::serializes_to_nothing() {
+            return usize_len_hint(self.len(), mode.usize_len)
+                + self
+                    .keys()
+                    .map(|k| MapKey(k).size_hint(mode))
+                    .sum::<usize>();
         }
 
-        // this is safe since [MaybeUninit<T>; LEN] doesn't do anything on drop,
-        // since MaybeUninit<T>'s Drop is a no-op
-        Ok(unsafe { std::mem::transmute_copy(&arr) })
+        usize_len_hint(self.len(), mode.usize_len)
+            + self
+                .iter()
+                .map(|(k, v)| MapKey(k).size_hint(mode) + v.size_hint(mode))
+                .sum::<usize>()
+    }
+}
+
+/// Writes the items of a hash set, or the keys of a hash map whose values
+/// are written as nothing, in the order given by [`Mode::deterministic`].
+#[cfg(feature = "std")]
+fn serialize_hash_set<'a, I, T, S>(iter: I, serializer: S) -> Result<()>
+where
+    I: Iterator<Item = &'a T>,
+    T: BinSerialize + 'a,
+    S: BinSerializer,
+{
+    if serializer.mode().deterministic {
+        let items = sorted_by_bytes(iter, |el| *el, serializer.mode())?;
+        serialize_iter(items.into_iter().map(MapKey), serializer)
+    } else {
+        serialize_iter(iter.map(MapKey), serializer)
+    }
+}
+
+/// Reads the entry count of a map, checking it against
+/// [`Mode::max_map_entries`].
+fn map_entries<'de, D, K, V>(deserializer: D) -> Result<VecLikeIter<D, (MapKey<K>, V)>>
+where
+    D: BinDeserializer<'de>,
+    K: BinDeserialize<'de>,
+    V: BinDeserialize<'de>,
+{
+    let max = deserializer.mode().max_map_entries;
+    let iter = VecLikeIter::new(deserializer)?;
+
+    match max {
+        Some(max) if iter.len() > max => Err(Error::TooManyMapEntries(iter.len(), max)),
+        _ => Ok(iter),
+    }
+}
+
+// The maps are built with the hasher's default value, without reserving space
+// for the entries up front since their count comes from the input.
+#[cfg(feature = "std")]
+impl<'de, K, V, H> BinDeserialize<'de> for HashMap<K, V, H>
+where
+    K: BinDeserialize<'de> + Eq + Hash,
+    V: BinDeserialize<'de>,
+    H: BuildHasher + Default,
+{
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        let iter = map_entries::<_, K, V>(deserializer)?;
+        try_iter(iter, |iter| iter.map(|(k, v)| (k.0, v)).collect())
+    }
+
+    fn deserialize_in_place<D: BinDeserializer<'de>>(&mut self, deserializer: D) -> Result<()> {
+        self.clear();
+        let iter = map_entries::<_, K, V>(deserializer)?;
+        try_iter(iter, |iter| self.extend(iter.map(|(k, v)| (k.0, v))))
+    }
+
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        read_collection_option(deserializer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, H> BinSerialize for HashSet<T, H>
+where
+    T: BinSerialize,
+{
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        serialize_hash_set(self.iter(), serializer)
+    }
+
+    fn serialize_option<S: BinSerializer>(value: Option<&Self>, serializer: S) -> Result<()> {
+        write_collection_option(value, serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Map(Box::new(T::schema()), Box::new(Schema::Unit))
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        usize_len_hint(self.len(), mode.usize_len)
+            + self.iter().map(|el| el.size_hint(mode)).sum::<usize>()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de, T, H> BinDeserialize<'de> for HashSet<T, H>
+where
+    T: BinDeserialize<'de> + Hash + Eq,
+    H: BuildHasher + Default,
+{
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        let iter = VecLikeIter::<_, MapKey<T>>::new(deserializer)?;
+        try_iter(iter, |iter| iter.map(|el| el.0).collect())
     }
 
-    fn deserialize_in_place<D: BinDeserializer<'de>>(&mut self, mut deserializer: D) -> Result<()> {
-        for idx in 0..LEN {
-            self[idx] = T::deserialize(&mut deserializer)?;
-        }
+    fn deserialize_in_place<D: BinDeserializer<'de>>(&mut self, deserializer: D) -> Result<()> {
+        self.clear();
+        let iter = VecLikeIter::<_, MapKey<T>>::new(deserializer)?;
+        try_iter(iter, |iter| self.extend(iter.map(|el| el.0)))
+    }
 
-        Ok(())
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        read_collection_option(deserializer)
     }
 }
 
-impl<K, V> BinSerialize for HashMap<K, V>
+// Maps and sets ordered by key are always written in that order, so unlike
+// with the hash based ones, equal values always produce the same output. The
+// encoding is the same, so each can be read back as the other.
+impl<K, V> BinSerialize for BTreeMap<K, V>
 where
     K: BinSerialize,
     V: BinSerialize,
 {
     fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
-        serialize_iter(self.iter(), serializer)
+        if V::serializes_to_nothing() {
+            serialize_iter(self.keys().map(MapKey), serializer)
+        } else {
+            serialize_iter(self.iter().map(|(k, v)| (MapKey(k), v)), serializer)
+        }
+    }
+
+    fn serialize_option<S: BinSerializer>(value: Option<&Self>, serializer: S) -> Result<()> {
+        write_collection_option(value, serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Map(Box::new(K::schema()), Box::new(V::schema()))
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        usize_len_hint(self.len(), mode.usize_len)
+            + self
+                .iter()
+                .map(|(k, v)| MapKey(k).size_hint(mode) + v.size_hint(mode))
+                .sum::<usize>()
     }
 }
 
-impl<'de, K, V> BinDeserialize<'de> for HashMap<K, V>
+impl<'de, K, V> BinDeserialize<'de> for BTreeMap<K, V>
 where
-    K: BinDeserialize<'de> + Eq + Hash,
+    K: BinDeserialize<'de> + Ord,
     V: BinDeserialize<'de>,
 {
     fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
-        let iter = VecLikeIter::new(deserializer)?;
-        try_iter(iter, |iter| iter.collect())
+        let iter = map_entries::<_, K, V>(deserializer)?;
+        try_iter(iter, |iter| iter.map(|(k, v)| (k.0, v)).collect())
     }
 
     fn deserialize_in_place<D: BinDeserializer<'de>>(&mut self, deserializer: D) -> Result<()> {
         self.clear();
-        let iter = VecLikeIter::new(deserializer)?;
-        try_iter(iter, |iter| self.extend(iter))
+        let iter = map_entries::<_, K, V>(deserializer)?;
+        try_iter(iter, |iter| self.extend(iter.map(|(k, v)| (k.0, v))))
+    }
+
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        read_collection_option(deserializer)
     }
 }
 
-impl<T> BinSerialize for HashSet<T>
+impl<T> BinSerialize for BTreeSet<T>
 where
     T: BinSerialize,
 {
     fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
-        serialize_iter(self.iter(), serializer)
+        serialize_iter(self.iter().map(MapKey), serializer)
+    }
+
+    fn serialize_option<S: BinSerializer>(value: Option<&Self>, serializer: S) -> Result<()> {
+        write_collection_option(value, serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Map(Box::new(T::schema()), Box::new(Schema::Unit))
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        usize_len_hint(self.len(), mode.usize_len)
+            + self.iter().map(|el| el.size_hint(mode)).sum::<usize>()
     }
 }
 
-impl<'de, T> BinDeserialize<'de> for HashSet<T>
+impl<'de, T> BinDeserialize<'de> for BTreeSet<T>
 where
-    T: BinDeserialize<'de> + Hash + Eq,
+    T: BinDeserialize<'de> + Ord,
 {
     fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
-        let iter = VecLikeIter::new(deserializer)?;
-        try_iter(iter, |iter| iter.collect())
+        let iter = VecLikeIter::<_, MapKey<T>>::new(deserializer)?;
+        try_iter(iter, |iter| iter.map(|el| el.0).collect())
     }
 
     fn deserialize_in_place<D: BinDeserializer<'de>>(&mut self, deserializer: D) -> Result<()> {
         self.clear();
-        let iter = VecLikeIter::new(deserializer)?;
-        try_iter(iter, |iter| self.extend(iter))
+        let iter = VecLikeIter::<_, MapKey<T>>::new(deserializer)?;
+        try_iter(iter, |iter| self.extend(iter.map(|el| el.0)))
+    }
+
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        read_collection_option(deserializer)
+    }
+}
+
+impl<T: ?Sized> BinSerialize for PhantomData<T> {
+    fn serialize<S: BinSerializer>(&self, _serializer: S) -> Result<()> {
+        Ok(())
+    }
+
+    fn serializes_to_nothing() -> bool {
+        true
+    }
+
+    fn schema() -> Schema {
+        Schema::Unit
+    }
+}
+
+impl<'de, T: ?Sized> BinDeserialize<'de> for PhantomData<T> {
+    fn deserialize<D: BinDeserializer<'de>>(_deserializer: D) -> Result<Self> {
+        Ok(PhantomData)
+    }
+}
+
+impl BinSerialize for PhantomPinned {
+    fn serialize<S: BinSerializer>(&self, _serializer: S) -> Result<()> {
+        Ok(())
+    }
+
+    fn serializes_to_nothing() -> bool {
+        true
+    }
+
+    fn schema() -> Schema {
+        Schema::Unit
+    }
+}
+
+impl<'de> BinDeserialize<'de> for PhantomPinned {
+    fn deserialize<D: BinDeserializer<'de>>(_deserializer: D) -> Result<Self> {
+        Ok(PhantomPinned)
     }
 }
 
@@ -284,6 +1281,18 @@ impl BinSerialize for () {
     fn serialize<S: BinSerializer>(&self, _serializer: S) -> Result<(), Error> {
         Ok(())
     }
+
+    fn serialize_slice<S: BinSerializer>(slice: &[Self], serializer: S) -> Result<(), Error> {
+        slice.len().serialize(serializer)
+    }
+
+    fn serializes_to_nothing() -> bool {
+        true
+    }
+
+    fn schema() -> Schema {
+        Schema::Unit
+    }
 }
 
 impl<'de> BinDeserialize<'de> for () {
@@ -297,23 +1306,47 @@ impl<'de> BinDeserialize<'de> for () {
     ) -> Result<(), Error> {
         Ok(())
     }
+
+    fn deserialize_extend<D: BinDeserializer<'de>>(
+        target: &mut Vec<Self>,
+        deserializer: D,
+    ) -> Result<(), Error> {
+        // there is nothing to read per element, so just add them all at once
+        let len = read_elements_len(deserializer)?;
+        let new_len = target
+            .len()
+            .checked_add(len)
+            .ok_or_else(|| Error::custom("sequence length overflows usize"))?;
+        target.resize(new_len, ());
+        Ok(())
+    }
 }
 
 macro_rules! impl_tuple {
     ($($tp:ident)+) => {
-        impl<$($tp),+> BinSerialize for ($($tp),+)
+        impl<$($tp),+> BinSerialize for ($($tp,)+)
         where
             $($tp: BinSerialize),+
         {
             #[allow(non_snake_case)]
             fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
-                let ($(ref $tp),+) = *self;
+                let ($(ref $tp,)+) = *self;
                 $($tp.serialize(&mut serializer)?;)+
                 Ok(())
             }
+
+            fn schema() -> Schema {
+                Schema::Tuple(vec![$($tp::schema()),+])
+            }
+
+            #[allow(non_snake_case)]
+            fn size_hint(&self, mode: Mode) -> usize {
+                let ($(ref $tp,)+) = *self;
+                0 $(+ $tp.size_hint(mode))+
+            }
         }
 
-        impl<'de, $($tp),+> BinDeserialize<'de> for ($($tp),+)
+        impl<'de, $($tp),+> BinDeserialize<'de> for ($($tp,)+)
         where
             $($tp: BinDeserialize<'de>),+
         {
@@ -321,13 +1354,14 @@ macro_rules! impl_tuple {
                 mut deserializer: De
             ) -> Result<Self> {
                 Ok((
-                    $($tp::deserialize(&mut deserializer)?),+
+                    $($tp::deserialize(&mut deserializer)?,)+
                 ))
             }
         }
     }
 }
 
+impl_tuple! { A }
 impl_tuple! { A B }
 impl_tuple! { A B C }
 impl_tuple! { A B C D }
@@ -339,7 +1373,15 @@ impl_tuple! { A B C D E F G H I }
 impl_tuple! { A B C D E F G H I J }
 impl_tuple! { A B C D E F G H I J K }
 impl_tuple! { A B C D E F G H I J K L }
+impl_tuple! { A B C D E F G H I J K L M }
+impl_tuple! { A B C D E F G H I J K L M N }
+impl_tuple! { A B C D E F G H I J K L M N O }
+impl_tuple! { A B C D E F G H I J K L M N O P }
 
+/// Serialized as a string so that the data is the same on every platform.
+/// Paths that aren't valid UTF-8 can't be serialized and return an error
+/// instead of being converted lossily.
+#[cfg(feature = "std")]
 impl BinSerialize for Path {
     fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
         match self.to_str() {
@@ -349,46 +1391,170 @@ impl BinSerialize for Path {
             Some(s) => s.serialize(serializer),
         }
     }
+
+    fn schema() -> Schema {
+        Schema::String
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        self.to_str().map_or(0, |s| s.size_hint(mode))
+    }
 }
 
+#[cfg(feature = "std")]
 impl BinSerialize for PathBuf {
     fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
         self.as_path().serialize(serializer)
     }
+
+    fn schema() -> Schema {
+        Schema::String
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        self.as_path().size_hint(mode)
+    }
 }
 
+#[cfg(feature = "std")]
 impl<'de> BinDeserialize<'de> for PathBuf {
     fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
         Ok(PathBuf::from(String::deserialize(deserializer)?))
     }
 }
 
-impl<T> BinSerialize for Option<T>
-where
-    T: BinSerialize,
-{
+impl BinSerialize for Ipv4Addr {
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        self.octets().serialize(serializer)
+    }
+
+    fn schema() -> Schema {
+        <[u8; 4]>::schema()
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        self.octets().size_hint(mode)
+    }
+}
+
+impl<'de> BinDeserialize<'de> for Ipv4Addr {
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        Ok(Ipv4Addr::from(<[u8; 4]>::deserialize(deserializer)?))
+    }
+}
+
+impl BinSerialize for Ipv6Addr {
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        self.octets().serialize(serializer)
+    }
+
+    fn schema() -> Schema {
+        <[u8; 16]>::schema()
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        self.octets().size_hint(mode)
+    }
+}
+
+impl<'de> BinDeserialize<'de> for Ipv6Addr {
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        Ok(Ipv6Addr::from(<[u8; 16]>::deserialize(deserializer)?))
+    }
+}
+
+impl BinSerialize for IpAddr {
     fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
         match self {
-            None => 0u8.serialize(&mut serializer),
-            Some(v) => {
+            IpAddr::V4(addr) => {
+                0u8.serialize(&mut serializer)?;
+                addr.serialize(&mut serializer)
+            }
+            IpAddr::V6(addr) => {
                 1u8.serialize(&mut serializer)?;
-                v.serialize(&mut serializer)
+                addr.serialize(&mut serializer)
             }
         }
     }
+
+    fn schema() -> Schema {
+        Schema::Enum {
+            tag: Box::new(Schema::U8),
+            variants: vec![
+                (0, "V4".to_string(), Ipv4Addr::schema()),
+                (1, "V6".to_string(), Ipv6Addr::schema()),
+            ],
+        }
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        1 + match self {
+            IpAddr::V4(addr) => addr.size_hint(mode),
+            IpAddr::V6(addr) => addr.size_hint(mode),
+        }
+    }
+}
+
+impl<'de> BinDeserialize<'de> for IpAddr {
+    fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
+        match u8::deserialize(&mut deserializer)? {
+            0 => Ok(IpAddr::V4(Ipv4Addr::deserialize(deserializer)?)),
+            1 => Ok(IpAddr::V6(Ipv6Addr::deserialize(deserializer)?)),
+            x => Err(Error::custom(format!("invalid enum variant index {}", x))),
+        }
+    }
+}
+
+/// Serialized as the [`IpAddr`] followed by the port. The flow info and scope
+/// ID of IPv6 socket addresses are not stored and are zero after
+/// deserializing.
+impl BinSerialize for SocketAddr {
+    fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
+        self.ip().serialize(&mut serializer)?;
+        self.port().serialize(&mut serializer)?;
+        Ok(())
+    }
+
+    fn schema() -> Schema {
+        Schema::Tuple(vec![IpAddr::schema(), Schema::U16])
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        self.ip().size_hint(mode) + self.port().size_hint(mode)
+    }
+}
+
+impl<'de> BinDeserialize<'de> for SocketAddr {
+    fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
+        let ip = IpAddr::deserialize(&mut deserializer)?;
+        let port = u16::deserialize(&mut deserializer)?;
+        Ok(SocketAddr::new(ip, port))
+    }
+}
+
+impl<T> BinSerialize for Option<T>
+where
+    T: BinSerialize,
+{
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        T::serialize_option(self.as_ref(), serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Option(Box::new(T::schema()))
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        1 + self.as_ref().map_or(0, |v| v.size_hint(mode))
+    }
 }
 
 impl<'de, T> BinDeserialize<'de> for Option<T>
 where
     T: BinDeserialize<'de>,
 {
-    fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
-        let variant = u8::deserialize(&mut deserializer)?;
-        Ok(match variant {
-            0 => None,
-            1 => Some(T::deserialize(deserializer)?),
-            x @ _ => Err(Error::custom(format!("invalid enum variant index {}", x)))?,
-        })
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        T::deserialize_option(deserializer)
     }
 }
 
@@ -409,6 +1575,16 @@ where
             }
         }
     }
+
+    fn schema() -> Schema {
+        Schema::Enum {
+            tag: Box::new(Schema::U8),
+            variants: vec![
+                (0, "Ok".to_string(), T::schema()),
+                (1, "Err".to_string(), R::schema()),
+            ],
+        }
+    }
 }
 
 impl<'de, T, R> BinDeserialize<'de> for Result<T, R>
@@ -421,7 +1597,7 @@ where
         Ok(match variant {
             0 => Ok(T::deserialize(deserializer)?),
             1 => Err(R::deserialize(deserializer)?),
-            x @ _ => Err(Error::custom(format!("invalid enum variant index {}", x)))?,
+            x => Err(Error::custom(format!("invalid enum variant index {}", x)))?,
         })
     }
 }
@@ -432,9 +1608,13 @@ where
 {
     fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<(), Error> {
         self.try_borrow()
-            .map_err(|e| Error::custom(e))?
+            .map_err(Error::custom)?
             .serialize(serializer)
     }
+
+    fn schema() -> Schema {
+        T::schema()
+    }
 }
 
 impl<'de, T> BinDeserialize<'de> for RefCell<T>
@@ -448,11 +1628,15 @@ where
 
 impl<T> BinSerialize for Cell<T>
 where
-    T: BinSerialize + Copy + ?Sized,
+    T: BinSerialize + Copy,
 {
     fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<(), Error> {
         self.get().serialize(serializer)
     }
+
+    fn schema() -> Schema {
+        T::schema()
+    }
 }
 
 impl<'de, T> BinDeserialize<'de> for Cell<T>
@@ -469,7 +1653,19 @@ where
     T: BinSerialize + ToOwned + ?Sized,
 {
     fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
-        (&**self).serialize(serializer)
+        (**self).serialize(serializer)
+    }
+
+    fn serialize_option<S: BinSerializer>(value: Option<&Self>, serializer: S) -> Result<()> {
+        T::serialize_option(value.map(|v| &**v), serializer)
+    }
+
+    fn schema() -> Schema {
+        T::schema()
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        (**self).size_hint(mode)
     }
 }
 
@@ -482,6 +1678,111 @@ where
     fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
         Ok(Cow::Owned(BinDeserialize::deserialize(deserializer)?))
     }
+
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        Ok(T::Owned::deserialize_option(deserializer)?.map(Cow::Owned))
+    }
+}
+
+// The contents of a box are (de)serialized with a type-erased serializer, so
+// that recursive types which go through a box can be compiled.
+impl<T> BinSerialize for Box<T>
+where
+    T: BinSerialize + ?Sized,
+{
+    fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
+        (**self).serialize(DynSerializer::new(&mut serializer))
+    }
+
+    fn serialize_option<S: BinSerializer>(value: Option<&Self>, mut serializer: S) -> Result<()> {
+        T::serialize_option(value.map(|v| &**v), DynSerializer::new(&mut serializer))
+    }
+
+    fn schema() -> Schema {
+        T::schema()
+    }
+
+    fn size_hint(&self, mode: Mode) -> usize {
+        (**self).size_hint(mode)
+    }
+}
+
+impl<'de, T> BinDeserialize<'de> for Box<T>
+where
+    T: BinDeserialize<'de>,
+{
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        read_boxed(deserializer, |d| Ok(Box::new(T::deserialize(d)?)))
+    }
+
+    fn deserialize_in_place<D: BinDeserializer<'de>>(&mut self, deserializer: D) -> Result<()> {
+        read_boxed(deserializer, |d| (**self).deserialize_in_place(d))
+    }
+
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        read_boxed(
+            deserializer,
+            |d| Ok(T::deserialize_option(d)?.map(Box::new)),
+        )
+    }
+}
+
+impl<'de, T> BinDeserialize<'de> for Box<[T]>
+where
+    T: BinDeserialize<'de>,
+{
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        Ok(Vec::deserialize(deserializer)?.into_boxed_slice())
+    }
+
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        Ok(Vec::deserialize_option(deserializer)?.map(Vec::into_boxed_slice))
+    }
+}
+
+impl<'de> BinDeserialize<'de> for Box<str> {
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        Ok(String::deserialize(deserializer)?.into_boxed_str())
+    }
+
+    fn deserialize_option<D: BinDeserializer<'de>>(deserializer: D) -> Result<Option<Self>> {
+        Ok(String::deserialize_option(deserializer)?.map(String::into_boxed_str))
+    }
+}
+
+// Written like a derived enum with the variants in declaration order would
+// be, but always using a single byte.
+impl BinSerialize for Ordering {
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        let v: u8 = match self {
+            Ordering::Less => 0,
+            Ordering::Equal => 1,
+            Ordering::Greater => 2,
+        };
+        v.serialize(serializer)
+    }
+
+    fn schema() -> Schema {
+        Schema::Enum {
+            tag: Box::new(Schema::U8),
+            variants: vec![
+                (0, "Less".to_string(), Schema::Unit),
+                (1, "Equal".to_string(), Schema::Unit),
+                (2, "Greater".to_string(), Schema::Unit),
+            ],
+        }
+    }
+}
+
+impl<'de> BinDeserialize<'de> for Ordering {
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        match u8::deserialize(deserializer)? {
+            0 => Ok(Ordering::Less),
+            1 => Ok(Ordering::Equal),
+            2 => Ok(Ordering::Greater),
+            x => Err(Error::custom(format!("invalid enum variant index {}", x))),
+        }
+    }
 }
 
 impl BinSerialize for Duration {
@@ -490,12 +1791,134 @@ impl BinSerialize for Duration {
         self.subsec_nanos().serialize(&mut serializer)?;
         Ok(())
     }
+
+    fn schema() -> Schema {
+        Schema::Tuple(vec![Schema::U64, Schema::U32])
+    }
 }
 
 impl<'de> BinDeserialize<'de> for Duration {
     fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
         let secs = u64::deserialize(&mut deserializer)?;
         let nanos = u32::deserialize(&mut deserializer)?;
+
+        if nanos >= 1_000_000_000 {
+            return Err(Error::custom(format!(
+                "{} nanoseconds is more than a second",
+                nanos
+            )));
+        }
+
         Ok(Duration::new(secs, nanos))
     }
 }
+
+/// Serialized as the [`Duration`] since [`Mode::time_epoch`], which is
+/// [`UNIX_EPOCH`] by default. Times before it can't be serialized.
+///
+/// [`UNIX_EPOCH`]: std::time::UNIX_EPOCH
+#[cfg(feature = "std")]
+impl BinSerialize for SystemTime {
+    fn serialize<S: BinSerializer>(&self, serializer: S) -> Result<()> {
+        let since_epoch = self
+            .duration_since(serializer.mode().time_epoch)
+            .map_err(|_| Error::custom("can't serialize time before the epoch"))?;
+        since_epoch.serialize(serializer)
+    }
+
+    fn schema() -> Schema {
+        Duration::schema()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de> BinDeserialize<'de> for SystemTime {
+    fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+        let epoch = deserializer.mode().time_epoch;
+        epoch
+            .checked_add(Duration::deserialize(deserializer)?)
+            .ok_or_else(|| Error::custom("time out of range for this platform"))
+    }
+}
+
+/// Tests for the `unsafe` code in [`read_array`]. These are kept small so
+/// that they can be run under Miri with
+/// `cargo +nightly miri test --lib serdeimpl::test`.
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::panic::{self, AssertUnwindSafe};
+
+    use crate::{deserialize, serialize, BinDeserialize, BinDeserializer, Error, Result};
+
+    thread_local! {
+        static LIVE: Cell<isize> = const { Cell::new(0) };
+    }
+
+    /// Keeps track of how many instances are alive, and fails or panics when
+    /// deserializing certain values.
+    #[derive(Debug)]
+    struct Tracked(Box<u8>);
+
+    impl Tracked {
+        fn live() -> isize {
+            LIVE.with(|v| v.get())
+        }
+    }
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            LIVE.with(|v| v.set(v.get() - 1));
+        }
+    }
+
+    impl<'de> BinDeserialize<'de> for Tracked {
+        fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+            match u8::deserialize(deserializer)? {
+                0xFE => Err(Error::custom("rejected")),
+                0xFF => panic!("rejected"),
+                x => {
+                    LIVE.with(|v| v.set(v.get() + 1));
+                    Ok(Tracked(Box::new(x)))
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn array_round_trip() {
+        let arr = ["a".to_string(), "bc".to_string(), String::new()];
+        let vec = serialize(&arr).unwrap();
+        assert_eq!(arr, deserialize::<[String; 3]>(&vec).unwrap());
+
+        let vec = serialize(&[(); 4]).unwrap();
+        assert_eq!([(); 4], deserialize::<[(); 4]>(&vec).unwrap());
+
+        let empty: [String; 0] = deserialize(&[]).unwrap();
+        assert_eq!(0, empty.len());
+
+        let out: [Tracked; 3] = deserialize(&[1, 2, 3]).unwrap();
+        assert_eq!(3, Tracked::live());
+        assert_eq!([1, 2, 3], [*out[0].0, *out[1].0, *out[2].0]);
+        drop(out);
+        assert_eq!(0, Tracked::live());
+    }
+
+    #[test]
+    fn array_partial_error() {
+        for input in [&[0xFE, 1, 2, 3][..], &[1, 2, 0xFE, 3], &[1, 2, 3]] {
+            assert!(deserialize::<[Tracked; 4]>(input).is_err());
+            assert_eq!(0, Tracked::live());
+        }
+    }
+
+    #[test]
+    fn array_partial_panic() {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            deserialize::<[Tracked; 4]>(&[1, 2, 0xFF, 3])
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(0, Tracked::live());
+    }
+}
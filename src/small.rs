@@ -0,0 +1,149 @@
+//! A byte buffer that stores short contents inline, as returned by
+//! [`serialize_small`].
+//!
+//! [`serialize_small`]: crate::serialize_small
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Deref;
+
+use crate::io::{self, Write};
+
+/// The number of bytes a [`SmallBuf`] can hold before moving its contents
+/// to the heap.
+pub const INLINE_CAP: usize = 64;
+
+#[derive(Clone)]
+pub struct SmallBuf {
+    inline: [u8; INLINE_CAP],
+    len: usize,
+    heap: Option<Vec<u8>>,
+}
+
+impl SmallBuf {
+    pub fn new() -> Self {
+        SmallBuf {
+            inline: [0; INLINE_CAP],
+            len: 0,
+            heap: None,
+        }
+    }
+
+    /// Returns whether the contents have been moved to the heap.
+    pub fn spilled(&self) -> bool {
+        self.heap.is_some()
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.heap {
+            None => self.inline[..self.len].to_vec(),
+            Some(vec) => vec,
+        }
+    }
+}
+
+impl Default for SmallBuf {
+    fn default() -> Self {
+        SmallBuf::new()
+    }
+}
+
+impl Deref for SmallBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match &self.heap {
+            None => &self.inline[..self.len],
+            Some(vec) => vec,
+        }
+    }
+}
+
+impl AsRef<[u8]> for SmallBuf {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for SmallBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl Write for SmallBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.heap {
+            Some(vec) => vec.extend_from_slice(buf),
+            None if self.len + buf.len() <= INLINE_CAP => {
+                self.inline[self.len..self.len + buf.len()].copy_from_slice(buf);
+                self.len += buf.len();
+            }
+            None => {
+                let mut vec = Vec::with_capacity((self.len + buf.len()).max(2 * INLINE_CAP));
+                vec.extend_from_slice(&self.inline[..self.len]);
+                vec.extend_from_slice(buf);
+                self.heap = Some(vec);
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    use crate::{serialize_small, Mode};
+
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS.with(|c| c.set(c.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAlloc = CountingAlloc;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(|c| c.get())
+    }
+
+    #[test]
+    fn small_value_stays_inline() {
+        let value = (1u32, -5i64, true, [7u8; 16]);
+
+        let before = allocations();
+        let buf = serialize_small(&value, Mode::default()).unwrap();
+        assert_eq!(before, allocations());
+
+        assert!(!buf.spilled());
+        assert_eq!(&*crate::serialize(&value).unwrap(), &*buf);
+    }
+
+    #[test]
+    fn large_value_spills() {
+        let value = vec![3u8; 200];
+        let buf = serialize_small(&value, Mode::default()).unwrap();
+
+        assert!(buf.spilled());
+        assert_eq!(crate::serialize(&value).unwrap(), buf.into_vec());
+    }
+}
@@ -0,0 +1,67 @@
+//! Column-oriented ("struct of arrays") serialization of sequences.
+//!
+//! Wrapping a [`Vec`] of a struct in [`Soa`] serializes it field by field
+//! instead of element by element, that is, the first field of every element,
+//! followed by the second field of every element, and so on. Grouping values
+//! of the same type together tends to make the output compress better.
+//!
+//! The struct needs to opt in with `#[binserde(soa)]`, which makes
+//! `#[derive(BinSerialize)]` and `#[derive(BinDeserialize)]` additionally
+//! implement [`SoaSerialize`] and [`SoaDeserialize`] respectively.
+//!
+//! ```
+//! use binserde::soa::Soa;
+//! use binserde_derive::{BinDeserialize, BinSerialize};
+//!
+//! #[derive(Debug, BinSerialize, BinDeserialize, Eq, PartialEq)]
+//! #[binserde(soa)]
+//! struct Point {
+//!     x: u8,
+//!     y: u8,
+//! }
+//!
+//! let points = Soa(vec![Point { x: 1, y: 2 }, Point { x: 3, y: 4 }]);
+//! let vec = binserde::serialize(&points).unwrap();
+//!
+//! assert_eq!(&[2, 1, 3, 2, 4], &*vec);
+//! assert_eq!(points, binserde::deserialize(&vec).unwrap());
+//! ```
+
+use alloc::vec::Vec;
+
+use crate::util::read_elements_len;
+use crate::{BinDeserialize, BinDeserializer, BinSerialize, BinSerializer, Result};
+
+pub trait SoaSerialize: Sized {
+    fn serialize_columns<S: BinSerializer>(items: &[Self], serializer: S) -> Result<()>;
+}
+
+pub trait SoaDeserialize<'de>: Sized {
+    fn deserialize_columns<D: BinDeserializer<'de>>(
+        len: usize,
+        deserializer: D,
+    ) -> Result<Vec<Self>>;
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Hash)]
+pub struct Soa<T>(pub Vec<T>);
+
+impl<T> BinSerialize for Soa<T>
+where
+    T: SoaSerialize,
+{
+    fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
+        self.0.len().serialize(&mut serializer)?;
+        T::serialize_columns(&self.0, serializer)
+    }
+}
+
+impl<'de, T> BinDeserialize<'de> for Soa<T>
+where
+    T: SoaDeserialize<'de>,
+{
+    fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
+        let len = read_elements_len(&mut deserializer)?;
+        Ok(Soa(T::deserialize_columns(len, deserializer)?))
+    }
+}
@@ -1,6 +1,38 @@
-use std::marker::PhantomData;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
 
-use crate::{BinDeserialize, BinDeserializer, BinSerialize, BinSerializer, Result};
+use crate::de::BinDeserializeOwned;
+use crate::{
+    deserialize_with_from, serialize_with, BinDeserialize, BinDeserializer, BinSerialize,
+    BinSerializer, Error, Mode, Result,
+};
+
+/// Reads the length of a container, checking it against
+/// [`Mode::max_container_len`]. The length is only used to decide how many
+/// elements to read, never to allocate memory up front, since it might not
+/// match the actual size of the input.
+pub(crate) fn read_len<'de, D: BinDeserializer<'de>>(mut deserializer: D) -> Result<usize> {
+    let len = usize::deserialize(&mut deserializer)?;
+
+    match deserializer.mode().max_container_len {
+        Some(max) if len > max => Err(Error::ContainerTooLong(len, max)),
+        _ => Ok(len),
+    }
+}
+
+/// Like [`read_len`], but for collections of elements, whose length also
+/// counts towards [`Mode::max_total_elements`].
+pub(crate) fn read_elements_len<'de, D: BinDeserializer<'de>>(
+    mut deserializer: D,
+) -> Result<usize> {
+    let len = read_len(&mut deserializer)?;
+    let total = deserializer.dedup().count_elements(len);
+
+    match deserializer.mode().max_total_elements {
+        Some(max) if total > max => Err(Error::TooManyElements(max)),
+        _ => Ok(len),
+    }
+}
 
 pub struct VecLikeIter<D, T> {
     deserializer: D,
@@ -14,7 +46,7 @@ where
     T: BinDeserialize<'de>,
 {
     pub fn new(mut deserializer: D) -> Result<Self> {
-        let len = usize::deserialize(&mut deserializer)?;
+        let len = read_elements_len(&mut deserializer)?;
         Ok(VecLikeIter {
             deserializer,
             remaining: len,
@@ -51,7 +83,7 @@ where
 {
 }
 
-pub fn serialize_iter<I, S>(mut iter: I, mut serializer: S) -> Result<()>
+pub fn serialize_iter<I, S>(iter: I, mut serializer: S) -> Result<()>
 where
     I: Iterator,
     I::Item: BinSerialize,
@@ -68,3 +100,126 @@ where
 
     Ok(())
 }
+
+/// Serializes a slice either like `[T]`, with its length in front, or like
+/// `[T; N]`, as just its elements. The latter is useful for embedding a
+/// slice whose length the reader already knows into a larger layout.
+pub struct SliceWriter<'a, T> {
+    slice: &'a [T],
+    len_prefix: bool,
+}
+
+impl<'a, T> SliceWriter<'a, T> {
+    pub fn with_len_prefix(slice: &'a [T], len_prefix: bool) -> Self {
+        SliceWriter { slice, len_prefix }
+    }
+}
+
+impl<T> BinSerialize for SliceWriter<'_, T>
+where
+    T: BinSerialize,
+{
+    fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
+        if self.len_prefix {
+            return T::serialize_slice(self.slice, serializer);
+        }
+
+        for el in self.slice {
+            el.serialize(&mut serializer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that deserializing `value` after serializing it with `mode` reads
+/// exactly as many bytes as were written. Intended for testing custom
+/// [`BinSerialize`] and [`BinDeserialize`] implementations. Does nothing in
+/// release builds.
+///
+/// # Panics
+///
+/// Panics if (de)serialization fails or if the number of bytes read differs
+/// from the number of bytes written.
+pub fn debug_assert_symmetric<T>(value: &T, mode: Mode)
+where
+    T: BinSerialize + BinDeserializeOwned,
+{
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    let buf = serialize_with(value, mode).expect("failed to serialize value");
+    let mut pipe = &*buf;
+    let _: T = deserialize_with_from(&mut pipe, mode).expect("failed to deserialize value");
+    let read = buf.len() - pipe.len();
+
+    assert_eq!(
+        buf.len(),
+        read,
+        "wrote {} bytes, but read {} bytes back",
+        buf.len(),
+        read
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use crate::util::{debug_assert_symmetric, SliceWriter};
+    use crate::{
+        deserialize_with, serialize_with, BinDeserialize, BinDeserializer, BinSerialize,
+        BinSerializer, Mode, Result,
+    };
+
+    struct Asymmetric;
+
+    impl BinSerialize for Asymmetric {
+        fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
+            0u8.serialize(&mut serializer)?;
+            0u8.serialize(&mut serializer)
+        }
+    }
+
+    impl<'de> BinDeserialize<'de> for Asymmetric {
+        fn deserialize<D: BinDeserializer<'de>>(deserializer: D) -> Result<Self> {
+            u8::deserialize(deserializer)?;
+            Ok(Asymmetric)
+        }
+    }
+
+    #[test]
+    fn symmetric() {
+        debug_assert_symmetric(&("a".to_string(), vec![1u32, 2, 3]), Mode::dedup());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "wrote 2 bytes, but read 1 bytes back")]
+    fn asymmetric() {
+        debug_assert_symmetric(&Asymmetric, Mode::default());
+    }
+
+    #[test]
+    fn slice_writer() {
+        let values: &[i16] = &[1, -2, 300];
+
+        for mode in [
+            Mode::default(),
+            Mode::default().with_fixed_size_use_varint(true),
+        ] {
+            let vec = serialize_with(&SliceWriter::with_len_prefix(values, true), mode).unwrap();
+            assert_eq!(serialize_with(values, mode).unwrap(), vec);
+            assert_eq!(values, &*deserialize_with::<Vec<i16>>(&vec, mode).unwrap());
+
+            let vec = serialize_with(&SliceWriter::with_len_prefix(values, false), mode).unwrap();
+            assert_eq!(serialize_with(&[1i16, -2, 300], mode).unwrap(), vec);
+            assert_eq!(values, &deserialize_with::<[i16; 3]>(&vec, mode).unwrap());
+        }
+
+        let vec = serialize_with(
+            &SliceWriter::with_len_prefix(values, false),
+            Mode::default(),
+        );
+        assert_eq!(vec![1, 0, 0xFE, 0xFF, 0x2C, 0x01], vec.unwrap());
+    }
+}
@@ -1,21 +1,78 @@
-use std::io;
-use std::io::{Read, Write};
+//! The variable length integer encoding used by the built-in integer
+//! implementations, for writing it in custom [`BinSerialize`] and
+//! [`BinDeserialize`] implementations.
+//!
+//! Unsigned integers are encoded as unsigned LEB128: the number is split into
+//! groups of 7 bits starting with the lowest ones, and each group is written
+//! as one byte whose highest bit is set if another byte follows. A `u64`
+//! takes between 1 and 10 bytes. Signed integers are first mapped to unsigned
+//! ones using zigzag encoding (0, -1, 1, -2, ... become 0, 1, 2, 3, ...), so
+//! that numbers with a small absolute value stay short.
+//!
+//! The functions here produce exactly the same bytes as `u16`, `u32` and
+//! `u64` do with [`Mode::with_fixed_size_use_varint`], and as `i16`, `i32`
+//! and `i64` do when additionally [`Mode::with_fixed_signed`] isn't enabled.
+//! Lengths and `usize` values with [`UsizeLen::Variable`] use the unsigned
+//! encoding as well.
+//!
+//! ```
+//! use binserde::varint::{read_varint_i64, write_varint_i64};
+//! use binserde::{BinDeserialize, BinDeserializer, BinSerialize, BinSerializer, Result};
+//!
+//! /// An offset which is always written as a varint, regardless of the mode.
+//! #[derive(Debug, PartialEq)]
+//! struct Offset(i64);
+//!
+//! impl BinSerialize for Offset {
+//!     fn serialize<S: BinSerializer>(&self, mut serializer: S) -> Result<()> {
+//!         write_varint_i64(serializer.pipe(), self.0)
+//!     }
+//! }
+//!
+//! impl<'de> BinDeserialize<'de> for Offset {
+//!     fn deserialize<D: BinDeserializer<'de>>(mut deserializer: D) -> Result<Self> {
+//!         Ok(Offset(read_varint_i64(deserializer.pipe())?))
+//!     }
+//! }
+//!
+//! let vec = binserde::serialize(&Offset(-300)).unwrap();
+//! assert_eq!(&[0xD7, 0x04], &*vec);
+//! assert_eq!(Offset(-300), binserde::deserialize(&vec).unwrap());
+//!
+//! // the same bytes as the built-in implementation
+//! let mode = binserde::Mode::default().with_fixed_size_use_varint(true);
+//! assert_eq!(vec, binserde::serialize_with(&-300i64, mode).unwrap());
+//! ```
+//!
+//! [`BinSerialize`]: crate::BinSerialize
+//! [`BinDeserialize`]: crate::BinDeserialize
+//! [`Mode::with_fixed_size_use_varint`]: crate::Mode::with_fixed_size_use_varint
+//! [`Mode::with_fixed_signed`]: crate::Mode::with_fixed_signed
+//! [`UsizeLen::Variable`]: crate::serde::UsizeLen::Variable
 
-use byteorder::ReadBytesExt;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
 
-pub fn encode_min(num: i64) -> u64 {
+use crate::io::{self, Read, ReadBytesExt, Write};
+use crate::write_ext::ReadExt;
+use crate::Result;
+
+/// Maps signed to unsigned integers such that numbers with a small absolute
+/// value stay small (0, -1, 1, -2, ... become 0, 1, 2, 3, ...), so that they
+/// take up few bytes as a varint. This is the same as zigzag encoding.
+pub(crate) fn encode_min(num: i64) -> u64 {
     let u_num = num as u64;
     (u_num << 1 ^ (num >> 63) as u64) | u_num >> 63
 }
 
-pub fn decode_min(num: u64) -> i64 {
+pub(crate) fn decode_min(num: u64) -> i64 {
     (num >> 1) as i64 ^ ((num << 63) as i64) >> 63
 }
 
-pub fn varint_write<W: Write>(num: u64, mut pipe: W) -> io::Result<usize> {
+pub(crate) fn varint_write<W: Write>(num: u64, mut pipe: W) -> io::Result<usize> {
     let mut num_pos = 0;
     let mut idx = 0;
-    let mut buf = [0; 9];
+    let mut buf = [0; MAX_LEN];
     let data_bits = 64 - num.leading_zeros();
 
     loop {
@@ -29,37 +86,193 @@ pub fn varint_write<W: Write>(num: u64, mut pipe: W) -> io::Result<usize> {
         idx += 1;
         num_pos += 7;
 
-        if !(num_pos < data_bits) {
+        if num_pos >= data_bits {
             break;
         }
     }
 
-    pipe.write(&buf[..idx])?;
+    pipe.write_all(&buf[..idx])?;
 
     Ok(idx)
 }
 
-pub fn varint_read<R: Read>(mut pipe: R) -> io::Result<u64> {
-    let mut offset = 0;
-    let mut num = 0;
+/// Returns the number of bytes an unsigned varint takes up, which is at most
+/// [`MAX_LEN`].
+pub fn varint_len(num: u64) -> usize {
+    (64 - num.leading_zeros()).max(1).div_ceil(7) as usize
+}
+
+/// The maximum number of bytes of a varint.
+pub const MAX_LEN: usize = 10;
+
+/// Writes `num` as an unsigned varint.
+pub fn write_varint_u64<W: Write>(pipe: W, num: u64) -> Result<()> {
+    varint_write(num, pipe)?;
+    Ok(())
+}
+
+/// Reads an unsigned varint. Returns an error if it doesn't fit into a
+/// `u64`.
+pub fn read_varint_u64<R: Read>(mut pipe: R) -> Result<u64> {
+    pipe.read_varuint()
+}
+
+/// Writes `num` as a zigzag encoded varint.
+pub fn write_varint_i64<W: Write>(pipe: W, num: i64) -> Result<()> {
+    write_varint_u64(pipe, encode_min(num))
+}
+
+/// Reads a zigzag encoded varint. Returns an error if it doesn't fit into an
+/// `i64`.
+pub fn read_varint_i64<R: Read>(mut pipe: R) -> Result<i64> {
+    pipe.read_varint()
+}
+
+pub(crate) fn varint_read<R: Read>(pipe: R) -> io::Result<u64> {
+    varint_read_overflowing(pipe).map(|(num, _)| num)
+}
+
+/// Reads a varint, additionally returning whether it didn't fit into a `u64`
+/// (in which case the excess bits are discarded) or was longer than the
+/// longest possible encoding of one.
+pub(crate) fn varint_read_overflowing<R: Read>(mut pipe: R) -> io::Result<(u64, bool)> {
+    let first = pipe.read_u8()?;
+
+    // most varints (lengths, indices, small numbers) fit into a single byte,
+    // so handle that case without entering the loop
+    if first & 0b10000000 == 0 {
+        return Ok((first as u64, false));
+    }
+
+    let mut offset: u32 = 7;
+    let mut num = first as u64 & 0b01111111;
+    let mut overflow = false;
 
     loop {
         let byte = pipe.read_u8()?;
         let has_next = byte & 0b10000000 != 0;
-        num |= (byte as u64 & 0b01111111) << offset;
-        offset += 7;
+        let piece = byte as u64 & 0b01111111;
+
+        if offset < 64 {
+            num |= piece << offset;
+            overflow |= offset == 63 && piece > 1;
+        } else {
+            overflow = true;
+        }
+
+        offset = offset.saturating_add(7);
 
         if !has_next {
             break;
         }
     }
 
-    Ok(num)
+    Ok((num, overflow))
+}
+
+/// The byte lengths a value in a group of `u32` values can be encoded as with
+/// [`group_varint_write`].
+pub(crate) const GROUP_SIZES_U32: [usize; 4] = [1, 2, 3, 4];
+
+/// The byte lengths a value in a group of `u64` values can be encoded as with
+/// [`group_varint_write`].
+pub(crate) const GROUP_SIZES_U64: [usize; 4] = [1, 2, 4, 8];
+
+/// Writes `values` in groups of four, each starting with a control byte that
+/// holds a 2 bit index into `sizes` for every value, lowest bits first,
+/// followed by the values as little-endian integers of that many bytes. The
+/// last group can have less than four values, the unused codes are zero.
+///
+/// Unlike with [`varint_write`], the length of every value in a group is known
+/// after reading a single byte, which makes decoding faster. The number of
+/// values isn't written and must be passed to [`group_varint_read`].
+pub(crate) fn group_varint_write<W, T>(
+    values: &[T],
+    sizes: &[usize; 4],
+    mut pipe: W,
+) -> io::Result<()>
+where
+    W: Write,
+    T: Copy + Into<u64>,
+{
+    let mut buf = [0; 1 + 4 * 8];
+
+    for group in values.chunks(4) {
+        let mut control = 0;
+        let mut len = 1;
+
+        for (i, &value) in group.iter().enumerate() {
+            let value = value.into();
+            let code = sizes
+                .iter()
+                .position(|&size| size >= 8 || value >> (size * 8) == 0)
+                .expect("value doesn't fit in largest group size");
+            let size = sizes[code];
+
+            control |= (code as u8) << (i * 2);
+            buf[len..len + size].copy_from_slice(&value.to_le_bytes()[..size]);
+            len += size;
+        }
+
+        buf[0] = control;
+        pipe.write_all(&buf[..len])?;
+    }
+
+    Ok(())
+}
+
+/// Reads `len` values written by [`group_varint_write`] with the same
+/// `sizes` and appends them to `target`.
+pub(crate) fn group_varint_read<R, T>(
+    len: usize,
+    sizes: &[usize; 4],
+    mut pipe: R,
+    target: &mut Vec<T>,
+) -> io::Result<()>
+where
+    R: Read,
+    T: TryFrom<u64>,
+{
+    let mut buf = [0; 4 * 8];
+    let mut remaining = len;
+
+    while remaining > 0 {
+        let count = remaining.min(4);
+        let control = pipe.read_u8()?;
+        let code = |i: usize| (control >> (i * 2)) as usize & 0b11;
+        let total = (0..count).map(|i| sizes[code(i)]).sum();
+        pipe.read_exact(&mut buf[..total])?;
+
+        let mut pos = 0;
+
+        for i in 0..count {
+            let size = sizes[code(i)];
+            let mut bytes = [0; 8];
+            bytes[..size].copy_from_slice(&buf[pos..pos + size]);
+            pos += size;
+
+            let value = T::try_from(u64::from_le_bytes(bytes)).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "group varint out of range")
+            })?;
+            target.push(value);
+        }
+
+        remaining -= count;
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use crate::varint::{decode_min, encode_min, varint_read, varint_write};
+    use std::io;
+
+    use crate::varint::{
+        decode_min, encode_min, group_varint_read, group_varint_write, read_varint_i64,
+        read_varint_u64, varint_len, varint_read, varint_read_overflowing, varint_write,
+        write_varint_i64, write_varint_u64, GROUP_SIZES_U32, GROUP_SIZES_U64, MAX_LEN,
+    };
+    use crate::{serialize_with, Mode};
 
     #[test]
     fn test_encode_min() {
@@ -82,6 +295,31 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_encode_min_is_zigzag() {
+        for i in (-1000..1000).chain([i64::MIN, i64::MIN + 1, i64::MAX - 1, i64::MAX]) {
+            assert_eq!(((i << 1) ^ (i >> 63)) as u64, encode_min(i));
+        }
+
+        let cases: [(i64, &[u8]); 6] = [
+            (-1, &[0x01]),
+            (-2, &[0x03]),
+            (-3, &[0x05]),
+            (-64, &[0x7F]),
+            (-65, &[0x81, 0x01]),
+            (
+                i64::MIN,
+                &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01],
+            ),
+        ];
+
+        for (num, bytes) in cases {
+            let mut buf = Vec::new();
+            varint_write(encode_min(num), &mut buf).unwrap();
+            assert_eq!(bytes, &*buf);
+        }
+    }
+
     #[test]
     fn test_varint() {
         use std::io::Cursor;
@@ -111,4 +349,122 @@ mod test {
         assert_eq!(encode_min(-18), varint_read(&mut buf).unwrap());
         assert_eq!(encode_min(20000000), varint_read(&mut buf).unwrap());
     }
+
+    #[test]
+    fn test_varint_len() {
+        for num in [0, 1, 127, 128, 16383, 16384, u32::MAX as u64, u64::MAX] {
+            assert_eq!(varint_write(num, io::sink()).unwrap(), varint_len(num));
+        }
+    }
+
+    #[test]
+    fn test_varint_overflow() {
+        let max = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        assert_eq!(
+            (u64::MAX, false),
+            varint_read_overflowing(&max[..]).unwrap()
+        );
+
+        let too_big = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x03];
+        assert_eq!(
+            (u64::MAX, true),
+            varint_read_overflowing(&too_big[..]).unwrap()
+        );
+
+        let too_long = [
+            0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00,
+        ];
+        assert_eq!((0, true), varint_read_overflowing(&too_long[..]).unwrap());
+    }
+
+    #[test]
+    fn test_varint_sampled_range() {
+        use std::io::Cursor;
+
+        let mut buf = Cursor::new(Vec::new());
+        let mut expected = Vec::new();
+
+        // every power of two and its neighbours, plus a spread of values
+        for shift in 0..64 {
+            let p = 1u64 << shift;
+            expected.extend(&[p - 1, p, p + 1]);
+        }
+        expected.extend((0..10_000u64).map(|el| el.wrapping_mul(0x9E3779B97F4A7C15)));
+        expected.push(u64::MAX);
+
+        for num in expected.iter() {
+            varint_write(*num, &mut buf).unwrap();
+        }
+
+        buf.set_position(0);
+        for num in expected.iter() {
+            assert_eq!(*num, varint_read(&mut buf).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_group_varint() {
+        let mut values64 = vec![0, 1, 0xFF, 0x100, 0xFFFF, 0x1_0000, 0xFF_FFFF, 0x100_0000];
+        for shift in 0..64 {
+            let p = 1u64 << shift;
+            values64.extend(&[p - 1, p, p + 1]);
+        }
+        values64.extend((0..1000u64).map(|el| el.wrapping_mul(0x9E3779B97F4A7C15) >> (el % 64)));
+        values64.push(u64::MAX);
+
+        let values32: Vec<u32> = values64.iter().map(|&v| v as u32).collect();
+
+        // every length of the last group
+        for len in (0..12).chain([values64.len()]) {
+            let mut buf = Vec::new();
+            group_varint_write(&values32[..len], &GROUP_SIZES_U32, &mut buf).unwrap();
+            let mut out: Vec<u32> = Vec::new();
+            group_varint_read(len, &GROUP_SIZES_U32, &*buf, &mut out).unwrap();
+            assert_eq!(&values32[..len], &*out);
+
+            let mut buf = Vec::new();
+            group_varint_write(&values64[..len], &GROUP_SIZES_U64, &mut buf).unwrap();
+            let mut out: Vec<u64> = Vec::new();
+            group_varint_read(len, &GROUP_SIZES_U64, &*buf, &mut out).unwrap();
+            assert_eq!(&values64[..len], &*out);
+        }
+
+        let mut buf = Vec::new();
+        group_varint_write(
+            &[1u32, 0x100, 0x1_0000, 0x100_0000, 5],
+            &GROUP_SIZES_U32,
+            &mut buf,
+        )
+        .unwrap();
+        assert_eq!(&[0b11_10_01_00, 1, 0, 1, 0, 0, 1, 0, 0, 0, 1, 0, 5], &*buf);
+
+        // truncated input
+        let mut out: Vec<u32> = Vec::new();
+        assert!(group_varint_read(5, &GROUP_SIZES_U32, &buf[..12], &mut out).is_err());
+    }
+
+    #[test]
+    fn test_public_api_matches_builtin() {
+        let mode = Mode::default().with_fixed_size_use_varint(true);
+
+        for num in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = Vec::new();
+            write_varint_u64(&mut buf, num).unwrap();
+            assert_eq!(serialize_with(&num, mode).unwrap(), buf);
+            assert!(buf.len() <= MAX_LEN);
+            assert_eq!(num, read_varint_u64(&*buf).unwrap());
+        }
+
+        for num in [0, -1, 1, -300, i64::MIN, i64::MAX] {
+            let mut buf = Vec::new();
+            write_varint_i64(&mut buf, num).unwrap();
+            assert_eq!(serialize_with(&num, mode).unwrap(), buf);
+            assert_eq!(num, read_varint_i64(&*buf).unwrap());
+        }
+
+        // 11 bytes can't be a valid u64
+        let long = [0xFF; 10].iter().copied().chain([0x01]).collect::<Vec<_>>();
+        assert!(read_varint_u64(&*long).is_err());
+        assert!(read_varint_u64(&[0x80][..]).is_err());
+    }
 }
@@ -1,14 +1,10 @@
-use std::convert::TryInto;
-use std::io::{Read, Write};
+use core::convert::TryInto;
 
-use byteorder::{ReadBytesExt, LE};
-
-use crate::varint::{decode_min, encode_min, varint_read, varint_write};
-use crate::Result;
+use crate::io::{Read, Write};
+use crate::varint::{decode_min, encode_min, varint_read, varint_read_overflowing, varint_write};
+use crate::{Error, Result};
 
 pub trait WriteExt {
-    fn write_str(&mut self, s: &str) -> Result<usize>;
-
     fn write_varuint(&mut self, i: u64) -> Result<usize>;
 
     fn write_varint(&mut self, i: i64) -> Result<usize>;
@@ -17,12 +13,6 @@ pub trait WriteExt {
 }
 
 impl<W: Write> WriteExt for W {
-    fn write_str(&mut self, s: &str) -> Result<usize> {
-        self.write_varuint(s.len().try_into()?)?;
-        self.write(s.as_bytes())?;
-        Ok(2 + s.len())
-    }
-
     fn write_varuint(&mut self, i: u64) -> Result<usize> {
         Ok(varint_write(i, self)?)
     }
@@ -37,24 +27,26 @@ impl<W: Write> WriteExt for W {
 }
 
 pub trait ReadExt {
-    fn read_str(&mut self) -> Result<String>;
-
     fn read_varuint(&mut self) -> Result<u64>;
 
+    fn read_varuint_wrapping(&mut self) -> Result<u64>;
+
     fn read_varint(&mut self) -> Result<i64>;
 
+    fn read_varint_wrapping(&mut self) -> Result<i64>;
+
     fn read_varusize(&mut self) -> Result<usize>;
 }
 
 impl<R: Read> ReadExt for R {
-    fn read_str(&mut self) -> Result<String> {
-        let len = self.read_u16::<LE>()?;
-        let mut buf = vec![0; len as usize];
-        self.read_exact(&mut buf)?;
-        Ok(String::from_utf8(buf)?)
+    fn read_varuint(&mut self) -> Result<u64> {
+        match varint_read_overflowing(self)? {
+            (num, false) => Ok(num),
+            (_, true) => Err(Error::custom("varint overflows 64 bits")),
+        }
     }
 
-    fn read_varuint(&mut self) -> Result<u64> {
+    fn read_varuint_wrapping(&mut self) -> Result<u64> {
         Ok(varint_read(self)?)
     }
 
@@ -62,7 +54,12 @@ impl<R: Read> ReadExt for R {
         Ok(decode_min(self.read_varuint()?))
     }
 
+    fn read_varint_wrapping(&mut self) -> Result<i64> {
+        Ok(decode_min(self.read_varuint_wrapping()?))
+    }
+
     fn read_varusize(&mut self) -> Result<usize> {
-        Ok(self.read_varuint()?.try_into()?)
+        let num = self.read_varuint()?;
+        num.try_into().map_err(|_| Error::UsizeOverflow(num))
     }
 }
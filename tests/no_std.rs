@@ -0,0 +1,97 @@
+//! Uses the crate the way a `no_std` crate would, with only `core` and
+//! `alloc`. Run with `cargo test --no-default-features --test no_std` to
+//! check that it works without the `std` feature.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use binserde::{BinDeserialize, BinSerialize, Mode};
+
+#[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+struct Record {
+    name: String,
+    values: Vec<u32>,
+    kind: Kind,
+    parent: Option<Box<str>>,
+}
+
+#[derive(Debug, PartialEq, BinSerialize, BinDeserialize)]
+enum Kind {
+    Leaf,
+    Node(u8),
+}
+
+#[test]
+fn serialize_into_slice() {
+    let value: Vec<u32> = vec![1, 2, 300];
+    let mut buf = [0; 16];
+    let mut out = &mut buf[..];
+    binserde::serialize_with_into(&mut out, &value, Mode::default()).unwrap();
+    let len = 16 - out.len();
+
+    assert_eq!(&[3, 1, 0, 0, 0, 2, 0, 0, 0, 44, 1, 0, 0], &buf[..len]);
+
+    let out: Vec<u32> = binserde::deserialize(&buf[..len]).unwrap();
+    assert_eq!(value, out);
+
+    // the writer runs out of space
+    let mut buf = [0; 4];
+    let mut out = &mut buf[..];
+    assert!(binserde::serialize_with_into(&mut out, &value, Mode::default()).is_err());
+}
+
+#[test]
+fn derived_round_trip() {
+    let value = Record {
+        name: "child".to_string(),
+        values: vec![7; 3],
+        kind: Kind::Node(2),
+        parent: Some("child".into()),
+    };
+
+    for mode in [Mode::default(), Mode::dedup()] {
+        let vec = binserde::serialize_with(&value, mode).unwrap();
+        let out: Record = binserde::deserialize_with(&vec, mode).unwrap();
+        assert_eq!(value, out);
+    }
+
+    // strings are deduplicated as usual
+    let plain = binserde::serialize_with(&value, Mode::default()).unwrap();
+    let dedup = binserde::serialize_with(&value, Mode::dedup()).unwrap();
+    assert!(dedup.len() < plain.len());
+
+    assert!(binserde::deserialize::<Record>(&plain[..plain.len() - 1]).is_err());
+    let _ = Record::schema();
+}
+
+#[test]
+fn fingerprint_matches_std() {
+    // the same value is checked with std in the tests of the crate
+    assert_eq!(2902819308366345267, Mode::dedup().fingerprint());
+}
+
+#[test]
+fn ip_addr_round_trip() {
+    use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    let addr = Ipv4Addr::new(192, 168, 0, 1);
+    let vec = binserde::serialize(&addr).unwrap();
+    assert_eq!(&[192, 168, 0, 1], &*vec);
+    assert_eq!(addr, binserde::deserialize::<Ipv4Addr>(&vec).unwrap());
+
+    let addrs = [
+        SocketAddr::new(IpAddr::V4(addr), 8080),
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 443),
+    ];
+
+    for addr in addrs {
+        let vec = binserde::serialize(&addr).unwrap();
+        assert_eq!(addr, binserde::deserialize::<SocketAddr>(&vec).unwrap());
+    }
+}